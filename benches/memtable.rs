@@ -0,0 +1,44 @@
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use kvdb::{MemTable, ShardedMemTable, Storage};
+
+const WRITES_PER_THREAD: usize = 1000;
+
+const SHARDS: usize = 16;
+
+/// Write `WRITES_PER_THREAD` distinct keys of one table from each of `threads` threads
+fn concurrent_writes(store: &impl Storage, threads: usize) {
+    thread::scope(|s| {
+        for t in 0..threads {
+            s.spawn(move || {
+                for i in 0..WRITES_PER_THREAD {
+                    let key = format!("k{}-{}", t, i);
+                    store.set("t1", key, (i as i64).into()).unwrap();
+                }
+            });
+        }
+    });
+}
+
+fn memtable_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("single_table_writes");
+    group.sample_size(10);
+
+    for threads in [1, 4, 16] {
+        group.bench_with_input(BenchmarkId::new("memtable", threads), &threads, |b, &n| {
+            let store = MemTable::new();
+            b.iter(|| concurrent_writes(&store, n))
+        });
+
+        group.bench_with_input(BenchmarkId::new("sharded", threads), &threads, |b, &n| {
+            let store = ShardedMemTable::new(SHARDS);
+            b.iter(|| concurrent_writes(&store, n))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, memtable_benchmark);
+criterion_main!(benches);
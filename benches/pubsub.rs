@@ -0,0 +1,71 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use futures::{future, StreamExt};
+use kvdb::{BroadcasterConfig, CommandRequest, MemTable, Service, ServiceInner};
+use tokio::runtime::Runtime;
+
+const MESSAGES: usize = 100;
+
+/// Subscribe `subscribers` times to a topic, publish `MESSAGES` messages,
+/// and wait until every subscriber has received all of them.
+async fn fan_out(config: BroadcasterConfig, subscribers: usize) {
+    let service: Service = ServiceInner::new(MemTable::new())
+        .heartbeat_interval(None)
+        .broadcaster_config(config)
+        .into();
+
+    let mut streams = Vec::with_capacity(subscribers);
+    for _ in 0..subscribers {
+        let mut stream = service.execute(CommandRequest::new_subscribe("lobby"));
+        // the first frame is the subscription id
+        stream.next().await.unwrap();
+        streams.push(stream);
+    }
+
+    let receivers = streams.into_iter().map(|stream| {
+        tokio::spawn(async move {
+            stream.take(MESSAGES).for_each(|_| future::ready(())).await;
+        })
+    });
+    let receivers: Vec<_> = receivers.collect();
+
+    for i in 0..MESSAGES {
+        let cmd = CommandRequest::new_publish("lobby", vec![(i as i64).into()]);
+        service.execute(cmd).next().await.unwrap();
+    }
+
+    future::join_all(receivers).await;
+}
+
+fn pubsub_benchmark(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("fan_out");
+    group.sample_size(10);
+
+    for subscribers in [100, 1000, 5000] {
+        group.bench_with_input(
+            BenchmarkId::new("mpsc", subscribers),
+            &subscribers,
+            |b, &n| {
+                b.to_async(&rt)
+                    .iter(|| fan_out(BroadcasterConfig::default(), n))
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("broadcast", subscribers),
+            &subscribers,
+            |b, &n| {
+                let config = BroadcasterConfig {
+                    hot_topics: vec!["lobby".into()],
+                    ..Default::default()
+                };
+                b.to_async(&rt).iter(|| fan_out(config.clone(), n))
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, pubsub_benchmark);
+criterion_main!(benches);
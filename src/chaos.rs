@@ -0,0 +1,437 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    thread,
+    time::Duration,
+};
+
+use futures::{ready, Future};
+use rand::Rng;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{sleep, Sleep},
+};
+use tracing::warn;
+
+use crate::{
+    KvError, Kvpair, ScanOptions, Storage, StorageStats, StorageStream, UpdateFn, Value, WriteOp,
+};
+
+/// The faults to inject
+#[derive(Debug, Clone, Default)]
+pub struct ChaosConfig {
+    /// The latency added to every storage operation or network read
+    pub latency: Option<Duration>,
+    /// The probability of an operation to fail
+    pub error_rate: f64,
+    /// The probability of a write to be silently dropped,
+    /// for the network it usually drops a whole frame, since a frame is written at once.
+    pub drop_rate: f64,
+}
+
+impl ChaosConfig {
+    fn should_fail(&self) -> bool {
+        self.error_rate > 0.0 && rand::thread_rng().gen_bool(self.error_rate.min(1.0))
+    }
+
+    fn should_drop(&self) -> bool {
+        self.drop_rate > 0.0 && rand::thread_rng().gen_bool(self.drop_rate.min(1.0))
+    }
+}
+
+/// A storage wrapper injecting latency, errors and lost writes.
+/// The latency blocks the calling thread, as the storage interface is synchronous.
+pub struct ChaosStore<S> {
+    inner: S,
+    config: ChaosConfig,
+}
+
+impl<S: Storage> ChaosStore<S> {
+    pub fn new(inner: S, config: ChaosConfig) -> Self {
+        Self { inner, config }
+    }
+
+    /// Sleep and maybe fail before an operation
+    fn inject(&self, op: &str) -> Result<(), KvError> {
+        if let Some(latency) = self.config.latency {
+            thread::sleep(latency);
+        }
+        if self.config.should_fail() {
+            warn!("chaos: injected a failure of {}", op);
+            return Err(KvError::Internal(format!(
+                "chaos: injected failure of {}",
+                op
+            )));
+        }
+        Ok(())
+    }
+
+    /// Whether to drop a write
+    fn drop_write(&self, op: &str) -> bool {
+        let dropped = self.config.should_drop();
+        if dropped {
+            warn!("chaos: dropped a {}", op);
+        }
+        dropped
+    }
+}
+
+impl<S: Storage> Storage for ChaosStore<S> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.inject("get")?;
+        self.inner.get(table, key)
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
+        self.inject("set")?;
+        if self.drop_write("set") {
+            return self.inner.get(table, &key);
+        }
+        self.inner.set(table, key, value)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.inject("contains")?;
+        self.inner.contains(table, key)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.inject("del")?;
+        if self.drop_write("del") {
+            return self.inner.get(table, key);
+        }
+        self.inner.del(table, key)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.inject("get_all")?;
+        self.inner.get_all(table)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        self.inject("get_iter")?;
+        self.inner.get_iter(table)
+    }
+
+    fn get_stream(&self, table: &str) -> Result<StorageStream, KvError> {
+        self.inject("get_stream")?;
+        self.inner.get_stream(table)
+    }
+
+    fn scan(
+        &self,
+        table: &str,
+        opts: ScanOptions,
+    ) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        self.inject("scan")?;
+        self.inner.scan(table, opts)
+    }
+
+    fn sample(&self, table: &str, count: usize) -> Result<Vec<Kvpair>, KvError> {
+        self.inject("sample")?;
+        self.inner.sample(table, count)
+    }
+
+    fn len_of_table(&self, table: &str) -> Result<usize, KvError> {
+        self.inner.len_of_table(table)
+    }
+
+    fn copy_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        self.inject("copy_key")?;
+        if self.drop_write("copy_key") {
+            return self.inner.contains(from, key);
+        }
+        self.inner.copy_key(from, to, key, replace)
+    }
+
+    fn move_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        self.inject("move_key")?;
+        if self.drop_write("move_key") {
+            return self.inner.contains(from, key);
+        }
+        self.inner.move_key(from, to, key, replace)
+    }
+
+    fn clear_table(&self, table: &str) -> Result<usize, KvError> {
+        self.inject("clear_table")?;
+        if self.drop_write("clear_table") {
+            return self.inner.len_of_table(table);
+        }
+        self.inner.clear_table(table)
+    }
+
+    fn size_of_table(&self, table: &str) -> Result<usize, KvError> {
+        self.inner.size_of_table(table)
+    }
+
+    fn total_size(&self) -> Result<usize, KvError> {
+        self.inner.total_size()
+    }
+
+    fn stats(&self) -> Result<StorageStats, KvError> {
+        self.inner.stats()
+    }
+
+    fn transaction(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KvError> {
+        self.inject("transaction")?;
+        self.inner.transaction(ops)
+    }
+
+    fn flush(&self) -> Result<(), KvError> {
+        self.inject("flush")?;
+        self.inner.flush()
+    }
+
+    async fn flush_async(&self) -> Result<(), KvError> {
+        self.inject("flush")?;
+        self.inner.flush_async().await
+    }
+
+    fn snapshot(&self, writer: &mut dyn io::Write) -> Result<usize, KvError> {
+        self.inject("snapshot")?;
+        self.inner.snapshot(writer)
+    }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: &mut UpdateFn,
+    ) -> Result<(Option<Value>, Option<Value>), KvError> {
+        self.inject("update")?;
+        if self.drop_write("update") {
+            let old = self.inner.get(table, key)?;
+            return Ok((old.clone(), old));
+        }
+        self.inner.update(table, key, f)
+    }
+
+    fn expire(&self, table: &str, key: &str, deadline: Option<u64>) -> Result<bool, KvError> {
+        self.inject("expire")?;
+        if self.drop_write("expire") {
+            return self.inner.contains(table, key);
+        }
+        self.inner.expire(table, key, deadline)
+    }
+
+    fn deadline(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.inject("deadline")?;
+        self.inner.deadline(table, key)
+    }
+
+    fn purge_expired(&self, now: u64) -> Result<Vec<(String, String)>, KvError> {
+        self.inject("purge_expired")?;
+        self.inner.purge_expired(now)
+    }
+
+    fn touch(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.inject("touch")?;
+        self.inner.touch(table, key)
+    }
+
+    fn last_access(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.inject("last_access")?;
+        self.inner.last_access(table, key)
+    }
+
+    fn version(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.inject("version")?;
+        self.inner.version(table, key)
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: Option<&str>,
+        end: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        self.inject("get_range")?;
+        self.inner.get_range(table, start, end, limit)
+    }
+
+    fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<Vec<Option<Value>>, KvError> {
+        self.inject("set_batch")?;
+        if self.drop_write("set_batch") {
+            return pairs
+                .iter()
+                .map(|pair| self.inner.get(table, &pair.key))
+                .collect();
+        }
+        self.inner.set_batch(table, pairs)
+    }
+
+    fn del_batch(&self, table: &str, keys: Vec<String>) -> Result<Vec<Option<Value>>, KvError> {
+        self.inject("del_batch")?;
+        if self.drop_write("del_batch") {
+            return keys.iter().map(|key| self.inner.get(table, key)).collect();
+        }
+        self.inner.del_batch(table, keys)
+    }
+
+    fn modified(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.inject("modified")?;
+        self.inner.modified(table, key)
+    }
+
+    fn get_versioned(&self, table: &str, key: &str) -> Result<Option<(Value, u64)>, KvError> {
+        self.inject("get_versioned")?;
+        self.inner.get_versioned(table, key)
+    }
+
+    fn set_if_version(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        version: u64,
+    ) -> Result<Option<(Value, u64)>, KvError> {
+        self.inject("set_if_version")?;
+        if self.drop_write("set_if_version") {
+            let current = self.inner.get_versioned(table, &key)?;
+            return Ok(current.filter(|(_, v)| *v == version));
+        }
+        self.inner.set_if_version(table, key, value, version)
+    }
+}
+
+/// A network stream wrapper injecting read latency, IO errors and dropped writes
+pub struct ChaosStream<S> {
+    inner: S,
+    config: ChaosConfig,
+    /// The pending latency of the current read
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> ChaosStream<S> {
+    pub fn new(inner: S, config: ChaosConfig) -> Self {
+        Self {
+            inner,
+            config,
+            delay: None,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ChaosStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if let Some(latency) = this.config.latency {
+            let delay = this.delay.get_or_insert_with(|| Box::pin(sleep(latency)));
+            ready!(delay.as_mut().poll(cx));
+        }
+
+        let res = if this.config.should_fail() {
+            warn!("chaos: injected a read failure");
+            Err(io::Error::other("chaos: injected read failure"))
+        } else {
+            ready!(Pin::new(&mut this.inner).poll_read(cx, buf))
+        };
+        this.delay = None;
+        Poll::Ready(res)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ChaosStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.config.should_fail() {
+            warn!("chaos: injected a write failure");
+            return Poll::Ready(Err(io::Error::other("chaos: injected write failure")));
+        }
+        if this.config.should_drop() {
+            warn!("chaos: dropped {} bytes", buf.len());
+            return Poll::Ready(Ok(buf.len()));
+        }
+        Pin::new(&mut this.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use crate::MemTable;
+
+    use super::*;
+
+    #[test]
+    fn chaos_store_should_inject_faults() {
+        let store = ChaosStore::new(
+            MemTable::new(),
+            ChaosConfig {
+                error_rate: 1.0,
+                ..Default::default()
+            },
+        );
+        assert!(store.get("t1", "k1").is_err());
+
+        let store = ChaosStore::new(
+            MemTable::new(),
+            ChaosConfig {
+                drop_rate: 1.0,
+                latency: Some(Duration::from_millis(10)),
+                ..Default::default()
+            },
+        );
+        let start = Instant::now();
+        assert_eq!(store.set("t1", "k1".into(), "v1".into()).unwrap(), None);
+        assert_eq!(store.get("t1", "k1").unwrap(), None);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn chaos_stream_should_inject_faults() {
+        let (client, server) = duplex(64);
+        let config = ChaosConfig {
+            drop_rate: 1.0,
+            ..Default::default()
+        };
+        let mut client = ChaosStream::new(client, config);
+        let mut server = ChaosStream::new(
+            server,
+            ChaosConfig {
+                latency: Some(Duration::from_millis(10)),
+                ..Default::default()
+            },
+        );
+
+        // the dropped write never reaches the peer
+        client.write_all(b"lost").await.unwrap();
+        client.inner.write_all(b"kept").await.unwrap();
+        let start = Instant::now();
+        let mut buf = [0u8; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"kept");
+        assert!(start.elapsed() >= Duration::from_millis(10));
+
+        let mut failing = ChaosStream::new(
+            duplex(64).0,
+            ChaosConfig {
+                error_rate: 1.0,
+                ..Default::default()
+            },
+        );
+        assert!(failing.read(&mut buf).await.is_err());
+    }
+}
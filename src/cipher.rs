@@ -0,0 +1,201 @@
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use prost::Message;
+
+use crate::{
+    command_request::RequestData, CommandBatch, CommandRequest, CommandResponse, Hgetset, Hmset,
+    Hsetnx, Import, KvError, Lpush, Publish, Rpush, Txn, Value,
+};
+
+/// The length of the nonce prepended to the ciphertext
+const NONCE_LEN: usize = 24;
+
+/// How a value is encrypted by the client, the server stores it as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValueEncryption {
+    /// The value is plaintext.
+    #[default]
+    None,
+    /// The encoded value is sealed with XChaCha20-Poly1305, the binary is the nonce and the ciphertext.
+    XChaCha20Poly1305,
+}
+
+impl From<u32> for ValueEncryption {
+    fn from(v: u32) -> Self {
+        match v {
+            1 => ValueEncryption::XChaCha20Poly1305,
+            _ => ValueEncryption::None,
+        }
+    }
+}
+
+impl From<ValueEncryption> for u32 {
+    fn from(e: ValueEncryption) -> Self {
+        match e {
+            ValueEncryption::None => 0,
+            ValueEncryption::XChaCha20Poly1305 => 1,
+        }
+    }
+}
+
+/// Encrypts the values on the client with a key the server never sees
+#[derive(Clone)]
+pub struct ValueCipher {
+    cipher: XChaCha20Poly1305,
+}
+
+impl ValueCipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(key.into()),
+        }
+    }
+
+    /// Generate a random key, to be kept by the clients only
+    pub fn generate_key() -> [u8; 32] {
+        XChaCha20Poly1305::generate_key(&mut OsRng).into()
+    }
+
+    /// Encrypt a value, an encrypted value is returned as is
+    pub fn encrypt(&self, value: &Value) -> Result<Value, KvError> {
+        if value.encryption() != ValueEncryption::None {
+            return Ok(value.clone());
+        }
+
+        let mut value = self.seal(value)?;
+        value.encryption = ValueEncryption::XChaCha20Poly1305.into();
+        Ok(value)
+    }
+
+    /// Decrypt a value, a plaintext value is returned as is
+    pub fn decrypt(&self, value: &Value) -> Result<Value, KvError> {
+        if value.encryption() == ValueEncryption::None {
+            return Ok(value.clone());
+        }
+        self.open(value)
+    }
+
+    /// Seal the encoded value, whatever its encryption, into a binary of the nonce and the ciphertext
+    pub(crate) fn seal(&self, value: &Value) -> Result<Value, KvError> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = self
+            .cipher
+            .encrypt(&nonce, value.encode_to_vec().as_slice())
+            .map_err(|_| KvError::Internal("failed to encrypt value".into()))?;
+
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&sealed);
+        Ok(bytes::Bytes::from(data).into())
+    }
+
+    /// Open a value sealed by `seal`
+    pub(crate) fn open(&self, value: &Value) -> Result<Value, KvError> {
+        let data = match &value.value {
+            Some(crate::value::Value::Binary(data)) if data.len() > NONCE_LEN => data,
+            _ => return Err(KvError::DecryptError("malformed ciphertext".into())),
+        };
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        let plain = self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), sealed)
+            .map_err(|_| KvError::DecryptError("wrong key or tampered value".into()))?;
+        Ok(Value::decode(plain.as_slice())?)
+    }
+
+    /// Encrypt the values written by a command
+    pub(crate) fn encrypt_request(&self, cmd: &mut CommandRequest) -> Result<(), KvError> {
+        match cmd.request_data.as_mut() {
+            Some(RequestData::Hset(param)) => {
+                if let Some(pair) = param.pair.as_mut() {
+                    pair.value = pair.value.as_ref().map(|v| self.encrypt(v)).transpose()?;
+                }
+            }
+            Some(RequestData::Hsetnx(Hsetnx { pair, .. }))
+            | Some(RequestData::Hgetset(Hgetset { pair, .. })) => {
+                if let Some(pair) = pair.as_mut() {
+                    pair.value = pair.value.as_ref().map(|v| self.encrypt(v)).transpose()?;
+                }
+            }
+            Some(RequestData::Hmset(Hmset { pairs, .. }))
+            | Some(RequestData::Import(Import { pairs, .. })) => {
+                for pair in pairs.iter_mut() {
+                    pair.value = pair.value.as_ref().map(|v| self.encrypt(v)).transpose()?;
+                }
+            }
+            // each value of a list is encrypted on its own
+            Some(RequestData::Lpush(Lpush { values, .. }))
+            | Some(RequestData::Rpush(Rpush { values, .. }))
+            | Some(RequestData::Publish(Publish { values, .. })) => {
+                for value in values.iter_mut() {
+                    *value = self.encrypt(value)?;
+                }
+            }
+            Some(RequestData::Batch(CommandBatch { requests }))
+            | Some(RequestData::Txn(Txn { requests })) => {
+                for cmd in requests.iter_mut() {
+                    self.encrypt_request(cmd)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Decrypt the values returned in a response
+    pub(crate) fn decrypt_response(&self, res: &mut CommandResponse) -> Result<(), KvError> {
+        for value in res.values.iter_mut() {
+            *value = self.decrypt(value)?;
+        }
+        for pair in res.pairs.iter_mut() {
+            pair.value = pair.value.as_ref().map(|v| self.decrypt(v)).transpose()?;
+        }
+        for res in res.responses.iter_mut() {
+            self.decrypt_response(res)?;
+        }
+        Ok(())
+    }
+}
+
+impl Value {
+    /// How the value is encrypted
+    pub fn encryption(&self) -> ValueEncryption {
+        self.encryption.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_cipher_should_roundtrip() {
+        let cipher = ValueCipher::new(&ValueCipher::generate_key());
+        for value in [
+            Value::from("hello"),
+            Value::from(42),
+            Value::from([1u8, 2, 3]),
+        ] {
+            let sealed = cipher.encrypt(&value).unwrap();
+            assert_eq!(sealed.encryption(), ValueEncryption::XChaCha20Poly1305);
+            assert_ne!(sealed, value);
+            // encrypting twice does not double the encryption
+            assert_eq!(cipher.encrypt(&sealed).unwrap(), sealed);
+            assert_eq!(cipher.decrypt(&sealed).unwrap(), value);
+        }
+        // plaintext passes through
+        assert_eq!(cipher.decrypt(&"plain".into()).unwrap(), "plain".into());
+    }
+
+    #[test]
+    fn value_cipher_with_wrong_key_should_fail() {
+        let cipher = ValueCipher::new(&ValueCipher::generate_key());
+        let other = ValueCipher::new(&ValueCipher::generate_key());
+        let sealed = cipher.encrypt(&"secret".into()).unwrap();
+        assert!(matches!(
+            other.decrypt(&sealed),
+            Err(KvError::DecryptError(_))
+        ));
+    }
+}
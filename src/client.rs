@@ -1,7 +1,10 @@
-use std::time::Duration;
+use std::{env, time::Duration};
 
 use futures::StreamExt;
-use kvdb::{CommandRequest, KvError, ProstClientStream, TlsClientConnector, YamuxCtrl};
+use kvdb::{
+    CommandRequest, KvError, Multiplexer, ProstClientStream, ServerDiscovery, SrvDiscovery,
+    Subscription, TlsClientConnector, YamuxCtrl,
+};
 use tokio::{net::TcpStream, time};
 use tokio_util::compat::Compat;
 use tracing::{error, info};
@@ -10,12 +13,23 @@ use tracing::{error, info};
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
-    let ca_cert = include_str!("../fixtures/ca.cert");
+    // the TLS material is read at runtime, the fixtures are the defaults for local runs
+    let ca = env::var("KV_TLS_CA").unwrap_or_else(|_| "fixtures/ca.cert".into());
+    let identity = env::var("KV_TLS_CERT")
+        .ok()
+        .zip(env::var("KV_TLS_KEY").ok());
 
-    // connect to server
-    let addr = "127.0.0.1:9527";
+    // connect to server, discovered by a DNS SRV record if one is given
+    let addr = match env::var("KV_SRV") {
+        Ok(name) => {
+            let discovery = SrvDiscovery::from_system_conf(name)?;
+            let discovery = ServerDiscovery::start(discovery, Duration::from_secs(30)).await?;
+            discovery.servers()[0]
+        }
+        Err(_) => "127.0.0.1:9527".parse()?,
+    };
 
-    let connector = TlsClientConnector::new("kvserver.acme.inc", None, Some(ca_cert))?;
+    let connector = TlsClientConnector::from_pem_files("kvserver.acme.inc", identity, Some(ca))?;
     let stream = TcpStream::connect(addr).await?;
     let stream = connector.connect(stream).await?;
 
@@ -35,16 +49,19 @@ async fn main() -> anyhow::Result<()> {
     let resp = client.execute_unary(&cmd).await?;
     info!("Got response: {:?}", resp);
 
-    // subscribe to the channel
-    let cmd = CommandRequest::new_subscribe(channel);
-    let mut stream = client.execute_stream(&cmd).await?;
-    let id = stream.id;
-    start_unsubscribes(ctrl.open_stream().await?, channel, id)?;
+    // subscribe to the channel, and unsubscribe after 2 seconds
+    let mut subscription = Subscription::new(&mut ctrl, channel).await?;
+    let deadline = time::sleep(Duration::from_millis(2000));
+    tokio::pin!(deadline);
 
-    while let Some(Ok(data)) = stream.next().await {
-        info!("Got published data: {:?}", data);
+    loop {
+        tokio::select! {
+            Some(msg) = subscription.next() => info!("Got published data: {:?}", msg),
+            _ = &mut deadline => break,
+        }
     }
 
+    subscription.unsubscribe().await?;
     info!("Done!");
     Ok(())
 }
@@ -62,17 +79,3 @@ fn start_publishing(stream: Compat<yamux::Stream>, name: &str) -> Result<(), KvE
     });
     Ok(())
 }
-
-fn start_unsubscribes(stream: Compat<yamux::Stream>, name: &str, id: u32) -> Result<(), KvError> {
-    let cmd = CommandRequest::new_unsubscribe(name, id);
-    tokio::spawn(async move {
-        time::sleep(Duration::from_millis(2000)).await;
-        let mut client = ProstClientStream::new(stream);
-        let res = client.execute_unary(&cmd).await;
-        match res {
-            Ok(resp) => info!("Finished unsubscribing {:?}", resp),
-            Err(e) => error!("Failed to unsubscribe: {:?}", e),
-        }
-    });
-    Ok(())
-}
@@ -0,0 +1,27 @@
+//! Dump a sled data directory to the portable dump format, the server must be stopped.
+//!
+//! Usage: kvdb-dump <sled-dir> [output], the dump is written to stdout without an output file.
+
+use std::{env, fs::File, io, path::Path};
+
+use anyhow::Context;
+use kvdb::SledDb;
+
+fn main() -> anyhow::Result<()> {
+    let mut args = env::args().skip(1);
+    let dir = args
+        .next()
+        .context("usage: kvdb-dump <sled-dir> [output]")?;
+    // sled would create a missing directory and dump nothing
+    anyhow::ensure!(Path::new(&dir).is_dir(), "{} is not a directory", dir);
+    let db = SledDb::builder(&dir)
+        .open()
+        .with_context(|| format!("failed to open {}, is the server still running?", dir))?;
+
+    let count = match args.next() {
+        Some(path) => db.dump(File::create(&path)?)?,
+        None => db.dump(io::stdout().lock())?,
+    };
+    eprintln!("dumped {} pairs from {}", count, dir);
+    Ok(())
+}
@@ -1,3 +1,5 @@
+use std::io::ErrorKind;
+
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -5,8 +7,32 @@ pub enum KvError {
     #[error("Not found {0}")]
     NotFound(String),
 
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    #[error("Throttled: {0}")]
+    Throttled(String),
+
+    #[error("Insufficient storage: {0}")]
+    InsufficientStorage(String),
+
+    #[error("Too large: {0}")]
+    TooLarge(String),
+    #[error("Too large: key of {0} bytes, the limit is {1}")]
+    KeyTooLong(usize, usize),
+    #[error("Too large: value of {0} bytes, the limit is {1}")]
+    ValueTooLarge(usize, usize),
+
+    #[error("Condition not met: {0}")]
+    ConditionNotMet(String),
+
     #[error("Cannot parse command: `{0}`")]
     InvalidCommand(String),
+    #[error("Invalid table name {0:?}")]
+    InvalidTableName(String),
     #[error("Cannot convert value {0:?} to {1}")]
     ConvertCommand(String, &'static str),
     #[error("Cannot process command {0} with table: {1}, key: {2}. Error: {3}")]
@@ -17,21 +43,70 @@ pub enum KvError {
     #[error("Failed to decode protobuf message: {0}")]
     DecodeError(#[from] prost::DecodeError),
 
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+
     #[error("Sled error: {0}")]
     SledError(#[from] sled::Error),
 
     #[error("Internal error: {0}")]
     Internal(String),
 
+    #[error("Data corruption: {0}")]
+    Corruption(String),
+
     #[error("I/O error: {0}")]
     IOError(#[from] std::io::Error),
 
     #[error("Frame is large than max size")]
     FrameTooLarge,
 
+    #[error("Invalid frame: {0}")]
+    InvalidFrame(String),
+
+    #[error("Cannot decrypt value: {0}")]
+    DecryptError(String),
+
+    #[error("Server discovery failed: {0}")]
+    Discovery(String),
+
+    #[error("Invalid IP rule: {0}")]
+    InvalidIpRule(String),
+
+    #[error("Invalid PROXY protocol header: {0}")]
+    InvalidProxyHeader(String),
+
     #[error("Failed to parse certificate: {0} {1}")]
     CertificateParseError(&'static str, &'static str),
 
+    #[error("Invalid TLS material: {0}")]
+    TlsMaterial(String),
+
     #[error("TLS error")]
     TlsError(#[from] tokio_rustls::rustls::TLSError),
+
+    #[error("Noise error: {0}")]
+    NoiseError(#[from] snow::Error),
+
+    #[error("Yamux error: {0}")]
+    YamuxError(#[from] yamux::ConnectionError),
+
+    #[error("HTTP/2 error: {0}")]
+    H2Error(#[from] h2::Error),
+}
+
+impl KvError {
+    /// Whether the error is caused by a single malformed frame,
+    /// the connection could keep serving the following frames.
+    pub fn is_frame_error(&self) -> bool {
+        matches!(
+            self,
+            KvError::DecodeError(_) | KvError::InvalidFrame(_) | KvError::TooLarge(_)
+        )
+    }
+
+    /// Whether the error means the peer has closed the connection.
+    pub fn is_closed(&self) -> bool {
+        matches!(self, KvError::IOError(e) if e.kind() == ErrorKind::UnexpectedEof)
+    }
 }
@@ -1,11 +1,19 @@
+#[cfg(feature = "chaos")]
+mod chaos;
+mod cipher;
 mod error;
 mod network;
 mod pb;
 mod service;
-mod storage;
+pub mod storage;
+mod task;
 
+#[cfg(feature = "chaos")]
+pub use chaos::{ChaosConfig, ChaosStore, ChaosStream};
+pub use cipher::{ValueCipher, ValueEncryption};
 pub use error::KvError;
 pub use network::*;
 pub use pb::*;
 pub use service::*;
 pub use storage::*;
+pub use task::spawn_named;
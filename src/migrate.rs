@@ -0,0 +1,122 @@
+//! Migrate the pairs of a storage to a sled data directory, the server must be stopped.
+//! The source is a sled data directory, or a snapshot file of a MemTable server.
+//! The existing keys of the destination are overwritten, the others are kept.
+//!
+//! Usage: kvdb-migrate <source> <sled-dir> [--batch <n>] [--checkpoint <file>]
+//!
+//! With a checkpoint file, the position is saved after every batch and an interrupted
+//! migration resumes from it, the file is removed once the migration is done.
+
+use std::{env, fs, path::Path};
+
+use anyhow::Context;
+use kvdb::{
+    migrate_with, Kvpair, MemTable, MigrateCheckpoint, MigrateOptions, SledDb, Storage, Value,
+};
+use prost::Message;
+
+const USAGE: &str = "usage: kvdb-migrate <source> <sled-dir> [--batch <n>] [--checkpoint <file>]";
+
+fn main() -> anyhow::Result<()> {
+    let mut args = env::args().skip(1);
+    let src = args.next().context(USAGE)?;
+    let dst = args.next().context(USAGE)?;
+    let mut opts = MigrateOptions::default();
+    let mut checkpoint = None;
+    while let Some(arg) = args.next() {
+        let value = args.next().context(USAGE)?;
+        match arg.as_str() {
+            "--batch" => opts = opts.batch_size(value.parse().context("invalid batch size")?),
+            "--checkpoint" => checkpoint = Some(value),
+            _ => anyhow::bail!(USAGE),
+        }
+    }
+    if let Some(path) = &checkpoint {
+        if let Some(resume) = load_checkpoint(path)? {
+            eprintln!(
+                "resuming from table {} key {:?}",
+                resume.table, resume.cursor
+            );
+            opts = opts.resume(resume);
+        }
+    }
+
+    // sled would create a missing directory and migrate nothing
+    let src_is_dir = Path::new(&src).is_dir();
+    anyhow::ensure!(
+        src_is_dir || Path::new(&src).is_file(),
+        "{} does not exist",
+        src
+    );
+    let dst_db = SledDb::builder(&dst)
+        .open()
+        .with_context(|| format!("failed to open {}, is the server still running?", dst))?;
+    let count = match src_is_dir {
+        true => {
+            let src_db = SledDb::builder(&src)
+                .open()
+                .with_context(|| format!("failed to open {}, is the server still running?", src))?;
+            run(&src_db, &dst_db, opts, checkpoint.as_deref())?
+        }
+        false => {
+            let table = MemTable::load_snapshot(&src)?;
+            run(&table, &dst_db, opts, checkpoint.as_deref())?
+        }
+    };
+    if let Some(path) = &checkpoint {
+        fs::remove_file(path).ok();
+    }
+    eprintln!("migrated {} pairs from {} to {}", count, src, dst);
+    Ok(())
+}
+
+fn run(
+    src: &impl Storage,
+    dst: &SledDb,
+    opts: MigrateOptions,
+    checkpoint: Option<&str>,
+) -> anyhow::Result<usize> {
+    let mut saved = Ok(());
+    let count = migrate_with(src, dst, opts, |progress| {
+        eprintln!(
+            "{} pairs, at table {}",
+            progress.pairs, progress.checkpoint.table
+        );
+        if let (Some(path), Ok(())) = (checkpoint, &saved) {
+            // the checkpoint must not get ahead of the pairs on disk
+            saved = dst
+                .sync()
+                .map_err(anyhow::Error::from)
+                .and_then(|_| save_checkpoint(path, &progress.checkpoint));
+        }
+    })?;
+    saved.context("failed to save the checkpoint")?;
+    Ok(count)
+}
+
+/// The checkpoint is saved as a pair of the table and the cursor, replaced atomically
+fn save_checkpoint(path: &str, checkpoint: &MigrateCheckpoint) -> anyhow::Result<()> {
+    let cursor: Value = checkpoint.cursor.clone().unwrap_or_default().into();
+    let data = Kvpair::new(checkpoint.table.clone(), cursor).encode_to_vec();
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn load_checkpoint(path: &str) -> anyhow::Result<Option<MigrateCheckpoint>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let pair = Kvpair::decode(data.as_slice()).context("invalid checkpoint file")?;
+    let cursor = match pair.value.and_then(|v| v.value) {
+        Some(kvdb::value::Value::String(key)) if !key.is_empty() => Some(key),
+        _ => None,
+    };
+    Ok(Some(MigrateCheckpoint {
+        table: pair.key,
+        cursor,
+    }))
+}
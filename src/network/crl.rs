@@ -0,0 +1,148 @@
+use std::{collections::HashSet, sync::Arc};
+
+use tokio_rustls::rustls::{
+    internal::msgs::handshake::DigitallySignedStruct, Certificate, ClientCertVerified,
+    ClientCertVerifier, DistinguishedNames, HandshakeSignatureValid, TLSError,
+};
+use tokio_rustls::webpki::{self, DNSName};
+use x509_parser::{
+    certificate::X509Certificate, pem::Pem, prelude::FromDer,
+    revocation_list::CertificateRevocationList,
+};
+
+use crate::KvError;
+
+/// The algorithms a CRL could be signed with, the right one is found by trying them all
+static CRL_ALGORITHMS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+];
+
+/// The certificates revoked by a set of CRLs, keyed by the issuer name and the serial number
+#[derive(Debug, Default)]
+pub(crate) struct RevokedCerts(HashSet<(Vec<u8>, Vec<u8>)>);
+
+impl RevokedCerts {
+    /// Load the PEM encoded CRLs, each of them must be signed by one of the CA certificates
+    pub fn load(crls: &[&str], ca_certs: &[Certificate]) -> Result<Self, KvError> {
+        let mut revoked = HashSet::new();
+        for crl in crls {
+            for pem in Pem::iter_from_buffer(crl.as_bytes()) {
+                let pem = pem.map_err(|e| invalid(format!("bad PEM CRL: {}", e)))?;
+                let (_, crl) = CertificateRevocationList::from_der(&pem.contents)
+                    .map_err(|e| invalid(format!("bad CRL: {}", e)))?;
+                verify_crl(&crl, ca_certs)?;
+
+                let issuer = crl.issuer().as_raw().to_vec();
+                for cert in crl.iter_revoked_certificates() {
+                    revoked.insert((issuer.clone(), cert.raw_serial().to_vec()));
+                }
+            }
+        }
+        Ok(Self(revoked))
+    }
+
+    /// Whether the DER encoded certificate is revoked
+    fn contains(&self, cert: &Certificate) -> Result<bool, TLSError> {
+        let (_, cert) = X509Certificate::from_der(&cert.0)
+            .map_err(|e| TLSError::General(format!("bad client certificate: {}", e)))?;
+        let key = (cert.issuer().as_raw().to_vec(), cert.raw_serial().to_vec());
+        Ok(self.0.contains(&key))
+    }
+}
+
+/// Check the CRL is signed by the CA certificate it names as the issuer
+fn verify_crl(crl: &CertificateRevocationList, ca_certs: &[Certificate]) -> Result<(), KvError> {
+    let tbs = crl.tbs_cert_list.as_ref();
+    let signature = crl.signature_value.data.as_ref();
+
+    for ca in ca_certs {
+        let issued = X509Certificate::from_der(&ca.0)
+            .map(|(_, cert)| cert.subject().as_raw() == crl.issuer().as_raw())
+            .unwrap_or(false);
+        if !issued {
+            continue;
+        }
+        let verified = webpki::EndEntityCert::from(&ca.0)
+            .map(|cert| {
+                CRL_ALGORITHMS
+                    .iter()
+                    .any(|alg| cert.verify_signature(alg, tbs, signature).is_ok())
+            })
+            .unwrap_or(false);
+        if verified {
+            return Ok(());
+        }
+    }
+    Err(invalid(format!(
+        "the CRL of {} is not signed by a client CA",
+        crl.issuer()
+    )))
+}
+
+fn invalid(msg: String) -> KvError {
+    KvError::TlsMaterial(msg)
+}
+
+/// A client certificate verifier rejecting the certificates revoked by the CRLs
+pub(crate) struct CrlVerifier {
+    inner: Arc<dyn ClientCertVerifier>,
+    revoked: RevokedCerts,
+}
+
+impl CrlVerifier {
+    pub fn new(inner: Arc<dyn ClientCertVerifier>, revoked: RevokedCerts) -> Arc<Self> {
+        Arc::new(Self { inner, revoked })
+    }
+}
+
+impl ClientCertVerifier for CrlVerifier {
+    fn offer_client_auth(&self) -> bool {
+        self.inner.offer_client_auth()
+    }
+
+    fn client_auth_mandatory(&self, sni: Option<&DNSName>) -> Option<bool> {
+        self.inner.client_auth_mandatory(sni)
+    }
+
+    fn client_auth_root_subjects(&self, sni: Option<&DNSName>) -> Option<DistinguishedNames> {
+        self.inner.client_auth_root_subjects(sni)
+    }
+
+    fn verify_client_cert(
+        &self,
+        presented_certs: &[Certificate],
+        sni: Option<&DNSName>,
+    ) -> Result<ClientCertVerified, TLSError> {
+        let verified = self.inner.verify_client_cert(presented_certs, sni)?;
+        // the chain is verified, so the end entity certificate is the first one
+        if self.revoked.contains(&presented_certs[0])? {
+            return Err(TLSError::General("client certificate is revoked".into()));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TLSError> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TLSError> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+}
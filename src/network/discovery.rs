@@ -0,0 +1,180 @@
+use std::{net::SocketAddr, time::Duration};
+
+use futures::future::BoxFuture;
+use hickory_resolver::TokioAsyncResolver;
+use tokio::{sync::watch, task::JoinHandle, time};
+use tracing::warn;
+
+use crate::{spawn_named, KvError};
+
+/// A source of the current set of kvdb servers
+pub trait Discover: Send + Sync + 'static {
+    /// Get the addresses of the servers, the preferred ones first
+    fn discover(&self) -> BoxFuture<'_, Result<Vec<SocketAddr>, KvError>>;
+}
+
+/// Discover the servers with a DNS SRV record, like `_kvdb._tcp.example.com`
+pub struct SrvDiscovery {
+    name: String,
+    resolver: TokioAsyncResolver,
+}
+
+impl SrvDiscovery {
+    /// Resolve the record with the system DNS configuration
+    pub fn from_system_conf(name: impl Into<String>) -> Result<Self, KvError> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .map_err(|e| KvError::Discovery(e.to_string()))?;
+        Ok(Self::new(name, resolver))
+    }
+
+    pub fn new(name: impl Into<String>, resolver: TokioAsyncResolver) -> Self {
+        Self {
+            name: name.into(),
+            resolver,
+        }
+    }
+
+    async fn lookup(&self) -> Result<Vec<SocketAddr>, KvError> {
+        let err = |e: hickory_resolver::error::ResolveError| KvError::Discovery(e.to_string());
+        let mut records: Vec<_> = self
+            .resolver
+            .srv_lookup(&self.name)
+            .await
+            .map_err(err)?
+            .into_iter()
+            .collect();
+        // a lower priority is preferred, then a higher weight
+        records.sort_by_key(|srv| (srv.priority(), u16::MAX - srv.weight()));
+
+        let mut servers = Vec::new();
+        for srv in records {
+            let ips = self
+                .resolver
+                .lookup_ip(srv.target().clone())
+                .await
+                .map_err(err)?;
+            servers.extend(ips.iter().map(|ip| SocketAddr::new(ip, srv.port())));
+        }
+        Ok(servers)
+    }
+}
+
+impl Discover for SrvDiscovery {
+    fn discover(&self) -> BoxFuture<'_, Result<Vec<SocketAddr>, KvError>> {
+        Box::pin(self.lookup())
+    }
+}
+
+/// Keeps the set of servers refreshed in the background, for the client logic
+/// picking the server of a request. The task is stopped when this is dropped.
+pub struct ServerDiscovery {
+    servers: watch::Receiver<Vec<SocketAddr>>,
+    refresher: JoinHandle<()>,
+}
+
+impl ServerDiscovery {
+    /// Discover the servers once, then refresh them at the interval.
+    /// A failed or empty refresh keeps the previous set.
+    pub async fn start(discover: impl Discover, interval: Duration) -> Result<Self, KvError> {
+        let servers = discover.discover().await?;
+        if servers.is_empty() {
+            return Err(KvError::Discovery("no server found".into()));
+        }
+
+        let (tx, rx) = watch::channel(servers);
+        let refresher = spawn_named("server_discovery", async move {
+            let mut ticker = time::interval_at(time::Instant::now() + interval, interval);
+            loop {
+                ticker.tick().await;
+                match discover.discover().await {
+                    Ok(servers) if servers.is_empty() => {
+                        warn!("Discovered no server, keep the last set")
+                    }
+                    Ok(servers) => {
+                        tx.send_if_modified(|current| {
+                            let changed = *current != servers;
+                            *current = servers;
+                            changed
+                        });
+                    }
+                    Err(e) => warn!(error = ?e, "Failed to refresh the servers"),
+                }
+            }
+        });
+
+        Ok(Self {
+            servers: rx,
+            refresher,
+        })
+    }
+
+    /// The current set of servers
+    pub fn servers(&self) -> Vec<SocketAddr> {
+        self.servers.borrow().clone()
+    }
+
+    /// Watch the set of servers, notified when it changes
+    pub fn watch(&self) -> watch::Receiver<Vec<SocketAddr>> {
+        self.servers.clone()
+    }
+}
+
+impl Drop for ServerDiscovery {
+    fn drop(&mut self) {
+        self.refresher.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Returns the queued results in order, then keeps failing
+    struct Scripted(Mutex<Vec<Result<Vec<SocketAddr>, KvError>>>);
+
+    impl Discover for Scripted {
+        fn discover(&self) -> BoxFuture<'_, Result<Vec<SocketAddr>, KvError>> {
+            let mut results = self.0.lock().unwrap();
+            let res = match results.is_empty() {
+                true => Err(KvError::Discovery("down".into())),
+                false => results.remove(0),
+            };
+            Box::pin(async move { res })
+        }
+    }
+
+    fn addrs(ports: &[u16]) -> Vec<SocketAddr> {
+        ports
+            .iter()
+            .map(|port| SocketAddr::from(([127, 0, 0, 1], *port)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn server_discovery_should_refresh() -> anyhow::Result<()> {
+        let discover = Scripted(Mutex::new(vec![
+            Ok(addrs(&[1, 2])),
+            Ok(vec![]),
+            Ok(addrs(&[2, 3])),
+        ]));
+        let discovery = ServerDiscovery::start(discover, Duration::from_millis(10)).await?;
+        assert_eq!(discovery.servers(), addrs(&[1, 2]));
+
+        // the empty set is skipped, the failures afterwards keep the last set
+        let mut watch = discovery.watch();
+        watch.changed().await?;
+        assert_eq!(*watch.borrow(), addrs(&[2, 3]));
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(discovery.servers(), addrs(&[2, 3]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_discovery_should_fail_without_servers() {
+        let discover = Scripted(Mutex::new(vec![Ok(vec![])]));
+        let res = ServerDiscovery::start(discover, Duration::from_secs(1)).await;
+        assert!(matches!(res, Err(KvError::Discovery(_))));
+    }
+}
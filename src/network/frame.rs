@@ -3,7 +3,7 @@ use std::io::{Read, Write};
 use bytes::{Buf, BufMut, BytesMut};
 use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use prost::Message;
-use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::io::{self, AsyncRead, AsyncReadExt};
 use tracing::debug;
 
 use crate::{CommandRequest, CommandResponse, KvError};
@@ -12,7 +12,7 @@ use crate::{CommandRequest, CommandResponse, KvError};
 pub const LEN_LEN: usize = 4;
 
 /// The maximum length of a frame is 2GB.
-const MAX_FRAME: usize = 2 * 1024 * 1024 * 1024;
+pub const MAX_FRAME: usize = 2 * 1024 * 1024 * 1024;
 
 /// If the length of the frame is larger than 1436(1500-20-20-20-4) bytes, it will be compressed.
 /// 1500: MTU
@@ -25,6 +25,34 @@ const COMPRESSION_LIMIT: usize = 1436;
 /// The first bit of the frame is used to indicate whether the frame is compressed.
 const COMPRESSION_BIT: usize = 1 << 31;
 
+/// How the frames sent by a stream are compressed, the receiver decodes both kinds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrameCompression {
+    /// Gzip the frames larger than the MTU.
+    #[default]
+    Gzip,
+    /// Never compress, for CPU-bound peers.
+    None,
+}
+
+impl From<u32> for FrameCompression {
+    fn from(v: u32) -> Self {
+        match v {
+            1 => FrameCompression::None,
+            _ => FrameCompression::Gzip,
+        }
+    }
+}
+
+impl From<FrameCompression> for u32 {
+    fn from(c: FrameCompression) -> Self {
+        match c {
+            FrameCompression::Gzip => 0,
+            FrameCompression::None => 1,
+        }
+    }
+}
+
 /// FrameCoder is a trait that defines the methods for encoding and decoding frames.
 pub trait FrameCoder
 where
@@ -32,6 +60,15 @@ where
 {
     /// Encode a completed frame into the buffer.
     fn encode_frame(&self, buf: &mut BytesMut) -> Result<(), KvError> {
+        self.encode_frame_with(buf, FrameCompression::default())
+    }
+
+    /// Encode a completed frame into the buffer with the given compression.
+    fn encode_frame_with(
+        &self,
+        buf: &mut BytesMut,
+        compression: FrameCompression,
+    ) -> Result<(), KvError> {
         let size = self.encoded_len();
 
         if size >= MAX_FRAME {
@@ -40,7 +77,7 @@ where
 
         buf.put_u32(size as _);
 
-        if size > COMPRESSION_LIMIT {
+        if size > COMPRESSION_LIMIT && compression == FrameCompression::Gzip {
             let mut buf1 = Vec::with_capacity(size);
             self.encode(&mut buf1)?;
 
@@ -69,17 +106,19 @@ where
         let (len, compressed) = decode_header(header);
         debug!("Got a frame: msg len {}, compressed {}", len, compressed);
 
+        // consume the whole frame first, so a malformed frame won't pollute the next one
+        let payload = buf.split_to(len);
+
         if compressed {
-            let mut decoder = GzDecoder::new(&buf[..len]);
+            let mut decoder = GzDecoder::new(&payload[..]);
             let mut buf1 = Vec::with_capacity(len * 2);
-            decoder.read_to_end(&mut buf1)?;
-            buf.advance(len);
+            decoder
+                .read_to_end(&mut buf1)
+                .map_err(|e| KvError::InvalidFrame(e.to_string()))?;
 
             Ok(Self::decode(&buf1[..buf1.len()])?)
         } else {
-            let msg = Self::decode(&buf[..len])?;
-            buf.advance(len);
-            Ok(msg)
+            Ok(Self::decode(&payload[..])?)
         }
     }
 }
@@ -95,12 +134,34 @@ impl FrameCoder for CommandResponse {}
 
 /// Read a completed frame from the stream.
 pub async fn read_frame<S>(stream: &mut S, buf: &mut BytesMut) -> Result<(), KvError>
+where
+    S: AsyncRead + Unpin + Send,
+{
+    read_frame_limited(stream, buf, MAX_FRAME).await
+}
+
+/// Read a completed frame from the stream, a frame longer than `max_len` is skipped
+/// without being buffered, and reported as `KvError::TooLarge`.
+pub async fn read_frame_limited<S>(
+    stream: &mut S,
+    buf: &mut BytesMut,
+    max_len: usize,
+) -> Result<(), KvError>
 where
     S: AsyncRead + Unpin + Send,
 {
     let header = stream.read_u32().await? as usize;
     let (len, _compressed) = decode_header(header);
 
+    if len > max_len {
+        // drain the payload, so the stream stays in sync with the next frame
+        io::copy(&mut stream.take(len as u64), &mut io::sink()).await?;
+        return Err(KvError::TooLarge(format!(
+            "frame of {} bytes, the limit is {}",
+            len, max_len
+        )));
+    }
+
     buf.reserve(LEN_LEN + len);
     buf.put_u32(header as _);
 
@@ -147,6 +208,35 @@ mod tests {
         assert_eq!(res, res1);
     }
 
+    #[test]
+    fn disabled_compression_should_send_plain_frames() {
+        let mut buf = BytesMut::new();
+
+        let value: Value = Bytes::from(vec![0u8; COMPRESSION_LIMIT + 1]).into();
+        let res: CommandResponse = value.into();
+        res.encode_frame_with(&mut buf, FrameCompression::None)
+            .unwrap();
+
+        assert!(!is_compressed(&buf));
+
+        let res1 = CommandResponse::decode_frame(&mut buf).unwrap();
+        assert_eq!(res, res1);
+    }
+
+    #[test]
+    fn malformed_frame_should_be_consumed() {
+        let mut buf = BytesMut::new();
+        buf.put_u32(3);
+        buf.put_slice(b"bad");
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        cmd.encode_frame(&mut buf).unwrap();
+
+        assert!(CommandRequest::decode_frame(&mut buf).is_err());
+
+        let cmd1 = CommandRequest::decode_frame(&mut buf).unwrap();
+        assert_eq!(cmd, cmd1);
+    }
+
     fn is_compressed(buf: &BytesMut) -> bool {
         if let &[v] = &buf[..1] {
             v >> 7 == 1
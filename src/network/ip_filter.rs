@@ -0,0 +1,167 @@
+use std::{
+    fs,
+    net::IpAddr,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use ipnet::IpNet;
+
+use crate::KvError;
+
+/// The CIDR rules of an IpFilter
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpRules {
+    /// The networks allowed to connect, empty to allow every address not denied
+    pub allow: Vec<IpNet>,
+    /// The networks never allowed to connect, deny rules win over allow rules
+    pub deny: Vec<IpNet>,
+}
+
+impl IpRules {
+    /// Parse the rules, one per line: `allow <cidr>` or `deny <cidr>`, `#` starts a comment.
+    /// A bare address is taken as a single host network.
+    pub fn parse(rules: &str) -> Result<Self, KvError> {
+        let mut parsed = Self::default();
+        for (i, line) in rules.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = |msg: &str| KvError::InvalidIpRule(format!("line {}: {}", i + 1, msg));
+            let (action, net) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| invalid("expect `allow <cidr>` or `deny <cidr>`"))?;
+            let net = parse_net(net.trim()).ok_or_else(|| invalid("bad CIDR"))?;
+            match action {
+                "allow" => parsed.allow.push(net),
+                "deny" => parsed.deny.push(net),
+                _ => return Err(invalid("the action must be allow or deny")),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Whether the address is allowed by the rules
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+pub(crate) fn parse_net(s: &str) -> Option<IpNet> {
+    s.parse()
+        .ok()
+        .or_else(|| s.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+/// Filters the connections by the address of the peer, checked before any handshake.
+/// The clones share the rules, so a reload applies to every listener using the filter.
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    rules: Arc<RwLock<Arc<IpRules>>>,
+}
+
+impl IpFilter {
+    pub fn new(rules: IpRules) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(Arc::new(rules))),
+        }
+    }
+
+    /// Load the rules from a file, see `IpRules::parse` for the format
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        Ok(Self::new(read_rules(path.as_ref())?))
+    }
+
+    /// Replace the rules, the connections accepted before are not affected
+    pub fn reload(&self, rules: IpRules) {
+        *self.rules.write().unwrap() = Arc::new(rules);
+    }
+
+    /// Reload the rules from a file, the current rules are kept if the file is invalid
+    pub fn reload_file(&self, path: impl AsRef<Path>) -> Result<(), KvError> {
+        self.reload(read_rules(path.as_ref())?);
+        Ok(())
+    }
+
+    /// The current rules
+    pub fn rules(&self) -> Arc<IpRules> {
+        self.rules.read().unwrap().clone()
+    }
+
+    /// Whether the address is allowed to connect
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        self.rules().is_allowed(ip)
+    }
+}
+
+fn read_rules(path: &Path) -> Result<IpRules, KvError> {
+    let rules = fs::read_to_string(path)
+        .map_err(|e| KvError::InvalidIpRule(format!("failed to read {:?}: {}", path, e)))?;
+    IpRules::parse(&rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ip_rules_should_work() {
+        let rules = IpRules::parse(
+            "# the office network\n\
+             allow 10.0.0.0/8\n\
+             deny 10.1.0.0/16   # the guest wifi\n\
+             allow ::1\n",
+        )
+        .unwrap();
+
+        let allowed = |ip: &str| rules.is_allowed(ip.parse().unwrap());
+        assert!(allowed("10.0.0.1"));
+        assert!(!allowed("10.1.2.3"));
+        assert!(!allowed("192.168.0.1"));
+        assert!(allowed("::1"));
+        // IPv4-mapped addresses of dual stack listeners match the IPv4 rules
+        assert!(allowed("::ffff:10.0.0.1"));
+
+        // no allow rule allows everything not denied
+        let rules = IpRules::parse("deny 192.168.0.0/24").unwrap();
+        assert!(rules.is_allowed("10.0.0.1".parse().unwrap()));
+        assert!(!rules.is_allowed("192.168.0.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn bad_ip_rules_should_be_rejected() {
+        for rules in ["allow", "allow 10.0.0.0/33", "permit 10.0.0.0/8"] {
+            assert!(matches!(
+                IpRules::parse(rules),
+                Err(KvError::InvalidIpRule(msg)) if msg.starts_with("line 1")
+            ));
+        }
+    }
+
+    #[test]
+    fn ip_filter_reload_should_work() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rules");
+        fs::write(&path, "allow 127.0.0.1").unwrap();
+
+        let filter = IpFilter::from_file(&path).unwrap();
+        let listener = filter.clone();
+        let ip = "10.0.0.1".parse().unwrap();
+        assert!(!listener.is_allowed(ip));
+
+        fs::write(&path, "allow 10.0.0.0/8").unwrap();
+        filter.reload_file(&path).unwrap();
+        assert!(listener.is_allowed(ip));
+
+        // a bad file keeps the current rules
+        fs::write(&path, "allow nowhere").unwrap();
+        assert!(filter.reload_file(&path).is_err());
+        assert!(listener.is_allowed(ip));
+    }
+}
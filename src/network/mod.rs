@@ -1,30 +1,54 @@
+mod crl;
+mod discovery;
 mod frame;
+mod ip_filter;
 mod multiplex;
+mod noise;
+mod proxy;
+mod scheduler;
 mod stream;
 mod stream_result;
+mod subscription;
 mod tls;
 
 use futures::prelude::*;
-use stream_result::StreamResult;
+use http::StatusCode;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tracing::{error, info};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
-use crate::{CommandRequest, CommandResponse, KvError, Service};
+use crate::{CommandRequest, CommandResponse, ConnContext, KvError, Kvpair, Service, ValueCipher};
 
-pub use frame::{read_frame, FrameCoder};
-pub use multiplex::YamuxCtrl;
+pub use discovery::{Discover, ServerDiscovery, SrvDiscovery};
+pub use frame::{read_frame, read_frame_limited, FrameCoder, FrameCompression};
+pub use ip_filter::{IpFilter, IpRules};
+pub use multiplex::{H2Ctrl, H2Stream, Multiplexer, YamuxCtrl};
+pub use noise::{NoiseClientConnector, NoiseKeypair, NoiseServerAcceptor, NoiseStream};
+pub use proxy::{read_proxy_header, TrustedProxies};
+pub use scheduler::{Priority, SendPermit, SendScheduler};
 pub use stream::ProstStream;
+pub use stream_result::{parse_status, parse_subscription_id, StreamResult};
+pub use subscription::{Subscription, TopicMessage};
 pub use tls::{TlsClientConnector, TlsServerAcceptor};
 
 /// A stream used to handle the read and write of a socket accepted by the server
 pub struct ProstServerStream<S> {
     inner: ProstStream<S, CommandRequest, CommandResponse>,
     service: Service,
+    /// The context of the connection, shared by all streams of a multiplexed connection
+    context: ConnContext,
+    /// The scheduler shared by all streams of the same multiplexed connection
+    scheduler: SendScheduler,
 }
 
 /// A stream used to handle the read and write of a socket connected to the server
 pub struct ProstClientStream<S> {
     inner: ProstStream<S, CommandResponse, CommandRequest>,
+    /// The priority of the stream, marked on every command sent by the stream
+    priority: Option<Priority>,
+    /// The compression of the frames in both directions, marked on every command sent by the stream
+    compression: Option<FrameCompression>,
+    /// The cipher encrypting the values written and decrypting the values read by the stream
+    cipher: Option<ValueCipher>,
 }
 
 impl<S> ProstServerStream<S>
@@ -32,29 +56,67 @@ where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     pub fn new(stream: S, service: Service) -> Self {
+        let inner = match service.max_frame_len() {
+            Some(len) => ProstStream::new(stream).with_max_frame(len),
+            None => ProstStream::new(stream),
+        };
         Self {
-            inner: ProstStream::new(stream),
+            inner,
             service,
+            context: ConnContext::default(),
+            scheduler: SendScheduler::default(),
         }
     }
 
-    /// Process the client connection
-    pub async fn process(mut self) -> Result<(), KvError> {
+    /// Share the send scheduler with the other streams of the same connection
+    pub fn with_scheduler(mut self, scheduler: SendScheduler) -> Self {
+        self.scheduler = scheduler;
+        self
+    }
+
+    /// Set the context of the connection the stream belongs to
+    pub fn with_context(mut self, context: ConnContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Process the client connection,
+    /// a malformed frame is reported to the client and the connection keeps serving,
+    /// only unrecoverable stream errors terminate the connection.
+    pub async fn process(self) -> Result<(), KvError> {
+        let span = info_span!(
+            "conn",
+            id = self.context.id(),
+            peer = self.context.peer().map(tracing::field::display),
+        );
+        self.serve().instrument(span).await
+    }
+
+    async fn serve(mut self) -> Result<(), KvError> {
         info!("Processing connection");
         let stream = &mut self.inner;
         while let Some(data) = stream.next().await {
             match data {
                 Ok(cmd) => {
-                    info!("Got a new command: {:?}", cmd);
-                    let mut resp = self.service.execute(cmd);
+                    let priority = Priority::from(cmd.priority);
+                    // the client picks the compression of the responses per stream
+                    stream.set_compression(cmd.compression.into());
+                    let mut resp = self.service.execute_with(cmd, &self.context);
                     while let Some(v) = resp.next().await {
-                        info!("Sending response: {:?}", v);
+                        debug!(status = v.status, "Sending response");
+                        let _permit = self.scheduler.acquire(priority).await;
                         stream.send(&v).await?;
                     }
                 }
+                Err(e) if e.is_frame_error() => {
+                    warn!(error = ?e, "Got a malformed frame");
+                    let resp: CommandResponse = e.into();
+                    stream.send(&resp).await?;
+                }
+                Err(e) if e.is_closed() => break,
                 Err(e) => {
-                    error!("Failed to read command: {:?}", e);
-                    return Ok(());
+                    error!(error = ?e, "Failed to read command");
+                    return Err(e);
                 }
             }
         }
@@ -70,33 +132,131 @@ where
     pub fn new(stream: S) -> Self {
         Self {
             inner: ProstStream::new(stream),
+            priority: None,
+            compression: None,
+            cipher: None,
         }
     }
 
+    /// Select the compression of the frames sent by both sides of the stream
+    pub fn with_compression(mut self, compression: FrameCompression) -> Self {
+        self.inner.set_compression(compression);
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Mark the stream with the given priority, the server schedules its responses accordingly
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Encrypt the values of Hset, Hmset and Publish before sending them and decrypt
+    /// the values of unary responses, the server only stores the ciphertext.
+    /// The values of subscriptions are left encrypted, decrypt them with the same cipher.
+    pub fn with_cipher(mut self, cipher: ValueCipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Mark the command with the priority and the compression of the stream,
+    /// and encrypt its values if the stream has a cipher
+    fn mark(&self, cmd: &CommandRequest) -> Result<CommandRequest, KvError> {
+        let mut cmd = cmd.clone();
+        if let Some(cipher) = &self.cipher {
+            cipher.encrypt_request(&mut cmd)?;
+        }
+        if let Some(priority) = self.priority {
+            cmd.priority = priority.into();
+        }
+        if let Some(compression) = self.compression {
+            cmd.compression = compression.into();
+        }
+        Ok(cmd)
+    }
+
     /// Send a command to the server and wait for the response, use for unary commands
     pub async fn execute_unary(
         &mut self,
         cmd: &CommandRequest,
     ) -> Result<CommandResponse, KvError> {
+        let cmd = &self.mark(cmd)?;
         let stream = &mut self.inner;
         match stream.send(cmd).await {
             Ok(_) => info!("Sent command to server: {:?}", cmd),
             Err(e) => error!("Failed to send command {:?} to server: {:?}", cmd, e),
         }
-        match stream.next().await {
-            Some(v) => v,
-            None => Err(KvError::Internal("Didn't get any response".into())),
+        let mut res = match stream.next().await {
+            Some(v) => v?,
+            None => return Err(KvError::Internal("Didn't get any response".into())),
+        };
+        if let Some(cipher) = &self.cipher {
+            cipher.decrypt_response(&mut res)?;
+        }
+        Ok(res)
+    }
+
+    /// Send the unary commands in one frame and wait for their responses, in order
+    pub async fn execute_batch(
+        &mut self,
+        cmds: Vec<CommandRequest>,
+    ) -> Result<Vec<CommandResponse>, KvError> {
+        let res = self.execute_unary(&CommandRequest::new_batch(cmds)).await?;
+        if res.status != StatusCode::OK.as_u16() as u32 {
+            return Err(KvError::Internal(format!(
+                "batch failed with {}: {}",
+                res.status, res.message
+            )));
+        }
+        Ok(res.responses)
+    }
+
+    /// Load the chunks of pairs into a table, each chunk is set by one Import command,
+    /// and return the number of pairs set. It stops at the first failed chunk,
+    /// the chunks before it stay imported.
+    pub async fn import(
+        &mut self,
+        table: &str,
+        chunks: impl Stream<Item = Vec<Kvpair>>,
+    ) -> Result<usize, KvError> {
+        let mut chunks = std::pin::pin!(chunks);
+        let mut imported = 0;
+        while let Some(pairs) = chunks.next().await {
+            let res = self
+                .execute_unary(&CommandRequest::new_import(table, pairs))
+                .await?;
+            let n: i64 = (&res).try_into().map_err(|_| {
+                KvError::Internal(format!(
+                    "import failed with {} after {} pairs: {}",
+                    res.status, imported, res.message
+                ))
+            })?;
+            imported += n as usize;
         }
+        Ok(imported)
     }
 
-    /// Send a command to the server and wait for the response, use for streaming commands
+    /// Send a subscription command to the server and wait for the subscription id
     pub async fn execute_stream(self, cmd: &CommandRequest) -> Result<StreamResult, KvError> {
+        self.execute_stream_with(cmd, parse_subscription_id).await
+    }
+
+    /// Send a streaming command to the server, the first frame is parsed by the given parser
+    pub async fn execute_stream_with<H, F>(
+        self,
+        cmd: &CommandRequest,
+        parser: F,
+    ) -> Result<StreamResult<H>, KvError>
+    where
+        F: FnOnce(CommandResponse) -> Result<H, KvError>,
+    {
+        let cmd = self.mark(cmd)?;
         let mut stream = self.inner;
 
-        stream.send(cmd).await?;
+        stream.send(&cmd).await?;
         stream.close().await?;
 
-        StreamResult::new(stream).await
+        StreamResult::with_parser(stream, parser).await
     }
 }
 
@@ -105,9 +265,12 @@ mod tests {
     use std::net::SocketAddr;
 
     use bytes::Bytes;
-    use tokio::net::{TcpListener, TcpStream};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
 
-    use crate::{assert_res_ok, MemTable, ServiceInner, Value};
+    use crate::{assert_res_error, assert_res_ok, MemTable, ServiceInner, Value, ValueEncryption};
 
     use super::*;
 
@@ -134,6 +297,48 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn client_server_batch_should_work() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        let resps = client
+            .execute_batch(vec![
+                CommandRequest::new_hset("t1", "k1", "v1".into()),
+                CommandRequest::new_hget("t1", "k1"),
+                CommandRequest::new_hget("t1", "k2"),
+            ])
+            .await?;
+        assert_eq!(resps.len(), 3);
+        assert_res_ok(&resps[0], &[Value::default()], &[]);
+        assert_res_ok(&resps[1], &["v1".into()], &[]);
+        assert_res_error(&resps[2], 404, "Not found");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_import_should_work() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        let pairs: Vec<_> = (0..25)
+            .map(|i| Kvpair::new(format!("k{:02}", i), i.into()))
+            .collect();
+        let chunks =
+            futures::stream::iter(pairs.chunks(10).map(|c| c.to_vec()).collect::<Vec<_>>());
+        assert_eq!(client.import("t9", chunks).await?, 25);
+
+        let resp = client
+            .execute_unary(&CommandRequest::new_hlen("t9"))
+            .await?;
+        assert_res_ok(&resp, &[25.into()], &[]);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn client_server_compression_should_work() -> anyhow::Result<()> {
         let addr = start_server().await?;
@@ -154,15 +359,150 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn client_server_malformed_frame_should_not_close_connection() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let mut stream = TcpStream::connect(addr).await?;
+        stream.write_all(&[0, 0, 0, 3, 0xff, 0xff, 0xff]).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        // the malformed frame should be reported
+        let resp = client.inner.next().await.unwrap()?;
+        assert_res_error(&resp, 400, "Failed to decode");
+
+        // the connection should still work
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let resp = client.execute_unary(&cmd).await?;
+        assert_res_ok(&resp, &[Value::default()], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_oversized_frame_should_be_rejected() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let mut stream = TcpStream::connect(addr).await?;
+        let len = 100 * 1024;
+        stream.write_all(&(len as u32).to_be_bytes()).await?;
+        stream.write_all(&vec![0u8; len]).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        let resp = client.inner.next().await.unwrap()?;
+        assert_res_error(&resp, 413, "Too large");
+
+        // the payload is drained, the connection should still work
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let resp = client.execute_unary(&cmd).await?;
+        assert_res_ok(&resp, &[Value::default()], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_over_noise_should_work() -> anyhow::Result<()> {
+        let server_key = NoiseKeypair::generate()?;
+        let client_key = NoiseKeypair::generate()?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let acceptor = NoiseServerAcceptor::new(&server_key.private);
+        tokio::spawn(async move {
+            let (socket, peer) = listener.accept().await.unwrap();
+            let stream = acceptor.accept(socket).await.unwrap();
+            let service: Service = ServiceInner::new(MemTable::new()).into();
+            let context = service.new_context(Some(peer));
+            ProstServerStream::new(stream, service)
+                .with_context(context)
+                .process()
+                .await
+                .unwrap();
+        });
+
+        let connector = NoiseClientConnector::new(&client_key.private, Some(&server_key.public));
+        let stream = connector.connect(TcpStream::connect(addr).await?).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        let v: Value = Bytes::from(vec![7u8; 100 * 1024]).into();
+        let cmd = CommandRequest::new_hset("t1", "k1", v.clone());
+        assert_res_ok(&client.execute_unary(&cmd).await?, &[Value::default()], &[]);
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        assert_res_ok(&client.execute_unary(&cmd).await?, &[v], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_with_cipher_should_hide_values() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        // both clients share the storage
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(ProstServerStream::new(socket, service.clone()).process());
+            }
+        });
+        let cipher = ValueCipher::new(&ValueCipher::generate_key());
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream).with_cipher(cipher);
+        let cmd = CommandRequest::new_hset("t1", "k1", "secret".into());
+        assert_res_ok(&client.execute_unary(&cmd).await?, &[Value::default()], &[]);
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        assert_res_ok(&client.execute_unary(&cmd).await?, &["secret".into()], &[]);
+
+        // a client without the key only sees the ciphertext
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        let res = client.execute_unary(&cmd).await?;
+        assert_eq!(
+            res.values[0].encryption(),
+            ValueEncryption::XChaCha20Poly1305
+        );
+        assert_ne!(res.values[0], "secret".into());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_compression_opt_out_should_work() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+        let big: Value = Bytes::from(vec![0u8; 4096]).into();
+
+        for (compression, compressed) in [
+            (FrameCompression::Gzip, true),
+            (FrameCompression::None, false),
+        ] {
+            let stream = TcpStream::connect(addr).await?;
+            let mut client = ProstClientStream::new(stream).with_compression(compression);
+            client
+                .execute_unary(&CommandRequest::new_hset("t1", "big", big.clone()))
+                .await?;
+
+            // read the raw response header to check the compression bit
+            let mut stream = client.inner.into_inner();
+            let mut client = ProstStream::<_, CommandResponse, CommandRequest>::new(&mut stream)
+                .with_compression(compression);
+            let mut cmd = CommandRequest::new_hget("t1", "big");
+            cmd.compression = compression.into();
+            client.send(&cmd).await?;
+            let header = stream.read_u32().await?;
+            assert_eq!(header >> 31 == 1, compressed);
+        }
+        Ok(())
+    }
+
     async fn start_server() -> anyhow::Result<SocketAddr> {
         let listener = TcpListener::bind("127.0.0.1:0").await?;
         let addr = listener.local_addr().unwrap();
 
         tokio::spawn(async move {
             loop {
-                let (socket, _) = listener.accept().await.unwrap();
-                let service: Service = ServiceInner::new(MemTable::new()).into();
-                let server = ProstServerStream::new(socket, service);
+                let (socket, peer) = listener.accept().await.unwrap();
+                let service: Service = ServiceInner::new(MemTable::new())
+                    .max_frame_len(64 * 1024)
+                    .into();
+                let context = service.new_context(Some(peer));
+                let server = ProstServerStream::new(socket, service).with_context(context);
                 tokio::spawn(server.process());
             }
         });
@@ -0,0 +1,281 @@
+use std::{
+    io,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::{ready, Future};
+use h2::{client, server, RecvStream, SendStream};
+use http::{Method, Request, Response};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::{error, warn};
+
+use crate::{spawn_named, KvError};
+
+use super::Multiplexer;
+
+/// The uri used for every HTTP/2 stream opened by the client
+const H2_URI: &str = "/kv";
+
+/// A multiplexed connection based on HTTP/2
+pub struct H2Ctrl<S> {
+    /// The sender of the HTTP/2 connection, used to create streams
+    sender: client::SendRequest<Bytes>,
+    _conn: PhantomData<S>,
+}
+
+impl<S> H2Ctrl<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    /// Create a new multiplexed client connection,
+    /// unlike yamux, HTTP/2 needs a handshake before any stream could be opened.
+    pub async fn new_client(stream: S, config: Option<client::Builder>) -> Result<Self, KvError> {
+        let builder = config.unwrap_or_default();
+        let (sender, conn) = builder.handshake(stream).await?;
+
+        spawn_named("h2-client-conn", async move {
+            if let Err(e) = conn.await {
+                error!("HTTP/2 connection error: {:?}", e);
+            }
+        });
+
+        Ok(Self {
+            sender,
+            _conn: PhantomData,
+        })
+    }
+
+    /// Create a new multiplexed server connection,
+    /// every stream opened by the client will be handled by `f`.
+    pub fn new_server<F, Fut>(stream: S, config: Option<server::Builder>, mut f: F)
+    where
+        F: FnMut(H2Stream) -> Fut,
+        F: Send + 'static,
+        Fut: Future<Output = Result<(), KvError>> + Send + 'static,
+    {
+        let builder = config.unwrap_or_default();
+        spawn_named("h2-server-conn", async move {
+            let mut conn = match builder.handshake::<_, Bytes>(stream).await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    error!("Failed to handshake HTTP/2 connection: {:?}", e);
+                    return;
+                }
+            };
+
+            while let Some(result) = conn.accept().await {
+                let (req, mut respond) = match result {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!("Failed to accept HTTP/2 stream: {:?}", e);
+                        break;
+                    }
+                };
+
+                let send = match respond.send_response(Response::new(()), false) {
+                    Ok(send) => send,
+                    Err(e) => {
+                        warn!("Failed to respond HTTP/2 stream: {:?}", e);
+                        continue;
+                    }
+                };
+
+                let fut = f(H2Stream::new(send, req.into_body()));
+                spawn_named("h2-stream", async move {
+                    if let Err(e) = fut.await {
+                        warn!("Failed to process HTTP/2 stream: {:?}", e);
+                    }
+                });
+            }
+        });
+    }
+}
+
+impl<S> Clone for H2Ctrl<S> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            _conn: PhantomData,
+        }
+    }
+}
+
+impl<S> Multiplexer for H2Ctrl<S>
+where
+    S: Send + 'static,
+{
+    type Stream = H2Stream;
+
+    /// Open a new stream
+    async fn open_stream(&mut self) -> Result<Self::Stream, KvError> {
+        let mut sender = self.sender.clone().ready().await?;
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(H2_URI)
+            .body(())
+            .map_err(|e| KvError::Internal(e.to_string()))?;
+
+        let (resp, send) = sender.send_request(req, false)?;
+        let resp = resp.await?;
+
+        Ok(H2Stream::new(send, resp.into_body()))
+    }
+}
+
+/// A bidirectional byte stream built on a pair of HTTP/2 send and receive streams
+pub struct H2Stream {
+    send: SendStream<Bytes>,
+    recv: RecvStream,
+
+    /// The data received but not read yet.
+    rbuf: Bytes,
+
+    /// Whether the end of stream has been sent.
+    closed: bool,
+}
+
+impl H2Stream {
+    fn new(send: SendStream<Bytes>, recv: RecvStream) -> Self {
+        Self {
+            send,
+            recv,
+            rbuf: Bytes::new(),
+            closed: false,
+        }
+    }
+}
+
+impl AsyncRead for H2Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if !this.rbuf.is_empty() {
+                let n = std::cmp::min(buf.remaining(), this.rbuf.len());
+                buf.put_slice(&this.rbuf.split_to(n));
+                return Poll::Ready(Ok(()));
+            }
+
+            match ready!(this.recv.poll_data(cx)) {
+                Some(Ok(data)) => {
+                    // give the capacity back to the peer, so it could send more data
+                    let _ = this.recv.flow_control().release_capacity(data.len());
+                    this.rbuf = data;
+                }
+                Some(Err(e)) => return Poll::Ready(Err(h2_to_io_error(e))),
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+    }
+}
+
+impl AsyncWrite for H2Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        this.send.reserve_capacity(buf.len());
+        loop {
+            match ready!(this.send.poll_capacity(cx)) {
+                Some(Ok(0)) => continue,
+                Some(Ok(n)) => {
+                    let n = std::cmp::min(n, buf.len());
+                    this.send
+                        .send_data(Bytes::copy_from_slice(&buf[..n]), false)
+                        .map_err(h2_to_io_error)?;
+                    return Poll::Ready(Ok(n));
+                }
+                Some(Err(e)) => return Poll::Ready(Err(h2_to_io_error(e))),
+                None => return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        // the HTTP/2 connection task flushes the data
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = self.get_mut();
+        if !this.closed {
+            this.closed = true;
+            this.send
+                .send_data(Bytes::new(), true)
+                .map_err(h2_to_io_error)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn h2_to_io_error(e: h2::Error) -> io::Error {
+    if e.is_io() {
+        e.into_io().unwrap()
+    } else {
+        io::Error::other(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpStream;
+
+    use crate::{
+        assert_res_ok,
+        network::{
+            multiplex::mux_utils::start_h2_server,
+            tls::tls_utils::{tls_acceptor, tls_connector},
+        },
+        CommandRequest, MemTable, ProstClientStream,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn h2_ctrl_client_server_should_work() -> anyhow::Result<()> {
+        // create a HTTP/2 server
+        let acceptor = tls_acceptor(false)?;
+        let addr = start_h2_server("127.0.0.1:0", acceptor, MemTable::new()).await?;
+
+        // create a client stream
+        let connector = tls_connector(false)?;
+        let stream = TcpStream::connect(&addr).await?;
+        let stream = connector.connect(stream).await?;
+
+        // create a HTTP/2 client
+        let mut ctrl = H2Ctrl::new_client(stream, None).await?;
+        let stream = ctrl.open_stream().await?;
+
+        // wrap into a prost stream
+        let mut client = ProstClientStream::new(stream);
+
+        // send a command
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        client.execute_unary(&cmd).await.unwrap();
+
+        // send again
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        let res = client.execute_unary(&cmd).await.unwrap();
+        assert_res_ok(&res, &["v1".into()], &[]);
+
+        // another stream on the same connection should work too
+        let stream = ctrl.open_stream().await?;
+        let mut client = ProstClientStream::new(stream);
+        let res = client.execute_unary(&cmd).await.unwrap();
+        assert_res_ok(&res, &["v1".into()], &[]);
+        Ok(())
+    }
+}
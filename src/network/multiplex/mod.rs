@@ -0,0 +1,116 @@
+mod h2_ctrl;
+mod yamux_ctrl;
+
+use futures::Future;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::KvError;
+
+pub use h2_ctrl::{H2Ctrl, H2Stream};
+pub use yamux_ctrl::YamuxCtrl;
+
+/// A multiplexer opens many logical streams over a single connection,
+/// the backend may be yamux, HTTP/2 or other multiplexing protocols.
+pub trait Multiplexer: Clone + Send + 'static {
+    /// The logical stream opened by the multiplexer
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// Open a new logical stream
+    fn open_stream(&mut self) -> impl Future<Output = Result<Self::Stream, KvError>> + Send;
+}
+
+#[cfg(test)]
+pub mod mux_utils {
+    use std::net::SocketAddr;
+
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::server;
+    use tracing::warn;
+
+    use crate::{KvError, ProstServerStream, Service, ServiceInner, Storage, TlsServerAcceptor};
+
+    use super::{H2Ctrl, YamuxCtrl};
+
+    pub async fn start_server_with<Store>(
+        addr: &str,
+        tls: TlsServerAcceptor,
+        store: Store,
+        f: impl Fn(server::TlsStream<TcpStream>, Service) + Send + Sync + 'static,
+    ) -> Result<SocketAddr, KvError>
+    where
+        Store: Storage,
+        Service: From<ServiceInner<Store>>,
+    {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service: Service = ServiceInner::new(store).into();
+
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _addr)) => match tls.accept(stream).await {
+                        Ok(stream) => {
+                            f(stream, service.clone());
+                        }
+                        Err(e) => {
+                            warn!("failed to accept tls stream: {}", e);
+                        }
+                    },
+                    Err(e) => {
+                        warn!("failed to accept tcp stream: {}", e);
+                    }
+                }
+            }
+        });
+
+        Ok(addr)
+    }
+
+    pub async fn start_yamux_server<Store>(
+        addr: &str,
+        tls: TlsServerAcceptor,
+        store: Store,
+    ) -> Result<SocketAddr, KvError>
+    where
+        Store: Storage,
+        Service: From<ServiceInner<Store>>,
+    {
+        let f = |stream, service: Service| {
+            let context = service.new_context(None);
+            YamuxCtrl::new_server(stream, None, move |s| {
+                let svc = service.clone();
+                let context = context.clone();
+                async move {
+                    let stream = ProstServerStream::new(s, svc).with_context(context);
+                    stream.process().await
+                }
+            });
+        };
+
+        start_server_with(addr, tls, store, f).await
+    }
+
+    pub async fn start_h2_server<Store>(
+        addr: &str,
+        tls: TlsServerAcceptor,
+        store: Store,
+    ) -> Result<SocketAddr, KvError>
+    where
+        Store: Storage,
+        Service: From<ServiceInner<Store>>,
+    {
+        let f = |stream, service: Service| {
+            let context = service.new_context(None);
+            H2Ctrl::new_server(stream, None, move |s| {
+                let svc = service.clone();
+                let context = context.clone();
+                async move {
+                    let stream = ProstServerStream::new(s, svc).with_context(context);
+                    stream.process().await
+                }
+            });
+        };
+
+        start_server_with(addr, tls, store, f).await
+    }
+}
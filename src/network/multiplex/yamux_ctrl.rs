@@ -3,10 +3,14 @@ use std::marker::PhantomData;
 use futures::{future, Future, TryStreamExt};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
-use tracing::error;
-use yamux::{Config, Connection, ConnectionError, Control, Mode, WindowUpdateMode};
+use tracing::{error, warn};
+use yamux::{Config, Connection, Control, Mode, WindowUpdateMode};
 
-/// A multiplexed connection
+use crate::{spawn_named, KvError};
+
+use super::Multiplexer;
+
+/// A multiplexed connection based on yamux
 pub struct YamuxCtrl<S> {
     /// The control of the multiplexed connection, used to create streams
     ctrl: Control,
@@ -25,19 +29,19 @@ where
     /// Create a new multiplexed server connection
     pub fn new_server<F, Fut>(stream: S, config: Option<Config>, f: F) -> Self
     where
-        F: FnMut(yamux::Stream) -> Fut,
+        F: FnMut(Compat<yamux::Stream>) -> Fut,
         F: Send + 'static,
-        Fut: Future<Output = Result<(), ConnectionError>> + Send + 'static,
+        Fut: Future<Output = Result<(), KvError>> + Send + 'static,
     {
         Self::new(stream, config, false, f)
     }
 
     /// Create a new multiplexed connection
-    fn new<F, Fut>(stream: S, config: Option<Config>, is_client: bool, f: F) -> Self
+    fn new<F, Fut>(stream: S, config: Option<Config>, is_client: bool, mut f: F) -> Self
     where
-        F: FnMut(yamux::Stream) -> Fut,
+        F: FnMut(Compat<yamux::Stream>) -> Fut,
         F: Send + 'static,
-        Fut: Future<Output = Result<(), ConnectionError>> + Send + 'static,
+        Fut: Future<Output = Result<(), KvError>> + Send + 'static,
     {
         let mode = if is_client {
             Mode::Client
@@ -52,22 +56,49 @@ where
 
         let ctrl = conn.control();
 
-        tokio::spawn(yamux::into_stream(conn).try_for_each_concurrent(None, f));
+        spawn_named(
+            "yamux-conn",
+            yamux::into_stream(conn).try_for_each_concurrent(None, move |stream| {
+                let fut = f(stream.compat());
+                async move {
+                    if let Err(e) = fut.await {
+                        warn!("Failed to process yamux stream: {:?}", e);
+                    }
+                    Ok(())
+                }
+            }),
+        );
 
         Self {
             ctrl,
             _conn: PhantomData,
         }
     }
+}
+
+impl<S> Clone for YamuxCtrl<S> {
+    fn clone(&self) -> Self {
+        Self {
+            ctrl: self.ctrl.clone(),
+            _conn: PhantomData,
+        }
+    }
+}
+
+impl<S> Multiplexer for YamuxCtrl<S>
+where
+    S: Send + 'static,
+{
+    type Stream = Compat<yamux::Stream>;
 
     /// Open a new stream
-    pub async fn open_stream(&mut self) -> Result<Compat<yamux::Stream>, ConnectionError> {
+    async fn open_stream(&mut self) -> Result<Self::Stream, KvError> {
         let stream = self.ctrl.open_stream().await;
         match stream {
             Ok(stream) => Ok(stream.compat()),
             Err(e) => {
                 error!("Failed to open stream: {:?}", e);
-                Err(e)
+                Err(e.into())
             }
         }
     }
@@ -75,80 +106,20 @@ where
 
 #[cfg(test)]
 mod tests {
-    use std::net::SocketAddr;
-
-    use tokio::net::{TcpListener, TcpStream};
-    use tokio_rustls::server;
-    use tracing::warn;
+    use tokio::net::TcpStream;
 
     use crate::{
         assert_res_ok,
-        network::tls::tls_utils::{tls_acceptor, tls_connector},
+        network::{
+            multiplex::mux_utils::start_yamux_server,
+            tls::tls_utils::{tls_acceptor, tls_connector},
+        },
         utils::DummyStream,
-        CommandRequest, KvError, MemTable, ProstClientStream, ProstServerStream, Service,
-        ServiceInner, Storage, TlsServerAcceptor,
+        CommandRequest, MemTable, ProstClientStream,
     };
 
     use super::*;
 
-    pub async fn start_server_with<Store>(
-        addr: &str,
-        tls: TlsServerAcceptor,
-        store: Store,
-        f: impl Fn(server::TlsStream<TcpStream>, Service) + Send + Sync + 'static,
-    ) -> Result<SocketAddr, KvError>
-    where
-        Store: Storage,
-        Service: From<ServiceInner<Store>>,
-    {
-        let listener = TcpListener::bind(addr).await.unwrap();
-        let addr = listener.local_addr().unwrap();
-        let service: Service = ServiceInner::new(store).into();
-
-        tokio::spawn(async move {
-            loop {
-                match listener.accept().await {
-                    Ok((stream, _addr)) => match tls.accept(stream).await {
-                        Ok(stream) => {
-                            f(stream, service.clone());
-                        }
-                        Err(e) => {
-                            warn!("failed to accept tls stream: {}", e);
-                        }
-                    },
-                    Err(e) => {
-                        warn!("failed to accept tcp stream: {}", e);
-                    }
-                }
-            }
-        });
-
-        Ok(addr)
-    }
-
-    pub async fn start_yamux_server<Store>(
-        addr: &str,
-        tls: TlsServerAcceptor,
-        store: Store,
-    ) -> Result<SocketAddr, KvError>
-    where
-        Store: Storage,
-        Service: From<ServiceInner<Store>>,
-    {
-        let f = |stream, service: Service| {
-            YamuxCtrl::new_server(stream, None, move |s| {
-                let svc = service.clone();
-                async move {
-                    let stream = ProstServerStream::new(s.compat(), svc);
-                    stream.process().await.unwrap();
-                    Ok(())
-                }
-            });
-        };
-
-        start_server_with(addr, tls, store, f).await
-    }
-
     #[tokio::test]
     async fn yamux_ctrl_creation_should_work() -> anyhow::Result<()> {
         let s = DummyStream::default();
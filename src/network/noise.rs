@@ -0,0 +1,382 @@
+use std::{
+    collections::HashSet,
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::{Buf, BufMut, BytesMut};
+use futures::ready;
+use snow::{params::NoiseParams, Builder, HandshakeState, TransportState};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tracing::instrument;
+
+use crate::KvError;
+
+/// Both peers authenticate with static keys, no certificate is involved
+const NOISE_PATTERN: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// The max length of a Noise message, the length prefix is 2 bytes
+const MAX_MESSAGE: usize = 65535;
+
+/// The max plaintext carried by a transport message, leaving room for the AEAD tag
+const MAX_PLAINTEXT: usize = MAX_MESSAGE - 16;
+
+/// The size of the buffer reading ciphertext from the underlying stream
+const READ_CHUNK: usize = 8192;
+
+/// A static X25519 keypair identifying a Noise peer
+#[derive(Clone)]
+pub struct NoiseKeypair {
+    pub private: Vec<u8>,
+    pub public: Vec<u8>,
+}
+
+impl NoiseKeypair {
+    /// Generate a random keypair, the public key is what the peers are provisioned with
+    pub fn generate() -> Result<Self, KvError> {
+        let keypair = Builder::new(params()).generate_keypair()?;
+        Ok(Self {
+            private: keypair.private,
+            public: keypair.public,
+        })
+    }
+}
+
+/// The server side of the Noise transport, accepting the clients in the allow list
+#[derive(Clone)]
+pub struct NoiseServerAcceptor {
+    private: Arc<Vec<u8>>,
+    clients: Option<Arc<HashSet<Vec<u8>>>>,
+}
+
+/// The client side of the Noise transport, optionally pinning the server public key
+#[derive(Clone)]
+pub struct NoiseClientConnector {
+    private: Arc<Vec<u8>>,
+    server: Option<Arc<Vec<u8>>>,
+}
+
+impl NoiseServerAcceptor {
+    /// Accept any client, a client is identified by its public key after the handshake
+    pub fn new(private: &[u8]) -> Self {
+        Self {
+            private: Arc::new(private.to_vec()),
+            clients: None,
+        }
+    }
+
+    /// Only accept the clients with one of the public keys
+    pub fn allow_clients(mut self, keys: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        self.clients = Some(Arc::new(keys.into_iter().collect()));
+        self
+    }
+
+    /// Run the handshake as the responder and encrypt the stream
+    #[instrument(name = "noise_server_accept", skip_all)]
+    pub async fn accept<S>(&self, mut stream: S) -> Result<NoiseStream<S>, KvError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let mut handshake = Builder::new(params())
+            .local_private_key(&self.private)
+            .build_responder()?;
+
+        // -> e
+        recv_handshake(&mut stream, &mut handshake).await?;
+        // <- e, ee, s, es
+        send_handshake(&mut stream, &mut handshake).await?;
+        // -> s, se
+        recv_handshake(&mut stream, &mut handshake).await?;
+
+        if let Some(clients) = &self.clients {
+            let key = handshake.get_remote_static().unwrap_or_default();
+            if !clients.contains(key) {
+                return Err(KvError::PermissionDenied("unknown Noise client key".into()));
+            }
+        }
+        Ok(NoiseStream::new(stream, handshake.into_transport_mode()?))
+    }
+}
+
+impl NoiseClientConnector {
+    /// Connect to any server, pin the server public key with `server` to reject impostors
+    pub fn new(private: &[u8], server: Option<&[u8]>) -> Self {
+        Self {
+            private: Arc::new(private.to_vec()),
+            server: server.map(|key| Arc::new(key.to_vec())),
+        }
+    }
+
+    /// Run the handshake as the initiator and encrypt the stream
+    #[instrument(name = "noise_client_connect", skip_all)]
+    pub async fn connect<S>(&self, mut stream: S) -> Result<NoiseStream<S>, KvError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let mut handshake = Builder::new(params())
+            .local_private_key(&self.private)
+            .build_initiator()?;
+
+        // -> e
+        send_handshake(&mut stream, &mut handshake).await?;
+        // <- e, ee, s, es
+        recv_handshake(&mut stream, &mut handshake).await?;
+
+        // check the server before revealing our own static key
+        if let Some(server) = &self.server {
+            if handshake.get_remote_static() != Some(server.as_slice()) {
+                return Err(KvError::PermissionDenied(
+                    "unexpected Noise server key".into(),
+                ));
+            }
+        }
+
+        // -> s, se
+        send_handshake(&mut stream, &mut handshake).await?;
+        Ok(NoiseStream::new(stream, handshake.into_transport_mode()?))
+    }
+}
+
+fn params() -> NoiseParams {
+    NOISE_PATTERN.parse().expect("valid Noise pattern")
+}
+
+async fn send_handshake<S>(stream: &mut S, handshake: &mut HandshakeState) -> Result<(), KvError>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; MAX_MESSAGE];
+    let len = handshake.write_message(&[], &mut buf)?;
+    stream.write_all(&(len as u16).to_be_bytes()).await?;
+    stream.write_all(&buf[..len]).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn recv_handshake<S>(stream: &mut S, handshake: &mut HandshakeState) -> Result<(), KvError>
+where
+    S: AsyncRead + Unpin,
+{
+    let len = stream.read_u16().await? as usize;
+    let mut msg = vec![0u8; len];
+    stream.read_exact(&mut msg).await?;
+    let mut payload = vec![0u8; MAX_MESSAGE];
+    handshake.read_message(&msg, &mut payload)?;
+    Ok(())
+}
+
+/// A stream encrypted by the Noise transport, each write is sent as length prefixed
+/// transport messages, so it can be wrapped by ProstStream like a TLS stream.
+pub struct NoiseStream<S> {
+    stream: S,
+    state: TransportState,
+
+    /// The ciphertext read from the stream, not yet a whole message
+    rbuf: BytesMut,
+
+    /// The decrypted data not yet consumed by the reader
+    plain: BytesMut,
+
+    /// The encrypted messages not yet written to the stream
+    wbuf: BytesMut,
+}
+
+impl<S> NoiseStream<S> {
+    fn new(stream: S, state: TransportState) -> Self {
+        Self {
+            stream,
+            state,
+            rbuf: BytesMut::new(),
+            plain: BytesMut::new(),
+            wbuf: BytesMut::new(),
+        }
+    }
+
+    /// The static public key of the peer
+    pub fn remote_static(&self) -> Option<&[u8]> {
+        self.state.get_remote_static()
+    }
+
+    /// Decrypt the first message of the read buffer, if it is complete
+    fn decrypt_message(&mut self) -> io::Result<bool> {
+        if self.rbuf.len() < 2 {
+            return Ok(false);
+        }
+        let len = u16::from_be_bytes([self.rbuf[0], self.rbuf[1]]) as usize;
+        if self.rbuf.len() < 2 + len {
+            return Ok(false);
+        }
+
+        self.rbuf.advance(2);
+        let msg = self.rbuf.split_to(len);
+        let mut payload = vec![0u8; len];
+        let n = self
+            .state
+            .read_message(&msg, &mut payload)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.plain.extend_from_slice(&payload[..n]);
+        Ok(true)
+    }
+}
+
+impl<S> NoiseStream<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.wbuf.is_empty() {
+            let n = ready!(Pin::new(&mut self.stream).poll_write(cx, &self.wbuf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.wbuf.advance(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> AsyncRead for NoiseStream<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if !this.plain.is_empty() {
+                let n = this.plain.len().min(buf.remaining());
+                buf.put_slice(&this.plain.split_to(n));
+                return Poll::Ready(Ok(()));
+            }
+            if this.decrypt_message()? {
+                continue;
+            }
+
+            let mut chunk = [0u8; READ_CHUNK];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.stream).poll_read(cx, &mut chunk))?;
+            if chunk.filled().is_empty() {
+                // a clean EOF only happens between two messages
+                return match this.rbuf.is_empty() {
+                    true => Poll::Ready(Ok(())),
+                    false => Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+                };
+            }
+            this.rbuf.extend_from_slice(chunk.filled());
+        }
+    }
+}
+
+impl<S> AsyncWrite for NoiseStream<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+
+        let len = buf.len().min(MAX_PLAINTEXT);
+        let mut msg = vec![0u8; MAX_MESSAGE];
+        let n = this
+            .state
+            .write_message(&buf[..len], &mut msg)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        this.wbuf.put_u16(n as u16);
+        this.wbuf.extend_from_slice(&msg[..n]);
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        Pin::new(&mut this.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        Pin::new(&mut this.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn noise_should_work() -> Result<()> {
+        let server = NoiseKeypair::generate()?;
+        let client = NoiseKeypair::generate()?;
+        let acceptor =
+            NoiseServerAcceptor::new(&server.private).allow_clients([client.public.clone()]);
+        let addr = start_echo(acceptor).await?;
+
+        let connector = NoiseClientConnector::new(&client.private, Some(&server.public));
+        let mut stream = connector.connect(TcpStream::connect(addr).await?).await?;
+        assert_eq!(stream.remote_static(), Some(server.public.as_slice()));
+
+        // larger than a transport message
+        let data: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+        stream.write_all(&data).await?;
+        stream.flush().await?;
+        let mut buf = vec![0u8; data.len()];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(buf, data);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn noise_with_unknown_keys_should_not_work() -> Result<()> {
+        let server = NoiseKeypair::generate()?;
+        let client = NoiseKeypair::generate()?;
+        let stranger = NoiseKeypair::generate()?;
+
+        // the client pins another server key
+        let acceptor = NoiseServerAcceptor::new(&server.private);
+        let addr = start_echo(acceptor).await?;
+        let connector = NoiseClientConnector::new(&client.private, Some(&stranger.public));
+        let result = connector.connect(TcpStream::connect(addr).await?).await;
+        assert!(matches!(result, Err(KvError::PermissionDenied(_))));
+
+        // the server does not know the client key
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let acceptor = NoiseServerAcceptor::new(&server.private).allow_clients([client.public]);
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            tx.send(acceptor.accept(stream).await.map(|_| ())).unwrap();
+        });
+        let connector = NoiseClientConnector::new(&stranger.private, None);
+        let _stream = connector.connect(TcpStream::connect(addr).await?).await?;
+        assert!(matches!(rx.await?, Err(KvError::PermissionDenied(_))));
+        Ok(())
+    }
+
+    async fn start_echo(acceptor: NoiseServerAcceptor) -> Result<std::net::SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            if let Ok(stream) = acceptor.accept(stream).await {
+                let (mut reader, mut writer) = tokio::io::split(stream);
+                tokio::io::copy(&mut reader, &mut writer).await.ok();
+                writer.flush().await.ok();
+            }
+        });
+        Ok(addr)
+    }
+}
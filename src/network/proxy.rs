@@ -0,0 +1,290 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use ipnet::IpNet;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    time,
+};
+
+use crate::KvError;
+
+use super::ip_filter::parse_net;
+
+/// The signature starting a PROXY protocol v2 header
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The max length of a v1 header, including the CRLF
+const V1_MAX_LEN: usize = 107;
+
+/// How long a trusted proxy has to send the header by default
+const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The load balancers allowed to send the PROXY protocol header. The header of any other peer
+/// would be a spoofed client address, so it is never read from them.
+#[derive(Debug, Clone)]
+pub struct TrustedProxies {
+    nets: Vec<IpNet>,
+    /// How long a proxy has to send the header once connected
+    header_timeout: Duration,
+}
+
+impl TrustedProxies {
+    pub fn new(nets: Vec<IpNet>) -> Self {
+        Self {
+            nets,
+            header_timeout: DEFAULT_HEADER_TIMEOUT,
+        }
+    }
+
+    /// Parse comma separated networks in the CIDR notation, a bare address is a single host
+    pub fn parse(list: &str) -> Result<Self, KvError> {
+        let nets = list
+            .split(',')
+            .map(str::trim)
+            .filter(|net| !net.is_empty())
+            .map(|net| {
+                parse_net(net).ok_or_else(|| KvError::InvalidIpRule(format!("bad CIDR {}", net)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(nets))
+    }
+
+    pub fn header_timeout(mut self, timeout: Duration) -> Self {
+        self.header_timeout = timeout;
+        self
+    }
+
+    /// Whether the peer is a trusted proxy
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.nets.iter().any(|net| net.contains(&ip))
+    }
+
+    /// The address of the client of a connection: read from the header of a trusted proxy,
+    /// the header being mandatory then, or the peer address for the other peers
+    pub async fn client_addr<S>(
+        &self,
+        stream: &mut S,
+        peer: SocketAddr,
+    ) -> Result<SocketAddr, KvError>
+    where
+        S: AsyncRead + Unpin,
+    {
+        if !self.is_trusted(peer.ip()) {
+            return Ok(peer);
+        }
+        match time::timeout(self.header_timeout, read_proxy_header(stream)).await {
+            Ok(client) => Ok(client?.unwrap_or(peer)),
+            Err(_) => Err(invalid("timed out waiting for the header")),
+        }
+    }
+}
+
+/// Read the PROXY protocol (v1 or v2) header the load balancer sends before the client data,
+/// and return the address of the real client. The header is mandatory on the connection,
+/// None is returned if it carries no address, like the health checks of the balancer.
+///
+/// Only the header is consumed, the stream is left at the first byte of the client data.
+pub async fn read_proxy_header<S>(stream: &mut S) -> Result<Option<SocketAddr>, KvError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut prefix = [0u8; 6];
+    stream.read_exact(&mut prefix).await?;
+
+    if &prefix == b"PROXY " {
+        read_v1(stream).await
+    } else if prefix == V2_SIGNATURE[..6] {
+        read_v2(stream).await
+    } else {
+        Err(invalid("missing PROXY protocol signature"))
+    }
+}
+
+async fn read_v1<S>(stream: &mut S) -> Result<Option<SocketAddr>, KvError>
+where
+    S: AsyncRead + Unpin,
+{
+    // read byte by byte, the client data follows the line
+    let mut line = Vec::with_capacity(V1_MAX_LEN);
+    while !line.ends_with(b"\r\n") {
+        if line.len() + 6 >= V1_MAX_LEN {
+            return Err(invalid("v1 header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    line.truncate(line.len() - 2);
+
+    let line = std::str::from_utf8(&line).map_err(|_| invalid("v1 header is not ASCII"))?;
+    let parts: Vec<&str> = line.split(' ').collect();
+    match parts.as_slice() {
+        ["UNKNOWN", ..] => Ok(None),
+        [proto @ ("TCP4" | "TCP6"), src, _dst, src_port, _dst_port] => {
+            let ip: IpAddr = src.parse().map_err(|_| invalid("bad v1 source address"))?;
+            let port: u16 = src_port
+                .parse()
+                .map_err(|_| invalid("bad v1 source port"))?;
+            if ip.is_ipv4() != (*proto == "TCP4") {
+                return Err(invalid("v1 address does not match the protocol"));
+            }
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("bad v1 header")),
+    }
+}
+
+async fn read_v2<S>(stream: &mut S) -> Result<Option<SocketAddr>, KvError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut rest = [0u8; 10];
+    stream.read_exact(&mut rest).await?;
+    if rest[..6] != V2_SIGNATURE[6..] {
+        return Err(invalid("missing PROXY protocol signature"));
+    }
+
+    let (version, command, family) = (rest[6] >> 4, rest[6] & 0x0f, rest[7]);
+    let len = u16::from_be_bytes([rest[8], rest[9]]) as usize;
+    let mut addrs = vec![0u8; len];
+    stream.read_exact(&mut addrs).await?;
+
+    if version != 2 {
+        return Err(invalid("unsupported version"));
+    }
+    match command {
+        // LOCAL, the connection is made by the balancer itself
+        0 => return Ok(None),
+        // PROXY
+        1 => {}
+        _ => return Err(invalid("unsupported command")),
+    }
+
+    // the high nibble is the address family, the low nibble the transport
+    match family >> 4 {
+        1 if len >= 12 => {
+            let ip = Ipv4Addr::new(addrs[0], addrs[1], addrs[2], addrs[3]);
+            let port = u16::from_be_bytes([addrs[8], addrs[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        2 if len >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addrs[..16]);
+            let port = u16::from_be_bytes([addrs[32], addrs[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        // UNSPEC or unix sockets carry no IP address
+        0 | 3 => Ok(None),
+        _ => Err(invalid("bad v2 address block")),
+    }
+}
+
+fn invalid(msg: &str) -> KvError {
+    KvError::InvalidProxyHeader(msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(data: &[u8]) -> (Result<Option<SocketAddr>, KvError>, Vec<u8>) {
+        let mut stream = data;
+        let res = read_proxy_header(&mut stream).await;
+        (res, stream.to_vec())
+    }
+
+    fn v2(command: u8, family: u8, addrs: &[u8]) -> Vec<u8> {
+        let mut data = V2_SIGNATURE.to_vec();
+        data.push(0x20 | command);
+        data.push(family);
+        data.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+        data.extend_from_slice(addrs);
+        data.extend_from_slice(b"data");
+        data
+    }
+
+    #[tokio::test]
+    async fn proxy_v1_header_should_work() {
+        let (res, rest) = parse(b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 9527\r\ndata").await;
+        assert_eq!(res.unwrap(), Some("192.168.0.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"data");
+
+        let (res, _) = parse(b"PROXY TCP6 ::1 ::1 56324 9527\r\n").await;
+        assert_eq!(res.unwrap(), Some("[::1]:56324".parse().unwrap()));
+
+        let (res, rest) = parse(b"PROXY UNKNOWN\r\ndata").await;
+        assert_eq!(res.unwrap(), None);
+        assert_eq!(rest, b"data");
+    }
+
+    #[tokio::test]
+    async fn proxy_v2_header_should_work() {
+        let mut addrs = vec![192, 168, 0, 1, 10, 0, 0, 1];
+        addrs.extend_from_slice(&56324u16.to_be_bytes());
+        addrs.extend_from_slice(&9527u16.to_be_bytes());
+        let (res, rest) = parse(&v2(1, 0x11, &addrs)).await;
+        assert_eq!(res.unwrap(), Some("192.168.0.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"data");
+
+        let mut addrs = Ipv6Addr::LOCALHOST.octets().to_vec();
+        addrs.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        addrs.extend_from_slice(&56324u16.to_be_bytes());
+        addrs.extend_from_slice(&9527u16.to_be_bytes());
+        // trailing TLVs are skipped
+        addrs.extend_from_slice(&[0x04, 0, 1, 0]);
+        let (res, rest) = parse(&v2(1, 0x21, &addrs)).await;
+        assert_eq!(res.unwrap(), Some("[::1]:56324".parse().unwrap()));
+        assert_eq!(rest, b"data");
+
+        let (res, rest) = parse(&v2(0, 0x00, &[])).await;
+        assert_eq!(res.unwrap(), None);
+        assert_eq!(rest, b"data");
+    }
+
+    #[tokio::test]
+    async fn proxy_header_should_only_be_read_from_trusted_proxies() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8, 192.168.0.1")
+            .unwrap()
+            .header_timeout(Duration::from_millis(10));
+        let header = b"PROXY TCP4 172.16.0.9 10.0.0.1 56324 9527\r\ndata";
+
+        let proxy: SocketAddr = "10.1.2.3:40000".parse().unwrap();
+        let mut stream = &header[..];
+        let client = proxies.client_addr(&mut stream, proxy).await.unwrap();
+        assert_eq!(client, "172.16.0.9:56324".parse().unwrap());
+        assert_eq!(stream, b"data");
+
+        // the header of another peer is left unread, the peer address is kept
+        let peer: SocketAddr = "[::ffff:172.16.0.1]:40000".parse().unwrap();
+        let mut stream = &header[..];
+        let client = proxies.client_addr(&mut stream, peer).await.unwrap();
+        assert_eq!(client, peer);
+        assert_eq!(stream, &header[..]);
+
+        // a proxy not sending the header in time is rejected
+        let (mut silent, _other) = tokio::io::duplex(64);
+        let res = proxies.client_addr(&mut silent, proxy).await;
+        assert!(matches!(res, Err(KvError::InvalidProxyHeader(_))));
+
+        assert!(TrustedProxies::parse("10.0.0.0/33").is_err());
+    }
+
+    #[tokio::test]
+    async fn bad_proxy_header_should_be_rejected() {
+        for data in [
+            &b"GET / HTTP/1.1\r\n"[..],
+            b"PROXY TCP4 ::1 ::1 1 2\r\n",
+            b"PROXY TCP4 192.168.0.1\r\n",
+            &v2(1, 0x11, &[1, 2, 3]),
+        ] {
+            let (res, _) = parse(data).await;
+            assert!(
+                matches!(res, Err(KvError::InvalidProxyHeader(_))),
+                "{:?}",
+                data
+            );
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use tokio::sync::Notify;
+
+/// The priority of a stream on a multiplexed connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    /// Latency-sensitive streams, like unary commands.
+    #[default]
+    High,
+    /// Heavy streams, like subscriptions and dumps.
+    Bulk,
+}
+
+impl From<u32> for Priority {
+    fn from(v: u32) -> Self {
+        match v {
+            0 => Priority::High,
+            _ => Priority::Bulk,
+        }
+    }
+}
+
+impl From<Priority> for u32 {
+    fn from(p: Priority) -> Self {
+        match p {
+            Priority::High => 0,
+            Priority::Bulk => 1,
+        }
+    }
+}
+
+/// A scheduler for the send path of all streams on the same multiplexed connection,
+/// bulk streams yield to high priority streams before sending every frame,
+/// so a heavy stream won't starve the latency-sensitive ones.
+#[derive(Debug, Clone, Default)]
+pub struct SendScheduler {
+    inner: Arc<SchedulerInner>,
+}
+
+#[derive(Debug, Default)]
+struct SchedulerInner {
+    /// The number of high priority frames being sent.
+    high: AtomicUsize,
+    /// Notify the bulk streams when there is no high priority frame being sent.
+    idle: Notify,
+}
+
+/// A permit to send a frame, it should be held until the frame is sent.
+pub struct SendPermit<'a> {
+    scheduler: &'a SchedulerInner,
+    priority: Priority,
+}
+
+impl SendScheduler {
+    /// Wait for the turn to send a frame with the given priority
+    pub async fn acquire(&self, priority: Priority) -> SendPermit<'_> {
+        let scheduler = self.inner.as_ref();
+        match priority {
+            Priority::High => {
+                scheduler.high.fetch_add(1, Ordering::AcqRel);
+            }
+            Priority::Bulk => loop {
+                // create the future before checking, so we won't miss the notification
+                let idle = scheduler.idle.notified();
+                if scheduler.high.load(Ordering::Acquire) == 0 {
+                    break;
+                }
+                idle.await;
+            },
+        }
+        SendPermit {
+            scheduler,
+            priority,
+        }
+    }
+}
+
+impl Drop for SendPermit<'_> {
+    fn drop(&mut self) {
+        if self.priority == Priority::High
+            && self.scheduler.high.fetch_sub(1, Ordering::AcqRel) == 1
+        {
+            self.scheduler.idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn bulk_should_wait_for_high_priority() {
+        let scheduler = SendScheduler::default();
+        let high = scheduler.acquire(Priority::High).await;
+
+        let cloned = scheduler.clone();
+        let bulk = tokio::spawn(async move {
+            let _permit = cloned.acquire(Priority::Bulk).await;
+        });
+
+        time::sleep(Duration::from_millis(10)).await;
+        assert!(!bulk.is_finished());
+
+        drop(high);
+        time::timeout(Duration::from_millis(100), bulk)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn high_priority_should_not_wait() {
+        let scheduler = SendScheduler::default();
+        let _bulk = scheduler.acquire(Priority::Bulk).await;
+        let _high1 = scheduler.acquire(Priority::High).await;
+        let _high2 = scheduler.acquire(Priority::High).await;
+    }
+}
@@ -8,9 +8,9 @@ use bytes::BytesMut;
 use futures::{ready, FutureExt, Sink, Stream};
 use tokio::io::{AsyncRead, AsyncWrite};
 
-use crate::{read_frame, KvError};
+use crate::{read_frame_limited, KvError};
 
-use super::FrameCoder;
+use super::{frame::MAX_FRAME, FrameCoder, FrameCompression};
 
 // A stream used to handle the stream of kv server prost frame.
 pub struct ProstStream<S, In, Out> {
@@ -25,6 +25,12 @@ pub struct ProstStream<S, In, Out> {
     /// The buffer used to read data from the stream.
     rbuf: BytesMut,
 
+    /// The max length of a frame to read, longer frames are rejected before being buffered.
+    max_frame: usize,
+
+    /// How the frames written to the stream are compressed.
+    compression: FrameCompression,
+
     _in: PhantomData<In>,
     _out: PhantomData<Out>,
 }
@@ -42,7 +48,8 @@ where
 
         let mut rest = self.rbuf.split_off(0);
 
-        let fut = read_frame(&mut self.stream, &mut rest);
+        let max_frame = self.max_frame;
+        let fut = read_frame_limited(&mut self.stream, &mut rest, max_frame);
         ready!(Box::pin(fut).poll_unpin(cx))?;
 
         self.rbuf.unsplit(rest);
@@ -65,7 +72,7 @@ where
 
     fn start_send(self: Pin<&mut Self>, item: &Out) -> Result<(), Self::Error> {
         let this = self.get_mut();
-        item.encode_frame(&mut this.wbuf)?;
+        item.encode_frame_with(&mut this.wbuf, this.compression)?;
         Ok(())
     }
 
@@ -99,10 +106,34 @@ impl<S, In, Out> ProstStream<S, In, Out> {
             written: 0,
             wbuf: BytesMut::new(),
             rbuf: BytesMut::new(),
+            max_frame: MAX_FRAME,
+            compression: FrameCompression::default(),
             _in: PhantomData,
             _out: PhantomData,
         }
     }
+
+    /// Set the max length of a frame to read
+    pub fn with_max_frame(mut self, len: usize) -> Self {
+        self.max_frame = len;
+        self
+    }
+
+    /// Consume the wrapper and return the underlying stream
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Set the compression of the frames to write
+    pub fn with_compression(mut self, compression: FrameCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Change the compression of the following frames to write
+    pub fn set_compression(&mut self, compression: FrameCompression) {
+        self.compression = compression;
+    }
 }
 
 /// In most cases, the stream is Unpin, so we implement it for ProstStream.
@@ -1,54 +1,196 @@
 use std::{
     ops::{Deref, DerefMut},
     pin::Pin,
+    sync::{Arc, Mutex},
+    time::Instant,
 };
 
-use futures::{Stream, StreamExt};
+use futures::{future, Stream, StreamExt};
+use http::StatusCode;
 
 use crate::{CommandResponse, KvError};
 
-pub struct StreamResult {
-    pub id: u32,
-    inner: Pin<Box<dyn Stream<Item = Result<CommandResponse, KvError>> + Send>>,
+/// The boxed stream of the responses after the first frame
+type ResponseStream = Pin<Box<dyn Stream<Item = Result<CommandResponse, KvError>> + Send>>;
+
+/// A streaming result, the first frame of the stream is parsed into the header,
+/// the following frames are yielded by the stream.
+pub struct StreamResult<H = u64> {
+    pub header: H,
+    inner: ResponseStream,
+    /// The last time a frame (including keepalive frames) was received
+    last_seen: Arc<Mutex<Instant>>,
+}
+
+impl StreamResult<u64> {
+    /// Create a streaming result of a subscription, the first frame carries the subscription id
+    pub async fn new<T>(stream: T) -> Result<Self, KvError>
+    where
+        T: Stream<Item = Result<CommandResponse, KvError>> + Send + Unpin + 'static,
+    {
+        Self::with_parser(stream, parse_subscription_id).await
+    }
+
+    /// The id of the subscription
+    pub fn id(&self) -> u64 {
+        self.header
+    }
 }
 
-impl StreamResult {
-    pub async fn new<T>(mut stream: T) -> Result<Self, KvError>
+impl<H> StreamResult<H> {
+    /// Create a streaming result, the first frame is parsed by the given parser
+    pub async fn with_parser<T, F>(stream: T, parser: F) -> Result<Self, KvError>
     where
         T: Stream<Item = Result<CommandResponse, KvError>> + Send + Unpin + 'static,
+        F: FnOnce(CommandResponse) -> Result<H, KvError>,
     {
-        let id = match stream.next().await {
-            Some(Ok(CommandResponse {
-                status: 200,
-                values: v,
-                ..
-            })) => {
-                if v.is_empty() {
-                    return Err(KvError::Internal("Invalid stream".into()));
-                }
-                let id: i64 = (&v[0]).try_into().unwrap();
-                Ok(id as u32)
+        let last_seen = Arc::new(Mutex::new(Instant::now()));
+        let seen = last_seen.clone();
+
+        // keepalive frames only refresh the last seen time, they are never yielded
+        let mut stream = stream.filter_map(move |res| {
+            *seen.lock().unwrap() = Instant::now();
+            let heartbeat = matches!(&res, Ok(v) if v.is_heartbeat());
+            future::ready((!heartbeat).then_some(res))
+        });
+
+        let header = match stream.next().await {
+            Some(Ok(res)) => parser(res)?,
+            Some(Err(e)) => return Err(e),
+            None => {
+                return Err(KvError::Internal(
+                    "Stream closed before the first frame".into(),
+                ))
             }
-            _ => Err(KvError::Internal("Invalid stream".into())),
         };
 
         Ok(Self {
-            id: id?,
+            header,
             inner: Box::pin(stream),
+            last_seen,
         })
     }
+
+    /// The last time the server was seen alive on the stream,
+    /// the server sends keepalive frames even if there is no data.
+    pub fn last_seen(&self) -> Instant {
+        *self.last_seen.lock().unwrap()
+    }
 }
 
-impl Deref for StreamResult {
-    type Target = Pin<Box<dyn Stream<Item = Result<CommandResponse, KvError>> + Send>>;
+/// Parse the subscription id from the first frame of a subscription
+pub fn parse_subscription_id(res: CommandResponse) -> Result<u64, KvError> {
+    check_status(&res)?;
+    match res.values.first() {
+        Some(v) => {
+            let id: i64 = v.try_into()?;
+            u64::try_from(id).map_err(|_| KvError::ConvertCommand(v.format(), "subscription id"))
+        }
+        None => Err(KvError::Internal(
+            "Invalid stream: missing subscription id".into(),
+        )),
+    }
+}
+
+/// Check the status of the first frame and keep it as the header
+pub fn parse_status(res: CommandResponse) -> Result<CommandResponse, KvError> {
+    check_status(&res)?;
+    Ok(res)
+}
+
+/// Convert a non-2xx response into an error
+pub(crate) fn check_status(res: &CommandResponse) -> Result<(), KvError> {
+    match StatusCode::from_u16(res.status as u16) {
+        Ok(status) if status.is_success() => Ok(()),
+        Ok(StatusCode::NOT_FOUND) => Err(KvError::NotFound(res.message.clone())),
+        Ok(StatusCode::BAD_REQUEST) => Err(KvError::InvalidCommand(res.message.clone())),
+        Ok(StatusCode::PAYLOAD_TOO_LARGE) => Err(KvError::TooLarge(res.message.clone())),
+        Ok(StatusCode::TOO_MANY_REQUESTS) if res.message.starts_with("Throttled") => {
+            Err(KvError::Throttled(res.message.clone()))
+        }
+        Ok(StatusCode::TOO_MANY_REQUESTS) => Err(KvError::QuotaExceeded(res.message.clone())),
+        Ok(StatusCode::INSUFFICIENT_STORAGE) => {
+            Err(KvError::InsufficientStorage(res.message.clone()))
+        }
+        _ => Err(KvError::Internal(res.message.clone())),
+    }
+}
+
+impl<H> Deref for StreamResult<H> {
+    type Target = ResponseStream;
 
     fn deref(&self) -> &Self::Target {
         &self.inner
     }
 }
 
-impl DerefMut for StreamResult {
+impl<H> DerefMut for StreamResult<H> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.inner
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use crate::{CommandResponse, Value};
+
+    use super::*;
+
+    fn responses(
+        v: Vec<CommandResponse>,
+    ) -> impl Stream<Item = Result<CommandResponse, KvError>> + Send + Unpin + 'static {
+        stream::iter(v.into_iter().map(Ok))
+    }
+
+    #[tokio::test]
+    async fn stream_result_should_parse_subscription_id() {
+        let id: Value = 42.into();
+        let msg: Value = "hello".into();
+        let mut result = StreamResult::new(responses(vec![id.into(), msg.clone().into()]))
+            .await
+            .unwrap();
+        assert_eq!(result.id(), 42);
+        assert_eq!(result.next().await.unwrap().unwrap().values, vec![msg]);
+    }
+
+    #[tokio::test]
+    async fn stream_result_should_skip_heartbeats() {
+        let id: Value = 42.into();
+        let msg: Value = "hello".into();
+        let frames = vec![
+            CommandResponse::heartbeat(),
+            id.into(),
+            CommandResponse::heartbeat(),
+            msg.clone().into(),
+        ];
+        let mut result = StreamResult::new(responses(frames)).await.unwrap();
+        assert_eq!(result.id(), 42);
+        assert_eq!(result.next().await.unwrap().unwrap().values, vec![msg]);
+        assert!(result.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn stream_result_should_reject_bad_first_frame() {
+        let not_id: Value = "hello".into();
+        let result = StreamResult::new(responses(vec![not_id.into()])).await;
+        assert!(matches!(result, Err(KvError::ConvertCommand(..))));
+
+        let not_found: CommandResponse = KvError::NotFound("subscription 1".into()).into();
+        let result = StreamResult::new(responses(vec![not_found])).await;
+        assert!(matches!(result, Err(KvError::NotFound(_))));
+
+        let result = StreamResult::new(responses(vec![])).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn stream_result_should_work_with_status_parser() {
+        let result =
+            StreamResult::with_parser(responses(vec![CommandResponse::ok()]), parse_status)
+                .await
+                .unwrap();
+        assert_eq!(result.header, CommandResponse::ok());
+    }
+}
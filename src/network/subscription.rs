@@ -0,0 +1,278 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use futures::{ready, Stream, StreamExt};
+use tracing::{debug, warn};
+
+use crate::{CommandRequest, KvError, Multiplexer, ProstClientStream, Value};
+
+use super::{
+    stream_result::{check_status, StreamResult},
+    Priority,
+};
+
+/// A message published to a topic
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicMessage {
+    /// The sequence number of the message, increasing by one per topic
+    pub id: u64,
+    /// The topic of the message
+    pub topic: String,
+    /// The published values
+    pub values: Vec<Value>,
+    /// The number of messages of the topic missed right before this one, like the messages
+    /// dropped by a full subscription, 0 for the first message seen of the topic
+    pub missed: u64,
+}
+
+/// A subscription to a topic, it yields the messages published to the topic,
+/// and unsubscribes from the topic when dropped.
+pub struct Subscription<M: Multiplexer> {
+    /// The id of the subscription
+    pub id: u64,
+    /// The subscribed topic
+    topic: String,
+    /// The id of the last message yielded per topic, a wildcard subscription gets many topics
+    last_ids: HashMap<String, u64>,
+    /// The stream of the published messages
+    inner: StreamResult,
+    /// The multiplexer used to open a new stream to unsubscribe,
+    /// it is taken once the subscription is unsubscribed.
+    ctrl: Option<M>,
+}
+
+impl<M: Multiplexer> Subscription<M> {
+    /// Subscribe to the topic on a new stream of the multiplexer
+    pub async fn new(ctrl: &mut M, topic: impl Into<String>) -> Result<Self, KvError> {
+        Self::resume(ctrl, topic, 0).await
+    }
+
+    /// Subscribe to the topic again after a disconnection, the retained messages published
+    /// after the message id `after`, usually the `last_id` of the broken subscription, come first.
+    pub async fn resume(
+        ctrl: &mut M,
+        topic: impl Into<String>,
+        after: u64,
+    ) -> Result<Self, KvError> {
+        let topic = topic.into();
+        let stream = ctrl.open_stream().await?;
+        let cmd = CommandRequest::new_resume(topic.clone(), after);
+        let inner = ProstClientStream::new(stream)
+            .with_priority(Priority::Bulk)
+            .execute_stream(&cmd)
+            .await?;
+
+        let mut last_ids = HashMap::new();
+        if after > 0 {
+            last_ids.insert(topic.clone(), after);
+        }
+
+        Ok(Self {
+            id: inner.id(),
+            topic,
+            last_ids,
+            inner,
+            ctrl: Some(ctrl.clone()),
+        })
+    }
+
+    /// The subscribed topic
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// The id of the last message yielded of the subscribed topic, to resume from
+    pub fn last_id(&self) -> u64 {
+        self.last_ids.get(&self.topic).copied().unwrap_or_default()
+    }
+
+    /// The last time the server was seen alive on the subscription stream
+    pub fn last_seen(&self) -> Instant {
+        self.inner.last_seen()
+    }
+
+    /// Unsubscribe from the topic and wait for the server's confirmation
+    pub async fn unsubscribe(mut self) -> Result<(), KvError> {
+        match self.ctrl.take() {
+            Some(ctrl) => unsubscribe(ctrl, self.topic.clone(), self.id).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl<M> Stream for Subscription<M>
+where
+    M: Multiplexer + Unpin,
+{
+    type Item = TopicMessage;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            return match ready!(this.inner.poll_next_unpin(cx)) {
+                Some(Ok(res)) => {
+                    let topic = match res.topic.is_empty() {
+                        true => this.topic.clone(),
+                        false => res.topic,
+                    };
+                    let last_id = this.last_ids.get(&topic).copied();
+                    // a message both replayed and delivered on resume comes twice
+                    if matches!(last_id, Some(last_id) if res.message_id <= last_id) {
+                        continue;
+                    }
+                    let missed = last_id.map_or(0, |last_id| res.message_id - last_id - 1);
+                    if missed > 0 {
+                        warn!(
+                            "Subscription {} missed {} messages of {}",
+                            this.id, missed, topic
+                        );
+                    }
+                    this.last_ids.insert(topic.clone(), res.message_id);
+                    Poll::Ready(Some(TopicMessage {
+                        id: res.message_id,
+                        topic,
+                        values: res.values,
+                        missed,
+                    }))
+                }
+                Some(Err(e)) => {
+                    warn!("Subscription {} is broken: {:?}", this.id, e);
+                    Poll::Ready(None)
+                }
+                None => Poll::Ready(None),
+            };
+        }
+    }
+}
+
+impl<M: Multiplexer> Drop for Subscription<M> {
+    fn drop(&mut self) {
+        let Some(ctrl) = self.ctrl.take() else {
+            return;
+        };
+
+        // the drop can't be async, so unsubscribe in the background
+        let (topic, id) = (self.topic.clone(), self.id);
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    if let Err(e) = unsubscribe(ctrl, topic, id).await {
+                        warn!("Failed to unsubscribe {} on drop: {:?}", id, e);
+                    }
+                });
+            }
+            Err(_) => warn!("No runtime to unsubscribe {} on drop", id),
+        }
+    }
+}
+
+/// Send an Unsubscribe command on a new stream
+async fn unsubscribe<M: Multiplexer>(mut ctrl: M, topic: String, id: u64) -> Result<(), KvError> {
+    let stream = ctrl.open_stream().await?;
+    let mut client = ProstClientStream::new(stream);
+    let cmd = CommandRequest::new_unsubscribe(topic, id);
+    let res = client.execute_unary(&cmd).await?;
+    debug!("Unsubscribed {}: {:?}", id, res);
+    check_status(&res)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{net::TcpStream, time};
+
+    use crate::{
+        network::{
+            multiplex::mux_utils::start_yamux_server,
+            tls::tls_utils::{tls_acceptor, tls_connector},
+        },
+        MemTable, YamuxCtrl,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn subscription_should_work() -> anyhow::Result<()> {
+        let acceptor = tls_acceptor(false)?;
+        let addr = start_yamux_server("127.0.0.1:0", acceptor, MemTable::new()).await?;
+
+        let connector = tls_connector(false)?;
+        let stream = TcpStream::connect(&addr).await?;
+        let stream = connector.connect(stream).await?;
+        let mut ctrl = YamuxCtrl::new_client(stream, None);
+
+        let mut sub = Subscription::new(&mut ctrl, "lobby").await?;
+        assert!(sub.id > 0);
+
+        // publish a message on another stream
+        let mut client = ProstClientStream::new(ctrl.open_stream().await?);
+        let cmd = CommandRequest::new_publish("lobby", vec!["hello".into()]);
+        client.execute_unary(&cmd).await?;
+
+        let msg = sub.next().await.unwrap();
+        assert_eq!(msg.id, 1);
+        assert_eq!(msg.topic, "lobby");
+        assert_eq!(msg.values, vec!["hello".into()]);
+
+        // unsubscribe twice, the second one should fail
+        let id = sub.id;
+        sub.unsubscribe().await?;
+        let res = unsubscribe(ctrl.clone(), "lobby".into(), id).await;
+        assert!(matches!(res, Err(KvError::NotFound(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subscription_should_report_missed_messages() -> anyhow::Result<()> {
+        let acceptor = tls_acceptor(false)?;
+        let addr = start_yamux_server("127.0.0.1:0", acceptor, MemTable::new()).await?;
+
+        let connector = tls_connector(false)?;
+        let stream = TcpStream::connect(&addr).await?;
+        let stream = connector.connect(stream).await?;
+        let mut ctrl = YamuxCtrl::new_client(stream, None);
+
+        let mut client = ProstClientStream::new(ctrl.open_stream().await?);
+        let cmd = CommandRequest::new_publish("lobby", vec!["hello".into()]);
+        for _ in 0..4 {
+            client.execute_unary(&cmd).await?;
+        }
+
+        // nothing is retained, so the messages after 1 are lost
+        let mut sub = Subscription::resume(&mut ctrl, "lobby", 1).await?;
+        client.execute_unary(&cmd).await?;
+        let msg = sub.next().await.unwrap();
+        assert_eq!((msg.id, msg.missed), (5, 3));
+
+        client.execute_unary(&cmd).await?;
+        let msg = sub.next().await.unwrap();
+        assert_eq!((msg.id, msg.missed), (6, 0));
+        assert_eq!(sub.last_id(), 6);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subscription_should_unsubscribe_on_drop() -> anyhow::Result<()> {
+        let acceptor = tls_acceptor(false)?;
+        let addr = start_yamux_server("127.0.0.1:0", acceptor, MemTable::new()).await?;
+
+        let connector = tls_connector(false)?;
+        let stream = TcpStream::connect(&addr).await?;
+        let stream = connector.connect(stream).await?;
+        let mut ctrl = YamuxCtrl::new_client(stream, None);
+
+        let sub = Subscription::new(&mut ctrl, "lobby").await?;
+        let id = sub.id;
+        drop(sub);
+        time::sleep(Duration::from_millis(50)).await;
+
+        let res = unsubscribe(ctrl.clone(), "lobby".into(), id).await;
+        assert!(matches!(res, Err(KvError::NotFound(_))));
+        Ok(())
+    }
+}
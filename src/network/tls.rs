@@ -1,16 +1,20 @@
+use std::fs;
 use std::io::Cursor;
+use std::path::Path;
 use std::sync::Arc;
 
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_rustls::rustls::{internal::pemfile, Certificate, ClientConfig, ServerConfig};
+use tokio_rustls::rustls::{sign, SignatureScheme};
 use tokio_rustls::rustls::{AllowAnyAuthenticatedClient, NoClientAuth, PrivateKey, RootCertStore};
-use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::webpki::{self, DNSNameRef};
 use tokio_rustls::TlsConnector;
 use tokio_rustls::{
     client::TlsStream as ClientTlsStream, server::TlsStream as ServerTlsStream, TlsAcceptor,
 };
 use tracing::instrument;
 
+use super::crl::{CrlVerifier, RevokedCerts};
 use crate::KvError;
 
 /// KV Server 自己的 ALPN (Application-Layer Protocol Negotiation)
@@ -20,6 +24,7 @@ const ALPN_KV: &str = "kv";
 #[derive(Clone)]
 pub struct TlsServerAcceptor {
     inner: Arc<ServerConfig>,
+    client_ca: Option<Arc<String>>,
 }
 
 /// 存放 TLS Client 并提供方法 connect 把底层的协议转换成 TLS
@@ -65,6 +70,21 @@ impl TlsClientConnector {
         })
     }
 
+    /// 从 PEM 文件加载 client cert / key 和 CA cert，生成 ClientConfig
+    pub fn from_pem_files(
+        domain: impl Into<String> + std::fmt::Debug,
+        identity: Option<(impl AsRef<Path>, impl AsRef<Path>)>,
+        server_ca: Option<impl AsRef<Path>>,
+    ) -> Result<Self, KvError> {
+        let identity = identity
+            .map(|(cert, key)| read_identity(cert.as_ref(), key.as_ref()))
+            .transpose()?;
+        let server_ca = server_ca.map(|path| read_ca(path.as_ref())).transpose()?;
+
+        let identity = identity.as_ref().map(|(c, k)| (c.as_str(), k.as_str()));
+        Self::new(domain, identity, server_ca.as_deref())
+    }
+
     #[instrument(name = "tls_client_connect", skip_all)]
     /// 触发 TLS 协议，把底层的 stream 转换成 TLS stream
     pub async fn connect<S>(&self, stream: S) -> Result<ClientTlsStream<S>, KvError>
@@ -112,9 +132,49 @@ impl TlsServerAcceptor {
 
         Ok(Self {
             inner: Arc::new(config),
+            client_ca: client_ca.map(|ca| Arc::new(ca.to_owned())),
         })
     }
 
+    /// 加载 CRL（证书吊销列表），握手时拒绝已被吊销的客户端证书。
+    /// CRL 必须由 client CA 签发，没有配置 client CA 时返回错误
+    pub fn with_crls(mut self, crls: &[&str]) -> Result<Self, KvError> {
+        let client_ca = self.client_ca.as_deref().ok_or_else(|| {
+            KvError::TlsMaterial("CRLs require a client CA to verify clients".into())
+        })?;
+        let ca_certs = load_certs(client_ca)?;
+        let revoked = RevokedCerts::load(crls, &ca_certs)?;
+
+        let mut store = RootCertStore::empty();
+        store
+            .add_pem_file(&mut Cursor::new(client_ca))
+            .map_err(|_| KvError::CertificateParseError("CA", "cert"))?;
+        let verifier = CrlVerifier::new(AllowAnyAuthenticatedClient::new(store), revoked);
+        Arc::make_mut(&mut self.inner).set_client_certificate_verifier(verifier);
+        Ok(self)
+    }
+
+    /// 从 PEM 文件加载 CRL
+    pub fn with_crl_files(self, paths: &[impl AsRef<Path>]) -> Result<Self, KvError> {
+        let crls = paths
+            .iter()
+            .map(|path| read_pem(path.as_ref(), "CRL"))
+            .collect::<Result<Vec<_>, _>>()?;
+        let crls: Vec<&str> = crls.iter().map(|crl| crl.as_str()).collect();
+        self.with_crls(&crls)
+    }
+
+    /// 从 PEM 文件加载 server cert / key 和 CA cert，生成 ServerConfig
+    pub fn from_pem_files(
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+        client_ca: Option<impl AsRef<Path>>,
+    ) -> Result<Self, KvError> {
+        let (cert, key) = read_identity(cert_path.as_ref(), key_path.as_ref())?;
+        let client_ca = client_ca.map(|path| read_ca(path.as_ref())).transpose()?;
+        Self::new(&cert, &key, client_ca.as_deref())
+    }
+
     #[instrument(name = "tls_server_accept", skip_all)]
     /// 触发 TLS 协议，把底层的 stream 转换成 TLS stream
     pub async fn accept<S>(&self, stream: S) -> Result<ServerTlsStream<S>, KvError>
@@ -126,6 +186,85 @@ impl TlsServerAcceptor {
     }
 }
 
+/// 读取 PEM 文件，错误信息带上文件路径
+fn read_pem(path: &Path, what: &str) -> Result<String, KvError> {
+    fs::read_to_string(path)
+        .map_err(|e| KvError::TlsMaterial(format!("failed to read {} {:?}: {}", what, path, e)))
+}
+
+/// 读取并校验证书和私钥，私钥必须和证书匹配
+fn read_identity(cert_path: &Path, key_path: &Path) -> Result<(String, String), KvError> {
+    let cert = read_pem(cert_path, "certificate")?;
+    let certs = match load_certs(&cert) {
+        Ok(certs) if !certs.is_empty() => certs,
+        _ => {
+            return Err(KvError::TlsMaterial(format!(
+                "no PEM certificate found in {:?}",
+                cert_path
+            )))
+        }
+    };
+
+    let key = read_pem(key_path, "private key")?;
+    let private_key = load_key(&key).map_err(|_| {
+        KvError::TlsMaterial(format!(
+            "no PKCS8 or RSA PEM private key found in {:?}",
+            key_path
+        ))
+    })?;
+
+    verify_key_pair(&certs[0], &private_key).map_err(|e| key_mismatch(cert_path, key_path, e))?;
+    Ok((cert, key))
+}
+
+/// 用私钥签名，再用证书的公钥验证签名，以确认两者匹配
+fn verify_key_pair(cert: &Certificate, key: &PrivateKey) -> Result<(), String> {
+    const MESSAGE: &[u8] = b"kvdb key pair check";
+    let schemes = [
+        SignatureScheme::ED25519,
+        SignatureScheme::ECDSA_NISTP256_SHA256,
+        SignatureScheme::ECDSA_NISTP384_SHA384,
+        SignatureScheme::RSA_PKCS1_SHA256,
+    ];
+
+    let signer = sign::any_supported_type(key)
+        .map_err(|_| "unsupported private key".to_string())?
+        .choose_scheme(&schemes)
+        .ok_or("unsupported signature scheme")?;
+    let alg = match signer.get_scheme() {
+        SignatureScheme::ED25519 => &webpki::ED25519,
+        SignatureScheme::ECDSA_NISTP256_SHA256 => &webpki::ECDSA_P256_SHA256,
+        SignatureScheme::ECDSA_NISTP384_SHA384 => &webpki::ECDSA_P384_SHA384,
+        _ => &webpki::RSA_PKCS1_2048_8192_SHA256,
+    };
+    let signature = signer.sign(MESSAGE).map_err(|e| e.to_string())?;
+
+    webpki::EndEntityCert::from(&cert.0)
+        .map_err(|e| format!("bad certificate: {:?}", e))?
+        .verify_signature(alg, MESSAGE, &signature)
+        .map_err(|_| "the signature check failed".to_string())
+}
+
+/// 读取并校验 CA 证书
+fn read_ca(path: &Path) -> Result<String, KvError> {
+    let ca = read_pem(path, "CA certificate")?;
+    let mut store = RootCertStore::empty();
+    match store.add_pem_file(&mut Cursor::new(&ca)) {
+        Ok((valid, _)) if valid > 0 => Ok(ca),
+        _ => Err(KvError::TlsMaterial(format!(
+            "no valid PEM CA certificate found in {:?}",
+            path
+        ))),
+    }
+}
+
+fn key_mismatch(cert_path: &Path, key_path: &Path, e: impl std::fmt::Display) -> KvError {
+    KvError::TlsMaterial(format!(
+        "the private key {:?} does not match the certificate {:?}: {}",
+        key_path, cert_path, e
+    ))
+}
+
 fn load_certs(cert: &str) -> Result<Vec<Certificate>, KvError> {
     let mut cert = Cursor::new(cert);
     pemfile::certs(&mut cert).map_err(|_| KvError::CertificateParseError("server", "cert"))
@@ -189,6 +328,7 @@ pub mod tls_utils {
 mod tests {
     use super::tls_utils::tls_acceptor;
     use crate::network::tls::tls_utils::tls_connector;
+    use crate::{KvError, TlsClientConnector, TlsServerAcceptor};
     use anyhow::Result;
     use std::net::SocketAddr;
     use std::sync::Arc;
@@ -225,6 +365,148 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn tls_from_pem_files_should_work() -> Result<()> {
+        let acceptor = TlsServerAcceptor::from_pem_files(
+            "fixtures/server.cert",
+            "fixtures/server.key",
+            None::<&str>,
+        )?;
+        let connector = TlsClientConnector::from_pem_files(
+            "kvserver.acme.inc",
+            None::<(&str, &str)>,
+            Some("fixtures/ca.cert"),
+        )?;
+
+        let echo = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = echo.local_addr()?;
+        tokio::spawn(async move {
+            let (stream, _) = echo.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+        });
+
+        let mut stream = connector.connect(TcpStream::connect(addr).await?).await?;
+        stream.write_all(b"hello").await?;
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    fn tls_from_bad_pem_files_should_explain() {
+        let err = |res: Result<TlsServerAcceptor, KvError>| match res {
+            Err(KvError::TlsMaterial(msg)) => msg,
+            Err(e) => panic!("unexpected error: {:?}", e),
+            Ok(_) => panic!("bad material should be rejected"),
+        };
+
+        let missing = TlsServerAcceptor::from_pem_files(
+            "fixtures/missing.cert",
+            "fixtures/server.key",
+            None::<&str>,
+        );
+        assert!(err(missing).contains("failed to read certificate \"fixtures/missing.cert\""));
+
+        let bad_pem = TlsServerAcceptor::from_pem_files(
+            "fixtures/server.key",
+            "fixtures/server.key",
+            None::<&str>,
+        );
+        assert!(err(bad_pem).contains("no PEM certificate"));
+
+        let bad_key = TlsServerAcceptor::from_pem_files(
+            "fixtures/server.cert",
+            "fixtures/server.cert",
+            None::<&str>,
+        );
+        assert!(err(bad_key).contains("no PKCS8 or RSA PEM private key"));
+
+        let mismatch = TlsServerAcceptor::from_pem_files(
+            "fixtures/server.cert",
+            "fixtures/client.key",
+            None::<&str>,
+        );
+        assert!(err(mismatch).contains("does not match"));
+    }
+
+    #[tokio::test]
+    async fn tls_with_crl_should_reject_revoked_clients() -> Result<()> {
+        let ca = new_cert("Acme CA", None, true)?;
+        let ca_pem = ca.serialize_pem()?;
+        let server = new_cert("kvserver.acme.inc", None, false)?;
+        let good = new_cert("good client", Some(1), false)?;
+        let revoked = new_cert("revoked client", Some(2), false)?;
+        let crl = new_crl(&ca, 2)?;
+
+        let server_cert = server.serialize_pem_with_signer(&ca)?;
+        let server_key = server.serialize_private_key_pem();
+        let acceptor =
+            TlsServerAcceptor::new(&server_cert, &server_key, Some(&ca_pem))?.with_crls(&[&crl])?;
+
+        let echo = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = echo.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = echo.accept().await {
+                if let Ok(mut stream) = acceptor.accept(stream).await {
+                    let mut buf = [0; 5];
+                    stream.read_exact(&mut buf).await.unwrap();
+                    stream.write_all(&buf).await.unwrap();
+                }
+            }
+        });
+
+        let echo = |client: rcgen::Certificate| {
+            let (cert, key) = (
+                client.serialize_pem_with_signer(&ca).unwrap(),
+                client.serialize_private_key_pem(),
+            );
+            let ca = ca_pem.clone();
+            async move {
+                let connector =
+                    TlsClientConnector::new("kvserver.acme.inc", Some((&cert, &key)), Some(&ca))?;
+                let mut stream = connector.connect(TcpStream::connect(addr).await?).await?;
+                stream.write_all(b"hello").await?;
+                let mut buf = [0; 5];
+                stream.read_exact(&mut buf).await?;
+                Ok::<_, anyhow::Error>(buf)
+            }
+        };
+        assert_eq!(&echo(good).await?, b"hello");
+        assert!(echo(revoked).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn tls_with_bad_crl_should_not_work() -> Result<()> {
+        let ca = new_cert("Acme CA", None, true)?;
+        let ca_pem = ca.serialize_pem()?;
+        let server = new_cert("kvserver.acme.inc", None, false)?;
+        let server_cert = server.serialize_pem_with_signer(&ca)?;
+        let server_key = server.serialize_private_key_pem();
+
+        // 没有 client CA 时无法校验 CRL
+        let no_ca = TlsServerAcceptor::new(&server_cert, &server_key, None)?;
+        let crl = new_crl(&ca, 2)?;
+        assert!(matches!(
+            no_ca.with_crls(&[&crl]),
+            Err(KvError::TlsMaterial(_))
+        ));
+
+        // 不是 client CA 签发的 CRL 被拒绝
+        let other = new_cert("Acme CA", None, true)?;
+        let forged = new_crl(&other, 2)?;
+        let acceptor = TlsServerAcceptor::new(&server_cert, &server_key, Some(&ca_pem))?;
+        match acceptor.with_crls(&[&forged]) {
+            Err(KvError::TlsMaterial(msg)) => assert!(msg.contains("not signed by a client CA")),
+            _ => panic!("a forged CRL should be rejected"),
+        }
+        Ok(())
+    }
+
     #[tokio::test]
     async fn tls_with_bad_domain_should_not_work() -> Result<()> {
         let addr = start_server(false).await?;
@@ -255,4 +537,35 @@ mod tests {
 
         Ok(addr)
     }
+
+    fn new_cert(name: &str, serial: Option<u64>, is_ca: bool) -> Result<rcgen::Certificate> {
+        let mut params = rcgen::CertificateParams::new(vec![name.to_string()]);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, name);
+        params.serial_number = serial.map(Into::into);
+        if is_ca {
+            params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        }
+        Ok(rcgen::Certificate::from_params(params)?)
+    }
+
+    fn new_crl(ca: &rcgen::Certificate, revoked: u64) -> Result<String> {
+        let params = rcgen::CertificateRevocationListParams {
+            this_update: rcgen::date_time_ymd(2024, 1, 1),
+            next_update: rcgen::date_time_ymd(2099, 1, 1),
+            crl_number: rcgen::SerialNumber::from(1),
+            issuing_distribution_point: None,
+            revoked_certs: vec![rcgen::RevokedCertParams {
+                serial_number: rcgen::SerialNumber::from(revoked),
+                revocation_time: rcgen::date_time_ymd(2024, 6, 1),
+                reason_code: Some(rcgen::RevocationReason::KeyCompromise),
+                invalidity_date: None,
+            }],
+            alg: &rcgen::PKCS_ECDSA_P256_SHA256,
+            key_identifier_method: rcgen::KeyIdMethod::Sha256,
+        };
+        let crl = rcgen::CertificateRevocationList::from_params(params)?;
+        Ok(crl.serialize_pem_with_signer(ca)?)
+    }
 }
@@ -1,8 +1,14 @@
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct CommandRequest {
+    /// the priority of the stream carrying this request, 0 is high priority, 1 is bulk
+    #[prost(uint32, tag = "100")]
+    pub priority: u32,
+    /// the compression of the response frames, 0 is gzip above the MTU, 1 is none
+    #[prost(uint32, tag = "101")]
+    pub compression: u32,
     #[prost(
         oneof = "command_request::RequestData",
-        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12"
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69"
     )]
     pub request_data: ::core::option::Option<command_request::RequestData>,
 }
@@ -34,6 +40,120 @@ pub mod command_request {
         Unsubscribe(super::Unsubscribe),
         #[prost(message, tag = "12")]
         Publish(super::Publish),
+        #[prost(message, tag = "13")]
+        Flush(super::Flush),
+        #[prost(message, tag = "14")]
+        Hrandfield(super::Hrandfield),
+        #[prost(message, tag = "15")]
+        Mget(super::Mget),
+        #[prost(message, tag = "16")]
+        Stats(super::Stats),
+        #[prost(message, tag = "17")]
+        StatsReset(super::StatsReset),
+        #[prost(message, tag = "18")]
+        Select(super::Select),
+        #[prost(message, tag = "19")]
+        Batch(super::CommandBatch),
+        #[prost(message, tag = "20")]
+        Hexpire(super::Hexpire),
+        #[prost(message, tag = "21")]
+        Httl(super::Httl),
+        #[prost(message, tag = "22")]
+        Hincr(super::Hincr),
+        #[prost(message, tag = "23")]
+        Hsetnx(super::Hsetnx),
+        #[prost(message, tag = "24")]
+        Hgetdel(super::Hgetdel),
+        #[prost(message, tag = "25")]
+        Hscan(super::Hscan),
+        #[prost(message, tag = "26")]
+        Hkeys(super::Hkeys),
+        #[prost(message, tag = "27")]
+        Hvals(super::Hvals),
+        #[prost(message, tag = "28")]
+        Hlen(super::Hlen),
+        #[prost(message, tag = "29")]
+        Hcleartable(super::Hcleartable),
+        #[prost(message, tag = "30")]
+        Hcopy(super::Hcopy),
+        #[prost(message, tag = "31")]
+        Hmove(super::Hmove),
+        #[prost(message, tag = "32")]
+        Hgetset(super::Hgetset),
+        #[prost(message, tag = "33")]
+        Happend(super::Happend),
+        #[prost(message, tag = "34")]
+        Hgetrange(super::Hgetrange),
+        #[prost(message, tag = "35")]
+        Htype(super::Htype),
+        #[prost(message, tag = "36")]
+        Txn(super::Txn),
+        #[prost(message, tag = "37")]
+        Eval(super::Eval),
+        #[prost(message, tag = "38")]
+        Hsort(super::Hsort),
+        #[prost(message, tag = "39")]
+        Hdump(super::Hdump),
+        #[prost(message, tag = "40")]
+        Hrestore(super::Hrestore),
+        #[prost(message, tag = "41")]
+        Htouch(super::Htouch),
+        #[prost(message, tag = "42")]
+        Hmeta(super::Hmeta),
+        #[prost(message, tag = "43")]
+        Lpush(super::Lpush),
+        #[prost(message, tag = "44")]
+        Rpush(super::Rpush),
+        #[prost(message, tag = "45")]
+        Lpop(super::Lpop),
+        #[prost(message, tag = "46")]
+        Rpop(super::Rpop),
+        #[prost(message, tag = "47")]
+        Lrange(super::Lrange),
+        #[prost(message, tag = "48")]
+        Llen(super::Llen),
+        #[prost(message, tag = "49")]
+        Sadd(super::Sadd),
+        #[prost(message, tag = "50")]
+        Srem(super::Srem),
+        #[prost(message, tag = "51")]
+        Sismember(super::Sismember),
+        #[prost(message, tag = "52")]
+        Smembers(super::Smembers),
+        #[prost(message, tag = "53")]
+        Sunion(super::Sunion),
+        #[prost(message, tag = "54")]
+        Sinter(super::Sinter),
+        #[prost(message, tag = "55")]
+        Zadd(super::Zadd),
+        #[prost(message, tag = "56")]
+        Zrange(super::Zrange),
+        #[prost(message, tag = "57")]
+        Zrangebyscore(super::Zrangebyscore),
+        #[prost(message, tag = "58")]
+        HincrEx(super::HincrEx),
+        #[prost(message, tag = "59")]
+        Export(super::Export),
+        #[prost(message, tag = "60")]
+        Import(super::Import),
+        #[prost(message, tag = "61")]
+        Hdelpattern(super::Hdelpattern),
+        #[prost(message, tag = "62")]
+        Hgetmeta(super::Hgetmeta),
+        #[prost(message, tag = "63")]
+        Hstrlen(super::Hstrlen),
+        #[prost(message, tag = "64")]
+        UseNamespace(super::UseNamespace),
+        #[prost(message, tag = "65")]
+        Hexpireat(super::Hexpireat),
+        #[prost(message, tag = "66")]
+        Hpersist(super::Hpersist),
+        #[prost(message, tag = "67")]
+        Hrange(super::Hrange),
+        #[prost(message, tag = "68")]
+        Backup(super::Backup),
+        #[prost(message, tag = "69")]
+        Restore(super::Restore),
     }
 }
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
@@ -50,6 +170,473 @@ pub struct CommandResponse {
     /// if success, return the key-value pairs
     #[prost(message, repeated, tag = "4")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+    /// the sequence number of a published message, increasing by one per topic
+    #[prost(uint64, tag = "5")]
+    pub message_id: u64,
+    /// the topic a message was published to, wildcard subscriptions get messages of many topics
+    #[prost(string, tag = "6")]
+    pub topic: ::prost::alloc::string::String,
+    /// the responses of the commands of a batch, in order
+    #[prost(message, repeated, tag = "7")]
+    pub responses: ::prost::alloc::vec::Vec<CommandResponse>,
+    /// the cursor of the next page of a scan, empty when the scan is done
+    #[prost(string, tag = "8")]
+    pub cursor: ::prost::alloc::string::String,
+    /// the version of the key read by Hget or written by Hset, 0 if the storage does not track versions
+    #[prost(uint64, tag = "9")]
+    pub version: u64,
+}
+/// expire a key after the given milliseconds, and return whether the key exists
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hexpire {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub ttl_ms: u64,
+}
+/// expire a key at the given milliseconds since the unix epoch, and return whether the key exists
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hexpireat {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub deadline_ms: u64,
+}
+/// make a key never expire, and return whether the key had a deadline
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hpersist {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// get the remaining milliseconds to live of a key, -1 if the key never expires
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Httl {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// add the delta to the integer value of a key, a missing key counts as 0, and return the new value
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hincr {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub delta: i64,
+}
+/// like Hincr, and expire the key after ttl_ms if the increment created it, ttl_ms must not be 0,
+/// the building block of the fixed and sliding window rate limiters
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct HincrEx {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub delta: i64,
+    #[prost(uint64, tag = "4")]
+    pub ttl_ms: u64,
+}
+/// set a key-value pair only if the key does not exist, and return whether it was set
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hsetnx {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub pair: ::core::option::Option<Kvpair>,
+}
+/// get the value of a key and delete the key
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hgetdel {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// get a page of the key-value pairs of a table in key order, resuming after the cursor
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hscan {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    /// the cursor returned with the previous page, empty for the first page
+    #[prost(string, tag = "2")]
+    pub cursor: ::prost::alloc::string::String,
+    /// the number of keys to scan, 10 if 0; fewer pairs are returned if a pattern filters them
+    #[prost(uint32, tag = "3")]
+    pub count: u32,
+    /// only return the keys matching the glob pattern, like `user:*`, all keys if empty
+    #[prost(string, tag = "4")]
+    pub pattern: ::prost::alloc::string::String,
+}
+/// get the key-value pairs of a table in key order, from start (inclusive) to end (exclusive)
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hrange {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    /// the first key of the range, from the first key of the table if empty
+    #[prost(string, tag = "2")]
+    pub start: ::prost::alloc::string::String,
+    /// the end of the range, to the last key of the table if empty
+    #[prost(string, tag = "3")]
+    pub end: ::prost::alloc::string::String,
+    /// the max number of pairs to return, unlimited if 0
+    #[prost(uint32, tag = "4")]
+    pub limit: u32,
+}
+/// get the number of keys of a table
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hlen {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// remove all keys of a table, and return the number of keys removed
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hcleartable {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// copy a key with its ttl to another table, and return whether it was copied
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hcopy {
+    #[prost(string, tag = "1")]
+    pub from_table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub to_table: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub key: ::prost::alloc::string::String,
+    /// overwrite the key if it exists in the destination, the key is not copied otherwise
+    #[prost(bool, tag = "4")]
+    pub replace: bool,
+}
+/// move a key with its ttl to another table, and return whether it was moved
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hmove {
+    #[prost(string, tag = "1")]
+    pub from_table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub to_table: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub key: ::prost::alloc::string::String,
+    /// overwrite the key if it exists in the destination, the key is not moved otherwise
+    #[prost(bool, tag = "4")]
+    pub replace: bool,
+}
+/// set a key-value pair, and return the old value, empty if the key did not exist
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hgetset {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub pair: ::core::option::Option<Kvpair>,
+}
+/// append a String or Binary value to the value of a key, of the same type, and return the new length
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Happend {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "3")]
+    pub value: ::core::option::Option<Value>,
+}
+/// get the bytes from start to end, both inclusive, of a String or Binary value,
+/// the negative offsets count from the end, -1 is the last byte
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hgetrange {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub start: i64,
+    #[prost(int64, tag = "4")]
+    pub end: i64,
+}
+/// get the type of the value of a key: String, Binary, Integer, Float, Bool, List, Set, Zset,
+/// or None if empty
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Htype {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// get the pairs of a table ordered by key, or by value if all the values are numbers,
+/// skipping offset pairs and returning at most limit pairs, 0 for no limit
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hsort {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(bool, tag = "2")]
+    pub by_value: bool,
+    #[prost(bool, tag = "3")]
+    pub desc: bool,
+    #[prost(uint32, tag = "4")]
+    pub offset: u32,
+    #[prost(uint32, tag = "5")]
+    pub limit: u32,
+}
+/// serialize the value and the ttl of a key into an opaque blob, see Hrestore
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hdump {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// re-create a key from the blob of an Hdump, fails if the key exists unless replace is set
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hrestore {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(bytes = "bytes", tag = "3")]
+    pub data: ::prost::bytes::Bytes,
+    #[prost(bool, tag = "4")]
+    pub replace: bool,
+}
+/// the blob of a dumped key
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct DumpPayload {
+    #[prost(uint32, tag = "1")]
+    pub version: u32,
+    #[prost(message, optional, tag = "2")]
+    pub value: ::core::option::Option<Value>,
+    /// the remaining milliseconds to live, 0 if the key never expires
+    #[prost(uint64, tag = "3")]
+    pub ttl_ms: u64,
+}
+/// mark the keys as accessed now without reading them, and return the number of existing keys
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Htouch {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// get the access metadata of a key: last_access_ms since the unix epoch and idle_ms,
+/// both -1 if the storage does not track the accesses
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hmeta {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// push the values to the front of the list of a key, one by one so the last value ends up
+/// first, and return the new length. A missing key is created as an empty list.
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Lpush {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "3")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// push the values to the back of the list of a key, and return the new length
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Rpush {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "3")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// pop at most count values from the front of the list of a key, 1 if count is 0.
+/// The key is deleted once its list is empty.
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Lpop {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "3")]
+    pub count: u32,
+}
+/// pop at most count values from the back of the list of a key, 1 if count is 0
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Rpop {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "3")]
+    pub count: u32,
+}
+/// get the values of the list of a key from start to stop, both inclusive,
+/// the negative offsets count from the end
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Lrange {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub start: i64,
+    #[prost(int64, tag = "4")]
+    pub stop: i64,
+}
+/// get the length of the list of a key, 0 if the key does not exist
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Llen {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// add the members to the set of a key, and return the number of the new members.
+/// A missing key is created as an empty set.
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Sadd {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "3")]
+    pub members: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// remove the members from the set of a key, and return the number of the removed members.
+/// The key is deleted once its set is empty.
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Srem {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "3")]
+    pub members: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// check if a member is in the set of a key
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Sismember {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub member: ::prost::alloc::string::String,
+}
+/// get the members of the set of a key, sorted
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Smembers {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// get the members in any of the sets of the keys, sorted, a missing key is an empty set
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Sunion {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// get the members in all the sets of the keys, sorted, a missing key is an empty set
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Sinter {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// add the members to the sorted set of a key or update their scores, and return the number of
+/// the new members. A missing key is created as an empty sorted set.
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Zadd {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "3")]
+    pub members: ::prost::alloc::vec::Vec<ZsetMember>,
+}
+/// get the members of the sorted set of a key from rank start to stop, both inclusive,
+/// the negative ranks count from the end. The members are returned as pairs with their scores.
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Zrange {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub start: i64,
+    #[prost(int64, tag = "4")]
+    pub stop: i64,
+}
+/// get the members of the sorted set of a key with a score between min and max, both inclusive,
+/// skipping offset members and returning at most limit members, 0 for no limit
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Zrangebyscore {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(double, tag = "3")]
+    pub min: f64,
+    #[prost(double, tag = "4")]
+    pub max: f64,
+    #[prost(uint32, tag = "5")]
+    pub offset: u32,
+    #[prost(uint32, tag = "6")]
+    pub limit: u32,
+}
+/// stream the pairs of a table with a key starting with the prefix, in key order.
+/// The first frame is an empty header, each following frame carries at most chunk_size pairs,
+/// 100 if it is 0.
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Export {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub prefix: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "3")]
+    pub chunk_size: u32,
+}
+/// set a chunk of pairs of a bulk load in one storage batch, and return the number of
+/// pairs set. Unlike Hmset the old values are not returned.
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Import {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "2")]
+    pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+}
+/// delete the keys of a table matching a glob pattern, and return the number of deleted keys
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hdelpattern {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub pattern: ::prost::alloc::string::String,
+}
+/// get the value of a key with its metadata: the version, ttl_ms (-1 if the key never expires),
+/// size (the bytes of the encoded value) and modified_ms since the unix epoch (-1 if not tracked)
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hgetmeta {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// get the size in bytes of the encoded value of a key without the value, 0 for a missing key
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hstrlen {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
 }
 /// get a key-value pair from the given table
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
@@ -59,12 +646,24 @@ pub struct Hget {
     #[prost(string, tag = "2")]
     pub key: ::prost::alloc::string::String,
 }
-/// get all key-value pairs of the given table
+/// get all key-value pairs of the given table, sorted by key
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Hgetall {
     #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
 }
+/// get all keys of the given table, sorted
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hkeys {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// get all values of the given table, in the order of their keys
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hvals {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
 /// get multiple keys from the given table
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Hmget {
@@ -80,6 +679,18 @@ pub struct Hset {
     pub table: ::prost::alloc::string::String,
     #[prost(message, optional, tag = "2")]
     pub pair: ::core::option::Option<Kvpair>,
+    /// only set the key if it does not exist
+    #[prost(bool, tag = "3")]
+    pub nx: bool,
+    /// only set the key if it exists
+    #[prost(bool, tag = "4")]
+    pub xx: bool,
+    /// keep the deadline of the key, which is cleared otherwise
+    #[prost(bool, tag = "5")]
+    pub keep_ttl: bool,
+    /// only set the key if it exists with the given version, 0 to set it whatever its version
+    #[prost(uint64, tag = "6")]
+    pub if_version: u64,
 }
 /// set multiple key-value pairs
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
@@ -123,7 +734,10 @@ pub struct Hmexist {
 }
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Value {
-    #[prost(oneof = "value::Value", tags = "1, 2, 3, 4, 5")]
+    /// how the client encrypted the value, 0 is plaintext, 1 is XChaCha20-Poly1305 over the encoded value
+    #[prost(uint32, tag = "6")]
+    pub encryption: u32,
+    #[prost(oneof = "value::Value", tags = "1, 2, 3, 4, 5, 7, 8, 9")]
     pub value: ::core::option::Option<value::Value>,
 }
 /// Nested message and enum types in `Value`.
@@ -140,8 +754,40 @@ pub mod value {
         Float(f64),
         #[prost(bool, tag = "5")]
         Bool(bool),
+        #[prost(message, tag = "7")]
+        List(super::ValueList),
+        #[prost(message, tag = "8")]
+        Set(super::ValueSet),
+        #[prost(message, tag = "9")]
+        Zset(super::ValueZset),
     }
 }
+/// the values of a list, stored as one value, see Lpush
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct ValueList {
+    #[prost(message, repeated, tag = "1")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// the members of a set in order, stored as one value, see Sadd
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct ValueSet {
+    #[prost(string, repeated, tag = "1")]
+    pub members: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// a member of a sorted set with its score
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct ZsetMember {
+    #[prost(string, tag = "1")]
+    pub member: ::prost::alloc::string::String,
+    #[prost(double, tag = "2")]
+    pub score: f64,
+}
+/// the members of a sorted set ordered by score then member, stored as one value, see Zadd
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct ValueZset {
+    #[prost(message, repeated, tag = "1")]
+    pub members: ::prost::alloc::vec::Vec<ZsetMember>,
+}
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Kvpair {
     #[prost(string, tag = "1")]
@@ -149,17 +795,20 @@ pub struct Kvpair {
     #[prost(message, optional, tag = "2")]
     pub value: ::core::option::Option<Value>,
 }
+/// subscribe to a topic, and replay the retained messages after `resume_after` if it is not 0
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Subscribe {
     #[prost(string, tag = "1")]
     pub topic: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "2")]
+    pub resume_after: u64,
 }
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Unsubscribe {
     #[prost(string, tag = "1")]
     pub topic: ::prost::alloc::string::String,
-    #[prost(uint32, tag = "2")]
-    pub id: u32,
+    #[prost(uint64, tag = "2")]
+    pub id: u64,
 }
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Publish {
@@ -168,3 +817,106 @@ pub struct Publish {
     #[prost(message, repeated, tag = "2")]
     pub values: ::prost::alloc::vec::Vec<Value>,
 }
+/// get random keys (and values) of the given table, a positive count returns distinct keys,
+/// a negative count may return the same key multiple times, at most 10000 keys are returned
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hrandfield {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(int64, tag = "2")]
+    pub count: i64,
+    #[prost(bool, tag = "3")]
+    pub with_values: bool,
+}
+/// flush the pending writes of the storage to disk, an admin command
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Flush {}
+/// get the cumulative counters of the server, persisted across restarts, then the keys and the
+/// bytes of each table and the figures of the storage backend, an admin command
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Stats {}
+/// zero the cumulative counters, an admin command
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct StatsReset {}
+/// write a snapshot of the tables of the namespace and the database of the connection to a file
+/// in the backup directory of the server, replacing the file, and return the number of pairs
+/// written. The tables are named as the connection sees them, so a backup could be restored
+/// into another database. The file is a sequence of length-delimited Hset messages, or of JSON
+/// lines with ndjson. An admin command.
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Backup {
+    /// the name of the file, without any directory
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    /// write one {"table", "key", "value"} JSON object per line, see kvdb::write_ndjson
+    #[prost(bool, tag = "2")]
+    pub ndjson: bool,
+    /// only write the pairs of this table of the database of the connection, all if empty
+    #[prost(string, tag = "3")]
+    pub table: ::prost::alloc::string::String,
+}
+/// write the pairs of a backup file in the backup directory of the server to the tables of the
+/// namespace and the database of the connection, overwriting the existing keys, and return the
+/// number of pairs restored. The reserved table names are rejected. An admin command.
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Restore {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    /// the file holds JSON lines written by a Backup with ndjson
+    #[prost(bool, tag = "2")]
+    pub ndjson: bool,
+}
+/// select the logical database of the connection, the tables of a database are isolated
+/// from the others, the connections start on database 0
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Select {
+    #[prost(uint32, tag = "1")]
+    pub db: u32,
+}
+/// use a namespace for the following commands of the connection, the tables of a namespace are
+/// isolated from the other namespaces, each with its own databases. The connections start on the
+/// default namespace, the empty one. A name is made of ascii letters, digits, '_' and '-'.
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct UseNamespace {
+    #[prost(string, tag = "1")]
+    pub namespace: ::prost::alloc::string::String,
+}
+/// unary commands carried by one frame and executed in order, the responses are returned
+/// in one frame, a failed command does not stop the following ones
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct CommandBatch {
+    #[prost(message, repeated, tag = "1")]
+    pub requests: ::prost::alloc::vec::Vec<CommandRequest>,
+}
+/// Hset, Hmset and Hdel commands applied atomically in order, all or none,
+/// the responses are returned in one frame
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Txn {
+    #[prost(message, repeated, tag = "1")]
+    pub requests: ::prost::alloc::vec::Vec<CommandRequest>,
+}
+/// run a script on the server, its get, set and del calls are applied atomically,
+/// the writes are committed only if the script succeeds
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Eval {
+    #[prost(string, tag = "1")]
+    pub script: ::prost::alloc::string::String,
+    /// the arguments of the script, bound to ARGS
+    #[prost(message, repeated, tag = "2")]
+    pub args: ::prost::alloc::vec::Vec<Value>,
+}
+/// a key of a table
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct TableKey {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// get keys across tables, the values are returned in the order of the keys,
+/// a missing key gets an empty value
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Mget {
+    #[prost(message, repeated, tag = "1")]
+    pub keys: ::prost::alloc::vec::Vec<TableKey>,
+}
@@ -1,36 +1,672 @@
 mod abi;
 
+use std::collections::BTreeSet;
+
 pub use abi::{command_request::RequestData, *};
 use bytes::Bytes;
 use http::StatusCode;
 use prost::Message;
 
-use crate::KvError;
+use crate::KvError;
+
+impl CommandRequest {
+    pub fn new_hset(table: impl Into<String>, key: impl Into<String>, value: Value) -> Self {
+        Self {
+            request_data: Some(RequestData::Hset(Hset {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hget(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hget(Hget {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hgetall(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hgetall(Hgetall {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hkeys(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hkeys(Hkeys {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hvals(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hvals(Hvals {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hlen(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hlen(Hlen {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hcleartable(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hcleartable(Hcleartable {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hcopy(
+        from_table: impl Into<String>,
+        to_table: impl Into<String>,
+        key: impl Into<String>,
+        replace: bool,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hcopy(Hcopy {
+                from_table: from_table.into(),
+                to_table: to_table.into(),
+                key: key.into(),
+                replace,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hmove(
+        from_table: impl Into<String>,
+        to_table: impl Into<String>,
+        key: impl Into<String>,
+        replace: bool,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmove(Hmove {
+                from_table: from_table.into(),
+                to_table: to_table.into(),
+                key: key.into(),
+                replace,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hmget(
+        table: impl Into<String>,
+        keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmget(Hmget {
+                table: table.into(),
+                keys: keys.into_iter().map(Into::into).collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hmset(table: impl Into<String>, pairs: Vec<Kvpair>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmset(Hmset {
+                table: table.into(),
+                pairs,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hdel(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hdel(Hdel {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hmdel(
+        table: impl Into<String>,
+        keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmdel(Hmdel {
+                table: table.into(),
+                keys: keys.into_iter().map(Into::into).collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hmexist(
+        table: impl Into<String>,
+        keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmexist(Hmexist {
+                table: table.into(),
+                keys: keys.into_iter().map(Into::into).collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hexpire(table: impl Into<String>, key: impl Into<String>, ttl_ms: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hexpire(Hexpire {
+                table: table.into(),
+                key: key.into(),
+                ttl_ms,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hexpireat(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        deadline_ms: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hexpireat(Hexpireat {
+                table: table.into(),
+                key: key.into(),
+                deadline_ms,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hpersist(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hpersist(Hpersist {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_httl(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Httl(Httl {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hincr(table: impl Into<String>, key: impl Into<String>, delta: i64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hincr(Hincr {
+                table: table.into(),
+                key: key.into(),
+                delta,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hincr_ex(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        delta: i64,
+        ttl_ms: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::HincrEx(HincrEx {
+                table: table.into(),
+                key: key.into(),
+                delta,
+                ttl_ms,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hsetnx(table: impl Into<String>, key: impl Into<String>, value: Value) -> Self {
+        Self {
+            request_data: Some(RequestData::Hsetnx(Hsetnx {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hgetdel(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hgetdel(Hgetdel {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hscan(
+        table: impl Into<String>,
+        cursor: impl Into<String>,
+        count: u32,
+        pattern: impl Into<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hscan(Hscan {
+                table: table.into(),
+                cursor: cursor.into(),
+                count,
+                pattern: pattern.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hgetset(table: impl Into<String>, key: impl Into<String>, value: Value) -> Self {
+        Self {
+            request_data: Some(RequestData::Hgetset(Hgetset {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_happend(table: impl Into<String>, key: impl Into<String>, value: Value) -> Self {
+        Self {
+            request_data: Some(RequestData::Happend(Happend {
+                table: table.into(),
+                key: key.into(),
+                value: Some(value),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hgetrange(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        start: i64,
+        end: i64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hgetrange(Hgetrange {
+                table: table.into(),
+                key: key.into(),
+                start,
+                end,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_htype(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Htype(Htype {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hsort(table: impl Into<String>, offset: u32, limit: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Hsort(Hsort {
+                table: table.into(),
+                offset,
+                limit,
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hdump(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hdump(Hdump {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hrestore(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        data: Bytes,
+        replace: bool,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hrestore(Hrestore {
+                table: table.into(),
+                key: key.into(),
+                data,
+                replace,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_htouch(
+        table: impl Into<String>,
+        keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Htouch(Htouch {
+                table: table.into(),
+                keys: keys.into_iter().map(Into::into).collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hmeta(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmeta(Hmeta {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_lpush(table: impl Into<String>, key: impl Into<String>, values: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Lpush(Lpush {
+                table: table.into(),
+                key: key.into(),
+                values,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_rpush(table: impl Into<String>, key: impl Into<String>, values: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Rpush(Rpush {
+                table: table.into(),
+                key: key.into(),
+                values,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_lpop(table: impl Into<String>, key: impl Into<String>, count: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Lpop(Lpop {
+                table: table.into(),
+                key: key.into(),
+                count,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_rpop(table: impl Into<String>, key: impl Into<String>, count: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Rpop(Rpop {
+                table: table.into(),
+                key: key.into(),
+                count,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_lrange(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        start: i64,
+        stop: i64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Lrange(Lrange {
+                table: table.into(),
+                key: key.into(),
+                start,
+                stop,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_llen(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Llen(Llen {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_sadd(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        members: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Sadd(Sadd {
+                table: table.into(),
+                key: key.into(),
+                members: members.into_iter().map(Into::into).collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_srem(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        members: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Srem(Srem {
+                table: table.into(),
+                key: key.into(),
+                members: members.into_iter().map(Into::into).collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_sismember(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        member: impl Into<String>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Sismember(Sismember {
+                table: table.into(),
+                key: key.into(),
+                member: member.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_smembers(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Smembers(Smembers {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_sunion(
+        table: impl Into<String>,
+        keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Sunion(Sunion {
+                table: table.into(),
+                keys: keys.into_iter().map(Into::into).collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_sinter(
+        table: impl Into<String>,
+        keys: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Sinter(Sinter {
+                table: table.into(),
+                keys: keys.into_iter().map(Into::into).collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_zadd(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        members: impl IntoIterator<Item = (impl Into<String>, f64)>,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zadd(Zadd {
+                table: table.into(),
+                key: key.into(),
+                members: members
+                    .into_iter()
+                    .map(|(member, score)| ZsetMember::new(member, score))
+                    .collect(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_zrange(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        start: i64,
+        stop: i64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrange(Zrange {
+                table: table.into(),
+                key: key.into(),
+                start,
+                stop,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_zrangebyscore(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        min: f64,
+        max: f64,
+        offset: u32,
+        limit: u32,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrangebyscore(Zrangebyscore {
+                table: table.into(),
+                key: key.into(),
+                min,
+                max,
+                offset,
+                limit,
+            })),
+            ..Default::default()
+        }
+    }
 
-impl CommandRequest {
-    pub fn new_hset(table: impl Into<String>, key: impl Into<String>, value: Value) -> Self {
+    pub fn new_export(
+        table: impl Into<String>,
+        prefix: impl Into<String>,
+        chunk_size: u32,
+    ) -> Self {
         Self {
-            request_data: Some(RequestData::Hset(Hset {
+            request_data: Some(RequestData::Export(Export {
                 table: table.into(),
-                pair: Some(Kvpair::new(key, value)),
+                prefix: prefix.into(),
+                chunk_size,
             })),
+            ..Default::default()
         }
     }
 
-    pub fn new_hget(table: impl Into<String>, key: impl Into<String>) -> Self {
+    pub fn new_import(table: impl Into<String>, pairs: Vec<Kvpair>) -> Self {
         Self {
-            request_data: Some(RequestData::Hget(Hget {
+            request_data: Some(RequestData::Import(Import {
+                table: table.into(),
+                pairs,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hdelpattern(table: impl Into<String>, pattern: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hdelpattern(Hdelpattern {
+                table: table.into(),
+                pattern: pattern.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hgetmeta(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hgetmeta(Hgetmeta {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
         }
     }
 
-    pub fn new_hgetall(table: impl Into<String>) -> Self {
+    pub fn new_hstrlen(table: impl Into<String>, key: impl Into<String>) -> Self {
         Self {
-            request_data: Some(RequestData::Hgetall(Hgetall {
+            request_data: Some(RequestData::Hstrlen(Hstrlen {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hrange(
+        table: impl Into<String>,
+        start: impl Into<String>,
+        end: impl Into<String>,
+        limit: u32,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hrange(Hrange {
                 table: table.into(),
+                start: start.into(),
+                end: end.into(),
+                limit,
             })),
+            ..Default::default()
         }
     }
 
@@ -40,6 +676,7 @@ impl CommandRequest {
                 topic: topic.into(),
                 values,
             })),
+            ..Default::default()
         }
     }
 
@@ -47,17 +684,207 @@ impl CommandRequest {
         Self {
             request_data: Some(RequestData::Subscribe(Subscribe {
                 topic: topic.into(),
+                resume_after: 0,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Subscribe to a topic, and receive the retained messages published after the given message id
+    pub fn new_resume(topic: impl Into<String>, after: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Subscribe(Subscribe {
+                topic: topic.into(),
+                resume_after: after,
             })),
+            ..Default::default()
         }
     }
 
-    pub fn new_unsubscribe(topic: impl Into<String>, id: u32) -> Self {
+    pub fn new_unsubscribe(topic: impl Into<String>, id: u64) -> Self {
         Self {
             request_data: Some(RequestData::Unsubscribe(Unsubscribe {
                 topic: topic.into(),
                 id,
             })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_hrandfield(table: impl Into<String>, count: i64, with_values: bool) -> Self {
+        Self {
+            request_data: Some(RequestData::Hrandfield(Hrandfield {
+                table: table.into(),
+                count,
+                with_values,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_mget<T, K>(keys: impl IntoIterator<Item = (T, K)>) -> Self
+    where
+        T: Into<String>,
+        K: Into<String>,
+    {
+        let keys = keys
+            .into_iter()
+            .map(|(table, key)| TableKey {
+                table: table.into(),
+                key: key.into(),
+            })
+            .collect();
+        Self {
+            request_data: Some(RequestData::Mget(Mget { keys })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_flush() -> Self {
+        Self {
+            request_data: Some(RequestData::Flush(Flush {})),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_stats() -> Self {
+        Self {
+            request_data: Some(RequestData::Stats(Stats {})),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_stats_reset() -> Self {
+        Self {
+            request_data: Some(RequestData::StatsReset(StatsReset {})),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_backup(name: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Backup(Backup {
+                name: name.into(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Back up a table, or all tables if empty, as JSON lines
+    pub fn new_backup_ndjson(name: impl Into<String>, table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Backup(Backup {
+                name: name.into(),
+                ndjson: true,
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_restore(name: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Restore(Restore {
+                name: name.into(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_restore_ndjson(name: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Restore(Restore {
+                name: name.into(),
+                ndjson: true,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_batch(requests: Vec<CommandRequest>) -> Self {
+        Self {
+            request_data: Some(RequestData::Batch(CommandBatch { requests })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_txn(requests: Vec<CommandRequest>) -> Self {
+        Self {
+            request_data: Some(RequestData::Txn(Txn { requests })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_eval(script: impl Into<String>, args: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Eval(Eval {
+                script: script.into(),
+                args,
+            })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_select(db: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Select(Select { db })),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_use_namespace(namespace: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::UseNamespace(UseNamespace {
+                namespace: namespace.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Make an Hset only write if the key does not exist, ignored by the other commands
+    pub fn nx(self) -> Self {
+        self.with_hset(|req| req.nx = true)
+    }
+
+    /// Make an Hset only write if the key exists, ignored by the other commands
+    pub fn xx(self) -> Self {
+        self.with_hset(|req| req.xx = true)
+    }
+
+    /// Make an Hset keep the deadline of the key, ignored by the other commands
+    pub fn keep_ttl(self) -> Self {
+        self.with_hset(|req| req.keep_ttl = true)
+    }
+
+    /// Make an Hset only write if the key has the given version, ignored by the other commands
+    pub fn if_version(self, version: u64) -> Self {
+        self.with_hset(|req| req.if_version = version)
+    }
+
+    /// Make an Hsort order the pairs by their numeric values, ignored by the other commands
+    pub fn by_value(self) -> Self {
+        self.with_hsort(|req| req.by_value = true)
+    }
+
+    /// Make an Hsort order the pairs in descending order, ignored by the other commands
+    pub fn desc(self) -> Self {
+        self.with_hsort(|req| req.desc = true)
+    }
+
+    fn with_hsort(mut self, f: impl FnOnce(&mut Hsort)) -> Self {
+        if let Some(RequestData::Hsort(req)) = &mut self.request_data {
+            f(req);
         }
+        self
+    }
+
+    fn with_hset(mut self, f: impl FnOnce(&mut Hset)) -> Self {
+        if let Some(RequestData::Hset(req)) = &mut self.request_data {
+            f(req);
+        }
+        self
     }
 }
 
@@ -74,6 +901,7 @@ impl From<String> for Value {
     fn from(s: String) -> Self {
         Self {
             value: Some(value::Value::String(s)),
+            ..Default::default()
         }
     }
 }
@@ -82,6 +910,7 @@ impl From<&str> for Value {
     fn from(s: &str) -> Self {
         Self {
             value: Some(value::Value::String(s.to_string())),
+            ..Default::default()
         }
     }
 }
@@ -90,6 +919,25 @@ impl From<i64> for Value {
     fn from(i: i64) -> Self {
         Self {
             value: Some(value::Value::Integer(i)),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Self {
+            value: Some(value::Value::Bool(b)),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Self {
+            value: Some(value::Value::Float(f)),
+            ..Default::default()
         }
     }
 }
@@ -127,14 +975,34 @@ impl From<KvError> for CommandResponse {
         let mut res = Self {
             status: StatusCode::INTERNAL_SERVER_ERROR.as_u16() as u32,
             message: e.to_string(),
-            values: vec![],
-            pairs: vec![],
+            ..Default::default()
         };
 
         match e {
             KvError::NotFound(_) => res.status = StatusCode::NOT_FOUND.as_u16() as u32,
-            KvError::InvalidCommand(_) => res.status = StatusCode::BAD_REQUEST.as_u16() as u32,
+            KvError::PermissionDenied(_) => res.status = StatusCode::FORBIDDEN.as_u16() as u32,
+            KvError::TooLarge(_)
+            | KvError::FrameTooLarge
+            | KvError::KeyTooLong(..)
+            | KvError::ValueTooLarge(..) => {
+                res.status = StatusCode::PAYLOAD_TOO_LARGE.as_u16() as u32
+            }
+            KvError::QuotaExceeded(_) | KvError::Throttled(_) => {
+                res.status = StatusCode::TOO_MANY_REQUESTS.as_u16() as u32
+            }
+            KvError::ConditionNotMet(_) => {
+                res.status = StatusCode::PRECONDITION_FAILED.as_u16() as u32
+            }
+            KvError::InsufficientStorage(_) => {
+                res.status = StatusCode::INSUFFICIENT_STORAGE.as_u16() as u32
+            }
+            KvError::InvalidCommand(_) | KvError::InvalidTableName(_) => {
+                res.status = StatusCode::BAD_REQUEST.as_u16() as u32
+            }
             KvError::ConvertCommand(_, _) => res.status = StatusCode::BAD_REQUEST.as_u16() as u32,
+            KvError::DecodeError(_) | KvError::InvalidFrame(_) | KvError::JsonError(_) => {
+                res.status = StatusCode::BAD_REQUEST.as_u16() as u32
+            }
             _ => (),
         }
         res
@@ -157,6 +1025,7 @@ impl From<Bytes> for Value {
     fn from(data: Bytes) -> Self {
         Self {
             value: Some(value::Value::Binary(data)),
+            ..Default::default()
         }
     }
 }
@@ -219,6 +1088,78 @@ impl TryFrom<Value> for Bytes {
     }
 }
 
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        Self {
+            value: Some(value::Value::List(ValueList { values })),
+            ..Default::default()
+        }
+    }
+}
+
+impl TryFrom<&Value> for Vec<Value> {
+    type Error = KvError;
+
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match &v.value {
+            Some(value::Value::List(list)) => Ok(list.values.clone()),
+            _ => Err(KvError::ConvertCommand(v.format(), "List")),
+        }
+    }
+}
+
+impl From<BTreeSet<String>> for Value {
+    fn from(members: BTreeSet<String>) -> Self {
+        Self {
+            value: Some(value::Value::Set(ValueSet {
+                members: members.into_iter().collect(),
+            })),
+            ..Default::default()
+        }
+    }
+}
+
+impl TryFrom<&Value> for BTreeSet<String> {
+    type Error = KvError;
+
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match &v.value {
+            Some(value::Value::Set(set)) => Ok(set.members.iter().cloned().collect()),
+            _ => Err(KvError::ConvertCommand(v.format(), "Set")),
+        }
+    }
+}
+
+impl ZsetMember {
+    pub fn new(member: impl Into<String>, score: f64) -> Self {
+        Self {
+            member: member.into(),
+            score,
+        }
+    }
+}
+
+/// The members must be ordered by score then member
+impl From<Vec<ZsetMember>> for Value {
+    fn from(members: Vec<ZsetMember>) -> Self {
+        Self {
+            value: Some(value::Value::Zset(ValueZset { members })),
+            ..Default::default()
+        }
+    }
+}
+
+impl TryFrom<&Value> for Vec<ZsetMember> {
+    type Error = KvError;
+
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match &v.value {
+            Some(value::Value::Zset(zset)) => Ok(zset.members.clone()),
+            _ => Err(KvError::ConvertCommand(v.format(), "Zset")),
+        }
+    }
+}
+
 impl TryFrom<Value> for bool {
     type Error = KvError;
 
@@ -243,6 +1184,288 @@ impl CommandRequest {
     pub fn format(&self) -> String {
         format!("{:?}", self)
     }
+
+    /// The name of the command, used in logs and metrics
+    pub fn name(&self) -> &'static str {
+        match &self.request_data {
+            Some(RequestData::Hget(_)) => "hget",
+            Some(RequestData::Hgetall(_)) => "hgetall",
+            Some(RequestData::Hkeys(_)) => "hkeys",
+            Some(RequestData::Hvals(_)) => "hvals",
+            Some(RequestData::Hlen(_)) => "hlen",
+            Some(RequestData::Hcleartable(_)) => "hcleartable",
+            Some(RequestData::Hcopy(_)) => "hcopy",
+            Some(RequestData::Hmove(_)) => "hmove",
+            Some(RequestData::Hgetset(_)) => "hgetset",
+            Some(RequestData::Happend(_)) => "happend",
+            Some(RequestData::Hgetrange(_)) => "hgetrange",
+            Some(RequestData::Htype(_)) => "htype",
+            Some(RequestData::Hsort(_)) => "hsort",
+            Some(RequestData::Hdump(_)) => "hdump",
+            Some(RequestData::Hrestore(_)) => "hrestore",
+            Some(RequestData::Htouch(_)) => "htouch",
+            Some(RequestData::Hmeta(_)) => "hmeta",
+            Some(RequestData::Lpush(_)) => "lpush",
+            Some(RequestData::Rpush(_)) => "rpush",
+            Some(RequestData::Lpop(_)) => "lpop",
+            Some(RequestData::Rpop(_)) => "rpop",
+            Some(RequestData::Lrange(_)) => "lrange",
+            Some(RequestData::Llen(_)) => "llen",
+            Some(RequestData::Sadd(_)) => "sadd",
+            Some(RequestData::Srem(_)) => "srem",
+            Some(RequestData::Sismember(_)) => "sismember",
+            Some(RequestData::Smembers(_)) => "smembers",
+            Some(RequestData::Sunion(_)) => "sunion",
+            Some(RequestData::Sinter(_)) => "sinter",
+            Some(RequestData::Zadd(_)) => "zadd",
+            Some(RequestData::Zrange(_)) => "zrange",
+            Some(RequestData::Zrangebyscore(_)) => "zrangebyscore",
+            Some(RequestData::Hmget(_)) => "hmget",
+            Some(RequestData::Hset(_)) => "hset",
+            Some(RequestData::Hmset(_)) => "hmset",
+            Some(RequestData::Hdel(_)) => "hdel",
+            Some(RequestData::Hmdel(_)) => "hmdel",
+            Some(RequestData::Hexist(_)) => "hexist",
+            Some(RequestData::Hmexist(_)) => "hmexist",
+            Some(RequestData::Subscribe(_)) => "subscribe",
+            Some(RequestData::Unsubscribe(_)) => "unsubscribe",
+            Some(RequestData::Publish(_)) => "publish",
+            Some(RequestData::Flush(_)) => "flush",
+            Some(RequestData::Hrandfield(_)) => "hrandfield",
+            Some(RequestData::Mget(_)) => "mget",
+            Some(RequestData::Stats(_)) => "stats",
+            Some(RequestData::StatsReset(_)) => "stats_reset",
+            Some(RequestData::Backup(_)) => "backup",
+            Some(RequestData::Restore(_)) => "restore",
+            Some(RequestData::Select(_)) => "select",
+            Some(RequestData::UseNamespace(_)) => "use_namespace",
+            Some(RequestData::Batch(_)) => "batch",
+            Some(RequestData::Txn(_)) => "txn",
+            Some(RequestData::Eval(_)) => "eval",
+            Some(RequestData::Hexpire(_)) => "hexpire",
+            Some(RequestData::Hexpireat(_)) => "hexpireat",
+            Some(RequestData::Hpersist(_)) => "hpersist",
+            Some(RequestData::Hrange(_)) => "hrange",
+            Some(RequestData::Httl(_)) => "httl",
+            Some(RequestData::Hincr(_)) => "hincr",
+            Some(RequestData::HincrEx(_)) => "hincr_ex",
+            Some(RequestData::Export(_)) => "export",
+            Some(RequestData::Import(_)) => "import",
+            Some(RequestData::Hdelpattern(_)) => "hdelpattern",
+            Some(RequestData::Hgetmeta(_)) => "hgetmeta",
+            Some(RequestData::Hstrlen(_)) => "hstrlen",
+            Some(RequestData::Hsetnx(_)) => "hsetnx",
+            Some(RequestData::Hgetdel(_)) => "hgetdel",
+            Some(RequestData::Hscan(_)) => "hscan",
+            None => "none",
+        }
+    }
+
+    /// The table and the key the command operates on, the topic is taken as the table
+    pub fn target(&self) -> (Option<&str>, Option<&str>) {
+        match &self.request_data {
+            Some(RequestData::Hget(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Hgetall(v)) => (Some(&v.table), None),
+            Some(RequestData::Hkeys(v)) => (Some(&v.table), None),
+            Some(RequestData::Hvals(v)) => (Some(&v.table), None),
+            Some(RequestData::Hlen(v)) => (Some(&v.table), None),
+            Some(RequestData::Hcleartable(v)) => (Some(&v.table), None),
+            Some(RequestData::Hcopy(v)) => (Some(&v.from_table), Some(&v.key)),
+            Some(RequestData::Hmove(v)) => (Some(&v.from_table), Some(&v.key)),
+            Some(RequestData::Happend(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Hgetrange(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Htype(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Hsort(v)) => (Some(&v.table), None),
+            Some(RequestData::Hdump(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Hrestore(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Htouch(v)) => (Some(&v.table), None),
+            Some(RequestData::Hmeta(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Lpush(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Rpush(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Lpop(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Rpop(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Lrange(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Llen(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Sadd(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Srem(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Sismember(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Smembers(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Sunion(v)) => (Some(&v.table), None),
+            Some(RequestData::Sinter(v)) => (Some(&v.table), None),
+            Some(RequestData::Zadd(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Zrange(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Zrangebyscore(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Hgetset(v)) => {
+                (Some(&v.table), v.pair.as_ref().map(|p| p.key.as_str()))
+            }
+            Some(RequestData::Hmget(v)) => (Some(&v.table), None),
+            Some(RequestData::Hset(v)) => (Some(&v.table), v.pair.as_ref().map(|p| p.key.as_str())),
+            Some(RequestData::Hmset(v)) => (Some(&v.table), None),
+            Some(RequestData::Hdel(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Hmdel(v)) => (Some(&v.table), None),
+            Some(RequestData::Hexist(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Hmexist(v)) => (Some(&v.table), None),
+            Some(RequestData::Subscribe(v)) => (Some(&v.topic), None),
+            Some(RequestData::Unsubscribe(v)) => (Some(&v.topic), None),
+            Some(RequestData::Publish(v)) => (Some(&v.topic), None),
+            Some(RequestData::Hrandfield(v)) => (Some(&v.table), None),
+            Some(RequestData::Hscan(v)) => (Some(&v.table), None),
+            Some(RequestData::Hexpire(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Hexpireat(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Hpersist(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Hrange(v)) => (Some(&v.table), None),
+            Some(RequestData::Httl(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Hincr(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::HincrEx(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Export(v)) => (Some(&v.table), None),
+            Some(RequestData::Import(v)) => (Some(&v.table), None),
+            Some(RequestData::Hdelpattern(v)) => (Some(&v.table), None),
+            Some(RequestData::Hgetmeta(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Hstrlen(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Hgetdel(v)) => (Some(&v.table), Some(&v.key)),
+            Some(RequestData::Hsetnx(v)) => {
+                (Some(&v.table), v.pair.as_ref().map(|p| p.key.as_str()))
+            }
+            Some(RequestData::Mget(_))
+            | Some(RequestData::Flush(_))
+            | Some(RequestData::Stats(_))
+            | Some(RequestData::StatsReset(_))
+            | Some(RequestData::Backup(_))
+            | Some(RequestData::Restore(_))
+            | Some(RequestData::Select(_))
+            | Some(RequestData::UseNamespace(_))
+            | Some(RequestData::Batch(_))
+            | Some(RequestData::Txn(_))
+            | Some(RequestData::Eval(_))
+            | None => (None, None),
+        }
+    }
+
+    /// Qualify the tables of the command with the namespace and the logical database,
+    /// see `scoped_table`
+    pub(crate) fn select_scope(&mut self, namespace: &str, db: u32) {
+        if namespace.is_empty() && db == 0 {
+            return;
+        }
+        self.for_each_table(&mut |table| *table = scoped_table(namespace, db, table));
+    }
+
+    /// Visit the tables of the command, including the ones of the commands of a Txn
+    pub(crate) fn for_each_table(&mut self, f: &mut impl FnMut(&mut String)) {
+        match &mut self.request_data {
+            Some(RequestData::Hget(v)) => f(&mut v.table),
+            Some(RequestData::Hgetall(v)) => f(&mut v.table),
+            Some(RequestData::Hkeys(v)) => f(&mut v.table),
+            Some(RequestData::Hvals(v)) => f(&mut v.table),
+            Some(RequestData::Hlen(v)) => f(&mut v.table),
+            Some(RequestData::Hcleartable(v)) => f(&mut v.table),
+            Some(RequestData::Hgetset(v)) => f(&mut v.table),
+            Some(RequestData::Happend(v)) => f(&mut v.table),
+            Some(RequestData::Hgetrange(v)) => f(&mut v.table),
+            Some(RequestData::Htype(v)) => f(&mut v.table),
+            Some(RequestData::Hsort(v)) => f(&mut v.table),
+            Some(RequestData::Hdump(v)) => f(&mut v.table),
+            Some(RequestData::Hrestore(v)) => f(&mut v.table),
+            Some(RequestData::Htouch(v)) => f(&mut v.table),
+            Some(RequestData::Hmeta(v)) => f(&mut v.table),
+            Some(RequestData::Lpush(v)) => f(&mut v.table),
+            Some(RequestData::Rpush(v)) => f(&mut v.table),
+            Some(RequestData::Lpop(v)) => f(&mut v.table),
+            Some(RequestData::Rpop(v)) => f(&mut v.table),
+            Some(RequestData::Lrange(v)) => f(&mut v.table),
+            Some(RequestData::Llen(v)) => f(&mut v.table),
+            Some(RequestData::Sadd(v)) => f(&mut v.table),
+            Some(RequestData::Srem(v)) => f(&mut v.table),
+            Some(RequestData::Sismember(v)) => f(&mut v.table),
+            Some(RequestData::Smembers(v)) => f(&mut v.table),
+            Some(RequestData::Sunion(v)) => f(&mut v.table),
+            Some(RequestData::Sinter(v)) => f(&mut v.table),
+            Some(RequestData::Zadd(v)) => f(&mut v.table),
+            Some(RequestData::Zrange(v)) => f(&mut v.table),
+            Some(RequestData::Zrangebyscore(v)) => f(&mut v.table),
+            Some(RequestData::Hcopy(v)) => {
+                f(&mut v.from_table);
+                f(&mut v.to_table);
+            }
+            Some(RequestData::Hmove(v)) => {
+                f(&mut v.from_table);
+                f(&mut v.to_table);
+            }
+            Some(RequestData::Hmget(v)) => f(&mut v.table),
+            Some(RequestData::Hset(v)) => f(&mut v.table),
+            Some(RequestData::Hmset(v)) => f(&mut v.table),
+            Some(RequestData::Hdel(v)) => f(&mut v.table),
+            Some(RequestData::Hmdel(v)) => f(&mut v.table),
+            Some(RequestData::Hexist(v)) => f(&mut v.table),
+            Some(RequestData::Hmexist(v)) => f(&mut v.table),
+            Some(RequestData::Hrandfield(v)) => f(&mut v.table),
+            Some(RequestData::Hexpire(v)) => f(&mut v.table),
+            Some(RequestData::Hexpireat(v)) => f(&mut v.table),
+            Some(RequestData::Hpersist(v)) => f(&mut v.table),
+            Some(RequestData::Hrange(v)) => f(&mut v.table),
+            Some(RequestData::Httl(v)) => f(&mut v.table),
+            Some(RequestData::Hincr(v)) => f(&mut v.table),
+            Some(RequestData::HincrEx(v)) => f(&mut v.table),
+            Some(RequestData::Export(v)) => f(&mut v.table),
+            Some(RequestData::Import(v)) => f(&mut v.table),
+            Some(RequestData::Hdelpattern(v)) => f(&mut v.table),
+            Some(RequestData::Hgetmeta(v)) => f(&mut v.table),
+            Some(RequestData::Hstrlen(v)) => f(&mut v.table),
+            Some(RequestData::Hsetnx(v)) => f(&mut v.table),
+            Some(RequestData::Hgetdel(v)) => f(&mut v.table),
+            Some(RequestData::Hscan(v)) => f(&mut v.table),
+            Some(RequestData::Mget(v)) => v.keys.iter_mut().for_each(|k| f(&mut k.table)),
+            Some(RequestData::Txn(v)) => {
+                v.requests.iter_mut().for_each(|cmd| cmd.for_each_table(f))
+            }
+            // the topics and the admin commands are shared by all databases
+            _ => {}
+        }
+    }
+}
+
+/// Whether the table name is reserved to the scopes of the databases and the namespaces, and to
+/// the tables of the server, the clients can't name such a table
+pub fn is_reserved_table(table: &str) -> bool {
+    table.starts_with("__")
+}
+
+/// The table of the storage holding a table of a logical database. The tables of database 0
+/// are stored as is, the names starting with `__` are reserved so the databases never clash.
+pub fn db_table(db: u32, table: &str) -> String {
+    match db {
+        0 => table.to_string(),
+        _ => format!("__db{}__.{}", db, table),
+    }
+}
+
+/// The table of the storage holding a table of a logical database of a namespace.
+/// The default namespace is stored as is, the others are reserved names like the databases.
+pub fn scoped_table(namespace: &str, db: u32, table: &str) -> String {
+    match namespace {
+        "" => db_table(db, table),
+        _ => format!("__ns_{}__.{}", namespace, db_table(db, table)),
+    }
+}
+
+/// The table a client of a namespace and a database sees of a table of the storage, None if the
+/// table belongs to another scope or to the server, the reverse of `scoped_table`
+pub fn unscoped_table<'a>(namespace: &str, db: u32, table: &'a str) -> Option<&'a str> {
+    table
+        .strip_prefix(scoped_table(namespace, db, "").as_str())
+        .filter(|table| !is_reserved_table(table))
+}
+
+/// Check the name of a namespace, empty for the default one
+pub fn validate_namespace(namespace: &str) -> Result<(), KvError> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    match namespace.chars().all(valid) {
+        true => Ok(()),
+        false => Err(KvError::InvalidCommand(format!(
+            "invalid namespace {:?}, only ascii letters, digits, '_' and '-' are allowed",
+            namespace
+        ))),
+    }
 }
 
 impl CommandResponse {
@@ -256,10 +1479,47 @@ impl CommandResponse {
             ..Default::default()
         }
     }
+
+    /// A keepalive frame on streaming responses, it carries no data
+    pub fn heartbeat() -> Self {
+        Self {
+            status: StatusCode::CONTINUE.as_u16() as u32,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the response is a keepalive frame
+    pub fn is_heartbeat(&self) -> bool {
+        self.status == StatusCode::CONTINUE.as_u16() as u32
+    }
 }
 
 impl Value {
     pub fn format(&self) -> String {
         format!("{:?}", self)
     }
+
+    /// The name of the variant of the value, None if it is empty
+    pub fn type_name(&self) -> &'static str {
+        match &self.value {
+            Some(value::Value::String(_)) => "String",
+            Some(value::Value::Binary(_)) => "Binary",
+            Some(value::Value::Integer(_)) => "Integer",
+            Some(value::Value::Float(_)) => "Float",
+            Some(value::Value::Bool(_)) => "Bool",
+            Some(value::Value::List(_)) => "List",
+            Some(value::Value::Set(_)) => "Set",
+            Some(value::Value::Zset(_)) => "Zset",
+            None => "None",
+        }
+    }
+
+    /// The length in bytes of a String or Binary value, None for the other types
+    pub fn byte_len(&self) -> Option<usize> {
+        match &self.value {
+            Some(value::Value::String(s)) => Some(s.len()),
+            Some(value::Value::Binary(b)) => Some(b.len()),
+            _ => None,
+        }
+    }
 }
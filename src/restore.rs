@@ -0,0 +1,26 @@
+//! Restore a dump written by kvdb-dump into a sled data directory, the server must be stopped.
+//! The existing keys are overwritten, the others are kept.
+//!
+//! Usage: kvdb-restore <sled-dir> [input], the dump is read from stdin without an input file.
+
+use std::{env, fs::File, io};
+
+use anyhow::Context;
+use kvdb::SledDb;
+
+fn main() -> anyhow::Result<()> {
+    let mut args = env::args().skip(1);
+    let dir = args
+        .next()
+        .context("usage: kvdb-restore <sled-dir> [input]")?;
+    let db = SledDb::builder(&dir)
+        .open()
+        .with_context(|| format!("failed to open {}, is the server still running?", dir))?;
+
+    let count = match args.next() {
+        Some(path) => db.restore(File::open(&path)?)?,
+        None => db.restore(io::stdin().lock())?,
+    };
+    eprintln!("restored {} pairs into {}", count, dir);
+    Ok(())
+}
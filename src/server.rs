@@ -1,38 +1,110 @@
-use kvdb::{MemTable, ProstServerStream, Service, ServiceInner, TlsServerAcceptor, YamuxCtrl};
+use std::env;
+
+use kvdb::{
+    spawn_named, IpFilter, MemTable, ProstServerStream, SendScheduler, Service, ServiceInner,
+    TlsServerAcceptor, TrustedProxies, YamuxCtrl,
+};
 use tokio::net::TcpListener;
-use tokio_util::compat::FuturesAsyncReadCompatExt;
-use tracing::info;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    #[cfg(feature = "console")]
+    console_subscriber::init();
+    #[cfg(not(feature = "console"))]
     tracing_subscriber::fmt::init();
 
     let addr = "127.0.0.1:9527";
 
-    let server_cert = include_str!("../fixtures/server.cert");
-    let server_key = include_str!("../fixtures/server.key");
+    // the TLS material is read at runtime, the fixtures are the defaults for local runs
+    let cert = env::var("KV_TLS_CERT").unwrap_or_else(|_| "fixtures/server.cert".into());
+    let key = env::var("KV_TLS_KEY").unwrap_or_else(|_| "fixtures/server.key".into());
+    let client_ca = env::var("KV_TLS_CLIENT_CA").ok();
+    // comma separated CRL files, revoking client certificates issued by the client CA
+    let crls = env::var("KV_TLS_CRL").ok();
 
-    let acceptor = TlsServerAcceptor::new(server_cert, server_key, None)?;
+    let mut acceptor = TlsServerAcceptor::from_pem_files(cert, key, client_ca)?;
+    if let Some(crls) = crls {
+        let paths: Vec<&str> = crls.split(',').collect();
+        acceptor = acceptor.with_crl_files(&paths)?;
+    }
+    // the listener is behind load balancers sending the PROXY protocol header, only the
+    // comma separated networks of KV_TRUSTED_PROXIES are trusted to send it
+    let proxies = match env::var("KV_PROXY_PROTOCOL").is_ok_and(|v| v == "1") {
+        true => {
+            let trusted = env::var("KV_TRUSTED_PROXIES")
+                .map_err(|_| anyhow::anyhow!("KV_PROXY_PROTOCOL needs KV_TRUSTED_PROXIES"))?;
+            Some(TrustedProxies::parse(&trusted)?)
+        }
+        false => None,
+    };
+    // the CIDR allow/deny rules of the listener, reloaded on SIGHUP
+    let ip_filter = match env::var("KV_IP_RULES") {
+        Ok(path) => {
+            let filter = IpFilter::from_file(&path)?;
+            reload_on_sighup(filter.clone(), path)?;
+            Some(filter)
+        }
+        Err(_) => None,
+    };
     let service: Service = ServiceInner::new(MemTable::new()).into();
     let listener = TcpListener::bind(addr).await?;
     info!("start server at {}", addr);
 
     loop {
         let tls = acceptor.clone();
-        let (stream, addr) = listener.accept().await?;
-        info!("Client {:?} connected", addr);
+        let (mut stream, mut addr) = listener.accept().await?;
 
         let svc = service.clone();
-        tokio::spawn(async move {
-            let stream = tls.accept(stream).await.unwrap();
+        let ip_filter = ip_filter.clone();
+        let proxies = proxies.clone();
+        spawn_named("conn", async move {
+            if let Some(proxies) = proxies {
+                match proxies.client_addr(&mut stream, addr).await {
+                    Ok(client) => addr = client,
+                    Err(e) => return warn!(error = ?e, "Rejected connection from {:?}", addr),
+                }
+            }
+            // behind a load balancer, the rules apply to the real client address
+            if let Some(filter) = ip_filter {
+                if !filter.is_allowed(addr.ip()) {
+                    return warn!("Rejected connection from {:?} by the IP rules", addr);
+                }
+            }
+            info!("Client {:?} connected", addr);
+            let stream = match tls.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => return warn!(error = ?e, "TLS handshake with {:?} failed", addr),
+            };
+            // all streams of the connection share the same context and send scheduler
+            let context = svc.new_context(Some(addr));
+            let scheduler = SendScheduler::default();
             YamuxCtrl::new_server(stream, None, move |stream| {
                 let svc1 = svc.clone();
+                let context = context.clone();
+                let scheduler = scheduler.clone();
                 async move {
-                    let stream = ProstServerStream::new(stream.compat(), svc1.clone());
-                    stream.process().await.unwrap();
-                    Ok(())
+                    let stream = ProstServerStream::new(stream, svc1.clone())
+                        .with_context(context)
+                        .with_scheduler(scheduler);
+                    stream.process().await
                 }
             });
         });
     }
 }
+
+/// Reload the IP rules when the process receives SIGHUP, a bad file keeps the current rules
+fn reload_on_sighup(filter: IpFilter, path: String) -> anyhow::Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    spawn_named("ip_rules_reload", async move {
+        while hangup.recv().await.is_some() {
+            match filter.reload_file(&path) {
+                Ok(()) => info!("Reloaded the IP rules from {}", path),
+                Err(e) => warn!(error = ?e, "Failed to reload the IP rules"),
+            }
+        }
+    });
+    Ok(())
+}
@@ -1,52 +1,1900 @@
+use std::collections::BTreeSet;
+
+use bytes::Bytes;
+use http::StatusCode;
+use prost::Message;
+use rand::seq::SliceRandom;
+
+use super::glob::glob_match;
 use crate::*;
 
-impl CommandService for Hget {
-    fn execute(self, store: &impl Storage) -> CommandResponse {
-        match store.get(&self.table, &self.key) {
-            Ok(Some(v)) => v.into(),
-            Ok(None) => {
-                KvError::NotFound(format!("key: {}, table: {}", self.key, self.table)).into()
+/// The number of keys an Hscan without a count scans
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// The max number of keys an Hrandfield returns, whatever its count
+const MAX_RANDOM_FIELDS: usize = 10_000;
+
+impl CommandService for Hget {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.get_versioned(&self.table, &self.key) {
+            Ok(Some((v, version))) => {
+                let mut res: CommandResponse = v.into();
+                res.version = version;
+                res
+            }
+            Ok(None) => {
+                KvError::NotFound(format!("key: {}, table: {}", self.key, self.table)).into()
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hset {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let Some(key) = self.pair.as_ref().map(|pair| pair.key.clone()) else {
+            return Value::default().into();
+        };
+        let table = self.table.clone();
+        let mut res = self.write(store);
+        if res.status == StatusCode::OK.as_u16() as u32 && res.version == 0 {
+            res.version = store.version(&table, &key).ok().flatten().unwrap_or(0);
+        }
+        res
+    }
+}
+
+impl Hset {
+    /// Write the pair if the conditions of the command are met, and return the old value
+    fn write(self, store: &impl Storage) -> CommandResponse {
+        let Some(pair) = self.pair else {
+            return Value::default().into();
+        };
+        let value = pair.value.unwrap_or_default();
+        let (nx, xx) = (self.nx, self.xx);
+        if nx && xx {
+            return KvError::InvalidCommand("Hset with both nx and xx".into()).into();
+        }
+        if self.if_version != 0 {
+            if nx || self.keep_ttl {
+                let msg = "Hset with if_version and nx or keep_ttl";
+                return KvError::InvalidCommand(msg.into()).into();
+            }
+            return match store.set_if_version(&self.table, pair.key.clone(), value, self.if_version)
+            {
+                Ok(Some((old, version))) => {
+                    let mut res: CommandResponse = old.into();
+                    res.version = version;
+                    res
+                }
+                Ok(None) => {
+                    let msg = format!(
+                        "key: {}, table: {}, version: {}",
+                        pair.key, self.table, self.if_version
+                    );
+                    KvError::ConditionNotMet(msg).into()
+                }
+                Err(e) => e.into(),
+            };
+        }
+        if !(nx || xx || self.keep_ttl) {
+            return match store.set(&self.table, pair.key, value) {
+                Ok(v) => v.unwrap_or_default().into(),
+                Err(e) => e.into(),
+            };
+        }
+
+        // check the condition and write in one atomic update, a key failing the condition is
+        // left untouched, and the deadline is cleared in the same update unless keep_ttl
+        let (mut met, keep_ttl) = (false, self.keep_ttl);
+        let mut set = |old: Option<&Value>| {
+            met = !(nx && old.is_some() || xx && old.is_none());
+            Ok(match (met, keep_ttl) {
+                (false, _) => Update::Keep,
+                (true, true) => Update::Set(value.clone()),
+                (true, false) => Update::Overwrite(value.clone()),
+            })
+        };
+        let old = match store.update(&self.table, &pair.key, &mut set) {
+            Ok((old, _)) => old,
+            Err(e) => return e.into(),
+        };
+        if !met {
+            let msg = format!("key: {}, table: {}", pair.key, self.table);
+            return KvError::ConditionNotMet(msg).into();
+        }
+        old.unwrap_or_default().into()
+    }
+}
+
+impl CommandService for Hmset {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        // set all pairs in one batch, so a failure leaves none of them on atomic backends
+        match store.set_batch(&self.table, self.pairs) {
+            Ok(olds) => {
+                let mut res = CommandResponse::ok();
+                res.values = olds.into_iter().map(Option::unwrap_or_default).collect();
+                res
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Import {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let len = self.pairs.len();
+        match store.set_batch(&self.table, self.pairs) {
+            Ok(_) => Value::from(len as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hmdel {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.del_batch(&self.table, self.keys) {
+            Ok(olds) => {
+                let mut res = CommandResponse::ok();
+                res.values = olds.into_iter().map(Option::unwrap_or_default).collect();
+                res
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hdel {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.del(&self.table, &self.key) {
+            Ok(Some(v)) => v.into(),
+            Ok(None) => {
+                KvError::NotFound(format!("key: {}, table: {}", self.key, self.table)).into()
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hgetdel {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        // a del returns the deleted value, so it reads and deletes in one atomic step
+        let Hgetdel { table, key } = self;
+        Hdel { table, key }.execute(store)
+    }
+}
+
+impl CommandService for Hexpire {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let deadline = now_ms().saturating_add(self.ttl_ms);
+        match store.expire(&self.table, &self.key, Some(deadline)) {
+            Ok(exists) => Value::from(exists).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hexpireat {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.expire(&self.table, &self.key, Some(self.deadline_ms)) {
+            Ok(exists) => Value::from(exists).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hpersist {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let persisted =
+            store
+                .deadline(&self.table, &self.key)
+                .and_then(|deadline| match deadline {
+                    Some(_) => store.expire(&self.table, &self.key, None),
+                    None => Ok(false),
+                });
+        match persisted {
+            Ok(persisted) => Value::from(persisted).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Httl {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let deadline = match store.deadline(&self.table, &self.key) {
+            Ok(v) => v,
+            Err(e) => return e.into(),
+        };
+        // the deadline is None for both the persistent and the missing keys
+        match (deadline, store.contains(&self.table, &self.key)) {
+            (Some(deadline), _) => Value::from(deadline.saturating_sub(now_ms()) as i64).into(),
+            (None, Ok(true)) => Value::from(-1i64).into(),
+            (None, Ok(false)) => {
+                KvError::NotFound(format!("key: {}, table: {}", self.key, self.table)).into()
+            }
+            (None, Err(e)) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hincr {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match incr_by(store, &self.table, &self.key, self.delta, None) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for HincrEx {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        if self.ttl_ms == 0 {
+            return KvError::InvalidCommand("HincrEx with a ttl of 0".into()).into();
+        }
+        let deadline = now_ms().saturating_add(self.ttl_ms);
+        match incr_by(store, &self.table, &self.key, self.delta, Some(deadline)) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// Add the delta to the integer value of a key and return the new value. A key created
+/// by the increment gets the deadline in the same write, an existing key keeps its own.
+fn incr_by(
+    store: &impl Storage,
+    table: &str,
+    key: &str,
+    delta: i64,
+    deadline: Option<u64>,
+) -> Result<Value, KvError> {
+    let mut incr = |v: Option<&Value>| {
+        let n: i64 = v.map(|v| v.try_into()).transpose()?.unwrap_or(0);
+        match (n.checked_add(delta), deadline) {
+            (Some(n), Some(deadline)) if v.is_none() => Ok(Update::Expiring(n.into(), deadline)),
+            (Some(n), _) => Ok(Update::Set(n.into())),
+            (None, _) => Err(KvError::InvalidCommand(format!(
+                "{} + {} overflows",
+                n, delta
+            ))),
+        }
+    };
+    match store.update(table, key, &mut incr)? {
+        (_, Some(v)) => Ok(v),
+        (_, None) => Err(KvError::Internal("increment deleted the key".into())),
+    }
+}
+
+impl CommandService for Hgetset {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let Some(pair) = self.pair else {
+            return Value::default().into();
+        };
+        // a set returns the value it replaced, so the swap is a single write
+        match store.set(&self.table, pair.key, pair.value.unwrap_or_default()) {
+            Ok(old) => old.unwrap_or_default().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Happend {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        self.append(store, None)
+    }
+}
+
+impl Happend {
+    /// Append the value, rejected if the result is larger than `max_size` bytes encoded
+    pub(crate) fn append(self, store: &impl Storage, max_size: Option<usize>) -> CommandResponse {
+        let more = self.value.unwrap_or_default();
+        let mut append = |old: Option<&Value>| {
+            // the ciphertexts of the client encryption cannot be concatenated
+            let encrypted = |v: &Value| v.encryption() != ValueEncryption::None;
+            let encrypted = old.is_some_and(encrypted) || encrypted(&more);
+            if encrypted {
+                return Err(KvError::InvalidCommand(
+                    "cannot append encrypted values".into(),
+                ));
+            }
+            let appended = match (old.map(|v| &v.value), &more.value) {
+                (None, Some(value::Value::String(_) | value::Value::Binary(_))) => more.clone(),
+                (Some(Some(value::Value::String(s))), Some(value::Value::String(tail))) => {
+                    format!("{}{}", s, tail).into()
+                }
+                (Some(Some(value::Value::Binary(b))), Some(value::Value::Binary(tail))) => {
+                    Bytes::from([&b[..], &tail[..]].concat()).into()
+                }
+                _ => {
+                    let v = old.unwrap_or(&more);
+                    return Err(KvError::ConvertCommand(v.format(), "String or Binary"));
+                }
+            };
+            set_within(appended, max_size)
+        };
+        match store.update(&self.table, &self.key, &mut append) {
+            Ok((_, Some(v))) => Value::from(v.byte_len().unwrap_or_default() as i64).into(),
+            Ok((_, None)) => KvError::Internal("append deleted the key".into()).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hgetrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let value = match store.get(&self.table, &self.key) {
+            Ok(Some(v)) => v,
+            Ok(None) => {
+                return KvError::NotFound(format!("key: {}, table: {}", self.key, self.table))
+                    .into()
+            }
+            Err(e) => return e.into(),
+        };
+        if value.encryption() != ValueEncryption::None {
+            return KvError::InvalidCommand("cannot read a range of an encrypted value".into())
+                .into();
+        }
+        let Some(len) = value.byte_len() else {
+            return KvError::ConvertCommand(value.format(), "String or Binary").into();
+        };
+
+        let range = inclusive_range(len, self.start, self.end);
+        match value.value {
+            Some(value::Value::String(s)) => match s.get(range) {
+                Some(s) => Value::from(s).into(),
+                None => KvError::InvalidCommand("the range splits a character".into()).into(),
+            },
+            Some(value::Value::Binary(b)) => Value::from(b.slice(range)).into(),
+            _ => unreachable!("the value is a String or a Binary"),
+        }
+    }
+}
+
+impl CommandService for Htype {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.get(&self.table, &self.key) {
+            Ok(Some(v)) => Value::from(v.type_name()).into(),
+            Ok(None) => {
+                KvError::NotFound(format!("key: {}, table: {}", self.key, self.table)).into()
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// The version of the blobs of Hdump, bumped when their format changes
+const DUMP_VERSION: u32 = 1;
+
+impl CommandService for Hsort {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let mut pairs = match store.get_all(&self.table) {
+            Ok(pairs) => pairs,
+            Err(e) => return e.into(),
+        };
+        if self.by_value {
+            let mut scored = Vec::with_capacity(pairs.len());
+            for pair in pairs {
+                let score = match pair.value.as_ref().and_then(|v| v.value.as_ref()) {
+                    Some(value::Value::Integer(i)) => *i as f64,
+                    Some(value::Value::Float(f)) => *f,
+                    _ => {
+                        return KvError::InvalidCommand(format!(
+                            "value of key {} is not a number",
+                            pair.key
+                        ))
+                        .into()
+                    }
+                };
+                scored.push((score, pair));
+            }
+            // the pairs of the same value are ordered by key
+            scored.sort_by(|(a, pa), (b, pb)| a.total_cmp(b).then_with(|| pa.key.cmp(&pb.key)));
+            pairs = scored.into_iter().map(|(_, pair)| pair).collect();
+        } else {
+            pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        }
+        if self.desc {
+            pairs.reverse();
+        }
+        let limit = match self.limit {
+            0 => usize::MAX,
+            limit => limit as usize,
+        };
+        let pairs: Vec<_> = pairs
+            .into_iter()
+            .skip(self.offset as usize)
+            .take(limit)
+            .collect();
+        pairs.into()
+    }
+}
+
+impl CommandService for Hdump {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let value = match store.get(&self.table, &self.key) {
+            Ok(Some(v)) => v,
+            Ok(None) => {
+                return KvError::NotFound(format!("key: {}, table: {}", self.key, self.table))
+                    .into()
+            }
+            Err(e) => return e.into(),
+        };
+        let ttl_ms = match store.deadline(&self.table, &self.key) {
+            // a key expiring now keeps at least a millisecond, 0 is for the persistent keys
+            Ok(deadline) => deadline.map_or(0, |d| d.saturating_sub(now_ms()).max(1)),
+            Err(e) => return e.into(),
+        };
+        let dump = DumpPayload {
+            version: DUMP_VERSION,
+            value: Some(value),
+            ttl_ms,
+        };
+        Value::from(Bytes::from(dump.encode_to_vec())).into()
+    }
+}
+
+impl CommandService for Hrestore {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let dump = match DumpPayload::decode(self.data) {
+            Ok(dump) if dump.version == DUMP_VERSION => dump,
+            Ok(dump) => {
+                return KvError::InvalidCommand(format!(
+                    "dump version {} is not supported",
+                    dump.version
+                ))
+                .into()
+            }
+            Err(e) => return KvError::InvalidCommand(format!("invalid dump: {}", e)).into(),
+        };
+        let Some(value) = dump.value else {
+            return KvError::InvalidCommand("invalid dump: no value".into()).into();
+        };
+        let replace = self.replace;
+        // the deadline is written with the value, a key replaced never keeps its own
+        let deadline = match dump.ttl_ms {
+            0 => None,
+            ttl_ms => Some(now_ms().saturating_add(ttl_ms)),
+        };
+        let mut restore = |v: Option<&Value>| match (v, deadline) {
+            (Some(_), _) if !replace => Err(KvError::ConditionNotMet(format!(
+                "key {} exists in table {}",
+                self.key, self.table
+            ))),
+            (_, Some(deadline)) => Ok(Update::Expiring(value.clone(), deadline)),
+            (_, None) => Ok(Update::Overwrite(value.clone())),
+        };
+        match store.update(&self.table, &self.key, &mut restore) {
+            Ok(_) => CommandResponse::ok(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Htouch {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let mut touched = 0i64;
+        for key in &self.keys {
+            match store.touch(&self.table, key) {
+                Ok(true) => touched += 1,
+                Ok(false) => {}
+                Err(e) => return e.into(),
+            }
+        }
+        Value::from(touched).into()
+    }
+}
+
+impl CommandService for Hmeta {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let (exists, last_access) = match (
+            store.contains(&self.table, &self.key),
+            store.last_access(&self.table, &self.key),
+        ) {
+            (Ok(exists), Ok(last_access)) => (exists, last_access),
+            (Err(e), _) | (_, Err(e)) => return e.into(),
+        };
+        if !exists {
+            return KvError::NotFound(format!("key: {}, table: {}", self.key, self.table)).into();
+        }
+        let (last_access_ms, idle_ms) = match last_access {
+            Some(ms) => (ms as i64, now_ms().saturating_sub(ms) as i64),
+            None => (-1, -1),
+        };
+        let mut res = CommandResponse::ok();
+        res.pairs = vec![
+            Kvpair::new("last_access_ms", last_access_ms.into()),
+            Kvpair::new("idle_ms", idle_ms.into()),
+        ];
+        res
+    }
+}
+
+impl CommandService for Hgetmeta {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let (value, version) = match store.get_versioned(&self.table, &self.key) {
+            Ok(Some(versioned)) => versioned,
+            Ok(None) => {
+                return KvError::NotFound(format!("key: {}, table: {}", self.key, self.table))
+                    .into()
+            }
+            Err(e) => return e.into(),
+        };
+        let (deadline, modified) = match (
+            store.deadline(&self.table, &self.key),
+            store.modified(&self.table, &self.key),
+        ) {
+            (Ok(deadline), Ok(modified)) => (deadline, modified),
+            (Err(e), _) | (_, Err(e)) => return e.into(),
+        };
+        let ttl_ms = match deadline {
+            Some(deadline) => deadline.saturating_sub(now_ms()) as i64,
+            None => -1,
+        };
+        let modified_ms = modified.map(|ms| ms as i64).unwrap_or(-1);
+        let size = value.encoded_len() as i64;
+
+        let mut res: CommandResponse = value.into();
+        res.version = version;
+        res.pairs = vec![
+            Kvpair::new("version", (version as i64).into()),
+            Kvpair::new("ttl_ms", ttl_ms.into()),
+            Kvpair::new("size", size.into()),
+            Kvpair::new("modified_ms", modified_ms.into()),
+        ];
+        res
+    }
+}
+
+impl CommandService for Hstrlen {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.get(&self.table, &self.key) {
+            Ok(v) => Value::from(v.map(|v| v.encoded_len()).unwrap_or(0) as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Lpush {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        list_push(store, &self.table, &self.key, self.values, true, None)
+    }
+}
+
+impl CommandService for Rpush {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        list_push(store, &self.table, &self.key, self.values, false, None)
+    }
+}
+
+/// Push the values to a list, rejected if the list grows larger than `max_size` bytes encoded
+pub(crate) fn list_push(
+    store: &impl Storage,
+    table: &str,
+    key: &str,
+    values: Vec<Value>,
+    front: bool,
+    max_size: Option<usize>,
+) -> CommandResponse {
+    if values.is_empty() {
+        return KvError::InvalidCommand("no value to push".into()).into();
+    }
+    match store.list_push(table, key, values, front, max_size) {
+        Ok(len) => Value::from(len as i64).into(),
+        Err(e) => e.into(),
+    }
+}
+
+impl CommandService for Lpop {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        list_pop(store, &self.table, &self.key, self.count, true)
+    }
+}
+
+impl CommandService for Rpop {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        list_pop(store, &self.table, &self.key, self.count, false)
+    }
+}
+
+fn list_pop(
+    store: &impl Storage,
+    table: &str,
+    key: &str,
+    count: u32,
+    front: bool,
+) -> CommandResponse {
+    match store.list_pop(table, key, count.max(1) as usize, front) {
+        Ok(values) => {
+            let mut res = CommandResponse::ok();
+            res.values = values;
+            res
+        }
+        Err(e) => e.into(),
+    }
+}
+
+impl CommandService for Lrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.list_range(&self.table, &self.key, self.start, self.stop) {
+            Ok(values) => {
+                let mut res = CommandResponse::ok();
+                res.values = values;
+                res
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Llen {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let len = match store.get(&self.table, &self.key) {
+            Ok(Some(v)) => Vec::<Value>::try_from(&v).map(|list| list.len()),
+            Ok(None) => Ok(0),
+            Err(e) => Err(e),
+        };
+        match len {
+            Ok(len) => Value::from(len as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Sadd {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        self.add(store, None)
+    }
+}
+
+impl Sadd {
+    /// Add the members, rejected if the set grows larger than `max_size` bytes encoded
+    pub(crate) fn add(self, store: &impl Storage, max_size: Option<usize>) -> CommandResponse {
+        if self.members.is_empty() {
+            return KvError::InvalidCommand("no member to add".into()).into();
+        }
+        match store.set_add(&self.table, &self.key, &self.members, max_size) {
+            Ok(added) => Value::from(added as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Srem {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.set_remove(&self.table, &self.key, &self.members) {
+            Ok(removed) => Value::from(removed as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Sismember {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.set_members(&self.table, &self.key) {
+            Ok(set) => Value::from(set.contains(&self.member)).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Smembers {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.set_members(&self.table, &self.key) {
+            Ok(set) => members_response(set),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Sunion {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let mut union = BTreeSet::new();
+        for key in &self.keys {
+            match store.set_members(&self.table, key) {
+                Ok(set) => union.extend(set),
+                Err(e) => return e.into(),
+            }
+        }
+        members_response(union)
+    }
+}
+
+impl CommandService for Sinter {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let mut inter: Option<BTreeSet<String>> = None;
+        for key in &self.keys {
+            let set = match store.set_members(&self.table, key) {
+                Ok(set) => set,
+                Err(e) => return e.into(),
+            };
+            inter = Some(match inter {
+                Some(inter) => inter.intersection(&set).cloned().collect(),
+                None => set,
+            });
+        }
+        members_response(inter.unwrap_or_default())
+    }
+}
+
+/// The members of a set as string values, in order
+fn members_response(members: BTreeSet<String>) -> CommandResponse {
+    let mut res = CommandResponse::ok();
+    res.values = members.into_iter().map(Value::from).collect();
+    res
+}
+
+impl CommandService for Zadd {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        self.add(store, None)
+    }
+}
+
+impl Zadd {
+    /// Add the members, rejected if the sorted set grows larger than `max_size` bytes encoded
+    pub(crate) fn add(self, store: &impl Storage, max_size: Option<usize>) -> CommandResponse {
+        if self.members.is_empty() {
+            return KvError::InvalidCommand("no member to add".into()).into();
+        }
+        if let Some(m) = self.members.iter().find(|m| m.score.is_nan()) {
+            return KvError::InvalidCommand(format!("score of {} is not a number", m.member))
+                .into();
+        }
+        match store.zset_add(&self.table, &self.key, &self.members, max_size) {
+            Ok(added) => Value::from(added as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.zset_members(&self.table, &self.key) {
+            Ok(mut zset) => {
+                let range = inclusive_range(zset.len(), self.start, self.stop);
+                zset_response(zset.drain(range))
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrangebyscore {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let limit = match self.limit {
+            0 => usize::MAX,
+            limit => limit as usize,
+        };
+        match store.zset_range_by_score(&self.table, &self.key, self.min, self.max) {
+            Ok(zset) => zset_response(zset.into_iter().skip(self.offset as usize).take(limit)),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// The members of a sorted set as pairs of the member and its score, in order
+fn zset_response(members: impl Iterator<Item = ZsetMember>) -> CommandResponse {
+    let mut res = CommandResponse::ok();
+    res.pairs = members
+        .map(|m| Kvpair::new(m.member, m.score.into()))
+        .collect();
+    res
+}
+
+impl CommandService for Hsetnx {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let Some(pair) = self.pair else {
+            return Value::from(false).into();
+        };
+        let value = pair.value.unwrap_or_default();
+        let mut written = false;
+        // an existing key is left untouched, so the check and the write are one atomic update
+        let mut setnx = |old: Option<&Value>| {
+            written = old.is_none();
+            Ok(match old {
+                Some(_) => Update::Keep,
+                None => Update::Set(value.clone()),
+            })
+        };
+        match store.update(&self.table, &pair.key, &mut setnx) {
+            Ok(_) => Value::from(written).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Txn {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        // the writes of all commands, and the number of writes of each command
+        let mut ops = Vec::new();
+        let mut counts = Vec::with_capacity(self.requests.len());
+        for cmd in &self.requests {
+            let before = ops.len();
+            match &cmd.request_data {
+                Some(RequestData::Hset(req))
+                    if !(req.nx || req.xx || req.keep_ttl || req.if_version != 0) =>
+                {
+                    ops.extend(req.pair.iter().map(|pair| {
+                        WriteOp::set(
+                            &req.table,
+                            &pair.key,
+                            pair.value.clone().unwrap_or_default(),
+                        )
+                    }))
+                }
+                Some(RequestData::Hmset(req)) => ops.extend(req.pairs.iter().map(|pair| {
+                    WriteOp::set(
+                        &req.table,
+                        &pair.key,
+                        pair.value.clone().unwrap_or_default(),
+                    )
+                })),
+                Some(RequestData::Hdel(req)) => ops.push(WriteOp::del(&req.table, &req.key)),
+                _ => {
+                    let msg = format!("{} is not allowed in a transaction", cmd.name());
+                    return KvError::InvalidCommand(msg).into();
+                }
+            }
+            counts.push(ops.len() - before);
+        }
+
+        let mut olds = match store.transaction(ops) {
+            Ok(olds) => olds.into_iter(),
+            Err(e) => return e.into(),
+        };
+        let mut res = CommandResponse::ok();
+        for (cmd, count) in self.requests.iter().zip(counts) {
+            let cmd_olds: Vec<_> = olds.by_ref().take(count).collect();
+            let sub = match &cmd.request_data {
+                Some(RequestData::Hmset(_)) => {
+                    let mut sub = CommandResponse::ok();
+                    sub.values = cmd_olds
+                        .into_iter()
+                        .map(Option::unwrap_or_default)
+                        .collect();
+                    sub
+                }
+                Some(RequestData::Hdel(req)) => match cmd_olds.into_iter().flatten().next() {
+                    Some(v) => v.into(),
+                    None => {
+                        KvError::NotFound(format!("key: {}, table: {}", req.key, req.table)).into()
+                    }
+                },
+                _ => cmd_olds
+                    .into_iter()
+                    .flatten()
+                    .next()
+                    .unwrap_or_default()
+                    .into(),
+            };
+            res.responses.push(sub);
+        }
+        res
+    }
+}
+
+impl CommandService for Hgetall {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.get_all(&self.table) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hscan {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let cursor = Some(self.cursor).filter(|c| !c.is_empty());
+        let count = match self.count {
+            0 => DEFAULT_SCAN_COUNT,
+            n => n as usize,
+        };
+        let page = match store.scan_page(&self.table, cursor, count) {
+            Ok(page) => page,
+            Err(e) => return e.into(),
+        };
+
+        let mut res = CommandResponse::ok();
+        res.pairs = page.pairs;
+        if !self.pattern.is_empty() {
+            res.pairs
+                .retain(|pair| glob_match(&self.pattern, &pair.key));
+        }
+        res.cursor = page.cursor.unwrap_or_default();
+        res
+    }
+}
+
+impl CommandService for Hrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let start = (!self.start.is_empty()).then_some(self.start.as_str());
+        let end = (!self.end.is_empty()).then_some(self.end.as_str());
+        let limit = (self.limit > 0).then_some(self.limit as usize);
+        match store.get_range(&self.table, start, end, limit) {
+            Ok(pairs) => pairs.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl Hkeys {
+    /// The sorted keys of the pairs of a table
+    pub(crate) fn response(mut pairs: Vec<Kvpair>) -> CommandResponse {
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        let mut res = CommandResponse::ok();
+        res.values = pairs.into_iter().map(|pair| pair.key.into()).collect();
+        res
+    }
+}
+
+impl CommandService for Hkeys {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.get_all(&self.table) {
+            Ok(pairs) => Self::response(pairs),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl Hvals {
+    /// The values of the pairs of a table, in the order of their keys
+    pub(crate) fn response(mut pairs: Vec<Kvpair>) -> CommandResponse {
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        let mut res = CommandResponse::ok();
+        res.values = pairs
+            .into_iter()
+            .map(|pair| pair.value.unwrap_or_default())
+            .collect();
+        res
+    }
+}
+
+impl CommandService for Hvals {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.get_all(&self.table) {
+            Ok(pairs) => Self::response(pairs),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hlen {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.len_of_table(&self.table) {
+            Ok(len) => Value::from(len as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hdelpattern {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        // only the matching keys are collected, the table is scanned by the iterator
+        let keys: Vec<String> = match store.get_iter(&self.table) {
+            Ok(iter) => iter
+                .filter(|pair| glob_match(&self.pattern, &pair.key))
+                .map(|pair| pair.key)
+                .collect(),
+            Err(e) => return e.into(),
+        };
+        let mut deleted = 0;
+        for key in keys {
+            match store.del(&self.table, &key) {
+                Ok(Some(_)) => deleted += 1,
+                Ok(None) => {}
+                Err(e) => return e.into(),
+            }
+        }
+        Value::from(deleted).into()
+    }
+}
+
+impl CommandService for Hcleartable {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.clear_table(&self.table) {
+            Ok(removed) => Value::from(removed as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hcopy {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.copy_key(&self.from_table, &self.to_table, &self.key, self.replace) {
+            Ok(copied) => Value::from(copied).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hmove {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.move_key(&self.from_table, &self.to_table, &self.key, self.replace) {
+            Ok(moved) => Value::from(moved).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hmget {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        // a missing key gets an empty value, so the values line up with the keys
+        let mut res = CommandResponse::ok();
+        for key in self.keys {
+            match store.get(&self.table, &key) {
+                Ok(v) => res.values.push(v.unwrap_or_default()),
+                Err(e) => return e.into(),
+            }
+        }
+        res
+    }
+}
+
+impl CommandService for Hmexist {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let mut res = CommandResponse::ok();
+        for key in self.keys {
+            match store.contains(&self.table, &key) {
+                Ok(exist) => res.values.push(exist.into()),
+                Err(e) => return e.into(),
+            }
+        }
+        res
+    }
+}
+
+impl CommandService for Hrandfield {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let count = (self.count.unsigned_abs() as usize).min(MAX_RANDOM_FIELDS);
+        let mut pairs = match store.sample(&self.table, count) {
+            Ok(v) => v,
+            Err(e) => return e.into(),
+        };
+
+        // a negative count allows repeated keys, so always return exactly `count` pairs
+        if self.count < 0 && !pairs.is_empty() {
+            let mut rng = rand::thread_rng();
+            let sampled = pairs.clone();
+            while pairs.len() < count {
+                pairs.push(sampled.choose(&mut rng).unwrap().clone());
+            }
+            pairs.shuffle(&mut rng);
+        }
+
+        if self.with_values {
+            return pairs.into();
+        }
+        // the response must not be empty, or it is taken as a stream command
+        let mut res = CommandResponse::ok();
+        res.values = pairs.into_iter().map(|pair| pair.key.into()).collect();
+        res
+    }
+}
+
+impl CommandService for Mget {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let mut res = CommandResponse::ok();
+        for TableKey { table, key } in self.keys {
+            match store.get(&table, &key) {
+                Ok(v) => res.values.push(v.unwrap_or_default()),
+                Err(e) => return e.into(),
+            }
+        }
+        res
+    }
+}
+
+impl CommandService for Flush {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.flush() {
+            Ok(()) => CommandResponse::ok(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hset_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_hset("t1", "hello", "world".into());
+        let res = dispatch(cmd.clone(), &store);
+        assert_res_ok(&res, &[Value::default()], &[]);
+
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &["world".into()], &[]);
+    }
+
+    #[test]
+    fn hset_with_flags_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hset("t1", "k1", 1.into()).xx(), &store);
+        assert_res_error(&res, 412, "Condition not met");
+        let res = dispatch(CommandRequest::new_hset("t1", "k1", 1.into()).nx(), &store);
+        assert_res_ok(&res, &[Value::default()], &[]);
+        // a key failing the condition is not written again
+        let version = store.version("t1", "k1").unwrap();
+        let res = dispatch(CommandRequest::new_hset("t1", "k1", 2.into()).nx(), &store);
+        assert_res_error(&res, 412, "Condition not met");
+        assert_eq!(store.version("t1", "k1").unwrap(), version);
+        assert_eq!(store.get("t1", "k1").unwrap(), Some(1.into()));
+
+        dispatch(CommandRequest::new_hexpire("t1", "k1", 60_000), &store);
+        let cmd = CommandRequest::new_hset("t1", "k1", 3.into())
+            .xx()
+            .keep_ttl();
+        assert_res_ok(&dispatch(cmd, &store), &[1.into()], &[]);
+        assert!(store.deadline("t1", "k1").unwrap().is_some());
+        let res = dispatch(CommandRequest::new_hset("t1", "k1", 4.into()).xx(), &store);
+        assert_res_ok(&res, &[3.into()], &[]);
+        assert_eq!(store.deadline("t1", "k1").unwrap(), None);
+
+        let res = dispatch(
+            CommandRequest::new_hset("t1", "k1", 5.into()).nx().xx(),
+            &store,
+        );
+        assert_res_error(&res, 400, "both nx and xx");
+    }
+
+    #[test]
+    fn hmset_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+
+        let pairs = vec![Kvpair::new("k1", "v2".into()), Kvpair::new("k2", 2.into())];
+        let res = dispatch(CommandRequest::new_hmset("t1", pairs), &store);
+        assert_res_ok(&res, &["v1".into(), Value::default()], &[]);
+
+        let res = dispatch(CommandRequest::new_hmget("t1", ["k1", "k2"]), &store);
+        assert_res_ok(&res, &["v2".into(), 2.into()], &[]);
+    }
+
+    #[test]
+    fn hmdel_should_work() {
+        let store = MemTable::new();
+        let pairs = vec![Kvpair::new("k1", "v1".into()), Kvpair::new("k2", 2.into())];
+        dispatch(CommandRequest::new_hmset("t1", pairs), &store);
+
+        let res = dispatch(CommandRequest::new_hmdel("t1", ["k1", "k3"]), &store);
+        assert_res_ok(&res, &["v1".into(), Value::default()], &[]);
+        let res = dispatch(CommandRequest::new_hkeys("t1"), &store);
+        assert_res_ok(&res, &["k2".into()], &[]);
+    }
+
+    #[test]
+    fn hdel_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+
+        let res = dispatch(CommandRequest::new_hdel("t1", "k1"), &store);
+        assert_res_ok(&res, &["v1".into()], &[]);
+
+        let res = dispatch(CommandRequest::new_hdel("t1", "k1"), &store);
+        assert_res_error(&res, 404, "Not found");
+    }
+
+    #[test]
+    fn hgetdel_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+        let res = dispatch(CommandRequest::new_hgetdel("t1", "k1"), &store);
+        assert_res_ok(&res, &["v1".into()], &[]);
+        let res = dispatch(CommandRequest::new_hgetdel("t1", "k1"), &store);
+        assert_res_error(&res, 404, "Not found");
+    }
+
+    #[test]
+    fn hscan_should_page_through_a_table() {
+        let store = MemTable::new();
+        for i in 0..5 {
+            dispatch(
+                CommandRequest::new_hset("t1", format!("k{}", i), i.into()),
+                &store,
+            );
+        }
+        dispatch(CommandRequest::new_hset("t1", "other", 9.into()), &store);
+
+        let mut cursor = String::new();
+        let mut keys = vec![];
+        loop {
+            let res = dispatch(CommandRequest::new_hscan("t1", &cursor, 2, "k*"), &store);
+            assert_eq!(res.status, 200);
+            assert!(res.pairs.len() <= 2);
+            keys.extend(res.pairs.into_iter().map(|pair| pair.key));
+            if res.cursor.is_empty() {
+                break;
             }
-            Err(e) => e.into(),
+            cursor = res.cursor;
         }
+        assert_eq!(keys, ["k0", "k1", "k2", "k3", "k4"]);
+
+        let res = dispatch(CommandRequest::new_hscan("t1", "", 0, ""), &store);
+        assert_eq!(res.pairs.len(), 6);
+        assert!(res.cursor.is_empty());
     }
-}
 
-impl CommandService for Hset {
-    fn execute(self, store: &impl Storage) -> CommandResponse {
-        match self.pair {
-            None => Value::default().into(),
-            Some(v) => match store.set(&self.table, v.key, v.value.unwrap_or_default()) {
-                Ok(Some(v)) => v.into(),
-                Ok(None) => Value::default().into(),
-                Err(e) => e.into(),
-            },
+    #[test]
+    fn hrange_should_work() {
+        let store = MemTable::new();
+        for key in ["2024-01", "2024-02", "2024-03", "2025-01"] {
+            dispatch(CommandRequest::new_hset("t1", key, "v".into()), &store);
         }
+        let keys = |res: CommandResponse| -> Vec<String> {
+            res.pairs.into_iter().map(|pair| pair.key).collect()
+        };
+
+        let res = dispatch(
+            CommandRequest::new_hrange("t1", "2024-02", "2025", 0),
+            &store,
+        );
+        assert_eq!(keys(res), ["2024-02", "2024-03"]);
+        let res = dispatch(CommandRequest::new_hrange("t1", "", "", 3), &store);
+        assert_eq!(keys(res), ["2024-01", "2024-02", "2024-03"]);
+        let res = dispatch(CommandRequest::new_hrange("t1", "2025", "", 0), &store);
+        assert_eq!(keys(res), ["2025-01"]);
+        let res = dispatch(CommandRequest::new_hrange("t1", "2025", "2024", 0), &store);
+        assert_eq!(keys(res), Vec::<String>::new());
     }
-}
 
-impl CommandService for Hgetall {
-    fn execute(self, store: &impl Storage) -> CommandResponse {
-        match store.get_all(&self.table) {
-            Ok(v) => v.into(),
-            Err(e) => e.into(),
+    #[test]
+    fn hkeys_and_hvals_should_work() {
+        let store = MemTable::new();
+        for (key, value) in [("k2", 2), ("k1", 1), ("k3", 3)] {
+            dispatch(CommandRequest::new_hset("t1", key, value.into()), &store);
         }
+        let res = dispatch(CommandRequest::new_hkeys("t1"), &store);
+        assert_res_ok(&res, &["k1".into(), "k2".into(), "k3".into()], &[]);
+        let res = dispatch(CommandRequest::new_hvals("t1"), &store);
+        assert_res_ok(&res, &[1.into(), 2.into(), 3.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_hkeys("t2"), &store);
+        assert_res_ok(&res, &[], &[]);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn hlen_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hlen("t1"), &store);
+        assert_res_ok(&res, &[0.into()], &[]);
+        dispatch(CommandRequest::new_hset("t1", "k1", 1.into()), &store);
+        dispatch(CommandRequest::new_hset("t1", "k2", 2.into()), &store);
+        let res = dispatch(CommandRequest::new_hlen("t1"), &store);
+        assert_res_ok(&res, &[2.into()], &[]);
+    }
 
     #[test]
-    fn hset_should_work() {
+    fn hcleartable_should_work() {
         let store = MemTable::new();
-        let cmd = CommandRequest::new_hset("t1", "hello", "world".into());
-        let res = dispatch(cmd.clone(), &store);
+        dispatch(CommandRequest::new_hset("t1", "k1", 1.into()), &store);
+        dispatch(CommandRequest::new_hset("t1", "k2", 2.into()), &store);
+        let res = dispatch(CommandRequest::new_hcleartable("t1"), &store);
+        assert_res_ok(&res, &[2.into()], &[]);
+        let res = dispatch(CommandRequest::new_hlen("t1"), &store);
+        assert_res_ok(&res, &[0.into()], &[]);
+    }
+
+    #[test]
+    fn hcopy_and_hmove_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+        let res = dispatch(CommandRequest::new_hcopy("t1", "t2", "k1", false), &store);
+        assert_res_ok(&res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_hmove("t1", "t2", "k1", false), &store);
+        assert_res_ok(&res, &[false.into()], &[]);
+        let res = dispatch(CommandRequest::new_hmove("t1", "t3", "k1", false), &store);
+        assert_res_ok(&res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_hget("t3", "k1"), &store);
+        assert_res_ok(&res, &["v1".into()], &[]);
+        let res = dispatch(CommandRequest::new_hget("t1", "k1"), &store);
+        assert_res_error(&res, 404, "Not found");
+
+        let res = dispatch(CommandRequest::new_hmove("t3", "t3", "k1", true), &store);
+        assert_res_error(&res, 400, "onto itself");
+    }
+
+    #[test]
+    fn hexpire_and_httl_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+
+        let res = dispatch(CommandRequest::new_httl("t1", "k1"), &store);
+        assert_res_ok(&res, &[(-1i64).into()], &[]);
+
+        let res = dispatch(CommandRequest::new_hexpire("t1", "k1", 60_000), &store);
+        assert_res_ok(&res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_httl("t1", "k1"), &store);
+        let ttl: i64 = (&res.values[0]).try_into().unwrap();
+        assert!(ttl > 59_000 && ttl <= 60_000);
+
+        let res = dispatch(CommandRequest::new_hexpire("t1", "k1", 0), &store);
+        assert_res_ok(&res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_hget("t1", "k1"), &store);
+        assert_res_error(&res, 404, "Not found");
+
+        let res = dispatch(CommandRequest::new_hexpire("t1", "k2", 1000), &store);
+        assert_res_ok(&res, &[false.into()], &[]);
+        let res = dispatch(CommandRequest::new_httl("t1", "k2"), &store);
+        assert_res_error(&res, 404, "Not found");
+    }
+
+    #[test]
+    fn hexpireat_and_hpersist_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+
+        let deadline = now_ms() + 60_000;
+        let res = dispatch(CommandRequest::new_hexpireat("t1", "k1", deadline), &store);
+        assert_res_ok(&res, &[true.into()], &[]);
+        assert_eq!(store.deadline("t1", "k1").unwrap(), Some(deadline));
+
+        let res = dispatch(CommandRequest::new_hpersist("t1", "k1"), &store);
+        assert_res_ok(&res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_httl("t1", "k1"), &store);
+        assert_res_ok(&res, &[(-1i64).into()], &[]);
+        let res = dispatch(CommandRequest::new_hpersist("t1", "k1"), &store);
+        assert_res_ok(&res, &[false.into()], &[]);
+
+        // a deadline in the past expires the key at once
+        let res = dispatch(CommandRequest::new_hexpireat("t1", "k1", 1), &store);
+        assert_res_ok(&res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_hget("t1", "k1"), &store);
+        assert_res_error(&res, 404, "Not found");
+        let res = dispatch(CommandRequest::new_hexpireat("t1", "k1", deadline), &store);
+        assert_res_ok(&res, &[false.into()], &[]);
+    }
+
+    #[test]
+    fn hincr_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hincr("t1", "k1", 5), &store);
+        assert_res_ok(&res, &[5.into()], &[]);
+        let res = dispatch(CommandRequest::new_hincr("t1", "k1", -7), &store);
+        assert_res_ok(&res, &[(-2i64).into()], &[]);
+
+        let res = dispatch(CommandRequest::new_hincr("t1", "k1", i64::MIN), &store);
+        assert_res_error(&res, 400, "overflows");
+        let res = dispatch(CommandRequest::new_hget("t1", "k1"), &store);
+        assert_res_ok(&res, &[(-2i64).into()], &[]);
+
+        dispatch(CommandRequest::new_hset("t1", "k2", "v2".into()), &store);
+        let res = dispatch(CommandRequest::new_hincr("t1", "k2", 1), &store);
+        assert_res_error(&res, 400, "Integer");
+    }
+
+    #[test]
+    fn hgetset_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hgetset("t1", "k1", 1.into()), &store);
         assert_res_ok(&res, &[Value::default()], &[]);
+        let res = dispatch(CommandRequest::new_hgetset("t1", "k1", 2.into()), &store);
+        assert_res_ok(&res, &[1.into()], &[]);
+        let res = dispatch(CommandRequest::new_hget("t1", "k1"), &store);
+        assert_res_ok(&res, &[2.into()], &[]);
+    }
+
+    #[test]
+    fn happend_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(
+            CommandRequest::new_happend("t1", "k1", "hello".into()),
+            &store,
+        );
+        assert_res_ok(&res, &[5.into()], &[]);
+        let res = dispatch(
+            CommandRequest::new_happend("t1", "k1", " world".into()),
+            &store,
+        );
+        assert_res_ok(&res, &[11.into()], &[]);
+        let res = dispatch(CommandRequest::new_hget("t1", "k1"), &store);
+        assert_res_ok(&res, &["hello world".into()], &[]);
+
+        let bytes = |b: &'static [u8]| Value::from(Bytes::from_static(b));
+        dispatch(CommandRequest::new_hset("t1", "k2", bytes(b"ab")), &store);
+        let res = dispatch(CommandRequest::new_happend("t1", "k2", bytes(b"c")), &store);
+        assert_res_ok(&res, &[3.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_happend("t1", "k2", "c".into()), &store);
+        assert_res_error(&res, 400, "String or Binary");
+        let res = dispatch(CommandRequest::new_happend("t1", "k3", 1.into()), &store);
+        assert_res_error(&res, 400, "String or Binary");
+    }
+
+    #[test]
+    fn hgetrange_should_work() {
+        let store = MemTable::new();
+        dispatch(
+            CommandRequest::new_hset("t1", "k1", "hello world".into()),
+            &store,
+        );
+        let range = |start, end| {
+            dispatch(
+                CommandRequest::new_hgetrange("t1", "k1", start, end),
+                &store,
+            )
+        };
+        assert_res_ok(&range(0, 4), &["hello".into()], &[]);
+        assert_res_ok(&range(-5, -1), &["world".into()], &[]);
+        assert_res_ok(&range(6, 100), &["world".into()], &[]);
+        assert_res_ok(&range(5, 2), &["".into()], &[]);
+        assert_res_ok(&range(-100, -50), &["".into()], &[]);
+
+        let value = Value::from(Bytes::from_static(b"abc"));
+        dispatch(CommandRequest::new_hset("t1", "k2", value), &store);
+        let res = dispatch(CommandRequest::new_hgetrange("t1", "k2", 1, -1), &store);
+        assert_res_ok(&res, &[Bytes::from_static(b"bc").into()], &[]);
+
+        dispatch(CommandRequest::new_hset("t1", "k3", "é".into()), &store);
+        let res = dispatch(CommandRequest::new_hgetrange("t1", "k3", 0, 0), &store);
+        assert_res_error(&res, 400, "splits a character");
+    }
+
+    #[test]
+    fn htype_should_work() {
+        let store = MemTable::new();
+        let values: [(Value, &str); 5] = [
+            ("v".into(), "String"),
+            (Bytes::from_static(b"v").into(), "Binary"),
+            (1.into(), "Integer"),
+            (1.5.into(), "Float"),
+            (true.into(), "Bool"),
+        ];
+        for (value, name) in values {
+            dispatch(CommandRequest::new_hset("t1", "k1", value), &store);
+            let res = dispatch(CommandRequest::new_htype("t1", "k1"), &store);
+            assert_res_ok(&res, &[name.into()], &[]);
+        }
+        let res = dispatch(CommandRequest::new_htype("t1", "k2"), &store);
+        assert_res_error(&res, 404, "Not found");
+    }
+
+    #[test]
+    fn hsort_should_work() {
+        let store = MemTable::new();
+        for (key, value) in [
+            ("k1", 3.into()),
+            ("k2", 1.5.into()),
+            ("k3", 7.into()),
+            ("k4", 3.into()),
+        ] {
+            dispatch(CommandRequest::new_hset("t1", key, value), &store);
+        }
+        // assert_res_ok sorts the pairs, so compare the keys in the order they are returned
+        let keys = |cmd: CommandRequest| -> Vec<String> {
+            let res = dispatch(cmd, &store);
+            assert_eq!(res.status, 200);
+            res.pairs.into_iter().map(|pair| pair.key).collect()
+        };
+
+        assert_eq!(
+            keys(CommandRequest::new_hsort("t1", 0, 0)),
+            ["k1", "k2", "k3", "k4"]
+        );
+        assert_eq!(
+            keys(CommandRequest::new_hsort("t1", 1, 2).desc()),
+            ["k3", "k2"]
+        );
+        assert_eq!(
+            keys(CommandRequest::new_hsort("t1", 0, 0).by_value()),
+            ["k2", "k1", "k4", "k3"]
+        );
+        assert_eq!(
+            keys(CommandRequest::new_hsort("t1", 0, 3).by_value().desc()),
+            ["k3", "k4", "k1"]
+        );
+        assert!(keys(CommandRequest::new_hsort("t1", 10, 0)).is_empty());
+
+        dispatch(CommandRequest::new_hset("t1", "k5", "v5".into()), &store);
+        let res = dispatch(CommandRequest::new_hsort("t1", 0, 0).by_value(), &store);
+        assert_res_error(&res, 400, "value of key k5 is not a number");
+    }
+
+    #[test]
+    fn hdump_and_hrestore_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+        dispatch(CommandRequest::new_hset("t1", "k2", 2.into()), &store);
+        dispatch(CommandRequest::new_hexpire("t1", "k2", 60_000), &store);
+
+        let dump = |key| -> Bytes {
+            let res = dispatch(CommandRequest::new_hdump("t1", key), &store);
+            assert_eq!(res.status, 200);
+            res.values[0].clone().try_into().unwrap()
+        };
+        let (d1, d2) = (dump("k1"), dump("k2"));
+
+        let other = MemTable::new();
+        let res = dispatch(
+            CommandRequest::new_hrestore("t2", "k1", d1.clone(), false),
+            &other,
+        );
+        assert_res_ok(&res, &[], &[]);
+        let res = dispatch(CommandRequest::new_hrestore("t2", "k2", d2, false), &other);
+        assert_res_ok(&res, &[], &[]);
+        let res = dispatch(CommandRequest::new_hget("t2", "k1"), &other);
+        assert_res_ok(&res, &["v1".into()], &[]);
+        let res = dispatch(CommandRequest::new_httl("t2", "k1"), &other);
+        assert_res_ok(&res, &[(-1i64).into()], &[]);
+        let ttl: i64 = (&dispatch(CommandRequest::new_httl("t2", "k2"), &other))
+            .try_into()
+            .unwrap();
+        assert!(ttl > 0 && ttl <= 60_000);
+
+        let res = dispatch(
+            CommandRequest::new_hrestore("t2", "k2", d1.clone(), false),
+            &other,
+        );
+        assert_res_error(&res, 412, "Condition not met");
+        let res = dispatch(CommandRequest::new_hrestore("t2", "k2", d1, true), &other);
+        assert_res_ok(&res, &[], &[]);
+        let res = dispatch(CommandRequest::new_httl("t2", "k2"), &other);
+        assert_res_ok(&res, &[(-1i64).into()], &[]);
+
+        let res = dispatch(CommandRequest::new_hdump("t1", "k3"), &store);
+        assert_res_error(&res, 404, "Not found");
+        let data = Bytes::from_static(b"not a dump");
+        let res = dispatch(
+            CommandRequest::new_hrestore("t2", "k3", data, false),
+            &other,
+        );
+        assert_res_error(&res, 400, "invalid dump");
+    }
+
+    #[test]
+    fn htouch_and_hmeta_should_work() {
+        let store = MemTable::new();
+        let before = now_ms();
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+        dispatch(CommandRequest::new_hset("t1", "k2", "v2".into()), &store);
+
+        let res = dispatch(CommandRequest::new_htouch("t1", ["k1", "k2", "k3"]), &store);
+        assert_res_ok(&res, &[2.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_hmeta("t1", "k1"), &store);
+        assert_eq!(res.status, 200);
+        let last_access: i64 = res.pairs[0].value.clone().unwrap().try_into().unwrap();
+        let idle_ms: i64 = res.pairs[1].value.clone().unwrap().try_into().unwrap();
+        assert_eq!(res.pairs[0].key, "last_access_ms");
+        assert!(last_access as u64 >= before && last_access as u64 <= now_ms());
+        assert!(idle_ms >= 0);
+
+        let res = dispatch(CommandRequest::new_hmeta("t1", "k3"), &store);
+        assert_res_error(&res, 404, "Not found");
+    }
+
+    #[test]
+    fn list_commands_should_work() {
+        let store = MemTable::new();
+        let values = |v: &[i64]| -> Vec<Value> { v.iter().map(|i| (*i).into()).collect() };
+
+        let res = dispatch(
+            CommandRequest::new_rpush("t1", "l1", values(&[3, 4])),
+            &store,
+        );
+        assert_res_ok(&res, &[2.into()], &[]);
+        let res = dispatch(
+            CommandRequest::new_lpush("t1", "l1", values(&[2, 1])),
+            &store,
+        );
+        assert_res_ok(&res, &[4.into()], &[]);
+        let res = dispatch(CommandRequest::new_lrange("t1", "l1", 0, -1), &store);
+        assert_res_ok(&res, &values(&[1, 2, 3, 4]), &[]);
+        let res = dispatch(CommandRequest::new_lrange("t1", "l1", 1, 2), &store);
+        assert_res_ok(&res, &values(&[2, 3]), &[]);
+        let res = dispatch(CommandRequest::new_lrange("t1", "l1", -2, 10), &store);
+        assert_res_ok(&res, &values(&[3, 4]), &[]);
+        let res = dispatch(CommandRequest::new_llen("t1", "l1"), &store);
+        assert_res_ok(&res, &[4.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_lpop("t1", "l1", 0), &store);
+        assert_res_ok(&res, &values(&[1]), &[]);
+        let res = dispatch(CommandRequest::new_rpop("t1", "l1", 2), &store);
+        assert_res_ok(&res, &values(&[4, 3]), &[]);
+        let res = dispatch(CommandRequest::new_lpop("t1", "l1", 5), &store);
+        assert_res_ok(&res, &values(&[2]), &[]);
+
+        // the empty list is deleted
+        let res = dispatch(CommandRequest::new_hmexist("t1", ["l1"]), &store);
+        assert_res_ok(&res, &[false.into()], &[]);
+        let res = dispatch(CommandRequest::new_rpop("t1", "l1", 1), &store);
+        assert_res_ok(&res, &[], &[]);
+        let res = dispatch(CommandRequest::new_llen("t1", "l1"), &store);
+        assert_res_ok(&res, &[0.into()], &[]);
+
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+        let res = dispatch(CommandRequest::new_lpush("t1", "k1", values(&[1])), &store);
+        assert_res_error(&res, 400, "List");
+        let res = dispatch(CommandRequest::new_rpush("t1", "l1", vec![]), &store);
+        assert_res_error(&res, 400, "no value to push");
+    }
+
+    #[test]
+    fn set_commands_should_work() {
+        let store = MemTable::new();
+        let members = |m: &[&str]| -> Vec<Value> { m.iter().map(|m| (*m).into()).collect() };
+
+        let res = dispatch(
+            CommandRequest::new_sadd("t1", "s1", ["b", "a", "b"]),
+            &store,
+        );
+        assert_res_ok(&res, &[2.into()], &[]);
+        let res = dispatch(CommandRequest::new_sadd("t1", "s1", ["c", "a"]), &store);
+        assert_res_ok(&res, &[1.into()], &[]);
+        let res = dispatch(CommandRequest::new_smembers("t1", "s1"), &store);
+        assert_res_ok(&res, &members(&["a", "b", "c"]), &[]);
+        let res = dispatch(CommandRequest::new_sismember("t1", "s1", "b"), &store);
+        assert_res_ok(&res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_sismember("t1", "s2", "b"), &store);
+        assert_res_ok(&res, &[false.into()], &[]);
+
+        dispatch(
+            CommandRequest::new_sadd("t1", "s2", ["c", "d", "b"]),
+            &store,
+        );
+        let res = dispatch(CommandRequest::new_sunion("t1", ["s1", "s2", "s3"]), &store);
+        assert_res_ok(&res, &members(&["a", "b", "c", "d"]), &[]);
+        let res = dispatch(CommandRequest::new_sinter("t1", ["s1", "s2"]), &store);
+        assert_res_ok(&res, &members(&["b", "c"]), &[]);
+        let res = dispatch(CommandRequest::new_sinter("t1", ["s1", "s3"]), &store);
+        assert_res_ok(&res, &[], &[]);
+
+        let res = dispatch(CommandRequest::new_srem("t1", "s1", ["a", "x"]), &store);
+        assert_res_ok(&res, &[1.into()], &[]);
+        dispatch(CommandRequest::new_srem("t1", "s1", ["b", "c"]), &store);
+        let res = dispatch(CommandRequest::new_hmexist("t1", ["s1"]), &store);
+        assert_res_ok(&res, &[false.into()], &[]);
+
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+        let res = dispatch(CommandRequest::new_sadd("t1", "k1", ["a"]), &store);
+        assert_res_error(&res, 400, "Set");
+    }
+
+    #[test]
+    fn zset_commands_should_work() {
+        let store = MemTable::new();
+        let members = [
+            ("alice", 30.0),
+            ("bob", 10.0),
+            ("carol", 20.0),
+            ("dave", 20.0),
+        ];
+        let res = dispatch(CommandRequest::new_zadd("t1", "z1", members), &store);
+        assert_res_ok(&res, &[4.into()], &[]);
+        let res = dispatch(
+            CommandRequest::new_zadd("t1", "z1", [("bob", 40.0), ("eve", 5.0)]),
+            &store,
+        );
+        assert_res_ok(&res, &[1.into()], &[]);
+
+        // assert_res_ok sorts the pairs, so compare the members in the order they are returned
+        let ranked = |cmd: CommandRequest| -> Vec<(String, f64)> {
+            let res = dispatch(cmd, &store);
+            assert_eq!(res.status, 200);
+            res.pairs
+                .into_iter()
+                .map(|p| (p.key, p.value.unwrap().try_into().unwrap()))
+                .collect()
+        };
+        let expected = |m: &[(&str, f64)]| -> Vec<(String, f64)> {
+            m.iter().map(|(k, s)| (k.to_string(), *s)).collect()
+        };
+
+        assert_eq!(
+            ranked(CommandRequest::new_zrange("t1", "z1", 0, -1)),
+            expected(&[
+                ("eve", 5.0),
+                ("carol", 20.0),
+                ("dave", 20.0),
+                ("alice", 30.0),
+                ("bob", 40.0)
+            ])
+        );
+        assert_eq!(
+            ranked(CommandRequest::new_zrange("t1", "z1", -2, -1)),
+            expected(&[("alice", 30.0), ("bob", 40.0)])
+        );
+        assert_eq!(
+            ranked(CommandRequest::new_zrangebyscore(
+                "t1", "z1", 20.0, 30.0, 0, 0
+            )),
+            expected(&[("carol", 20.0), ("dave", 20.0), ("alice", 30.0)])
+        );
+        assert_eq!(
+            ranked(CommandRequest::new_zrangebyscore(
+                "t1", "z1", 6.0, 100.0, 1, 2
+            )),
+            expected(&[("dave", 20.0), ("alice", 30.0)])
+        );
+        assert!(ranked(CommandRequest::new_zrangebyscore(
+            "t1", "z1", 50.0, 60.0, 0, 0
+        ))
+        .is_empty());
+        assert!(ranked(CommandRequest::new_zrange("t1", "z2", 0, -1)).is_empty());
+
+        let res = dispatch(
+            CommandRequest::new_zadd("t1", "z1", [("x", f64::NAN)]),
+            &store,
+        );
+        assert_res_error(&res, 400, "not a number");
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+        let res = dispatch(CommandRequest::new_zrange("t1", "k1", 0, -1), &store);
+        assert_res_error(&res, 400, "Zset");
+    }
+
+    #[test]
+    fn hincr_ex_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hincr_ex("t1", "k1", 1, 60_000), &store);
+        assert_res_ok(&res, &[1.into()], &[]);
+        let deadline = store.deadline("t1", "k1").unwrap().unwrap();
+        assert!(deadline > now_ms() && deadline <= now_ms() + 60_000);
+
+        // the next increments keep the deadline of the window
+        let res = dispatch(CommandRequest::new_hincr_ex("t1", "k1", 2, 120_000), &store);
+        assert_res_ok(&res, &[3.into()], &[]);
+        assert_eq!(store.deadline("t1", "k1").unwrap(), Some(deadline));
+
+        // a new window starts once the key expired
+        store.expire("t1", "k1", Some(0)).unwrap();
+        let res = dispatch(CommandRequest::new_hincr_ex("t1", "k1", 5, 60_000), &store);
+        assert_res_ok(&res, &[5.into()], &[]);
+        assert!(store.deadline("t1", "k1").unwrap().is_some());
+
+        // a window of 0 would expire the key as soon as it is created
+        let res = dispatch(CommandRequest::new_hincr_ex("t1", "k2", 1, 0), &store);
+        assert_res_error(&res, 400, "ttl of 0");
+        assert!(!store.contains("t1", "k2").unwrap());
+    }
+
+    #[test]
+    fn import_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", "old".into()), &store);
+        let pairs = vec![
+            Kvpair::new("k1", "v1".into()),
+            Kvpair::new("k2", "v2".into()),
+        ];
+        let res = dispatch(CommandRequest::new_import("t1", pairs.clone()), &store);
+        assert_res_ok(&res, &[2.into()], &[]);
+        let res = dispatch(CommandRequest::new_hgetall("t1"), &store);
+        assert_res_ok(&res, &[], &pairs);
+    }
+
+    #[test]
+    fn hdelpattern_should_work() {
+        let store = MemTable::new();
+        for key in ["user:1", "user:2", "order:1"] {
+            dispatch(CommandRequest::new_hset("t1", key, "v".into()), &store);
+        }
+        let res = dispatch(CommandRequest::new_hdelpattern("t1", "user:*"), &store);
+        assert_res_ok(&res, &[2.into()], &[]);
+        let res = dispatch(CommandRequest::new_hkeys("t1"), &store);
+        assert_res_ok(&res, &["order:1".into()], &[]);
+
+        let res = dispatch(CommandRequest::new_hdelpattern("t1", "user:*"), &store);
+        assert_res_ok(&res, &[0.into()], &[]);
+    }
+
+    #[test]
+    fn hset_with_version_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+        let v1 = res.version;
+        assert!(v1 > 0);
+        let res = dispatch(CommandRequest::new_hget("t1", "k1"), &store);
+        assert_eq!(res.version, v1);
 
+        let cmd = CommandRequest::new_hset("t1", "k1", "v2".into()).if_version(v1);
+        let res = dispatch(cmd.clone(), &store);
+        assert_res_ok(&res, &["v1".into()], &[]);
+        assert!(res.version > v1);
+
+        // the version has changed since, the write is rejected
         let res = dispatch(cmd, &store);
-        assert_res_ok(&res, &["world".into()], &[]);
+        assert_res_error(&res, 412, "Condition not met");
+        let res = dispatch(CommandRequest::new_hget("t1", "k1"), &store);
+        assert_res_ok(&res, &["v2".into()], &[]);
+
+        let cmd = CommandRequest::new_hset("t1", "k2", "v1".into()).if_version(v1);
+        let res = dispatch(cmd, &store);
+        assert_res_error(&res, 412, "Condition not met");
+    }
+
+    #[test]
+    fn hgetmeta_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hgetmeta("t1", "k1"), &store);
+        assert_res_error(&res, 404, "Not found");
+
+        let before = now_ms();
+        let res = dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+        let version = res.version;
+        let res = dispatch(CommandRequest::new_hgetmeta("t1", "k1"), &store);
+        assert_eq!(res.values, vec!["v1".into()]);
+        assert_eq!(res.version, version);
+        let meta: std::collections::HashMap<_, _> = res
+            .pairs
+            .iter()
+            .map(|pair| {
+                (
+                    pair.key.as_str(),
+                    i64::try_from(pair.value.as_ref().unwrap()),
+                )
+            })
+            .map(|(key, value)| (key, value.unwrap()))
+            .collect();
+        assert_eq!(meta["version"], version as i64);
+        assert_eq!(meta["ttl_ms"], -1);
+        assert_eq!(meta["size"], Value::from("v1").encoded_len() as i64);
+        assert!(meta["modified_ms"] >= before as i64 && meta["modified_ms"] <= now_ms() as i64);
+
+        store.expire("t1", "k1", Some(now_ms() + 10_000)).unwrap();
+        let res = dispatch(CommandRequest::new_hgetmeta("t1", "k1"), &store);
+        let ttl = res.pairs.iter().find(|pair| pair.key == "ttl_ms").unwrap();
+        let ttl = i64::try_from(ttl.value.as_ref().unwrap()).unwrap();
+        assert!(ttl > 0 && ttl <= 10_000);
+    }
+
+    #[test]
+    fn hstrlen_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hstrlen("t1", "k1"), &store);
+        assert_res_ok(&res, &[0.into()], &[]);
+
+        let blob = Bytes::from(vec![0u8; 1000]);
+        dispatch(
+            CommandRequest::new_hset("t1", "k1", blob.clone().into()),
+            &store,
+        );
+        let res = dispatch(CommandRequest::new_hstrlen("t1", "k1"), &store);
+        let len = Value::from(blob).encoded_len() as i64;
+        assert_res_ok(&res, &[len.into()], &[]);
+        assert!(len > 1000);
+    }
+
+    #[test]
+    fn hsetnx_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hsetnx("t1", "k1", "v1".into()), &store);
+        assert_res_ok(&res, &[true.into()], &[]);
+
+        // the existing key is not written again
+        let version = store.version("t1", "k1").unwrap();
+        let res = dispatch(CommandRequest::new_hsetnx("t1", "k1", "v2".into()), &store);
+        assert_res_ok(&res, &[false.into()], &[]);
+        assert_eq!(store.version("t1", "k1").unwrap(), version);
+        let res = dispatch(CommandRequest::new_hget("t1", "k1"), &store);
+        assert_res_ok(&res, &["v1".into()], &[]);
+    }
+
+    #[test]
+    fn txn_should_apply_all_or_none() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", 1.into()), &store);
+        let txn = CommandRequest::new_txn(vec![
+            CommandRequest::new_hset("t1", "k1", 2.into()),
+            CommandRequest::new_hmset("t2", vec![Kvpair::new("k2", 3.into())]),
+            CommandRequest::new_hdel("t1", "missing"),
+            CommandRequest::new_hdel("t1", "k1"),
+        ]);
+        let res = dispatch(txn, &store);
+        assert_eq!(res.status, 200);
+        let statuses: Vec<_> = res.responses.iter().map(|res| res.status).collect();
+        assert_eq!(statuses, [200, 200, 404, 200]);
+        assert_eq!(res.responses[0].values, [1.into()]);
+        assert_eq!(res.responses[3].values, [2.into()]);
+        assert_eq!(store.get("t1", "k1").unwrap(), None);
+        assert_eq!(store.get("t2", "k2").unwrap(), Some(3.into()));
+
+        // a command that cannot be applied in a transaction rejects all of them
+        let txn = CommandRequest::new_txn(vec![
+            CommandRequest::new_hset("t1", "k1", 4.into()),
+            CommandRequest::new_hget("t1", "k1"),
+        ]);
+        let res = dispatch(txn, &store);
+        assert_res_error(&res, 400, "hget is not allowed");
+        assert_eq!(store.get("t1", "k1").unwrap(), None);
     }
 
     #[test]
@@ -68,6 +1916,91 @@ mod tests {
         assert_res_error(&res, 404, "Not found");
     }
 
+    #[test]
+    fn hmget_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+        dispatch(CommandRequest::new_hset("t1", "k3", 3.into()), &store);
+
+        let cmd = CommandRequest::new_hmget("t1", ["k1", "k2", "k3"]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &["v1".into(), Value::default(), 3.into()], &[]);
+    }
+
+    #[test]
+    fn hmexist_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+
+        let cmd = CommandRequest::new_hmexist("t1", ["k1", "k2"]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &[true.into(), false.into()], &[]);
+    }
+
+    #[test]
+    fn mget_should_work() {
+        let store = MemTable::new();
+        dispatch(
+            CommandRequest::new_hset("session", "s1", "u1".into()),
+            &store,
+        );
+        dispatch(
+            CommandRequest::new_hset("profile", "u1", "alice".into()),
+            &store,
+        );
+
+        let cmd =
+            CommandRequest::new_mget([("session", "s1"), ("profile", "u1"), ("profile", "u2")]);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(&res, &["u1".into(), "alice".into(), Value::default()], &[]);
+    }
+
+    #[test]
+    fn hrandfield_should_work() {
+        let store = MemTable::new();
+        for i in 0..10 {
+            dispatch(
+                CommandRequest::new_hset("t1", format!("k{i}"), i.into()),
+                &store,
+            );
+        }
+
+        let res = dispatch(CommandRequest::new_hrandfield("t1", 3, false), &store);
+        let mut keys = res.values.clone();
+        keys.dedup();
+        assert_eq!(keys.len(), 3);
+
+        // a positive count never returns more than the table has
+        let res = dispatch(CommandRequest::new_hrandfield("t1", 20, true), &store);
+        assert_eq!(res.pairs.len(), 10);
+
+        // a negative count returns exactly the count, with repeated keys
+        let res = dispatch(CommandRequest::new_hrandfield("t1", -20, false), &store);
+        assert_eq!(res.values.len(), 20);
+
+        // the huge counts are capped
+        let res = dispatch(CommandRequest::new_hrandfield("t1", i64::MAX, true), &store);
+        assert_eq!(res.pairs.len(), 10);
+        let res = dispatch(
+            CommandRequest::new_hrandfield("t1", i64::MIN, false),
+            &store,
+        );
+        assert_eq!(res.values.len(), MAX_RANDOM_FIELDS);
+
+        let res = dispatch(
+            CommandRequest::new_hrandfield("unexisting", 3, false),
+            &store,
+        );
+        assert_res_ok(&res, &[], &[]);
+    }
+
+    #[test]
+    fn flush_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_flush(), &store);
+        assert_res_ok(&res, &[], &[]);
+    }
+
     #[test]
     fn hgetall_should_work() {
         let store = MemTable::new();
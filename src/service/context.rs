@@ -0,0 +1,66 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, RwLock,
+    },
+};
+
+/// The context of a client connection, shared by all streams of a multiplexed connection.
+/// The default context is anonymous, all anonymous callers share the id 0.
+#[derive(Debug, Clone, Default)]
+pub struct ConnContext {
+    inner: Arc<ConnInfo>,
+}
+
+#[derive(Debug, Default)]
+struct ConnInfo {
+    /// The id of the connection, unique in the service
+    id: u64,
+    /// The address of the client
+    peer: Option<SocketAddr>,
+    /// The selected logical database
+    db: AtomicU32,
+    /// The namespace in use, empty for the default one
+    namespace: RwLock<String>,
+}
+
+impl ConnContext {
+    pub(crate) fn new(id: u64, peer: Option<SocketAddr>) -> Self {
+        Self {
+            inner: Arc::new(ConnInfo {
+                id,
+                peer,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// The id of the connection
+    pub fn id(&self) -> u64 {
+        self.inner.id
+    }
+
+    /// The address of the client
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.inner.peer
+    }
+
+    /// The logical database selected by the connection, 0 until one is selected
+    pub fn db(&self) -> u32 {
+        self.inner.db.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn select(&self, db: u32) {
+        self.inner.db.store(db, Ordering::Relaxed);
+    }
+
+    /// The namespace used by the connection, empty until one is used
+    pub fn namespace(&self) -> String {
+        self.inner.namespace.read().unwrap().clone()
+    }
+
+    pub(crate) fn use_namespace(&self, namespace: String) {
+        *self.inner.namespace.write().unwrap() = namespace;
+    }
+}
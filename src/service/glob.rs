@@ -0,0 +1,64 @@
+/// Match a key against a glob pattern: `*` matches any run of characters, `?` matches
+/// one character, and `\` escapes the next character.
+pub(crate) fn glob_match(pattern: &str, key: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let key: Vec<char> = key.chars().collect();
+    let (mut p, mut k) = (0, 0);
+    // the position after the last `*` and the key position it was tried at, to backtrack
+    let mut star: Option<(usize, usize)> = None;
+
+    while k < key.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, k));
+                p += 1;
+                continue;
+            }
+            Some('?') => {
+                p += 1;
+                k += 1;
+                continue;
+            }
+            Some('\\') if p + 1 < pattern.len() && pattern[p + 1] == key[k] => {
+                p += 2;
+                k += 1;
+                continue;
+            }
+            Some(c) if *c != '\\' && *c == key[k] => {
+                p += 1;
+                k += 1;
+                continue;
+            }
+            _ => {}
+        }
+        // let the last `*` take one more character
+        match star {
+            Some((sp, sk)) => {
+                star = Some((sp, sk + 1));
+                p = sp;
+                k = sk + 1;
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_match_should_work() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("user:*", "user:42"));
+        assert!(glob_match("user:*:name", "user:42:name"));
+        assert!(!glob_match("user:*:name", "user:42:age"));
+        assert!(glob_match("k?", "k1"));
+        assert!(!glob_match("k?", "k12"));
+        assert!(glob_match("*a*b", "xaxxab"));
+        assert!(glob_match(r"k\*", "k*"));
+        assert!(!glob_match(r"k\*", "k1"));
+        assert!(!glob_match("abc", "ab"));
+    }
+}
@@ -0,0 +1,498 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard},
+};
+
+use prost::Message;
+use tracing::{info, warn};
+
+use crate::{CommandRequest, Hset, KvError, Kvpair, RequestData, Storage};
+
+use super::dispatch;
+
+/// The extension of the segments holding the appended commands
+const LOG_EXT: &str = "log";
+
+/// The extension of the segments holding the compacted state, the replay starts from the last one
+const BASE_EXT: &str = "base";
+
+/// The configuration of a command journal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalConfig {
+    /// The directory of the segments
+    pub dir: PathBuf,
+    /// Rotate to a new segment once the current one reaches this size in bytes
+    pub segment_size: u64,
+    /// Compact the journal once it has this many segments after the last compaction
+    pub max_segments: usize,
+    /// Sync every append to disk, otherwise the appends are synced on Flush and on rotation
+    pub sync_every_write: bool,
+}
+
+impl JournalConfig {
+    /// Journal to `dir` in segments of 64MB, compacted every 8 segments
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            segment_size: 64 * 1024 * 1024,
+            max_segments: 8,
+            sync_every_write: false,
+        }
+    }
+
+    /// Set the size of the segments
+    pub fn segment_size(mut self, size: u64) -> Self {
+        self.segment_size = size;
+        self
+    }
+
+    /// Set the number of segments triggering a compaction
+    pub fn max_segments(mut self, n: usize) -> Self {
+        self.max_segments = n;
+        self
+    }
+
+    /// Sync every append to disk
+    pub fn sync_every_write(mut self, sync: bool) -> Self {
+        self.sync_every_write = sync;
+        self
+    }
+}
+
+/// An append-only journal of the successful writes, replayed into a fresh store at startup.
+///
+/// The journal is a directory of segments numbered in order, each a sequence of length-delimited
+/// `CommandRequest` messages. The tables of the commands are qualified by their namespace and
+/// database, and the relative deadlines are followed by an absolute `Hexpireat`, so a replay
+/// rebuilds the same state whenever it runs. A compaction writes the state of the store to a
+/// base segment and removes the segments before it.
+#[derive(Debug)]
+pub struct Journal {
+    config: JournalConfig,
+    /// Shared by the writes from their execution to their append, and held exclusively by the
+    /// compaction, so a compacted journal neither misses nor repeats a write
+    gate: RwLock<()>,
+    segment: Mutex<Segment>,
+}
+
+/// The segment the commands are appended to
+#[derive(Debug)]
+struct Segment {
+    seq: u64,
+    file: File,
+    size: u64,
+    /// The number of segments since the last base, this one included
+    count: usize,
+}
+
+impl Journal {
+    /// Open the journal in its directory, creating it if needed, and append to its last segment
+    pub fn open(config: JournalConfig) -> Result<Self, KvError> {
+        fs::create_dir_all(&config.dir)?;
+        let segments = list_segments(&config.dir)?;
+        let base = segments.iter().rposition(|(_, ext)| ext == BASE_EXT);
+        let count = segments.len() - base.map_or(0, |i| i + 1);
+        let segment = match segments.last() {
+            Some((seq, ext)) if ext == LOG_EXT => {
+                let path = segment_path(&config.dir, *seq, LOG_EXT);
+                let file = OpenOptions::new().append(true).open(&path)?;
+                let size = file.metadata()?.len();
+                Segment {
+                    seq: *seq,
+                    file,
+                    size,
+                    count,
+                }
+            }
+            last => {
+                let seq = last.map_or(1, |(seq, _)| seq + 1);
+                Segment::create(&config.dir, seq, count + 1)?
+            }
+        };
+        Ok(Self {
+            config,
+            gate: RwLock::new(()),
+            segment: Mutex::new(segment),
+        })
+    }
+
+    /// Execute the commands of the journal against the store, from the last base segment on,
+    /// and return the number of commands replayed. A truncated command at the end of the last
+    /// segment, left by a crash in the middle of an append, is dropped.
+    pub fn replay(&self, store: &impl Storage) -> Result<usize, KvError> {
+        let mut segment = self.lock_segment();
+        let segments = list_segments(&self.config.dir)?;
+        let start = segments
+            .iter()
+            .rposition(|(_, ext)| ext == BASE_EXT)
+            .unwrap_or(0);
+        let mut count = 0;
+        for (seq, ext) in &segments[start..] {
+            let data = fs::read(segment_path(&self.config.dir, *seq, ext))?;
+            let mut buf = &data[..];
+            while !buf.is_empty() {
+                // the decoding may consume the prefix of a truncated command
+                let valid = (data.len() - buf.len()) as u64;
+                let cmd = match CommandRequest::decode_length_delimited(&mut buf) {
+                    Ok(cmd) => cmd,
+                    Err(e) if *seq == segment.seq => {
+                        warn!(error = ?e, "Dropped a truncated command at {} of the journal", valid);
+                        segment.file.set_len(valid)?;
+                        segment.size = valid;
+                        break;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                let res = dispatch(cmd, store);
+                if res.status >= 500 {
+                    return Err(KvError::Internal(format!(
+                        "failed to replay the journal: {}",
+                        res.message
+                    )));
+                }
+                count += 1;
+            }
+        }
+        info!("Replayed {} commands of the journal", count);
+        Ok(count)
+    }
+
+    /// Hold the journal open to the writes until the guard is dropped, the writes must be
+    /// appended before then
+    pub(crate) fn writing(&self) -> RwLockReadGuard<'_, ()> {
+        self.gate.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Append a successful write, and return whether the journal should be compacted. A failed append is logged, the write is applied to the store anyway.
+    pub(crate) fn append(&self, cmd: &CommandRequest, store: &impl Storage) -> bool {
+        let mut cmds = vec![cmd.clone()];
+        if let Some(RequestData::Hset(req)) = &mut cmds[0].request_data {
+            // the condition on the version was met, which the replay cannot check
+            req.if_version = 0;
+        }
+        // the relative deadlines are made absolute, so the replay restores the same ones
+        let target = match &cmd.request_data {
+            Some(RequestData::Hexpire(req)) => Some((&req.table, &req.key)),
+            Some(RequestData::HincrEx(req)) => Some((&req.table, &req.key)),
+            Some(RequestData::Hrestore(req)) => Some((&req.table, &req.key)),
+            _ => None,
+        };
+        if let Some((table, key)) = target {
+            if let Ok(Some(deadline)) = store.deadline(table, key) {
+                cmds.push(CommandRequest::new_hexpireat(table, key, deadline));
+            }
+        }
+        self.write(&cmds)
+    }
+
+    /// Append the writes of a script as one transaction
+    #[cfg(feature = "scripting")]
+    pub(crate) fn append_ops(&self, ops: &[crate::WriteOp]) -> bool {
+        use crate::{Txn, WriteOp};
+
+        let requests = ops
+            .iter()
+            .map(|op| match op {
+                WriteOp::Set { table, key, value } => {
+                    CommandRequest::new_hset(table, key, value.clone())
+                }
+                WriteOp::Del { table, key } => CommandRequest::new_hdel(table, key),
+            })
+            .collect();
+        let txn = CommandRequest {
+            request_data: Some(RequestData::Txn(Txn { requests })),
+            ..Default::default()
+        };
+        self.write(&[txn])
+    }
+
+    /// Write the state of the store to a new base segment, and remove the segments before it.
+    /// The writes wait for the compaction, the point reads do not.
+    pub fn compact(&self, store: &impl Storage) -> Result<usize, KvError> {
+        let _gate = self.gate.write().unwrap_or_else(|e| e.into_inner());
+        let mut segment = self.lock_segment();
+        let mut snapshot = Vec::new();
+        let count = store.snapshot(&mut snapshot)?;
+
+        let seq = segment.seq + 1;
+        let path = segment_path(&self.config.dir, seq, BASE_EXT);
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        let mut buf = Vec::new();
+        let mut pairs = &snapshot[..];
+        while !pairs.is_empty() {
+            let Hset { table, pair, .. } = Hset::decode_length_delimited(&mut pairs)?;
+            let Kvpair { key, value } = pair.unwrap_or_default();
+            let deadline = store.deadline(&table, &key)?;
+            let mut cmds = vec![CommandRequest::new_hset(
+                &table,
+                &key,
+                value.unwrap_or_default(),
+            )];
+            if let Some(deadline) = deadline {
+                cmds.push(CommandRequest::new_hexpireat(&table, &key, deadline));
+            }
+            for cmd in cmds {
+                cmd.encode_length_delimited(&mut buf)?;
+            }
+            file.write_all(&buf)?;
+            buf.clear();
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+
+        *segment = Segment::create(&self.config.dir, seq + 1, 1)?;
+        for (old, ext) in list_segments(&self.config.dir)? {
+            if old < seq {
+                fs::remove_file(segment_path(&self.config.dir, old, &ext))?;
+            }
+        }
+        info!("Compacted the journal to {} pairs", count);
+        Ok(count)
+    }
+
+    /// Sync the appended commands to disk
+    pub fn sync(&self) -> Result<(), KvError> {
+        Ok(self.lock_segment().file.sync_data()?)
+    }
+
+    fn write(&self, cmds: &[CommandRequest]) -> bool {
+        let mut buf = Vec::new();
+        for cmd in cmds {
+            // encoding into a vec never fails
+            _ = cmd.encode_length_delimited(&mut buf);
+        }
+        let mut segment = self.lock_segment();
+        let written = segment.file.write_all(&buf).and_then(|_| {
+            if self.config.sync_every_write {
+                segment.file.sync_data()?;
+            }
+            Ok(())
+        });
+        if let Err(e) = written {
+            warn!(error = ?e, "Failed to append to the journal");
+            return false;
+        }
+        segment.size += buf.len() as u64;
+        if segment.size < self.config.segment_size {
+            return false;
+        }
+
+        let next = segment.seq + 1;
+        let rotated = segment
+            .file
+            .sync_data()
+            .map_err(KvError::from)
+            .and_then(|_| Segment::create(&self.config.dir, next, segment.count + 1));
+        match rotated {
+            Ok(new) => *segment = new,
+            Err(e) => warn!(error = ?e, "Failed to rotate the journal"),
+        }
+        segment.count > self.config.max_segments
+    }
+
+    fn lock_segment(&self) -> MutexGuard<'_, Segment> {
+        self.segment.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Segment {
+    fn create(dir: &Path, seq: u64, count: usize) -> Result<Self, KvError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(dir, seq, LOG_EXT))?;
+        Ok(Self {
+            seq,
+            file,
+            size: 0,
+            count,
+        })
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        if let Err(e) = self.sync() {
+            warn!(error = ?e, "Failed to sync the journal");
+        }
+    }
+}
+
+/// Whether the command writes to the store, and so is journaled once it succeeds
+pub(crate) fn is_journaled(cmd: &CommandRequest) -> bool {
+    matches!(
+        cmd.request_data,
+        Some(RequestData::Hset(_))
+            | Some(RequestData::Hmset(_))
+            | Some(RequestData::Hdel(_))
+            | Some(RequestData::Hmdel(_))
+            | Some(RequestData::Hexpire(_))
+            | Some(RequestData::Hexpireat(_))
+            | Some(RequestData::Hpersist(_))
+            | Some(RequestData::Hincr(_))
+            | Some(RequestData::HincrEx(_))
+            | Some(RequestData::Hsetnx(_))
+            | Some(RequestData::Hgetdel(_))
+            | Some(RequestData::Hgetset(_))
+            | Some(RequestData::Happend(_))
+            | Some(RequestData::Hcleartable(_))
+            | Some(RequestData::Hcopy(_))
+            | Some(RequestData::Hmove(_))
+            | Some(RequestData::Hrestore(_))
+            | Some(RequestData::Hdelpattern(_))
+            | Some(RequestData::Txn(_))
+            | Some(RequestData::Lpush(_))
+            | Some(RequestData::Rpush(_))
+            | Some(RequestData::Lpop(_))
+            | Some(RequestData::Rpop(_))
+            | Some(RequestData::Sadd(_))
+            | Some(RequestData::Srem(_))
+            | Some(RequestData::Zadd(_))
+            | Some(RequestData::Import(_))
+    )
+}
+
+/// The segments of the directory in order, with their extensions
+fn list_segments(dir: &Path) -> Result<Vec<(u64, String)>, KvError> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let (Some(stem), Some(ext)) = (path.file_stem(), path.extension()) else {
+            continue;
+        };
+        let ext = ext.to_string_lossy();
+        if ext != LOG_EXT && ext != BASE_EXT {
+            continue;
+        }
+        if let Ok(seq) = stem.to_string_lossy().parse() {
+            segments.push((seq, ext.into_owned()));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+fn segment_path(dir: &Path, seq: u64, ext: &str) -> PathBuf {
+    dir.join(format!("{:020}.{}", seq, ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{assert_res_error, assert_res_ok, MemTable, Service, ServiceInner};
+
+    async fn run(service: &Service, cmd: CommandRequest) -> crate::CommandResponse {
+        let ctx = service.new_context(None);
+        let res = service.execute_with(cmd, &ctx).next().await.unwrap();
+        std::sync::Arc::unwrap_or_clone(res)
+    }
+
+    fn reopen(config: &JournalConfig) -> Service {
+        let journal = Journal::open(config.clone()).unwrap();
+        let store = MemTable::new();
+        journal.replay(&store).unwrap();
+        ServiceInner::new(store).journal(journal).into()
+    }
+
+    #[tokio::test]
+    async fn journal_should_replay_the_writes() {
+        let dir = tempdir().unwrap();
+        let config = JournalConfig::new(dir.path());
+        let service = reopen(&config);
+        run(&service, CommandRequest::new_hset("t1", "k1", "v1".into())).await;
+        run(&service, CommandRequest::new_hset("t1", "k2", "v2".into())).await;
+        run(&service, CommandRequest::new_hincr("t1", "n", 2)).await;
+        run(&service, CommandRequest::new_hincr("t1", "n", 3)).await;
+        run(&service, CommandRequest::new_hexpire("t1", "k1", 60_000)).await;
+        run(&service, CommandRequest::new_hdel("t1", "k2")).await;
+        run(&service, CommandRequest::new_hget("t1", "k1")).await;
+        let batch = CommandRequest::new_batch(vec![
+            CommandRequest::new_select(1),
+            CommandRequest::new_hset("t1", "k1", "db1".into()),
+        ]);
+        run(&service, batch).await;
+        drop(service);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let service = reopen(&config);
+        let data = run(&service, CommandRequest::new_hget("t1", "k1")).await;
+        assert_res_ok(&data, &["v1".into()], &[]);
+        let data = run(&service, CommandRequest::new_hget("t1", "n")).await;
+        assert_res_ok(&data, &[5.into()], &[]);
+        let data = run(&service, CommandRequest::new_hget("t1", "k2")).await;
+        assert_res_error(&data, 404, "Not found");
+        let batch = CommandRequest::new_batch(vec![
+            CommandRequest::new_select(1),
+            CommandRequest::new_hget("t1", "k1"),
+        ]);
+        let data = run(&service, batch).await;
+        assert_res_ok(&data.responses[1], &["db1".into()], &[]);
+
+        // the deadline is restored as it was, not restarted by the replay
+        let data = run(&service, CommandRequest::new_httl("t1", "k1")).await;
+        let ttl: i64 = (&data.values[0]).try_into().unwrap();
+        assert!(ttl > 0 && ttl <= 60_000 - 50, "{}", ttl);
+    }
+
+    #[tokio::test]
+    async fn journal_should_rotate_and_compact() {
+        let dir = tempdir().unwrap();
+        let config = JournalConfig::new(dir.path())
+            .segment_size(1)
+            .max_segments(3);
+        let service = reopen(&config);
+        for i in 0..10 {
+            let cmd = CommandRequest::new_hset("t1", format!("k{}", i % 3), i.into());
+            run(&service, cmd).await;
+        }
+        run(&service, CommandRequest::new_hdel("t1", "k0")).await;
+        drop(service);
+
+        let segments = list_segments(dir.path()).unwrap();
+        assert!(segments.len() <= 4, "{:?}", segments);
+        assert!(segments.iter().any(|(_, ext)| ext == BASE_EXT));
+
+        let service = reopen(&config);
+        let data = run(&service, CommandRequest::new_hmget("t1", ["k1", "k2"])).await;
+        assert_res_ok(&data, &[7.into(), 8.into()], &[]);
+        let data = run(&service, CommandRequest::new_hget("t1", "k0")).await;
+        assert_res_error(&data, 404, "Not found");
+    }
+
+    #[tokio::test]
+    async fn journal_should_drop_a_truncated_append() {
+        let dir = tempdir().unwrap();
+        let config = JournalConfig::new(dir.path());
+        let service = reopen(&config);
+        run(&service, CommandRequest::new_hset("t1", "k1", "v1".into())).await;
+        drop(service);
+
+        // a crash in the middle of an append leaves the prefix of a command
+        let (seq, _) = list_segments(dir.path()).unwrap().pop().unwrap();
+        let path = segment_path(dir.path(), seq, LOG_EXT);
+        let mut buf = Vec::new();
+        CommandRequest::new_hset("t1", "k2", "v2".into())
+            .encode_length_delimited(&mut buf)
+            .unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&buf[..buf.len() - 2]).unwrap();
+
+        let service = reopen(&config);
+        run(&service, CommandRequest::new_hset("t1", "k3", "v3".into())).await;
+        drop(service);
+
+        let service = reopen(&config);
+        let data = run(&service, CommandRequest::new_hget("t1", "k2")).await;
+        assert_res_error(&data, 404, "Not found");
+        let data = run(&service, CommandRequest::new_hget("t1", "k3")).await;
+        assert_res_ok(&data, &["v3".into()], &[]);
+    }
+}
@@ -0,0 +1,15 @@
+use futures::future::BoxFuture;
+
+use crate::{KvError, Value};
+
+/// Loads the values missing from the storage from an external source, like the database
+/// kvdb is caching. Register it with `ServiceInner::loader` to read through on Hget misses.
+pub trait Loader: Send + Sync + 'static {
+    /// Load the value of a key, None if the source does not have it either.
+    /// The table is named as the client names it, whatever its database and namespace.
+    fn load<'a>(
+        &'a self,
+        table: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Value>, KvError>>;
+}
@@ -1,15 +1,67 @@
 mod command_service;
+mod context;
+mod glob;
+mod journal;
+mod loader;
+mod quota;
+mod rate_limit;
+mod script;
+mod sink;
+mod stats;
 mod topic;
 mod topic_service;
 
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Cursor},
+    net::SocketAddr,
+    path::PathBuf,
+    slice,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock, RwLockReadGuard, Weak,
+    },
+    time::Duration,
+};
 
-use futures::stream;
+use futures::{future, stream, StreamExt};
+use http::StatusCode;
+use sink::WriteBehind;
+use tokio::{
+    runtime::Handle,
+    time::{self, Instant},
+};
 use topic::{Broadcaster, Topic};
 use topic_service::{StreamingResponse, TopicService};
-use tracing::{debug, info};
+use tracing::{debug, field, info_span, warn, Instrument, Span};
 
-use crate::{CommandRequest, CommandResponse, KvError, MemTable, RequestData, Storage};
+use crate::{
+    check_size, is_reserved_table, now_ms, read_dump, read_ndjson, scoped_table, spawn_named,
+    storage::restore_pairs, unscoped_table, validate_namespace, write_dump, write_ndjson,
+    CommandRequest, CommandResponse, Export, Hgetset, Hkeys, Hmset, Hset, Hsetnx, Hvals, Import,
+    KvError, Lpush, MemTable, RequestData, Rpush, Storage, Value,
+};
+
+pub use context::ConnContext;
+pub use journal::{Journal, JournalConfig};
+pub use loader::Loader;
+pub use quota::TableQuota;
+pub use sink::{MutationSink, SinkConfig};
+pub use stats::{ServerStats, STATS_TABLE};
+pub use topic::{BroadcasterConfig, SlowSubscriber};
+
+/// The default interval of the keepalive frames on subscription streams
+const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The default number of logical databases, like Redis
+const DEFAULT_DATABASES: u32 = 16;
+
+/// The default number of pairs of an Export frame
+const DEFAULT_EXPORT_CHUNK_SIZE: usize = 100;
+
+/// The default interval of the sweeps removing the expired keys
+const DEFAULT_EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
 
 /// A trait for command service
 pub trait CommandService {
@@ -17,44 +69,713 @@ pub trait CommandService {
     fn execute(self, store: &impl Storage) -> CommandResponse;
 }
 
-pub struct Service<Store = MemTable> {
+pub struct Service<Store: Storage = MemTable> {
     inner: Arc<ServiceInner<Store>>,
     broadcaster: Arc<Broadcaster>,
 }
 
-pub struct ServiceInner<Store> {
+pub struct ServiceInner<Store: Storage> {
     store: Store,
+    /// The cumulative counters, persisted in the storage
+    stats: Arc<ServerStats>,
+    /// The interval of the keepalive frames on subscription streams, None to disable
+    heartbeat: Option<Duration>,
+    /// The configuration of the topic broadcaster
+    broadcaster: BroadcasterConfig,
+    /// Loads the values Hget misses, None to answer the misses with NotFound
+    loader: Option<Box<dyn Loader>>,
+    /// Forwards the successful writes, None to forward nothing
+    sink: Option<WriteBehind>,
+    /// Journals the successful writes, None to journal nothing
+    journal: Option<Journal>,
+    /// The number of logical databases the connections could select
+    databases: u32,
+    /// The interval of the sweeps removing the expired keys, None to disable the sweeps
+    expire_interval: Option<Duration>,
+    /// The max length of a key, None for unlimited
+    max_key_len: Option<usize>,
+    /// The max encoded size of a value, None for unlimited
+    max_value_size: Option<usize>,
+    /// The characters allowed in the table names, None to allow any
+    table_chars: Option<fn(char) -> bool>,
+    /// The max length of a request frame, checked before the frame is read into memory
+    max_frame_len: Option<usize>,
+    /// The directory of the Backup and Restore files, None to disable the commands
+    backup_dir: Option<PathBuf>,
+    /// The quotas of the tables by name, applied to the table in every database and namespace
+    quotas: HashMap<String, TableQuota>,
+    /// Held shared by the writes, and exclusively by a script checking its reads and
+    /// committing its writes, so no write slips in between
+    write_gate: RwLock<()>,
+    /// The last allocated connection id
+    last_conn_id: AtomicU64,
+    /// The last allocated request id, used to correlate the logs of a request
+    last_request_id: AtomicU64,
     on_received: Vec<fn(&CommandRequest)>,
-    on_executed: Vec<fn(&CommandResponse)>,
+    /// Called with the request, the response and the execution duration
+    on_executed: Vec<fn(&CommandRequest, &CommandResponse, Duration)>,
     on_before_send: Vec<fn(&mut CommandResponse)>,
     on_after_send: Vec<fn()>,
 }
 
 impl<Store: Storage> Service<Store> {
+    /// Create the context of a new client connection
+    pub fn new_context(&self, peer: Option<SocketAddr>) -> ConnContext {
+        let id = self.inner.last_conn_id.fetch_add(1, Ordering::Relaxed) + 1;
+        ConnContext::new(id, peer)
+    }
+
+    /// The max length of a request frame
+    pub fn max_frame_len(&self) -> Option<usize> {
+        self.inner.max_frame_len
+    }
+
+    /// Execute the command with an anonymous connection context
     pub fn execute(&self, cmd: CommandRequest) -> StreamingResponse {
-        debug!("Got request: {:?}", cmd);
+        self.execute_with(cmd, &ConnContext::default())
+    }
+
+    /// Execute the command on behalf of the given connection
+    pub fn execute_with(&self, cmd: CommandRequest, ctx: &ConnContext) -> StreamingResponse {
+        let span = self.inner.request_span(&cmd, ctx);
+        let _enter = span.enter();
+        debug!(request = ?cmd, "Got request");
         self.inner.on_received.notify(&cmd);
-        let mut res = dispatch(cmd.clone(), &self.inner.store);
+        let start = Instant::now();
+        if self.inner.stats.command() {
+            self.inner.persist_stats();
+        }
+
+        let mut cmd = cmd;
+        let checked = self
+            .inner
+            .check_limits(&cmd)
+            .and_then(|_| self.inner.check_tables(&mut cmd));
+        if let Err(e) = checked {
+            let res = self.inner.finish(&cmd, e.into(), start.elapsed());
+            return Box::pin(stream::once(async { res }));
+        }
+
+        // the admin commands on the counters are served by the service itself
+        match &cmd.request_data {
+            Some(RequestData::Stats(_)) => {
+                // the counters of the server, then the stats of the storage, with only the
+                // tables the connection could name
+                let res = match self.inner.store.stats() {
+                    Ok(storage) => {
+                        let mut res = CommandResponse::ok();
+                        res.pairs = self.inner.stats.pairs();
+                        res.pairs
+                            .extend(storage.scoped(&ctx.namespace(), ctx.db()).pairs());
+                        res
+                    }
+                    Err(e) => e.into(),
+                };
+                let res = self.inner.finish(&cmd, res, start.elapsed());
+                return Box::pin(stream::once(async { res }));
+            }
+            Some(RequestData::StatsReset(_)) => {
+                let res = match self.inner.stats.reset(&self.inner.store) {
+                    Ok(()) => CommandResponse::ok(),
+                    Err(e) => e.into(),
+                };
+                let res = self.inner.finish(&cmd, res, start.elapsed());
+                return Box::pin(stream::once(async { res }));
+            }
+            Some(RequestData::Select(req)) => {
+                let res = match req.db < self.inner.databases {
+                    true => {
+                        ctx.select(req.db);
+                        CommandResponse::ok()
+                    }
+                    false => KvError::InvalidCommand(format!(
+                        "database {} out of range, the server has {}",
+                        req.db, self.inner.databases
+                    ))
+                    .into(),
+                };
+                let res = self.inner.finish(&cmd, res, start.elapsed());
+                return Box::pin(stream::once(async { res }));
+            }
+            Some(RequestData::UseNamespace(req)) => {
+                let res = match validate_namespace(&req.namespace) {
+                    Ok(()) => {
+                        ctx.use_namespace(req.namespace.clone());
+                        CommandResponse::ok()
+                    }
+                    Err(e) => e.into(),
+                };
+                let res = self.inner.finish(&cmd, res, start.elapsed());
+                return Box::pin(stream::once(async { res }));
+            }
+            Some(RequestData::Batch(batch)) => {
+                let fut = self
+                    .clone()
+                    .execute_batch(batch.requests.clone(), ctx.clone());
+                let inner = Arc::clone(&self.inner);
+                let fut = async move {
+                    let res = fut.await;
+                    inner.finish(&cmd, res, start.elapsed())
+                };
+                return Box::pin(stream::once(fut.instrument(span.clone())));
+            }
+            Some(RequestData::Backup(req)) => {
+                // the table, if any, is named as the connection sees it, like the tables of the file
+                if let Err(e) = self.inner.check_table(&req.table) {
+                    let res = self.inner.finish(&cmd, e.into(), start.elapsed());
+                    return Box::pin(stream::once(async { res }));
+                }
+                let (req, namespace, db) = (req.clone(), ctx.namespace(), ctx.db());
+                return self.run_blocking(cmd, start, &span, move |inner| {
+                    let table = (!req.table.is_empty()).then_some(req.table.as_str());
+                    let count = inner.backup(&req.name, req.ndjson, &namespace, db, table)?;
+                    Ok(Value::from(count as i64))
+                });
+            }
+            Some(RequestData::Restore(req)) => {
+                let (req, namespace, db) = (req.clone(), ctx.namespace(), ctx.db());
+                return self.run_blocking(cmd, start, &span, move |inner| {
+                    let count = inner.restore(&req.name, req.ndjson, &namespace, db)?;
+                    Ok(Value::from(count as i64))
+                });
+            }
+            Some(RequestData::Eval(req)) => {
+                let (req, namespace, db) = (req.clone(), ctx.namespace(), ctx.db());
+                return self.run_blocking(cmd, start, &span, move |inner| {
+                    script::eval(inner, req, namespace, db)
+                });
+            }
+            Some(RequestData::Flush(_)) => {
+                self.inner.persist_stats();
+                if let Some(Err(e)) = self.inner.journal.as_ref().map(|j| j.sync()) {
+                    warn!(error = ?e, "Failed to sync the journal");
+                }
+            }
+            _ => {}
+        }
+
+        cmd.select_scope(&ctx.namespace(), ctx.db());
+        if let Err(e) = self.inner.check_quota(&cmd, &ctx.namespace(), ctx.db()) {
+            let res = self.inner.finish(&cmd, e.into(), start.elapsed());
+            return Box::pin(stream::once(async { res }));
+        }
+
+        // scanning a table may block on a slow disk, so read it from the storage stream
+        let table = match &cmd.request_data {
+            Some(RequestData::Hgetall(req)) => Some(&req.table),
+            Some(RequestData::Hkeys(req)) => Some(&req.table),
+            Some(RequestData::Hvals(req)) => Some(&req.table),
+            _ => None,
+        };
+        if let Some(table) = table {
+            let pairs = self.inner.store.get_stream(table);
+            let inner = Arc::clone(&self.inner);
+            let fut = async move {
+                let res = match pairs {
+                    Ok(pairs) => {
+                        let pairs = pairs.collect::<Vec<_>>().await;
+                        match &cmd.request_data {
+                            Some(RequestData::Hkeys(_)) => Hkeys::response(pairs),
+                            Some(RequestData::Hvals(_)) => Hvals::response(pairs),
+                            _ => pairs.into(),
+                        }
+                    }
+                    Err(e) => e.into(),
+                };
+                inner.finish(&cmd, res, start.elapsed())
+            };
+            return Box::pin(stream::once(fut.instrument(span.clone())));
+        }
+
+        if let Some(RequestData::Export(req)) = &cmd.request_data {
+            return self.export(req.clone(), cmd.clone(), start);
+        }
+
+        let res = self.inner.dispatch_journaled(&cmd);
+        if let Some(sink) = &self.inner.sink {
+            if res.status == StatusCode::OK.as_u16() as u32 {
+                sink.push(&cmd, &res);
+            }
+        }
+
+        // read through the loader on a miss, off the storage as the source may be slow
+        if let (Some(RequestData::Hget(req)), Some(_)) = (&cmd.request_data, &self.inner.loader) {
+            // the loader is given the table as the client names it, not the scoped one
+            let (namespace, db) = (ctx.namespace(), ctx.db());
+            let name = unscoped_table(&namespace, db, &req.table).map(String::from);
+            let missed = res.status == StatusCode::NOT_FOUND.as_u16() as u32;
+            if let (true, Some(name)) = (missed, name) {
+                let (table, key) = (req.table.clone(), req.key.clone());
+                let inner = Arc::clone(&self.inner);
+                let fut = async move {
+                    let res = match inner.load(table, name, key, &namespace, db).await {
+                        Ok(Some(loaded)) => loaded,
+                        Ok(None) => res,
+                        Err(e) => e.into(),
+                    };
+                    inner.finish(&cmd, res, start.elapsed())
+                };
+                return Box::pin(stream::once(fut.instrument(span.clone())));
+            }
+        }
 
         if res == CommandResponse::default() {
-            dispatch_stream(cmd, Arc::clone(&self.broadcaster))
+            let is_subscribe = matches!(cmd.request_data, Some(RequestData::Subscribe(_)));
+            let stream = dispatch_stream(cmd, Arc::clone(&self.broadcaster), ctx);
+            match self.inner.heartbeat {
+                Some(period) if is_subscribe => with_heartbeat(stream, period),
+                _ => stream,
+            }
         } else {
-            debug!("Executed response: {:?}", &res);
-            self.inner.on_executed.notify(&res);
-            self.inner.on_before_send.notify(&mut res);
-            if !self.inner.on_after_send.is_empty() {
-                debug!("Modified response: {:?}", &res);
+            let res = self.inner.finish(&cmd, res, start.elapsed());
+            Box::pin(stream::once(async { res }))
+        }
+    }
+}
+
+impl<Store: Storage> Service<Store> {
+    /// Run a command on a blocking thread, like one reading or writing a whole file or
+    /// running a script, and respond with the result of the job
+    fn run_blocking<F, R>(
+        &self,
+        cmd: CommandRequest,
+        start: Instant,
+        span: &Span,
+        job: F,
+    ) -> StreamingResponse
+    where
+        F: FnOnce(&Arc<ServiceInner<Store>>) -> Result<R, KvError> + Send + 'static,
+        R: Into<CommandResponse> + Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        let fut = async move {
+            let task = {
+                let inner = Arc::clone(&inner);
+                tokio::task::spawn_blocking(move || job(&inner))
+            };
+            let res = match task.await {
+                Ok(Ok(res)) => res.into(),
+                Ok(Err(e)) => e.into(),
+                Err(e) => KvError::Internal(e.to_string()).into(),
+            };
+            inner.finish(&cmd, res, start.elapsed())
+        };
+        Box::pin(stream::once(fut.instrument(span.clone())))
+    }
+
+    /// Stream the pairs of the prefix in chunks after a header frame, the table is read
+    /// from the storage stream so a large export never builds one giant frame
+    fn export(&self, req: Export, cmd: CommandRequest, start: Instant) -> StreamingResponse {
+        let pairs = match self.inner.store.get_stream(&req.table) {
+            Ok(pairs) => pairs,
+            Err(e) => {
+                let res = self.inner.finish(&cmd, e.into(), start.elapsed());
+                return Box::pin(stream::once(async { res }));
             }
+        };
+        let header = self
+            .inner
+            .finish(&cmd, CommandResponse::ok(), start.elapsed());
+        let chunk_size = match req.chunk_size {
+            0 => DEFAULT_EXPORT_CHUNK_SIZE,
+            size => size as usize,
+        };
+
+        // the pairs come in key order, so the prefix is a contiguous run
+        let prefix = req.prefix;
+        let chunks = pairs
+            .skip_while({
+                let prefix = prefix.clone();
+                move |pair| future::ready(!pair.key.starts_with(&prefix) && pair.key < prefix)
+            })
+            .take_while(move |pair| future::ready(pair.key.starts_with(&prefix)))
+            .chunks(chunk_size)
+            .map(|pairs| Arc::new(CommandResponse::from(pairs)));
+        Box::pin(stream::once(async { header }).chain(chunks))
+    }
 
-            Box::pin(stream::once(async { Arc::new(res) }))
+    /// Execute the commands of a batch in order, so a Select or an UseNamespace applies to
+    /// the following commands
+    async fn execute_batch(self, cmds: Vec<CommandRequest>, ctx: ConnContext) -> CommandResponse {
+        let mut res = CommandResponse::ok();
+        for cmd in cmds {
+            let sub = match &cmd.request_data {
+                Some(RequestData::Subscribe(_))
+                | Some(RequestData::Unsubscribe(_))
+                | Some(RequestData::Publish(_))
+                | Some(RequestData::Export(_))
+                | Some(RequestData::Batch(_)) => {
+                    let e = KvError::InvalidCommand(format!(
+                        "{} is not allowed in a batch",
+                        cmd.name()
+                    ));
+                    e.into()
+                }
+                _ => match self.execute_with(cmd, &ctx).next().await {
+                    Some(sub) => Arc::unwrap_or_clone(sub),
+                    None => KvError::Internal("no response".into()).into(),
+                },
+            };
+            res.responses.push(sub);
         }
+        res
     }
 }
 
 impl<Store: Storage> ServiceInner<Store> {
+    /// Check the keys and the values of the command against the size limits
+    fn check_limits(&self, cmd: &CommandRequest) -> Result<(), KvError> {
+        let pairs = match &cmd.request_data {
+            Some(RequestData::Hset(Hset {
+                pair: Some(pair), ..
+            })) => slice::from_ref(pair),
+            Some(RequestData::Hsetnx(Hsetnx {
+                pair: Some(pair), ..
+            }))
+            | Some(RequestData::Hgetset(Hgetset {
+                pair: Some(pair), ..
+            })) => slice::from_ref(pair),
+            Some(RequestData::Hmset(Hmset { pairs, .. }))
+            | Some(RequestData::Import(Import { pairs, .. })) => pairs,
+            Some(RequestData::Txn(txn)) => {
+                return txn
+                    .requests
+                    .iter()
+                    .try_for_each(|cmd| self.check_limits(cmd))
+            }
+            // the blob is checked as the value, it is a bit larger than the value it carries
+            Some(RequestData::Hrestore(req)) => {
+                return self.check_pair(&req.key, Some(&req.data.clone().into()))
+            }
+            Some(RequestData::Lpush(Lpush { key, values, .. }))
+            | Some(RequestData::Rpush(Rpush { key, values, .. })) => {
+                return values
+                    .iter()
+                    .try_for_each(|v| self.check_pair(key, Some(v)))
+            }
+            Some(RequestData::Sadd(req)) => {
+                return req
+                    .members
+                    .iter()
+                    .try_for_each(|m| self.check_pair(&req.key, Some(&m.as_str().into())))
+            }
+            Some(RequestData::Zadd(req)) => {
+                return req
+                    .members
+                    .iter()
+                    .try_for_each(|m| self.check_pair(&req.key, Some(&m.member.as_str().into())))
+            }
+            // the parts are checked first, the whole values once updated, see `dispatch_within`
+            Some(RequestData::Happend(req)) => {
+                return self.check_pair(&req.key, req.value.as_ref())
+            }
+            _ => return Ok(()),
+        };
+        for pair in pairs {
+            self.check_pair(&pair.key, pair.value.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Check the names of the tables of the command before they are scoped, see `check_table`.
+    /// The tables are visited mutably but left unchanged.
+    fn check_tables(&self, cmd: &mut CommandRequest) -> Result<(), KvError> {
+        let mut checked = Ok(());
+        cmd.for_each_table(&mut |table| {
+            if checked.is_ok() {
+                checked = self.check_table(table);
+            }
+        });
+        checked
+    }
+
+    /// Check a table named by a client: the reserved names would reach the tables of the other
+    /// databases, namespaces or of the server, and the others must use the allowed characters
+    pub(crate) fn check_table(&self, table: &str) -> Result<(), KvError> {
+        if is_reserved_table(table) {
+            return Err(KvError::PermissionDenied(format!(
+                "table {:?}, the names starting with `__` are reserved",
+                table
+            )));
+        }
+        match self.table_chars {
+            Some(allowed) if !table.chars().all(allowed) => {
+                Err(KvError::InvalidTableName(table.into()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the pairs Hset and Hmset add to a table against the quota of the table
+    fn check_quota(&self, cmd: &CommandRequest, namespace: &str, db: u32) -> Result<(), KvError> {
+        let (table, pairs) = match &cmd.request_data {
+            Some(RequestData::Hset(Hset {
+                table,
+                pair: Some(pair),
+                ..
+            })) => (table, slice::from_ref(pair)),
+            Some(RequestData::Hmset(Hmset { table, pairs, .. })) => (table, pairs.as_slice()),
+            _ => return Ok(()),
+        };
+        match self.quota_of(namespace, db, table) {
+            Some(quota) => quota.check(&self.store, table, pairs),
+            None => Ok(()),
+        }
+    }
+
+    /// The quota of a table already scoped, found by the name the client sees
+    pub(crate) fn quota_of(&self, namespace: &str, db: u32, table: &str) -> Option<&TableQuota> {
+        self.quotas
+            .iter()
+            .find(|(name, _)| scoped_table(namespace, db, name.as_str()) == table)
+            .map(|(_, quota)| quota)
+    }
+
+    /// Check a key and its value against the size limits
+    fn check_pair(&self, key: &str, value: Option<&Value>) -> Result<(), KvError> {
+        if let Some(max) = self.max_key_len {
+            if key.len() > max {
+                return Err(KvError::KeyTooLong(key.len(), max));
+            }
+        }
+        match value {
+            Some(value) => check_size(value, self.max_value_size),
+            None => Ok(()),
+        }
+    }
+
+    /// Create the span of a request, the status is recorded once the request is executed
+    fn request_span(&self, cmd: &CommandRequest, ctx: &ConnContext) -> Span {
+        let id = self.last_request_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let (table, key) = cmd.target();
+        info_span!(
+            "request",
+            id,
+            conn = ctx.id(),
+            peer = ctx.peer().map(field::display),
+            cmd = cmd.name(),
+            table,
+            key,
+            status = field::Empty,
+        )
+    }
+
+    /// Load a missing value of a table, named `name` by the client, with the loader and store it,
+    /// so the next reads hit the storage. The loaded pair is checked like a written one.
+    /// Return the response of the Hget, or of the failed write, None if the loader has no value.
+    async fn load(
+        &self,
+        table: String,
+        name: String,
+        key: String,
+        namespace: &str,
+        db: u32,
+    ) -> Result<Option<CommandResponse>, KvError> {
+        let Some(loader) = &self.loader else {
+            return Ok(None);
+        };
+        let Some(value) = loader.load(&name, &key).await? else {
+            return Ok(None);
+        };
+        debug!("Loaded the missing key {} of table {}", key, table);
+        self.check_pair(&key, Some(&value))?;
+        // stored like a Hset of the client, so it is journaled, counted and forwarded
+        let cmd = CommandRequest::new_hset(table, key, value.clone());
+        self.check_quota(&cmd, namespace, db)?;
+        let res = self.dispatch_journaled(&cmd);
+        if res.status != StatusCode::OK.as_u16() as u32 {
+            return Ok(Some(res));
+        }
+        if let Some(sink) = &self.sink {
+            sink.push(&cmd, &res);
+        }
+        Ok(Some(value.into()))
+    }
+
+    /// The path of a file of the backup directory, the name is made of ascii letters, digits,
+    /// '_', '-' and '.', and does not start with a '.'
+    fn backup_path(&self, name: &str) -> Result<PathBuf, KvError> {
+        let Some(dir) = &self.backup_dir else {
+            return Err(KvError::PermissionDenied(
+                "backups are disabled on the server".into(),
+            ));
+        };
+        let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.');
+        match !name.is_empty() && !name.starts_with('.') && name.chars().all(valid) {
+            true => Ok(dir.join(name)),
+            false => Err(KvError::InvalidCommand(format!(
+                "invalid backup name {:?}",
+                name
+            ))),
+        }
+    }
+
+    /// Write a snapshot of the tables of a namespace and a database, or the pairs of one of them,
+    /// to a temporary file renamed to the backup once synced, so a failed backup leaves the
+    /// previous one in place. The tables are written unscoped, see `unscoped_table`.
+    fn backup(
+        &self,
+        name: &str,
+        ndjson: bool,
+        namespace: &str,
+        db: u32,
+        table: Option<&str>,
+    ) -> Result<usize, KvError> {
+        let path = self.backup_path(name)?;
+        let tmp = path.with_file_name(format!(".{}.tmp", name));
+        let write = || -> Result<usize, KvError> {
+            let pairs: Box<dyn Iterator<Item = _>> = match table {
+                Some(table) => {
+                    let pairs = self.store.get_iter(&scoped_table(namespace, db, table))?;
+                    let table = table.to_string();
+                    Box::new(pairs.map(move |pair| Ok((table.clone(), pair))))
+                }
+                // the snapshot is buffered in memory, like `export_ndjson` does
+                None => {
+                    let mut snapshot = Vec::new();
+                    self.store.snapshot(&mut snapshot)?;
+                    let pairs = read_dump(Cursor::new(snapshot));
+                    Box::new(pairs.filter_map(move |pair| {
+                        match pair {
+                            Ok((table, pair)) => unscoped_table(namespace, db, &table)
+                                .map(|table| Ok((table.to_string(), pair))),
+                            Err(e) => Some(Err(e)),
+                        }
+                    }))
+                }
+            };
+            let mut file = File::create(&tmp)?;
+            let count = match ndjson {
+                true => write_ndjson(&mut file, pairs)?,
+                false => write_dump(&mut file, pairs)?,
+            };
+            file.sync_all()?;
+            fs::rename(&tmp, &path)?;
+            Ok(count)
+        };
+        let res = write();
+        if res.is_err() {
+            _ = fs::remove_file(&tmp);
+        }
+        res
+    }
+
+    /// Write the pairs of a backup to the tables of a namespace and a database, and flush them
+    fn restore(
+        &self,
+        name: &str,
+        ndjson: bool,
+        namespace: &str,
+        db: u32,
+    ) -> Result<usize, KvError> {
+        let path = self.backup_path(name)?;
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(KvError::NotFound(format!("backup {}", name)))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let pairs: Box<dyn Iterator<Item = _>> = match ndjson {
+            true => Box::new(read_ndjson(file)),
+            false => Box::new(read_dump(file)),
+        };
+        // a file naming a reserved table would reach the tables of the others
+        let pairs = pairs.map(|pair| {
+            let (table, pair) = pair?;
+            self.check_table(&table)?;
+            Ok((scoped_table(namespace, db, &table), pair))
+        });
+        let writing = self.writing();
+        let count = restore_pairs(&self.store, pairs)?;
+        drop(writing);
+        self.store.flush()?;
+        // the restored pairs are journaled by a compaction
+        self.compact_journal();
+        Ok(count)
+    }
+
+    /// Execute a command, and journal it if it writes and succeeds
+    fn dispatch_journaled(&self, cmd: &CommandRequest) -> CommandResponse {
+        let max_size = self.max_value_size;
+        if !journal::is_journaled(cmd) {
+            return dispatch_within(cmd.clone(), &self.store, max_size);
+        }
+        let writing = self.writing();
+        let Some(journal) = &self.journal else {
+            return dispatch_within(cmd.clone(), &self.store, max_size);
+        };
+        let journal_writing = journal.writing();
+        let res = dispatch_within(cmd.clone(), &self.store, max_size);
+        let compact =
+            res.status == StatusCode::OK.as_u16() as u32 && journal.append(cmd, &self.store);
+        drop(journal_writing);
+        drop(writing);
+        if compact {
+            self.compact_journal();
+        }
+        res
+    }
+
+    /// Hold off the scripts committing their writes until the guard is dropped
+    fn writing(&self) -> RwLockReadGuard<'_, ()> {
+        self.write_gate.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Compact the journal if any, a failure is only logged
+    fn compact_journal(&self) {
+        if let Some(Err(e)) = self.journal.as_ref().map(|j| j.compact(&self.store)) {
+            warn!(error = ?e, "Failed to compact the journal");
+        }
+    }
+
+    /// Write the counters to the storage, a failure is only logged
+    fn persist_stats(&self) {
+        if let Err(e) = self.stats.persist(&self.store) {
+            warn!(error = ?e, "Failed to persist the stats");
+        }
+    }
+
+    /// Run the hooks on an executed unary response
+    fn finish(
+        &self,
+        cmd: &CommandRequest,
+        mut res: CommandResponse,
+        elapsed: Duration,
+    ) -> Arc<CommandResponse> {
+        Span::current().record("status", res.status);
+        self.stats.executed(cmd, &res);
+        debug!(?elapsed, response = ?res, "Executed request");
+        for f in &self.on_executed {
+            f(cmd, &res, elapsed);
+        }
+        self.on_before_send.notify(&mut res);
+        if !self.on_after_send.is_empty() {
+            debug!(response = ?res, "Modified response");
+        }
+        Arc::new(res)
+    }
+
     pub fn new(store: Store) -> Self {
         Self {
+            stats: Arc::new(ServerStats::load(&store)),
             store,
+            heartbeat: Some(DEFAULT_HEARTBEAT_INTERVAL),
+            broadcaster: BroadcasterConfig::default(),
+            loader: None,
+            sink: None,
+            journal: None,
+            databases: DEFAULT_DATABASES,
+            expire_interval: Some(DEFAULT_EXPIRE_INTERVAL),
+            max_key_len: None,
+            max_value_size: None,
+            table_chars: None,
+            max_frame_len: None,
+            backup_dir: None,
+            quotas: HashMap::new(),
+            write_gate: RwLock::new(()),
+            last_conn_id: AtomicU64::new(0),
+            last_request_id: AtomicU64::new(0),
             on_received: Vec::new(),
             on_executed: Vec::new(),
             on_before_send: Vec::new(),
@@ -62,12 +783,99 @@ impl<Store: Storage> ServiceInner<Store> {
         }
     }
 
+    /// Set the interval of the keepalive frames on subscription streams, None to disable
+    pub fn heartbeat_interval(mut self, interval: Option<Duration>) -> Self {
+        self.heartbeat = interval;
+        self
+    }
+
+    /// Set the number of logical databases the connections could select
+    pub fn databases(mut self, n: u32) -> Self {
+        self.databases = n;
+        self
+    }
+
+    /// Set the interval of the sweeps removing the expired keys, None to disable the sweeps.
+    /// The sweeps run on the tokio runtime the service is created in, none without a runtime.
+    /// The expired keys are hidden from the reads and the scans anyway.
+    pub fn expire_interval(mut self, interval: Option<Duration>) -> Self {
+        self.expire_interval = interval;
+        self
+    }
+
+    /// Limit the length of the keys
+    pub fn max_key_len(mut self, len: usize) -> Self {
+        self.max_key_len = Some(len);
+        self
+    }
+
+    /// Limit the encoded size of the values
+    pub fn max_value_size(mut self, size: usize) -> Self {
+        self.max_value_size = Some(size);
+        self
+    }
+
+    /// Restrict the characters of the table names, see `is_table_name_char` for a safe set.
+    /// The commands naming another table are rejected before touching the storage.
+    pub fn table_chars(mut self, allowed: fn(char) -> bool) -> Self {
+        self.table_chars = Some(allowed);
+        self
+    }
+
+    /// Limit the length of the request frames
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.max_frame_len = Some(len);
+        self
+    }
+
+    /// Enable the Backup and Restore commands, reading and writing the files of the directory
+    pub fn backup_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.backup_dir = Some(dir.into());
+        self
+    }
+
+    /// Limit the keys and the bytes of the table, Hset and Hmset over the quota are rejected
+    pub fn table_quota(mut self, table: impl Into<String>, quota: TableQuota) -> Self {
+        self.quotas.insert(table.into(), quota);
+        self
+    }
+
+    /// Journal the successful writes, replay the journal into the store before serving it
+    pub fn journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Read through the loader when Hget misses, the loaded values are stored
+    pub fn loader(mut self, loader: impl Loader) -> Self {
+        self.loader = Some(Box::new(loader));
+        self
+    }
+
+    /// Forward the successful writes to the sink in the background, batched and retried
+    /// as configured. Must be called within a tokio runtime.
+    pub fn sink(mut self, sink: impl MutationSink, config: SinkConfig) -> Self {
+        self.sink = Some(WriteBehind::start(sink, config));
+        self
+    }
+
+    /// Set the configuration of the topic broadcaster
+    ///
+    /// # Panics
+    ///
+    /// Panics if `config.capacity` is 0.
+    pub fn broadcaster_config(mut self, config: BroadcasterConfig) -> Self {
+        config.check();
+        self.broadcaster = config;
+        self
+    }
+
     pub fn fn_received(mut self, f: fn(&CommandRequest)) -> Self {
         self.on_received.push(f);
         self
     }
 
-    pub fn fn_executed(mut self, f: fn(&CommandResponse)) -> Self {
+    pub fn fn_executed(mut self, f: fn(&CommandRequest, &CommandResponse, Duration)) -> Self {
         self.on_executed.push(f);
         self
     }
@@ -83,6 +891,12 @@ impl<Store: Storage> ServiceInner<Store> {
     }
 }
 
+impl<Store: Storage> Drop for ServiceInner<Store> {
+    fn drop(&mut self) {
+        self.persist_stats();
+    }
+}
+
 impl<Store: Storage> Clone for Service<Store> {
     fn clone(&self) -> Self {
         Self {
@@ -92,11 +906,39 @@ impl<Store: Storage> Clone for Service<Store> {
     }
 }
 
+/// The service must be created within a tokio runtime, unless the expiration sweeps are disabled
 impl<Store: Storage> From<ServiceInner<Store>> for Service<Store> {
     fn from(inner: ServiceInner<Store>) -> Self {
-        Self {
-            inner: Arc::new(inner),
-            broadcaster: Arc::new(Broadcaster::default()),
+        let broadcaster =
+            Broadcaster::new(inner.broadcaster.clone()).with_stats(inner.stats.clone());
+        let broadcaster = Arc::new(broadcaster);
+        let inner = Arc::new(inner);
+        match (inner.expire_interval, Handle::try_current()) {
+            (Some(interval), Ok(_)) => {
+                spawn_named(
+                    "expire-sweep",
+                    sweep_expired(Arc::downgrade(&inner), interval),
+                );
+            }
+            (Some(_), Err(_)) => warn!("No tokio runtime, the expired keys are not swept"),
+            (None, _) => {}
+        }
+        Self { inner, broadcaster }
+    }
+}
+
+/// Remove the expired keys periodically, until the service is dropped
+async fn sweep_expired<Store: Storage>(inner: Weak<ServiceInner<Store>>, interval: Duration) {
+    let mut ticker = time::interval_at(Instant::now() + interval, interval);
+    loop {
+        ticker.tick().await;
+        let Some(inner) = inner.upgrade() else {
+            break;
+        };
+        match inner.store.purge_expired(now_ms()) {
+            Ok(purged) if !purged.is_empty() => debug!("Removed {} expired keys", purged.len()),
+            Ok(_) => {}
+            Err(e) => warn!(error = ?e, "Failed to remove the expired keys"),
         }
     }
 }
@@ -127,29 +969,143 @@ impl<Arg> NotifyMut<Arg> for Vec<fn(&mut Arg)> {
     }
 }
 
+/// The ascii letters, the digits, `_`, `-` and `.`, a conservative set for the table names
+pub fn is_table_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+/// Execute a command like `dispatch`, the values growing in place are checked once updated,
+/// and left unchanged if larger than `max_size` bytes encoded
+fn dispatch_within(
+    cmd: CommandRequest,
+    store: &impl Storage,
+    max_size: Option<usize>,
+) -> CommandResponse {
+    match cmd.request_data {
+        Some(RequestData::Happend(req)) => req.append(store, max_size),
+        Some(RequestData::Lpush(req)) => {
+            command_service::list_push(store, &req.table, &req.key, req.values, true, max_size)
+        }
+        Some(RequestData::Rpush(req)) => {
+            command_service::list_push(store, &req.table, &req.key, req.values, false, max_size)
+        }
+        Some(RequestData::Sadd(req)) => req.add(store, max_size),
+        Some(RequestData::Zadd(req)) => req.add(store, max_size),
+        request_data => dispatch(
+            CommandRequest {
+                request_data,
+                ..cmd
+            },
+            store,
+        ),
+    }
+}
+
 pub fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
     match cmd.request_data {
         Some(RequestData::Hget(req)) => req.execute(store),
         Some(RequestData::Hset(req)) => req.execute(store),
+        Some(RequestData::Hmset(req)) => req.execute(store),
+        Some(RequestData::Import(req)) => req.execute(store),
+        Some(RequestData::Hdel(req)) => req.execute(store),
+        Some(RequestData::Hmdel(req)) => req.execute(store),
+        Some(RequestData::Hdelpattern(req)) => req.execute(store),
         Some(RequestData::Hgetall(req)) => req.execute(store),
+        Some(RequestData::Hkeys(req)) => req.execute(store),
+        Some(RequestData::Hvals(req)) => req.execute(store),
+        Some(RequestData::Hlen(req)) => req.execute(store),
+        Some(RequestData::Hcleartable(req)) => req.execute(store),
+        Some(RequestData::Hcopy(req)) => req.execute(store),
+        Some(RequestData::Hmove(req)) => req.execute(store),
+        Some(RequestData::Hgetset(req)) => req.execute(store),
+        Some(RequestData::Happend(req)) => req.execute(store),
+        Some(RequestData::Hgetrange(req)) => req.execute(store),
+        Some(RequestData::Htype(req)) => req.execute(store),
+        Some(RequestData::Hsort(req)) => req.execute(store),
+        Some(RequestData::Hdump(req)) => req.execute(store),
+        Some(RequestData::Hrestore(req)) => req.execute(store),
+        Some(RequestData::Htouch(req)) => req.execute(store),
+        Some(RequestData::Hmeta(req)) => req.execute(store),
+        Some(RequestData::Hgetmeta(req)) => req.execute(store),
+        Some(RequestData::Hstrlen(req)) => req.execute(store),
+        Some(RequestData::Lpush(req)) => req.execute(store),
+        Some(RequestData::Rpush(req)) => req.execute(store),
+        Some(RequestData::Lpop(req)) => req.execute(store),
+        Some(RequestData::Rpop(req)) => req.execute(store),
+        Some(RequestData::Lrange(req)) => req.execute(store),
+        Some(RequestData::Llen(req)) => req.execute(store),
+        Some(RequestData::Sadd(req)) => req.execute(store),
+        Some(RequestData::Srem(req)) => req.execute(store),
+        Some(RequestData::Sismember(req)) => req.execute(store),
+        Some(RequestData::Smembers(req)) => req.execute(store),
+        Some(RequestData::Sunion(req)) => req.execute(store),
+        Some(RequestData::Sinter(req)) => req.execute(store),
+        Some(RequestData::Zadd(req)) => req.execute(store),
+        Some(RequestData::Zrange(req)) => req.execute(store),
+        Some(RequestData::Zrangebyscore(req)) => req.execute(store),
+        Some(RequestData::Txn(req)) => req.execute(store),
+        Some(RequestData::Hrandfield(req)) => req.execute(store),
+        Some(RequestData::Hexpire(req)) => req.execute(store),
+        Some(RequestData::Hexpireat(req)) => req.execute(store),
+        Some(RequestData::Hpersist(req)) => req.execute(store),
+        Some(RequestData::Httl(req)) => req.execute(store),
+        Some(RequestData::Hincr(req)) => req.execute(store),
+        Some(RequestData::HincrEx(req)) => req.execute(store),
+        Some(RequestData::Hsetnx(req)) => req.execute(store),
+        Some(RequestData::Hgetdel(req)) => req.execute(store),
+        Some(RequestData::Hscan(req)) => req.execute(store),
+        Some(RequestData::Hrange(req)) => req.execute(store),
+        Some(RequestData::Hmget(req)) => req.execute(store),
+        Some(RequestData::Hmexist(req)) => req.execute(store),
+        Some(RequestData::Mget(req)) => req.execute(store),
+        Some(RequestData::Flush(req)) => req.execute(store),
+        Some(RequestData::Export(_)) => {
+            KvError::InvalidCommand("export is only streamed by the service".into()).into()
+        }
+        Some(RequestData::Eval(_)) => {
+            KvError::InvalidCommand("eval is only run by the service".into()).into()
+        }
+        Some(RequestData::Backup(_)) | Some(RequestData::Restore(_)) => {
+            KvError::InvalidCommand("backups are only run by the service".into()).into()
+        }
         None => KvError::InvalidCommand("Request has not data".into()).into(),
         // return a default response, and use dispatch_stream to handle the stream
         _ => CommandResponse::default(),
     }
 }
 
-pub fn dispatch_stream(cmd: CommandRequest, topic: impl Topic) -> StreamingResponse {
-    info!("Dispatching stream: {:?}", cmd);
+pub fn dispatch_stream(
+    cmd: CommandRequest,
+    topic: impl Topic,
+    ctx: &ConnContext,
+) -> StreamingResponse {
+    debug!("Dispatching stream");
     match cmd.request_data {
-        Some(RequestData::Subscribe(req)) => req.execute(topic),
-        Some(RequestData::Unsubscribe(req)) => req.execute(topic),
-        Some(RequestData::Publish(req)) => req.execute(topic),
+        Some(RequestData::Subscribe(req)) => req.execute(topic, ctx),
+        Some(RequestData::Unsubscribe(req)) => req.execute(topic, ctx),
+        Some(RequestData::Publish(req)) => req.execute(topic, ctx),
         _ => unreachable!(),
     }
 }
 
+/// Emit a keepalive frame every period until the stream ends,
+/// so the client could distinguish a quiet topic from a dead server.
+fn with_heartbeat(stream: StreamingResponse, period: Duration) -> StreamingResponse {
+    let ticker = time::interval_at(Instant::now() + period, period);
+    Box::pin(stream::unfold(
+        (stream, ticker),
+        |(mut stream, mut ticker)| async move {
+            tokio::select! {
+                biased;
+                v = stream.next() => v.map(|v| (v, (stream, ticker))),
+                _ = ticker.tick() => Some((Arc::new(CommandResponse::heartbeat()), (stream, ticker))),
+            }
+        },
+    ))
+}
+
 #[cfg(test)]
-use crate::{Kvpair, Value};
+use crate::Kvpair;
 
 #[cfg(test)]
 pub fn assert_res_ok(res: &CommandResponse, values: &[Value], pairs: &[Kvpair]) {
@@ -171,12 +1127,11 @@ pub fn assert_res_error(res: &CommandResponse, code: u32, msg: &str) {
 
 #[cfg(test)]
 mod tests {
-    use http::StatusCode;
-    use stream::StreamExt;
+    use futures::future::BoxFuture;
     use tracing::info;
 
     use super::*;
-    use crate::{CommandRequest, MemTable, Value};
+    use crate::{pair_size, CommandRequest, Kvpair, MemTable, SledDb, Value};
 
     #[tokio::test]
     async fn service_should_work() {
@@ -203,8 +1158,9 @@ mod tests {
         fn b(cmd: &CommandRequest) {
             info!("Received command: {:?}", cmd);
         }
-        fn c(res: &CommandResponse) {
-            info!("Executed command: {:?}", res);
+        fn c(cmd: &CommandRequest, res: &CommandResponse, elapsed: Duration) {
+            info!("Executed command {:?} in {:?}: {:?}", cmd, elapsed, res);
+            assert!(matches!(cmd.request_data, Some(RequestData::Hset(_))));
         }
         fn d(res: &mut CommandResponse) {
             res.status = StatusCode::CREATED.as_u16() as _;
@@ -223,8 +1179,678 @@ mod tests {
 
         let mut res = service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
         let data = res.next().await.unwrap();
-        assert_eq!(data.status, StatusCode::CREATED.as_u16() as _);
+        assert_eq!(data.status, StatusCode::CREATED.as_u16() as u32);
         assert_eq!(data.message, "");
         assert_eq!(data.values, vec![Value::default()]);
     }
+
+    #[tokio::test]
+    async fn subscription_should_emit_heartbeats() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .heartbeat_interval(Some(Duration::from_millis(10)))
+            .into();
+
+        let mut res = service.execute(CommandRequest::new_subscribe("lobby"));
+        let id: i64 = res.next().await.unwrap().as_ref().try_into().unwrap();
+        assert!(id > 0);
+
+        let data = res.next().await.unwrap();
+        assert!(data.is_heartbeat());
+    }
+
+    #[tokio::test]
+    async fn hgetall_should_read_storage_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let service: Service<SledDb> = ServiceInner::new(SledDb::new(dir.path())).into();
+        service
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .next()
+            .await;
+
+        let mut res = service.execute(CommandRequest::new_hgetall("t1"));
+        let data = res.next().await.unwrap();
+        assert_res_ok(&data, &[], &[Kvpair::new("k1", "v1".into())]);
+    }
+
+    #[tokio::test]
+    async fn table_names_should_be_validated() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .table_chars(is_table_name_char)
+            .into();
+
+        let cmd = CommandRequest::new_hset("t1:x", "k1", "v1".into());
+        let data = service.execute(cmd).next().await.unwrap();
+        assert_res_error(&data, 400, "Invalid table name \"t1:x\"");
+
+        let cmd = CommandRequest::new_txn(vec![
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+            CommandRequest::new_hget("t 2", "k1"),
+        ]);
+        let data = service.execute(cmd).next().await.unwrap();
+        assert_res_error(&data, 400, "Invalid table name");
+
+        let cmd = CommandRequest::new_hcopy("t1", "t:2", "k1", false);
+        let data = service.execute(cmd).next().await.unwrap();
+        assert_res_error(&data, 400, "Invalid table name");
+        let data = service
+            .execute(CommandRequest::new_hlen("t1"))
+            .next()
+            .await
+            .unwrap();
+        assert_res_ok(&data, &[0.into()], &[]);
+
+        let cmd = CommandRequest::new_hset("users.v2-a_b", "k1", "v1".into());
+        let data = service.execute(cmd).next().await.unwrap();
+        assert_res_ok(&data, &[Value::default()], &[]);
+    }
+
+    #[tokio::test]
+    async fn size_limits_should_be_enforced() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .max_key_len(4)
+            .max_value_size(16)
+            .into();
+
+        let cmd = CommandRequest::new_hset("t1", "long key", "v1".into());
+        let data = service.execute(cmd).next().await.unwrap();
+        assert_res_error(&data, 413, "Too large: key");
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "a very long value".into());
+        let data = service.execute(cmd).next().await.unwrap();
+        assert_res_error(&data, 413, "Too large: value");
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let data = service.execute(cmd).next().await.unwrap();
+        assert_res_ok(&data, &[Value::default()], &[]);
+
+        let pairs = vec![
+            Kvpair::new("k1", "v1".into()),
+            Kvpair::new("long key", "v2".into()),
+        ];
+        let data = service
+            .execute(CommandRequest::new_hmset("t1", pairs))
+            .next()
+            .await
+            .unwrap();
+        assert_res_error(&data, 413, "Too large: key");
+
+        // the values growing in place are checked as a whole, and left unchanged if too large
+        let cmd = CommandRequest::new_happend("t1", "k1", "0123456789".into());
+        let data = service.execute(cmd.clone()).next().await.unwrap();
+        assert_res_ok(&data, &[12.into()], &[]);
+        let data = service.execute(cmd).next().await.unwrap();
+        assert_res_error(&data, 413, "Too large: value");
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        let data = service.execute(cmd).next().await.unwrap();
+        assert_res_ok(&data, &["v10123456789".into()], &[]);
+
+        let mut pushed = 0;
+        let data = loop {
+            let cmd = CommandRequest::new_rpush("t1", "l1", vec!["abcdef".into()]);
+            let data = service.execute(cmd).next().await.unwrap();
+            if data.status != 200 {
+                break data;
+            }
+            pushed += 1;
+        };
+        assert_res_error(&data, 413, "Too large: value");
+        let cmd = CommandRequest::new_lrange("t1", "l1", 0, -1);
+        let data = service.execute(cmd).next().await.unwrap();
+        assert!(pushed > 0 && data.values.len() == pushed);
+
+        let mut added = 0;
+        let data = loop {
+            let cmd = CommandRequest::new_sadd("t1", "s1", [format!("m{}", added)]);
+            let data = service.execute(cmd).next().await.unwrap();
+            if data.status != 200 {
+                break data;
+            }
+            added += 1;
+        };
+        assert_res_error(&data, 413, "Too large: value");
+        let cmd = CommandRequest::new_smembers("t1", "s1");
+        let data = service.execute(cmd).next().await.unwrap();
+        assert!(added > 0 && data.values.len() == added);
+    }
+
+    #[tokio::test]
+    async fn table_quotas_should_be_enforced() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .table_quota("t1", TableQuota::default().max_keys(2))
+            .into();
+        let ctx = service.new_context(None);
+
+        let pairs = vec![
+            Kvpair::new("k1", "v1".into()),
+            Kvpair::new("k2", "v2".into()),
+        ];
+        let cmd = CommandRequest::new_hmset("t1", pairs);
+        let data = service.execute_with(cmd, &ctx).next().await.unwrap();
+        assert_res_ok(&data, &[Value::default(), Value::default()], &[]);
+
+        let cmd = CommandRequest::new_hset("t1", "k3", "v3".into());
+        let data = service.execute_with(cmd, &ctx).next().await.unwrap();
+        assert_res_error(&data, 507, "Insufficient storage: table t1 has 2 keys");
+
+        // overwriting a key adds nothing, the other tables are unlimited
+        let cmd = CommandRequest::new_hset("t1", "k1", "v3".into());
+        let data = service.execute_with(cmd, &ctx).next().await.unwrap();
+        assert_res_ok(&data, &["v1".into()], &[]);
+        let cmd = CommandRequest::new_hset("t2", "k3", "v3".into());
+        let data = service.execute_with(cmd, &ctx).next().await.unwrap();
+        assert_res_ok(&data, &[Value::default()], &[]);
+
+        // the quota applies to the table of every database on its own
+        service
+            .execute_with(CommandRequest::new_select(1), &ctx)
+            .next()
+            .await;
+        let cmd = CommandRequest::new_hset("t1", "k3", "v3".into());
+        let data = service.execute_with(cmd, &ctx).next().await.unwrap();
+        assert_res_ok(&data, &[Value::default()], &[]);
+    }
+
+    #[tokio::test]
+    async fn hget_should_read_through_the_loader() {
+        struct Source;
+
+        impl Loader for Source {
+            fn load<'a>(
+                &'a self,
+                table: &'a str,
+                key: &'a str,
+            ) -> BoxFuture<'a, Result<Option<Value>, KvError>> {
+                Box::pin(async move {
+                    match key {
+                        "broken" => Err(KvError::Internal("source is down".into())),
+                        "missing" => Ok(None),
+                        "huge" => Ok(Some("x".repeat(100).into())),
+                        _ => Ok(Some(format!("{}/{}", table, key).into())),
+                    }
+                })
+            }
+        }
+
+        let service: Service = ServiceInner::new(MemTable::new())
+            .loader(Source)
+            .max_value_size(16)
+            .into();
+        let ctx = service.new_context(None);
+        let hget = |key: &str| service.execute_with(CommandRequest::new_hget("t1", key), &ctx);
+
+        let data = hget("k1").next().await.unwrap();
+        assert_res_ok(&data, &["t1/k1".into()], &[]);
+        assert_eq!(
+            service.inner.store.get("t1", "k1").unwrap(),
+            Some("t1/k1".into())
+        );
+
+        // the loader sees the table the client names, the value lands in the database
+        service
+            .execute_with(CommandRequest::new_select(1), &ctx)
+            .next()
+            .await;
+        let data = hget("k2").next().await.unwrap();
+        assert_res_ok(&data, &["t1/k2".into()], &[]);
+        assert_eq!(
+            service.inner.store.get("__db1__.t1", "k2").unwrap(),
+            Some("t1/k2".into())
+        );
+
+        let data = hget("missing").next().await.unwrap();
+        assert_res_error(&data, 404, "Not found");
+        let data = hget("broken").next().await.unwrap();
+        assert_res_error(&data, 500, "source is down");
+
+        // the loaded values are limited like the written ones
+        let data = hget("huge").next().await.unwrap();
+        assert_res_error(&data, 413, "Too large: value");
+        assert_eq!(service.inner.store.get("__db1__.t1", "huge").unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn loaded_pairs_should_be_journaled_and_counted() {
+        struct Source;
+
+        impl Loader for Source {
+            fn load<'a>(
+                &'a self,
+                _table: &'a str,
+                key: &'a str,
+            ) -> BoxFuture<'a, Result<Option<Value>, KvError>> {
+                Box::pin(async move { Ok(Some(key.into())) })
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = JournalConfig::new(dir.path());
+        let service: Service = ServiceInner::new(MemTable::new())
+            .loader(Source)
+            .journal(Journal::open(config.clone()).unwrap())
+            .table_quota("t1", TableQuota::default().max_keys(1))
+            .into();
+        let ctx = service.new_context(None);
+        let hget = |key: &str| service.execute_with(CommandRequest::new_hget("t1", key), &ctx);
+
+        let data = hget("k1").next().await.unwrap();
+        assert_res_ok(&data, &["k1".into()], &[]);
+        // the quota of the table applies to the loaded pairs like to the written ones
+        let data = hget("k2").next().await.unwrap();
+        assert_res_error(&data, 507, "Insufficient storage: table t1 has 1 keys");
+        assert_eq!(service.inner.store.get("t1", "k2").unwrap(), None);
+        drop(service);
+
+        let store = MemTable::new();
+        Journal::open(config).unwrap().replay(&store).unwrap();
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("k1".into()));
+    }
+
+    #[tokio::test]
+    async fn export_should_stream_the_prefix_in_chunks() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        for key in ["a1", "user:1", "user:2", "user:3", "zz"] {
+            let cmd = CommandRequest::new_hset("t1", key, key.into());
+            service.execute(cmd).next().await.unwrap();
+        }
+
+        let frames: Vec<_> = service
+            .execute(CommandRequest::new_export("t1", "user:", 2))
+            .collect()
+            .await;
+        assert_eq!(frames.len(), 3);
+        assert_res_ok(&frames[0], &[], &[]);
+        let keys: Vec<Vec<&str>> = frames[1..]
+            .iter()
+            .map(|res| res.pairs.iter().map(|p| p.key.as_str()).collect())
+            .collect();
+        assert_eq!(keys, [vec!["user:1", "user:2"], vec!["user:3"]]);
+
+        let frames: Vec<_> = service
+            .execute(CommandRequest::new_export("t1", "none:", 0))
+            .collect()
+            .await;
+        assert_eq!(frames.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn databases_should_be_isolated() {
+        let service: Service = ServiceInner::new(MemTable::new()).databases(2).into();
+        let (ctx0, ctx1) = (service.new_context(None), service.new_context(None));
+        let run = |cmd: CommandRequest, ctx: &ConnContext| {
+            let res = service.execute_with(cmd, ctx);
+            async move { res.into_future().await.0.unwrap() }
+        };
+
+        let data = run(CommandRequest::new_select(1), &ctx1).await;
+        assert_res_ok(&data, &[], &[]);
+        assert_eq!(ctx1.db(), 1);
+        run(CommandRequest::new_hset("t1", "k1", "v1".into()), &ctx1).await;
+
+        let data = run(CommandRequest::new_hget("t1", "k1"), &ctx0).await;
+        assert_res_error(&data, 404, "Not found");
+        let data = run(CommandRequest::new_hget("t1", "k1"), &ctx1).await;
+        assert_res_ok(&data, &["v1".into()], &[]);
+        let data = run(CommandRequest::new_hgetall("t1"), &ctx1).await;
+        assert_res_ok(&data, &[], &[Kvpair::new("k1", "v1".into())]);
+
+        let data = run(CommandRequest::new_select(2), &ctx1).await;
+        assert_res_error(&data, 400, "database 2 out of range");
+        assert_eq!(ctx1.db(), 1);
+
+        // the tables of the other databases and of the server are out of reach
+        let data = run(CommandRequest::new_hget("__db1__.t1", "k1"), &ctx0).await;
+        assert_res_error(&data, 403, "reserved");
+        let data = run(
+            CommandRequest::new_hset("__stats__", "k1", "v1".into()),
+            &ctx0,
+        )
+        .await;
+        assert_res_error(&data, 403, "reserved");
+        let txn = CommandRequest::new_txn(vec![
+            CommandRequest::new_hset("t1", "k2", "v2".into()),
+            CommandRequest::new_hget("__db1__.t1", "k1"),
+        ]);
+        let data = run(txn, &ctx0).await;
+        assert_res_error(&data, 403, "reserved");
+        let data = run(CommandRequest::new_hget("t1", "k2"), &ctx0).await;
+        assert_res_error(&data, 404, "Not found");
+        let batch = CommandRequest::new_batch(vec![CommandRequest::new_hget("__db1__.t1", "k1")]);
+        let data = run(batch, &ctx0).await;
+        assert_eq!(data.responses.len(), 1);
+        assert_res_error(&data.responses[0], 403, "reserved");
+    }
+
+    #[tokio::test]
+    async fn namespaces_should_be_isolated() {
+        let service: Service = ServiceInner::new(MemTable::new()).databases(2).into();
+        let (ctx0, ctx1) = (service.new_context(None), service.new_context(None));
+        let run = |cmd: CommandRequest, ctx: &ConnContext| {
+            let res = service.execute_with(cmd, ctx);
+            async move { res.into_future().await.0.unwrap() }
+        };
+
+        let data = run(CommandRequest::new_use_namespace("app1"), &ctx1).await;
+        assert_res_ok(&data, &[], &[]);
+        assert_eq!(ctx1.namespace(), "app1");
+        run(CommandRequest::new_hset("t1", "k1", "v1".into()), &ctx1).await;
+        run(CommandRequest::new_hset("t1", "k1", "v0".into()), &ctx0).await;
+
+        let data = run(CommandRequest::new_hget("t1", "k1"), &ctx0).await;
+        assert_res_ok(&data, &["v0".into()], &[]);
+        let data = run(CommandRequest::new_hget("t1", "k1"), &ctx1).await;
+        assert_res_ok(&data, &["v1".into()], &[]);
+
+        // each namespace has its own databases
+        run(CommandRequest::new_select(1), &ctx1).await;
+        let data = run(CommandRequest::new_hget("t1", "k1"), &ctx1).await;
+        assert_res_error(&data, 404, "Not found");
+        run(CommandRequest::new_select(0), &ctx1).await;
+
+        let data = run(CommandRequest::new_use_namespace("app:1"), &ctx1).await;
+        assert_res_error(&data, 400, "invalid namespace");
+        assert_eq!(ctx1.namespace(), "app1");
+
+        run(CommandRequest::new_use_namespace(""), &ctx1).await;
+        let data = run(CommandRequest::new_hget("t1", "k1"), &ctx1).await;
+        assert_res_ok(&data, &["v0".into()], &[]);
+
+        // the tables of another namespace can't be named directly
+        let data = run(CommandRequest::new_hget("__ns_app1__.t1", "k1"), &ctx0).await;
+        assert_res_error(&data, 403, "reserved");
+        let data = run(
+            CommandRequest::new_hset("__ns_app1__.t1", "k1", "v2".into()),
+            &ctx1,
+        )
+        .await;
+        assert_res_error(&data, 403, "reserved");
+        run(CommandRequest::new_use_namespace("app1"), &ctx1).await;
+        let data = run(CommandRequest::new_hget("t1", "k1"), &ctx1).await;
+        assert_res_ok(&data, &["v1".into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn backup_should_restore_into_another_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let from: Service = ServiceInner::new(MemTable::new())
+            .backup_dir(dir.path())
+            .into();
+        let (ctx, other) = (from.new_context(None), from.new_context(None));
+        async fn run<S: Storage>(
+            service: &Service<S>,
+            cmd: CommandRequest,
+            ctx: &ConnContext,
+        ) -> Arc<CommandResponse> {
+            service.execute_with(cmd, ctx).next().await.unwrap()
+        }
+        run(
+            &from,
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+            &ctx,
+        )
+        .await;
+        run(&from, CommandRequest::new_select(1), &ctx).await;
+        run(&from, CommandRequest::new_hset("t1", "k1", 1.into()), &ctx).await;
+        run(&from, CommandRequest::new_use_namespace("app1"), &other).await;
+        run(
+            &from,
+            CommandRequest::new_hset("t2", "k1", 2.into()),
+            &other,
+        )
+        .await;
+
+        // only the tables of the database and the namespace of the connection are backed up
+        let data = run(&from, CommandRequest::new_backup("kvdb.bak"), &ctx).await;
+        assert_res_ok(&data, &[1.into()], &[]);
+        let data = run(&from, CommandRequest::new_backup("app1.bak"), &other).await;
+        assert_res_ok(&data, &[1.into()], &[]);
+        let data = run(&from, CommandRequest::new_backup("../kvdb.bak"), &ctx).await;
+        assert_res_error(&data, 400, "invalid backup name");
+
+        // and restored into the database and the namespace of the restoring connection
+        let to: Service<SledDb> = ServiceInner::new(SledDb::new(dir.path().join("db")))
+            .backup_dir(dir.path())
+            .into();
+        let ctx = to.new_context(None);
+        let data = run(&to, CommandRequest::new_restore("missing.bak"), &ctx).await;
+        assert_res_error(&data, 404, "backup missing.bak");
+        let data = run(&to, CommandRequest::new_restore("kvdb.bak"), &ctx).await;
+        assert_res_ok(&data, &[1.into()], &[]);
+        let data = run(&to, CommandRequest::new_hget("t1", "k1"), &ctx).await;
+        assert_res_ok(&data, &[1.into()], &[]);
+        let data = run(&to, CommandRequest::new_restore("app1.bak"), &ctx).await;
+        assert_res_ok(&data, &[1.into()], &[]);
+        let data = run(&to, CommandRequest::new_hget("t2", "k1"), &ctx).await;
+        assert_res_ok(&data, &[2.into()], &[]);
+        run(&to, CommandRequest::new_select(1), &ctx).await;
+        let data = run(&to, CommandRequest::new_hget("t1", "k1"), &ctx).await;
+        assert_res_error(&data, 404, "");
+
+        // a file can't name the tables of the other databases or namespaces
+        let file = File::create(dir.path().join("evil.bak")).unwrap();
+        let pairs = [Ok(("__ns_app1__.t2".into(), Kvpair::new("k1", 3.into())))];
+        write_dump(file, pairs).unwrap();
+        let data = run(&to, CommandRequest::new_restore("evil.bak"), &ctx).await;
+        assert_res_error(&data, 403, "reserved");
+        let data = run(&from, CommandRequest::new_hget("t2", "k1"), &other).await;
+        assert_res_ok(&data, &[2.into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn backup_should_export_a_table_as_ndjson() {
+        let dir = tempfile::tempdir().unwrap();
+        let from: Service = ServiceInner::new(MemTable::new())
+            .backup_dir(dir.path())
+            .into();
+        let ctx = from.new_context(None);
+        for cmd in [
+            CommandRequest::new_select(1),
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+            CommandRequest::new_hset("t2", "k1", "v2".into()),
+        ] {
+            from.execute_with(cmd, &ctx).next().await;
+        }
+
+        let cmd = CommandRequest::new_backup_ndjson("t1.json", "t1");
+        let data = from.execute_with(cmd, &ctx).next().await.unwrap();
+        assert_res_ok(&data, &[1.into()], &[]);
+        let text = std::fs::read_to_string(dir.path().join("t1.json")).unwrap();
+        assert!(text.contains(r#""key":"k1""#) && text.contains(r#""value":"v1""#));
+
+        let to: Service = ServiceInner::new(MemTable::new())
+            .backup_dir(dir.path())
+            .into();
+        let ctx = to.new_context(None);
+        to.execute_with(CommandRequest::new_select(1), &ctx)
+            .next()
+            .await;
+        let cmd = CommandRequest::new_restore_ndjson("t1.json");
+        let data = to.execute_with(cmd, &ctx).next().await.unwrap();
+        assert_res_ok(&data, &[1.into()], &[]);
+        let data = to
+            .execute_with(CommandRequest::new_hget("t1", "k1"), &ctx)
+            .next()
+            .await
+            .unwrap();
+        assert_res_ok(&data, &["v1".into()], &[]);
+        let data = to
+            .execute_with(CommandRequest::new_hget("t2", "k1"), &ctx)
+            .next()
+            .await
+            .unwrap();
+        assert_res_error(&data, 404, "");
+    }
+
+    #[tokio::test]
+    async fn backup_should_be_disabled_without_backup_dir() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let res = service.execute(CommandRequest::new_backup("kvdb.bak"));
+        let data = res.into_future().await.0.unwrap();
+        assert_res_error(&data, 403, "backups are disabled");
+    }
+
+    #[tokio::test]
+    async fn batch_should_execute_in_order() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let ctx = service.new_context(None);
+        let cmd = CommandRequest::new_batch(vec![
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+            CommandRequest::new_select(1),
+            CommandRequest::new_hget("t1", "k1"),
+            CommandRequest::new_publish("lobby", vec!["hello".into()]),
+            CommandRequest::new_select(0),
+            CommandRequest::new_hget("t1", "k1"),
+        ]);
+        let data = service.execute_with(cmd, &ctx).next().await.unwrap();
+        assert_res_ok(&data, &[], &[]);
+
+        let statuses: Vec<u32> = data.responses.iter().map(|res| res.status).collect();
+        assert_eq!(statuses, [200, 200, 404, 400, 200, 200]);
+        assert_res_ok(&data.responses[5], &["v1".into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn expired_keys_should_be_swept() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .expire_interval(Some(Duration::from_millis(10)))
+            .into();
+        for cmd in [
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+            CommandRequest::new_hset("t1", "k2", "v2".into()),
+            CommandRequest::new_hexpire("t1", "k1", 0),
+        ] {
+            service.execute(cmd).next().await;
+        }
+
+        time::sleep(Duration::from_millis(50)).await;
+        let pairs = service.inner.store.get_all("t1").unwrap();
+        assert_eq!(pairs, vec![Kvpair::new("k2", "v2".into())]);
+        // the sweep left nothing to purge
+        assert!(service
+            .inner
+            .store
+            .purge_expired(now_ms())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn service_should_be_created_without_a_runtime() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .expire_interval(Some(Duration::from_millis(10)))
+            .into();
+        let ctx = service.new_context(None);
+        assert_eq!(ctx.db(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity")]
+    fn broadcaster_config_with_zero_capacity_should_be_rejected() {
+        let _ = ServiceInner::new(MemTable::new()).broadcaster_config(BroadcasterConfig {
+            capacity: 0,
+            ..Default::default()
+        });
+    }
+
+    #[tokio::test]
+    async fn stats_should_survive_restarts_until_reset() {
+        let dir = tempfile::tempdir().unwrap();
+        let stats = |service: &Service<SledDb>| {
+            let res = service.execute(CommandRequest::new_stats());
+            async move { res.into_future().await.0.unwrap().pairs.clone() }
+        };
+
+        {
+            let service: Service<SledDb> = ServiceInner::new(SledDb::new(&dir)).into();
+            for cmd in [
+                CommandRequest::new_hset("t1", "k1", "v1".into()),
+                CommandRequest::new_hget("t1", "k1"),
+                CommandRequest::new_hget("t1", "k2"),
+            ] {
+                service.execute(cmd).next().await;
+            }
+            // the counters are persisted when the service is dropped
+        }
+
+        // every instance is dropped, the flusher thread of sled may hold the lock a bit longer
+        let reopened = (0..100)
+            .find_map(|_| {
+                SledDb::builder(&dir)
+                    .open()
+                    .map_err(|_| std::thread::sleep(Duration::from_millis(10)))
+                    .ok()
+            })
+            .expect("failed to reopen the store");
+        let service: Service<SledDb> = ServiceInner::new(reopened).into();
+        let expected: Vec<Kvpair> = [
+            ("total_commands", 4),
+            ("keyspace_hits", 1),
+            ("keyspace_misses", 1),
+            ("evictions", 0),
+        ]
+        .into_iter()
+        .map(|(k, n)| Kvpair::new(k, n.into()))
+        .collect();
+        assert_eq!(stats(&service).await[..4], expected);
+
+        let res = service.execute(CommandRequest::new_stats_reset());
+        assert_res_ok(&res.into_future().await.0.unwrap(), &[], &[]);
+        let counters = stats(&service).await;
+        assert_eq!(counters[0], Kvpair::new("total_commands", 1.into()));
+        assert_eq!(counters[1], Kvpair::new("keyspace_hits", 0.into()));
+    }
+
+    #[tokio::test]
+    async fn stats_should_report_the_tables_of_the_storage() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        for cmd in [
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+            CommandRequest::new_hset("t1", "k2", "v2".into()),
+            CommandRequest::new_hset("t2", "k1", "v1".into()),
+        ] {
+            service.execute(cmd).next().await;
+        }
+
+        let res = service.execute(CommandRequest::new_stats());
+        let pairs = res.into_future().await.0.unwrap().pairs.clone();
+        let size = pair_size("k1", &"v1".into()) as i64;
+        for pair in [
+            Kvpair::new("table:t1:keys", 2.into()),
+            Kvpair::new("table:t1:bytes", (size * 2).into()),
+            Kvpair::new("table:t2:keys", 1.into()),
+            Kvpair::new("table:t2:bytes", size.into()),
+        ] {
+            assert!(pairs.contains(&pair), "missing {:?}", pair);
+        }
+        assert!(pairs.iter().any(|pair| pair.key == "storage:total_size"));
+
+        // the tables of the other databases and namespaces, and of the server, are left out
+        let ctx = service.new_context(None);
+        for cmd in [
+            CommandRequest::new_use_namespace("app1"),
+            CommandRequest::new_hset("t3", "k1", "v1".into()),
+            CommandRequest::new_select(1),
+            CommandRequest::new_hset("t4", "k1", "v1".into()),
+        ] {
+            service.execute_with(cmd, &ctx).next().await;
+        }
+        let res = service.execute(CommandRequest::new_stats());
+        let pairs = res.into_future().await.0.unwrap().pairs.clone();
+        let tables: Vec<_> = pairs
+            .iter()
+            .filter(|p| p.key.starts_with("table:"))
+            .collect();
+        assert_eq!(tables.len(), 4, "{:?}", tables);
+        assert!(!pairs
+            .iter()
+            .any(|p| p.key.contains("t3") || p.key.contains("__")));
+
+        let res = service.execute_with(CommandRequest::new_stats(), &ctx);
+        let pairs = res.into_future().await.0.unwrap().pairs.clone();
+        assert!(pairs.contains(&Kvpair::new("table:t4:keys", 1.into())));
+        let tables: Vec<_> = pairs
+            .iter()
+            .filter(|p| p.key.starts_with("table:"))
+            .collect();
+        assert_eq!(tables.len(), 2, "{:?}", tables);
+    }
 }
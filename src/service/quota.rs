@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+
+use crate::{pair_size, KvError, Kvpair, Storage, Value};
+
+/// The limits of a table, checked before the writes adding keys or bytes to it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TableQuota {
+    /// The max number of keys, None for unlimited
+    pub max_keys: Option<usize>,
+    /// The max approximate size in bytes, None for unlimited
+    pub max_bytes: Option<usize>,
+}
+
+impl TableQuota {
+    /// Limit the number of keys of the table
+    pub fn max_keys(mut self, n: usize) -> Self {
+        self.max_keys = Some(n);
+        self
+    }
+
+    /// Limit the approximate size of the table, as reported by `Storage::size_of_table`
+    pub fn max_bytes(mut self, n: usize) -> Self {
+        self.max_bytes = Some(n);
+        self
+    }
+
+    /// Check that writing the pairs keeps the table within the quota. The writes that add
+    /// nothing, like overwriting a value with a smaller one, are always allowed.
+    /// The check and the write are not atomic, the concurrent writes may overshoot a little.
+    pub(crate) fn check(
+        &self,
+        store: &impl Storage,
+        table: &str,
+        pairs: &[Kvpair],
+    ) -> Result<(), KvError> {
+        if self.max_keys.is_none() && self.max_bytes.is_none() {
+            return Ok(());
+        }
+
+        let (mut new_keys, mut added, mut removed) = (0, 0, 0);
+        let mut seen = HashSet::new();
+        let default = Value::default();
+        // the last pair of a key wins, the earlier ones are overwritten in the same write
+        for pair in pairs.iter().rev() {
+            if !seen.insert(pair.key.as_str()) {
+                continue;
+            }
+            added += pair_size(&pair.key, pair.value.as_ref().unwrap_or(&default));
+            match store.get(table, &pair.key)? {
+                Some(old) => removed += pair_size(&pair.key, &old),
+                None => new_keys += 1,
+            }
+        }
+
+        if let Some(max) = self.max_keys {
+            if new_keys > 0 {
+                let len = store.len_of_table(table)?;
+                if len + new_keys > max {
+                    return Err(KvError::InsufficientStorage(format!(
+                        "table {} has {} keys, the quota is {}",
+                        table, len, max
+                    )));
+                }
+            }
+        }
+        if let Some(max) = self.max_bytes {
+            if added > removed {
+                let size = store.size_of_table(table)?;
+                if size + added - removed > max {
+                    return Err(KvError::InsufficientStorage(format!(
+                        "table {} has {} bytes, the quota is {}",
+                        table, size, max
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemTable;
+
+    #[test]
+    fn table_quota_should_count_only_the_added_keys_and_bytes() {
+        let store = MemTable::new();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        let quota = TableQuota::default().max_keys(2);
+        let pairs = vec![Kvpair::new("k1", "v".into()), Kvpair::new("k2", "v".into())];
+        assert!(quota.check(&store, "t1", &pairs).is_ok());
+        let pairs = vec![Kvpair::new("k2", "v".into()), Kvpair::new("k3", "v".into())];
+        let res = quota.check(&store, "t1", &pairs);
+        assert!(matches!(res, Err(KvError::InsufficientStorage(_))));
+
+        let size = store.size_of_table("t1").unwrap();
+        let quota = TableQuota::default().max_bytes(size);
+        // a smaller value frees bytes, a duplicated key is counted once
+        let pairs = vec![
+            Kvpair::new("k1", "long value".into()),
+            Kvpair::new("k1", "v".into()),
+        ];
+        assert!(quota.check(&store, "t1", &pairs).is_ok());
+        let pairs = vec![Kvpair::new("k1", "long value".into())];
+        let res = quota.check(&store, "t1", &pairs);
+        assert!(matches!(res, Err(KvError::InsufficientStorage(_))));
+    }
+}
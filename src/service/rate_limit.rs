@@ -0,0 +1,86 @@
+use std::{
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+
+/// The number of buckets kept before the idle ones are evicted
+const MAX_IDLE_BUCKETS: usize = 1024;
+
+/// A token bucket refilled at `rate` tokens per second, holding at most `rate` tokens
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+/// Token buckets limiting the rate of an operation per key
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter<K: Eq + Hash> {
+    buckets: DashMap<K, Mutex<Bucket>>,
+}
+
+impl<K: Eq + Hash + Clone> RateLimiter<K> {
+    /// Take a token of the key, return false if the key is over `rate` per second
+    pub fn acquire(&self, key: &K, rate: u32) -> bool {
+        let now = Instant::now();
+        let rate = rate as f64;
+        if self.buckets.len() > MAX_IDLE_BUCKETS {
+            self.evict_idle(now);
+        }
+
+        let bucket = match self.buckets.get(key) {
+            Some(bucket) => bucket,
+            None => self
+                .buckets
+                .entry(key.clone())
+                .or_insert_with(|| {
+                    Mutex::new(Bucket {
+                        tokens: rate,
+                        last: now,
+                    })
+                })
+                .downgrade(),
+        };
+        let mut bucket = bucket.lock().unwrap();
+        let elapsed = now.saturating_duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+        bucket.last = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Remove the buckets untouched for a second, they are full again
+    fn evict_idle(&self, now: Instant) {
+        self.buckets.retain(|_, bucket| {
+            let last = bucket.get_mut().unwrap().last;
+            now.saturating_duration_since(last) < Duration::from_secs(1)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn rate_limiter_should_refill() {
+        let limiter = RateLimiter::default();
+        for _ in 0..10 {
+            assert!(limiter.acquire(&"t1", 10));
+        }
+        assert!(!limiter.acquire(&"t1", 10));
+        // the other keys have their own buckets
+        assert!(limiter.acquire(&"t2", 10));
+
+        thread::sleep(Duration::from_millis(150));
+        assert!(limiter.acquire(&"t1", 10));
+    }
+}
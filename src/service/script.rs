@@ -0,0 +1,461 @@
+#[cfg(not(feature = "scripting"))]
+pub(crate) fn eval<Store: crate::Storage>(
+    _inner: &std::sync::Arc<super::ServiceInner<Store>>,
+    _req: crate::Eval,
+    _namespace: String,
+    _db: u32,
+) -> Result<crate::CommandResponse, crate::KvError> {
+    Err(crate::KvError::InvalidCommand(
+        "scripting is disabled, build with the scripting feature".into(),
+    ))
+}
+
+#[cfg(feature = "scripting")]
+pub(crate) use rhai_eval::eval;
+
+#[cfg(feature = "scripting")]
+mod rhai_eval {
+    use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Arc};
+
+    use bytes::Bytes;
+    use rhai::{Array, Blob, Dynamic, Engine, EvalAltResult, Position, Scope};
+
+    use crate::{
+        scoped_table, service::ServiceInner, value, CommandResponse, Eval, KvError, Kvpair,
+        Storage, Value, WriteOp,
+    };
+
+    /// The max number of operations of a script, so a runaway loop is stopped
+    const MAX_OPERATIONS: u64 = 1_000_000;
+
+    /// The max length of the strings and the blobs a script builds
+    const MAX_STRING_SIZE: usize = 16 * 1024 * 1024;
+
+    /// The max length of the arrays and the maps a script builds
+    const MAX_ARRAY_SIZE: usize = 100_000;
+
+    /// The max number of runs of a script whose reads are changed by other writes before it
+    /// commits
+    const MAX_ATTEMPTS: usize = 3;
+
+    type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+    /// The view of the storage a script runs against, the writes are buffered until the
+    /// script succeeds and read back by the following reads of the script
+    struct Overlay<Store: Storage> {
+        inner: Arc<ServiceInner<Store>>,
+        namespace: String,
+        db: u32,
+        /// The values read from the storage, None for the missing keys, checked again when
+        /// the writes are committed
+        reads: HashMap<(String, String), Option<Value>>,
+        /// The values written by the script, None for the deleted keys
+        writes: HashMap<(String, String), Option<Value>>,
+        ops: Vec<WriteOp>,
+    }
+
+    impl<Store: Storage> Overlay<Store> {
+        fn new(inner: &Arc<ServiceInner<Store>>, namespace: &str, db: u32) -> Self {
+            Self {
+                inner: Arc::clone(inner),
+                namespace: namespace.to_string(),
+                db,
+                reads: HashMap::new(),
+                writes: HashMap::new(),
+                ops: vec![],
+            }
+        }
+
+        fn get(&mut self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+            self.inner.check_table(table)?;
+            let id = (
+                scoped_table(&self.namespace, self.db, table),
+                key.to_string(),
+            );
+            if let Some(value) = self.writes.get(&id).or_else(|| self.reads.get(&id)) {
+                return Ok(value.clone());
+            }
+            let value = self.inner.store.get(&id.0, key)?;
+            self.reads.insert(id, value.clone());
+            Ok(value)
+        }
+
+        /// Buffer a write, None to delete the key, and return the previous value
+        fn write(
+            &mut self,
+            table: &str,
+            key: &str,
+            value: Option<Value>,
+        ) -> Result<Option<Value>, KvError> {
+            self.inner.check_pair(key, value.as_ref())?;
+            let old = self.get(table, key)?;
+            let table = scoped_table(&self.namespace, self.db, table);
+            self.ops.push(match &value {
+                Some(value) => WriteOp::set(&table, key, value.clone()),
+                None => WriteOp::del(&table, key),
+            });
+            self.writes.insert((table, key.to_string()), value);
+            Ok(old)
+        }
+
+        /// Commit the writes in one transaction, unless a value the script read has changed
+        /// since. Return whether the writes are committed.
+        fn commit(&mut self) -> Result<bool, KvError> {
+            let inner = Arc::clone(&self.inner);
+            let gate = inner.write_gate.write().unwrap_or_else(|e| e.into_inner());
+            for ((table, key), value) in &self.reads {
+                if inner.store.get(table, key)? != *value {
+                    return Ok(false);
+                }
+            }
+            if self.ops.is_empty() {
+                return Ok(true);
+            }
+            self.check_quotas()?;
+
+            let ops = std::mem::take(&mut self.ops);
+            let writing = inner.journal.as_ref().map(|j| j.writing());
+            inner.store.transaction(ops.clone())?;
+            let compact = match &inner.journal {
+                Some(journal) => journal.append_ops(&ops),
+                None => false,
+            };
+            drop(writing);
+            drop(gate);
+            if compact {
+                inner.compact_journal();
+            }
+            if let Some(sink) = &inner.sink {
+                sink.push_ops(ops);
+            }
+            Ok(true)
+        }
+
+        /// Check the keys the script leaves set against the quotas of their tables
+        fn check_quotas(&self) -> Result<(), KvError> {
+            let mut tables: HashMap<&str, Vec<Kvpair>> = HashMap::new();
+            for ((table, key), value) in &self.writes {
+                if let Some(value) = value {
+                    let pair = Kvpair::new(key.as_str(), value.clone());
+                    tables.entry(table.as_str()).or_default().push(pair);
+                }
+            }
+            for (table, pairs) in tables {
+                if let Some(quota) = self.inner.quota_of(&self.namespace, self.db, table) {
+                    quota.check(&self.inner.store, table, &pairs)?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Run the script of the command, and commit its writes in one transaction. A script
+    /// whose reads are changed by another write before it commits is run again.
+    pub(crate) fn eval<Store: Storage>(
+        inner: &Arc<ServiceInner<Store>>,
+        req: Eval,
+        namespace: String,
+        db: u32,
+    ) -> Result<CommandResponse, KvError> {
+        for _ in 0..MAX_ATTEMPTS {
+            let overlay = Rc::new(RefCell::new(Overlay::new(inner, &namespace, db)));
+            let res = run(&overlay, &req)?;
+            if overlay.borrow_mut().commit()? {
+                return Ok(res);
+            }
+        }
+        Err(KvError::ConditionNotMet(format!(
+            "the keys read by the script kept changing, gave up after {} attempts",
+            MAX_ATTEMPTS
+        )))
+    }
+
+    /// Run the script against the overlay, and respond with its result
+    fn run<Store: Storage>(
+        overlay: &Rc<RefCell<Overlay<Store>>>,
+        req: &Eval,
+    ) -> Result<CommandResponse, KvError> {
+        let result = {
+            let engine = new_engine(overlay);
+            let mut scope = Scope::new();
+            let args: Array = req.args.iter().cloned().map(to_dynamic).collect();
+            scope.push_constant("ARGS", args);
+            engine.eval_with_scope::<Dynamic>(&mut scope, &req.script)
+        };
+        let result =
+            result.map_err(|e| KvError::InvalidCommand(format!("script failed: {}", e)))?;
+        let mut res = CommandResponse::ok();
+        let values = match result.is_array() {
+            true => result.cast::<Array>(),
+            false => vec![result],
+        };
+        for value in values {
+            res.values.push(from_dynamic(value)?);
+        }
+        Ok(res)
+    }
+
+    /// Create a sandboxed engine with the get, set and del primitives bound to the overlay
+    fn new_engine<Store: Storage>(overlay: &Rc<RefCell<Overlay<Store>>>) -> Engine {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_ARRAY_SIZE)
+            .set_max_map_size(MAX_ARRAY_SIZE)
+            .on_print(|_| {})
+            .on_debug(|_, _, _| {});
+
+        let o = Rc::clone(overlay);
+        engine.register_fn(
+            "get",
+            move |table: &str, key: &str| -> ScriptResult<Dynamic> {
+                let value = o.borrow_mut().get(table, key).map_err(script_error)?;
+                Ok(value.map(to_dynamic).unwrap_or(Dynamic::UNIT))
+            },
+        );
+        let o = Rc::clone(overlay);
+        engine.register_fn(
+            "set",
+            move |table: &str, key: &str, value: Dynamic| -> ScriptResult<Dynamic> {
+                let value = from_dynamic(value).map_err(script_error)?;
+                let old = o
+                    .borrow_mut()
+                    .write(table, key, Some(value))
+                    .map_err(script_error)?;
+                Ok(old.map(to_dynamic).unwrap_or(Dynamic::UNIT))
+            },
+        );
+        let o = Rc::clone(overlay);
+        engine.register_fn(
+            "del",
+            move |table: &str, key: &str| -> ScriptResult<Dynamic> {
+                let old = o
+                    .borrow_mut()
+                    .write(table, key, None)
+                    .map_err(script_error)?;
+                Ok(old.map(to_dynamic).unwrap_or(Dynamic::UNIT))
+            },
+        );
+        engine
+    }
+
+    fn script_error(e: KvError) -> Box<EvalAltResult> {
+        EvalAltResult::ErrorRuntime(e.to_string().into(), Position::NONE).into()
+    }
+
+    /// Convert a value to a script value, an empty value is unit
+    fn to_dynamic(value: Value) -> Dynamic {
+        match value.value {
+            Some(value::Value::String(v)) => v.into(),
+            Some(value::Value::Binary(v)) => Dynamic::from_blob(v.to_vec()),
+            Some(value::Value::Integer(v)) => v.into(),
+            Some(value::Value::Float(v)) => v.into(),
+            Some(value::Value::Bool(v)) => v.into(),
+            Some(value::Value::List(list)) => {
+                Dynamic::from_array(list.values.into_iter().map(to_dynamic).collect())
+            }
+            Some(value::Value::Set(set)) => {
+                Dynamic::from_array(set.members.into_iter().map(Dynamic::from).collect())
+            }
+            Some(value::Value::Zset(zset)) => {
+                let scores = zset
+                    .members
+                    .into_iter()
+                    .map(|m| (m.member.into(), Dynamic::from(m.score)));
+                Dynamic::from_map(scores.collect())
+            }
+            None => Dynamic::UNIT,
+        }
+    }
+
+    /// Convert a script value to a value, unit is an empty value
+    fn from_dynamic(value: Dynamic) -> Result<Value, KvError> {
+        if value.is_unit() {
+            return Ok(Value::default());
+        }
+        let type_name = value.type_name();
+        if let Some(v) = value.clone().try_cast::<i64>() {
+            Ok(v.into())
+        } else if let Some(v) = value.clone().try_cast::<f64>() {
+            Ok(v.into())
+        } else if let Some(v) = value.clone().try_cast::<bool>() {
+            Ok(v.into())
+        } else if let Some(v) = value.clone().try_cast::<Blob>() {
+            Ok(Bytes::from(v).into())
+        } else if let Some(v) = value.clone().try_cast::<Array>() {
+            let values: Result<Vec<Value>, KvError> = v.into_iter().map(from_dynamic).collect();
+            Ok(values?.into())
+        } else if let Ok(v) = value.into_string() {
+            Ok(v.into())
+        } else {
+            Err(KvError::ConvertCommand(type_name.into(), "Value"))
+        }
+    }
+}
+
+#[cfg(all(test, feature = "scripting"))]
+mod tests {
+    use futures::StreamExt;
+
+    use crate::{
+        assert_res_error, assert_res_ok, CommandRequest, ConnContext, Journal, JournalConfig,
+        MemTable, Service, ServiceInner, Storage, TableQuota, Value,
+    };
+
+    async fn run(
+        service: &Service,
+        cmd: CommandRequest,
+        ctx: &ConnContext,
+    ) -> crate::CommandResponse {
+        let res = service.execute_with(cmd, ctx).next().await.unwrap();
+        std::sync::Arc::unwrap_or_clone(res)
+    }
+
+    #[tokio::test]
+    async fn eval_should_read_modify_write() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let ctx = service.new_context(None);
+        let script = r#"
+            let n = get("t1", "counter");
+            if n == () { n = 0; }
+            set("t1", "counter", n + ARGS[0]);
+            get("t1", "counter")
+        "#;
+        let data = run(
+            &service,
+            CommandRequest::new_eval(script, vec![5.into()]),
+            &ctx,
+        )
+        .await;
+        assert_res_ok(&data, &[5.into()], &[]);
+        let data = run(
+            &service,
+            CommandRequest::new_eval(script, vec![2.into()]),
+            &ctx,
+        )
+        .await;
+        assert_res_ok(&data, &[7.into()], &[]);
+
+        let data = run(&service, CommandRequest::new_hget("t1", "counter"), &ctx).await;
+        assert_res_ok(&data, &[7.into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn eval_should_commit_nothing_on_error() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let ctx = service.new_context(None);
+        let script = r#"set("t1", "k1", "v1"); del("t1", "k2"); throw "boom";"#;
+        let data = run(&service, CommandRequest::new_eval(script, vec![]), &ctx).await;
+        assert_res_error(&data, 400, "script failed");
+
+        let data = run(&service, CommandRequest::new_hget("t1", "k1"), &ctx).await;
+        assert_res_error(&data, 404, "Not found");
+
+        let data = run(&service, CommandRequest::new_eval("loop {}", vec![]), &ctx).await;
+        assert_res_error(&data, 400, "script failed");
+    }
+
+    #[tokio::test]
+    async fn eval_should_be_journaled() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = JournalConfig::new(dir.path());
+        let journal = Journal::open(config.clone()).unwrap();
+        let service: Service = ServiceInner::new(MemTable::new()).journal(journal).into();
+        let ctx = service.new_context(None);
+        let script = r#"set("t1", "k1", "v1"); set("t1", "k2", "v2"); del("t1", "k2");"#;
+        run(&service, CommandRequest::new_eval(script, vec![]), &ctx).await;
+        drop(service);
+
+        let store = MemTable::new();
+        Journal::open(config).unwrap().replay(&store).unwrap();
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("t1", "k2").unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn eval_should_use_the_selected_database() {
+        let service: Service = ServiceInner::new(MemTable::new()).databases(2).into();
+        let ctx = service.new_context(None);
+        run(&service, CommandRequest::new_select(1), &ctx).await;
+        let script = r#"set("t1", "k1", ARGS[0]); [get("t1", "k1"), del("t1", "k2")]"#;
+        let cmd = CommandRequest::new_eval(script, vec![Value::from("v1")]);
+        let data = run(&service, cmd, &ctx).await;
+        assert_res_ok(&data, &["v1".into(), Value::default()], &[]);
+
+        let data = run(&service, CommandRequest::new_hget("t1", "k1"), &ctx).await;
+        assert_res_ok(&data, &["v1".into()], &[]);
+        let data = run(
+            &service,
+            CommandRequest::new_hget("t1", "k1"),
+            &Default::default(),
+        )
+        .await;
+        assert_res_error(&data, 404, "Not found");
+    }
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn eval_should_not_lose_the_concurrent_writes() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let script = r#"
+            let n = get("t1", "counter");
+            if n == () { n = 0; }
+            set("t1", "counter", n + 1);
+        "#;
+        let tasks: Vec<_> = (0..4)
+            .map(|i| {
+                let service = service.clone();
+                tokio::spawn(async move {
+                    let ctx = service.new_context(None);
+                    let mut done = 0;
+                    for _ in 0..50 {
+                        let cmd = match i % 2 {
+                            0 => CommandRequest::new_eval(script, vec![]),
+                            _ => CommandRequest::new_hincr("t1", "counter", 1),
+                        };
+                        // a script may give up on a busy key
+                        if run(&service, cmd, &ctx).await.status == 200 {
+                            done += 1;
+                        }
+                    }
+                    done
+                })
+            })
+            .collect();
+        let mut done = 0i64;
+        for task in tasks {
+            done += task.await.unwrap();
+        }
+
+        let ctx = service.new_context(None);
+        let data = run(&service, CommandRequest::new_hget("t1", "counter"), &ctx).await;
+        assert_res_ok(&data, &[done.into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn eval_should_respect_the_table_quotas() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .table_quota("t1", TableQuota::default().max_keys(1))
+            .into();
+        let ctx = service.new_context(None);
+        let script = r#"set("t1", "k1", 1); set("t1", "k2", 2);"#;
+        let data = run(&service, CommandRequest::new_eval(script, vec![]), &ctx).await;
+        assert_res_error(&data, 507, "quota");
+        let data = run(&service, CommandRequest::new_hget("t1", "k1"), &ctx).await;
+        assert_res_error(&data, 404, "Not found");
+
+        // a key set and deleted again adds nothing
+        let script = r#"set("t1", "k1", 1); set("t1", "k2", 2); del("t1", "k2");"#;
+        let data = run(&service, CommandRequest::new_eval(script, vec![]), &ctx).await;
+        assert_res_ok(&data, &[2.into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn eval_should_reject_the_reserved_tables() {
+        let service: Service = ServiceInner::new(MemTable::new()).databases(2).into();
+        let ctx = service.new_context(None);
+        for script in [r#"get("__db1__.t1", "k1")"#, r#"set("__stats__", "k1", 1)"#] {
+            let data = run(&service, CommandRequest::new_eval(script, vec![]), &ctx).await;
+            assert_res_error(&data, 400, "reserved");
+        }
+    }
+}
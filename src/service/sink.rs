@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use http::StatusCode;
+use prost::Message;
+use tokio::{sync::mpsc, time};
+use tracing::warn;
+
+use crate::{
+    spawn_named, CommandRequest, CommandResponse, DumpPayload, Hgetset, Hincr, HincrEx, Hmset,
+    Hset, Hsetnx, Import, KvError, Kvpair, RequestData, WriteOp,
+};
+
+/// Receives the writes applied to the storage, to forward them to an external system
+/// like Kafka or the database kvdb is the fast front of.
+pub trait MutationSink: Send + Sync + 'static {
+    /// Forward a batch of writes, in the order they were applied
+    fn write<'a>(&'a self, batch: &'a [WriteOp]) -> BoxFuture<'a, Result<(), KvError>>;
+}
+
+/// The configuration of the write-behind queue feeding a `MutationSink`
+#[derive(Debug, Clone)]
+pub struct SinkConfig {
+    /// The max number of writes of a batch
+    pub batch_size: usize,
+    /// How long to wait for more writes to fill a batch
+    pub linger: Duration,
+    /// The max number of writes waiting to be forwarded, the new writes are dropped when full
+    pub capacity: usize,
+    /// The number of retries of a failed batch before it is dropped
+    pub max_retries: u32,
+    /// The delay before the first retry, doubled on each following retry
+    pub retry_backoff: Duration,
+}
+
+impl Default for SinkConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 100,
+            linger: Duration::from_millis(10),
+            capacity: 10000,
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+/// The queue of the writes to forward, drained by a background task.
+/// The task stops once the queue is dropped and the queued writes are forwarded.
+#[derive(Debug)]
+pub(crate) struct WriteBehind {
+    tx: mpsc::Sender<WriteOp>,
+}
+
+impl WriteBehind {
+    pub fn start(sink: impl MutationSink, config: SinkConfig) -> Self {
+        let (tx, rx) = mpsc::channel(config.capacity);
+        spawn_named("write-behind", forward(sink, config, rx));
+        Self { tx }
+    }
+
+    /// Queue the writes of an executed command, if it writes
+    pub fn push(&self, cmd: &CommandRequest, res: &CommandResponse) {
+        self.push_ops(mutations(cmd, res));
+    }
+
+    /// Queue the writes applied by the service itself
+    pub fn push_ops(&self, ops: Vec<WriteOp>) {
+        for op in ops {
+            if let Err(e) = self.tx.try_send(op) {
+                warn!(error = %e, "Failed to queue a write for the sink, dropped");
+            }
+        }
+    }
+}
+
+/// The writes of a command, empty if the command does not write.
+/// The written value is taken from the response for the commands computing it.
+fn mutations(cmd: &CommandRequest, res: &CommandResponse) -> Vec<WriteOp> {
+    let set = |table: &str, pair: &Kvpair| {
+        WriteOp::set(table, &pair.key, pair.value.clone().unwrap_or_default())
+    };
+    match &cmd.request_data {
+        Some(RequestData::Hset(Hset {
+            table,
+            pair: Some(pair),
+            ..
+        })) => vec![set(table, pair)],
+        Some(RequestData::Hmset(Hmset { table, pairs }))
+        | Some(RequestData::Import(Import { table, pairs })) => {
+            pairs.iter().map(|pair| set(table, pair)).collect()
+        }
+        Some(RequestData::Hdel(req)) => vec![WriteOp::del(&req.table, &req.key)],
+        Some(RequestData::Hmdel(req)) => req
+            .keys
+            .iter()
+            .map(|key| WriteOp::del(&req.table, key))
+            .collect(),
+        Some(RequestData::Hgetdel(req)) => vec![WriteOp::del(&req.table, &req.key)],
+        Some(RequestData::Hgetset(Hgetset {
+            table,
+            pair: Some(pair),
+        })) => vec![set(table, pair)],
+        // Hsetnx writes only if it returns true
+        Some(RequestData::Hsetnx(Hsetnx {
+            table,
+            pair: Some(pair),
+        })) if res.values.first() == Some(&true.into()) => vec![set(table, pair)],
+        Some(RequestData::Txn(txn)) => txn
+            .requests
+            .iter()
+            .zip(&res.responses)
+            .filter(|(_, res)| res.status == StatusCode::OK.as_u16() as u32)
+            .flat_map(|(cmd, res)| mutations(cmd, res))
+            .collect(),
+        Some(RequestData::Hrestore(req)) => DumpPayload::decode(req.data.clone())
+            .ok()
+            .and_then(|dump| dump.value)
+            .map(|v| WriteOp::set(&req.table, &req.key, v))
+            .into_iter()
+            .collect(),
+        Some(RequestData::Hincr(Hincr { table, key, .. }))
+        | Some(RequestData::HincrEx(HincrEx { table, key, .. })) => res
+            .values
+            .first()
+            .map(|v| WriteOp::set(table, key, v.clone()))
+            .into_iter()
+            .collect(),
+        // the sink only gets the writes carrying their values, a cleared table,
+        // a copied or moved key, the keys deleted by a pattern, a restored backup, an appended value and the list, set and sorted set updates are not forwarded
+        _ => vec![],
+    }
+}
+
+/// Forward the queued writes in batches until the queue is closed
+async fn forward(sink: impl MutationSink, config: SinkConfig, mut rx: mpsc::Receiver<WriteOp>) {
+    let mut batch = Vec::with_capacity(config.batch_size);
+    while let Some(op) = rx.recv().await {
+        batch.push(op);
+        let deadline = time::Instant::now() + config.linger;
+        while batch.len() < config.batch_size {
+            match time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(op)) => batch.push(op),
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        let mut backoff = config.retry_backoff;
+        for attempt in 0..=config.max_retries {
+            match sink.write(&batch).await {
+                Ok(()) => break,
+                Err(e) if attempt < config.max_retries => {
+                    let n = batch.len();
+                    warn!(error = ?e, "Failed to forward {} writes, retry in {:?}", n, backoff);
+                    time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(e) => warn!(error = ?e, "Failed to forward {} writes, dropped", batch.len()),
+            }
+        }
+        batch.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    };
+
+    use super::*;
+    use crate::Value;
+
+    /// Fails the first `failures` writes, then records the batches
+    #[derive(Default)]
+    struct Recorder {
+        failures: AtomicU32,
+        batches: Mutex<Vec<Vec<WriteOp>>>,
+    }
+
+    impl MutationSink for Arc<Recorder> {
+        fn write<'a>(&'a self, batch: &'a [WriteOp]) -> BoxFuture<'a, Result<(), KvError>> {
+            Box::pin(async move {
+                if self.failures.load(Ordering::Relaxed) > 0 {
+                    self.failures.fetch_sub(1, Ordering::Relaxed);
+                    return Err(KvError::Internal("sink is down".into()));
+                }
+                self.batches.lock().unwrap().push(batch.to_vec());
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn write_behind_should_batch_and_retry() {
+        let recorder = Arc::new(Recorder {
+            failures: AtomicU32::new(2),
+            ..Default::default()
+        });
+        let config = SinkConfig {
+            batch_size: 2,
+            retry_backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let queue = WriteBehind::start(recorder.clone(), config);
+        let ok = CommandResponse::ok();
+        for i in 0..3 {
+            queue.push(
+                &CommandRequest::new_hset("t1", format!("k{}", i), i.into()),
+                &ok,
+            );
+        }
+        queue.push(&CommandRequest::new_hdel("t1", "k0"), &ok);
+        // reads are not forwarded
+        queue.push(&CommandRequest::new_hget("t1", "k1"), &ok);
+        // the computed values are taken from the response
+        let incr = CommandRequest::new_hincr("t1", "k3", 2);
+        queue.push(&incr, &Value::from(5i64).into());
+        drop(queue);
+
+        time::sleep(Duration::from_millis(100)).await;
+        let batches = recorder.batches.lock().unwrap();
+        let expected = vec![
+            vec![
+                WriteOp::set("t1", "k0", 0.into()),
+                WriteOp::set("t1", "k1", 1.into()),
+            ],
+            vec![WriteOp::set("t1", "k2", 2.into()), WriteOp::del("t1", "k0")],
+            vec![WriteOp::set("t1", "k3", 5.into())],
+        ];
+        assert_eq!(*batches, expected);
+    }
+}
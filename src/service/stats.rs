@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use http::StatusCode;
+use tracing::warn;
+
+use crate::{CommandRequest, CommandResponse, KvError, Kvpair, RequestData, Storage, Value};
+
+/// The reserved table the counters are persisted to
+pub const STATS_TABLE: &str = "__stats__";
+
+/// Persist the counters every this many commands, besides on Flush and on shutdown
+const PERSIST_EVERY: u64 = 1000;
+
+/// The cumulative counters of a server, persisted across restarts
+#[derive(Debug, Default)]
+pub struct ServerStats {
+    commands: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl ServerStats {
+    /// Load the counters persisted in the storage, zeros if there are none
+    pub fn load(store: &impl Storage) -> Self {
+        let stats = Self::default();
+        for (key, counter) in stats.counters() {
+            match store.get(STATS_TABLE, key) {
+                Ok(Some(value)) => counter.store(value_to_u64(&value), Ordering::Relaxed),
+                Ok(None) => {}
+                Err(e) => warn!(error = ?e, "Failed to load the counter {}", key),
+            }
+        }
+        stats
+    }
+
+    /// Write the counters to the reserved table
+    pub fn persist(&self, store: &impl Storage) -> Result<(), KvError> {
+        for pair in self.pairs() {
+            store.set(STATS_TABLE, pair.key, pair.value.unwrap_or_default())?;
+        }
+        Ok(())
+    }
+
+    /// Zero the counters and persist them
+    pub fn reset(&self, store: &impl Storage) -> Result<(), KvError> {
+        for (_, counter) in self.counters() {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.persist(store)
+    }
+
+    /// Count a received command, return whether the counters are due to be persisted
+    pub fn command(&self) -> bool {
+        let commands = self.commands.fetch_add(1, Ordering::Relaxed) + 1;
+        commands.is_multiple_of(PERSIST_EVERY)
+    }
+
+    /// Count the keyspace hits and misses of an executed read
+    pub fn executed(&self, cmd: &CommandRequest, res: &CommandResponse) {
+        let (hits, misses) = match &cmd.request_data {
+            Some(RequestData::Hget(_)) | Some(RequestData::Hgetdel(_))
+                if res.status == StatusCode::OK.as_u16() as u32 =>
+            {
+                (1, 0)
+            }
+            Some(RequestData::Hget(_)) | Some(RequestData::Hgetdel(_))
+                if res.status == StatusCode::NOT_FOUND.as_u16() as u32 =>
+            {
+                (0, 1)
+            }
+            // a missing key of Hmget and Mget gets an empty value
+            Some(RequestData::Hmget(_)) | Some(RequestData::Mget(_)) => {
+                let misses = res.values.iter().filter(|v| v.value.is_none()).count() as u64;
+                (res.values.len() as u64 - misses, misses)
+            }
+            _ => return,
+        };
+        self.hits.fetch_add(hits, Ordering::Relaxed);
+        self.misses.fetch_add(misses, Ordering::Relaxed);
+    }
+
+    /// Count the evicted entries
+    pub fn evicted(&self, n: u64) {
+        self.evictions.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// The counters as integer pairs
+    pub fn pairs(&self) -> Vec<Kvpair> {
+        self.counters()
+            .into_iter()
+            .map(|(key, counter)| {
+                let n = counter.load(Ordering::Relaxed) as i64;
+                Kvpair::new(key, n.into())
+            })
+            .collect()
+    }
+
+    fn counters(&self) -> [(&'static str, &AtomicU64); 4] {
+        [
+            ("total_commands", &self.commands),
+            ("keyspace_hits", &self.hits),
+            ("keyspace_misses", &self.misses),
+            ("evictions", &self.evictions),
+        ]
+    }
+}
+
+fn value_to_u64(value: &Value) -> u64 {
+    match value.value {
+        Some(crate::value::Value::Integer(n)) => n.max(0) as u64,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemTable;
+
+    #[test]
+    fn stats_should_persist_and_reset() {
+        let store = MemTable::new();
+        let stats = ServerStats::load(&store);
+        stats.command();
+        stats.command();
+        let get = CommandRequest::new_hget("t1", "k1");
+        stats.executed(&get, &KvError::NotFound("k1".into()).into());
+        stats.executed(&get, &Value::from("v1").into());
+        stats.evicted(3);
+        stats.persist(&store).unwrap();
+
+        let reloaded = ServerStats::load(&store);
+        let counters: Vec<_> = reloaded
+            .pairs()
+            .into_iter()
+            .map(|p| p.value.unwrap())
+            .collect();
+        assert_eq!(counters, vec![2.into(), 1.into(), 1.into(), 3.into()]);
+
+        reloaded.reset(&store).unwrap();
+        assert!(ServerStats::load(&store)
+            .pairs()
+            .iter()
+            .all(|p| p.value == Some(0.into())));
+    }
+}
@@ -1,114 +1,638 @@
-use std::sync::{
-    atomic::{AtomicU32, Ordering},
-    Arc,
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
-use dashmap::{DashMap, DashSet};
-use tokio::sync::mpsc;
+use dashmap::{mapref::one::RefMut, DashMap, DashSet};
+use tokio::{
+    sync::{
+        broadcast,
+        mpsc::{self, error::TrySendError},
+    },
+    task::AbortHandle,
+};
 use tracing::{debug, info, warn};
 
-use crate::{CommandResponse, KvError, Value};
+use crate::{spawn_named, CommandResponse, KvError, Value};
 
-/// The capacity of a topic.
-const BROADCAST_CAPACITY: usize = 128;
+use super::{rate_limit::RateLimiter, ServerStats};
 
-/// The next id generator of a subscription.
-static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+/// The default capacity of a topic.
+const BROADCAST_CAPACITY: usize = 128;
 
-/// Get the next id of a subscription.
-fn get_next_subscription_id() -> u32 {
-    NEXT_ID.fetch_add(1, Ordering::Relaxed)
-}
+/// The separator of the topic levels, e.g. `home/kitchen/temperature`.
+const LEVEL_SEPARATOR: char = '/';
+/// The wildcard matching exactly one topic level.
+const SINGLE_LEVEL_WILDCARD: &str = "+";
+/// The wildcard matching the parent level and any number of child levels.
+const MULTI_LEVEL_WILDCARD: &str = "#";
 
 /// A trait for a topic.
 pub trait Topic: Send + Sync + 'static {
-    /// Subscribe to a topic.
-    fn subscribe(self, name: String) -> mpsc::Receiver<Arc<CommandResponse>>;
-    /// Unsubscribe from a topic.
-    fn unsubscribe(self, name: String, id: u32) -> Result<u32, KvError>;
-    /// Publish a message to a topic.
-    fn publish(self, name: String, value: Arc<CommandResponse>);
+    /// Subscribe to a topic, the subscription is owned by the given connection.
+    /// The name is a topic filter, it could contain `+` and `#` wildcards.
+    fn subscribe(
+        self,
+        name: String,
+        owner: u64,
+    ) -> Result<mpsc::Receiver<Arc<CommandResponse>>, KvError>;
+    /// Subscribe to a topic like `subscribe`, and replay the retained messages published after
+    /// the message id `after` first. Nothing is replayed if `after` is 0 or the name is a wildcard filter.
+    fn resume(
+        self,
+        name: String,
+        owner: u64,
+        after: u64,
+    ) -> Result<mpsc::Receiver<Arc<CommandResponse>>, KvError>;
+    /// Unsubscribe from a topic, only the owner connection could unsubscribe.
+    fn unsubscribe(self, name: String, id: u64, owner: u64) -> Result<u64, KvError>;
+    /// Publish a message to a topic on behalf of the publisher connection,
+    /// the name must not contain wildcards.
+    fn publish(
+        self,
+        name: String,
+        value: Arc<CommandResponse>,
+        publisher: u64,
+    ) -> Result<(), KvError>;
+}
+
+/// What to do when a subscriber's buffer is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SlowSubscriber {
+    /// Wait for the subscriber to catch up, a slow subscriber delays the others.
+    #[default]
+    Wait,
+    /// Drop the message for the subscriber, and keep the subscription.
+    DropMessage,
+    /// Remove the subscription, the subscriber's stream ends.
+    Evict,
+}
+
+/// The configuration of the broadcaster.
+#[derive(Debug, Clone)]
+pub struct BroadcasterConfig {
+    /// The number of messages buffered for each subscription and each hot topic.
+    pub capacity: usize,
+    /// What to do when a subscriber's buffer is full.
+    pub slow_subscriber: SlowSubscriber,
+    /// The topics always fanned out by a `tokio::sync::broadcast` channel.
+    pub hot_topics: Vec<String>,
+    /// A topic is treated as hot once its subscriber count reaches the threshold,
+    /// the following subscribers are fanned out by a `tokio::sync::broadcast` channel.
+    pub hot_threshold: Option<usize>,
+    /// The max number of subscriptions a connection could own, None for unlimited.
+    /// Anonymous callers (owner 0) are not connections, so they are not limited.
+    pub max_subscriptions_per_conn: Option<usize>,
+    /// The number of the latest messages retained per topic, for the subscribers to resume from.
+    pub retained_messages: usize,
+    /// The max number of messages per second published to a topic, None for unlimited.
+    pub max_publish_rate: Option<u32>,
+    /// The max number of messages per second a connection could publish, None for unlimited.
+    /// Like the subscription quota, anonymous callers (owner 0) are not limited.
+    pub max_publish_rate_per_conn: Option<u32>,
+}
+
+impl Default for BroadcasterConfig {
+    fn default() -> Self {
+        Self {
+            capacity: BROADCAST_CAPACITY,
+            slow_subscriber: SlowSubscriber::default(),
+            hot_topics: Vec::new(),
+            hot_threshold: None,
+            max_subscriptions_per_conn: None,
+            retained_messages: 0,
+            max_publish_rate: None,
+            max_publish_rate_per_conn: None,
+        }
+    }
+}
+
+impl BroadcasterConfig {
+    /// Reject the configuration the channels can't be created with
+    pub(crate) fn check(&self) {
+        assert!(
+            self.capacity > 0,
+            "the capacity of the broadcaster must be greater than 0"
+        );
+    }
+}
+
+/// The way a message reaches a subscription.
+enum FanOut {
+    /// The publisher sends the message to the subscription directly.
+    Direct(mpsc::Sender<Arc<CommandResponse>>),
+    /// The publisher sends the message to the topic's broadcast channel once,
+    /// and a forwarding task relays it to the subscription.
+    Broadcast(AbortHandle),
+}
+
+/// A subscription of a topic.
+struct Subscriber {
+    /// The id of the connection owning the subscription.
+    owner: u64,
+    /// How the messages reach the subscription.
+    fan_out: FanOut,
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        // stop relaying, so the subscription stream ends
+        if let FanOut::Broadcast(handle) = &self.fan_out {
+            handle.abort();
+        }
+    }
+}
+
+/// The published messages of a topic.
+#[derive(Default)]
+struct TopicLog {
+    /// The id of the last published message.
+    last_id: u64,
+    /// The latest messages, see `BroadcasterConfig::retained_messages`.
+    retained: VecDeque<Arc<CommandResponse>>,
+    /// The messages not yet delivered to the direct subscriptions, with the matching filters.
+    pending: VecDeque<(Vec<String>, Arc<CommandResponse>)>,
+    /// Whether a task is delivering the pending messages, one at most per topic,
+    /// so the messages of a topic are delivered in order.
+    draining: bool,
 }
 
 /// A broadcaster for topics.
 #[derive(Default)]
 pub struct Broadcaster {
+    config: BroadcasterConfig,
+    /// The last allocated subscription id, ids are scoped per broadcaster.
+    last_id: AtomicU64,
     /// The topics, key is the topic name, value is the set of subscription ids.
-    topics: DashMap<String, DashSet<u32>>,
-    /// The subscriptions, key is the subscription id, value is the sender of the subscription.
-    subscriptions: DashMap<u32, mpsc::Sender<Arc<CommandResponse>>>,
+    topics: DashMap<String, DashSet<u64>>,
+    /// The subscriptions, key is the subscription id.
+    subscriptions: DashMap<u64, Subscriber>,
+    /// The broadcast channels of the hot topics, key is the topic name.
+    hot: DashMap<String, broadcast::Sender<Arc<CommandResponse>>>,
+    /// The number of subscriptions owned by each connection, key is the owner id.
+    owned: DashMap<u64, usize>,
+    /// The subscribed topic filters containing wildcards.
+    wildcards: DashSet<String>,
+    /// The message logs of the topics, key is the topic name, removed once idle, see `is_idle`.
+    logs: DashMap<String, TopicLog>,
+    /// The highest message id of the removed logs, a new log continues from it, so the ids
+    /// of a topic keep increasing for the subscribers resuming from them.
+    removed_last_id: AtomicU64,
+    /// The publish rates of the topics.
+    topic_rates: RateLimiter<String>,
+    /// The publish rates of the connections.
+    conn_rates: RateLimiter<u64>,
+    /// The stats counting the evicted subscriptions.
+    stats: Arc<ServerStats>,
 }
 
 impl Topic for Arc<Broadcaster> {
-    fn subscribe(self, name: String) -> mpsc::Receiver<Arc<CommandResponse>> {
-        let id = {
-            let entry = self.topics.entry(name).or_default();
-            let id = get_next_subscription_id();
+    fn subscribe(
+        self,
+        name: String,
+        owner: u64,
+    ) -> Result<mpsc::Receiver<Arc<CommandResponse>>, KvError> {
+        self.resume(name, owner, 0)
+    }
+
+    fn resume(
+        self,
+        name: String,
+        owner: u64,
+        after: u64,
+    ) -> Result<mpsc::Receiver<Arc<CommandResponse>>, KvError> {
+        validate_filter(&name)?;
+        self.acquire_quota(owner)?;
+
+        let wildcard = is_wildcard(&name);
+        if wildcard {
+            self.wildcards.insert(name.clone());
+        }
+
+        // hold the log until the subscription is registered, so a message published meanwhile
+        // is either replayed or delivered, it may be both, the ids tell the duplicates
+        let log = (!wildcard).then(|| self.log_of(&name));
+        let replay: Vec<_> = match &log {
+            Some(log) if after > 0 => log
+                .retained
+                .iter()
+                .filter(|msg| msg.message_id > after)
+                .cloned()
+                .collect(),
+            _ => vec![],
+        };
+
+        let (id, is_hot) = {
+            let entry = self.topics.entry(name.clone()).or_default();
+            let id = self.next_subscription_id();
+            let is_hot = self.is_hot(&name, entry.value().len());
             entry.value().insert(id);
-            id
+            (id, is_hot)
         };
 
-        let (tx, rx) = mpsc::channel(BROADCAST_CAPACITY);
+        // the subscription id takes a slot as before, the replay gets extra room so it never blocks
+        let (tx, rx) = mpsc::channel(self.config.capacity + replay.len());
 
         let v: Value = (id as i64).into();
-
-        let tx1 = tx.clone();
-        tokio::spawn(async move {
-            if let Err(e) = tx1.send(Arc::new(v.into())).await {
-                warn!("Failed to send subscription id: {}. Error: {:?}", id, e);
+        for msg in std::iter::once(Arc::new(v.into())).chain(replay) {
+            if let Err(e) = tx.try_send(msg) {
+                warn!("Failed to send to subscription {}. Error: {:?}", id, e);
             }
-        });
+        }
+
+        let fan_out = if is_hot {
+            let brx = self
+                .hot
+                .entry(name.clone())
+                .or_insert_with(|| broadcast::channel(self.config.capacity).0)
+                .subscribe();
+            let handle = spawn_named("topic-forward", self.clone().forward(name, id, brx, tx));
+            FanOut::Broadcast(handle.abort_handle())
+        } else {
+            FanOut::Direct(tx)
+        };
 
-        self.subscriptions.insert(id, tx);
-        debug!("Subscription {} is added", id);
+        self.subscriptions.insert(id, Subscriber { owner, fan_out });
+        drop(log);
+        debug!("Subscription {} is added, hot: {}", id, is_hot);
 
-        rx
+        Ok(rx)
     }
 
-    fn unsubscribe(self, name: String, id: u32) -> Result<u32, KvError> {
+    fn unsubscribe(self, name: String, id: u64, owner: u64) -> Result<u64, KvError> {
+        match self.subscriptions.get(&id).map(|v| v.owner) {
+            Some(v) if v != owner => {
+                return Err(KvError::PermissionDenied(format!("subscription {}", id)))
+            }
+            _ => (),
+        }
         match self.remove_subscription(name, id) {
             Some(id) => Ok(id),
             None => Err(KvError::NotFound(format!("subscription {}", id))),
         }
     }
 
-    fn publish(self, name: String, value: Arc<CommandResponse>) {
-        tokio::spawn(async move {
-            let mut ids = vec![];
-            if let Some(topic) = self.topics.get(&name) {
-                let subscriptions = topic.value().clone();
+    fn publish(
+        self,
+        name: String,
+        value: Arc<CommandResponse>,
+        publisher: u64,
+    ) -> Result<(), KvError> {
+        validate_topic_name(&name)?;
+        self.throttle(&name, publisher)?;
 
-                drop(topic); // unlock quickly
+        let mut log = self.log_of(&name);
+        log.last_id += 1;
+        let mut msg = Arc::unwrap_or_clone(value);
+        msg.message_id = log.last_id;
+        msg.topic = name.clone();
+        let value = Arc::new(msg);
+        if self.config.retained_messages > 0 {
+            log.retained.push_back(value.clone());
+            if log.retained.len() > self.config.retained_messages {
+                log.retained.pop_front();
+            }
+        }
 
-                for id in subscriptions.into_iter() {
-                    if let Some(tx) = self.subscriptions.get(&id) {
-                        if let Err(e) = tx.send(value.clone()).await {
-                            warn!("Failed to send message to subscription {}, {}", id, e);
-                            ids.push(id);
+        let filters = self.matching_filters(&name);
+
+        // the hot subscriptions share a single send, the forwarding tasks do the fan-out
+        for filter in filters.iter() {
+            if let Some(tx) = self.hot.get(filter) {
+                _ = tx.send(value.clone());
+            }
+        }
+
+        log.pending.push_back((filters, value));
+        if !log.draining {
+            log.draining = true;
+            drop(log);
+            spawn_named("topic-publish", self.drain(name));
+        }
+
+        Ok(())
+    }
+}
+
+impl Broadcaster {
+    /// Deliver the pending messages of a topic to the direct subscriptions in order,
+    /// until there are no more
+    async fn drain(self: Arc<Self>, name: String) {
+        loop {
+            let (filters, value) = {
+                let Some(mut log) = self.logs.get_mut(&name) else {
+                    return;
+                };
+                let msg = log.pending.pop_front();
+                match msg {
+                    Some(msg) => msg,
+                    None => {
+                        log.draining = false;
+                        drop(log); // unlock before removing
+                        self.remove_idle_log(&name);
+                        return;
+                    }
+                }
+            };
+
+            for filter in filters {
+                let mut ids = vec![];
+                if let Some(topic) = self.topics.get(&filter) {
+                    let subscriptions = topic.value().clone();
+
+                    drop(topic); // unlock quickly
+
+                    for id in subscriptions.into_iter() {
+                        let tx = self.subscriptions.get(&id).and_then(|v| match &v.fan_out {
+                            FanOut::Direct(tx) => Some(tx.clone()),
+                            FanOut::Broadcast(_) => None,
+                        });
+                        if let Some(tx) = tx {
+                            if !self.deliver(id, &tx, value.clone()).await {
+                                ids.push(id);
+                            }
                         }
                     }
                 }
+
+                for id in ids {
+                    _ = self.remove_subscription(filter.clone(), id);
+                }
             }
+        }
+    }
+
+    /// Create a broadcaster with the given configuration
+    ///
+    /// # Panics
+    ///
+    /// Panics if `config.capacity` is 0, a channel can't be created without a buffer.
+    pub fn new(config: BroadcasterConfig) -> Self {
+        config.check();
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Count the evicted subscriptions in the stats of the service
+    pub(crate) fn with_stats(mut self, stats: Arc<ServerStats>) -> Self {
+        self.stats = stats;
+        self
+    }
 
-            for id in ids {
-                _ = self.remove_subscription(name.clone(), id);
+    /// The subscribed topic filters matching the topic name
+    fn matching_filters(&self, name: &str) -> Vec<String> {
+        let mut filters: Vec<String> = self
+            .wildcards
+            .iter()
+            .filter(|filter| topic_matches(filter.key(), name))
+            .map(|filter| filter.key().clone())
+            .collect();
+        if self.topics.contains_key(name) {
+            filters.push(name.to_string());
+        }
+        filters
+    }
+
+    /// Whether the log of a topic is idle: it has no message to deliver or to replay, and no
+    /// subscription expects the ids of the next messages to follow the previous ones
+    fn is_idle(&self, name: &str, log: &TopicLog) -> bool {
+        !log.draining
+            && log.pending.is_empty()
+            && log.retained.is_empty()
+            && self.matching_filters(name).is_empty()
+    }
+
+    /// The log of a topic, created if missing
+    fn log_of(&self, name: &str) -> RefMut<'_, String, TopicLog> {
+        self.logs
+            .entry(name.to_string())
+            .or_insert_with(|| TopicLog {
+                last_id: self.removed_last_id.load(Ordering::Relaxed),
+                ..Default::default()
+            })
+    }
+
+    /// Whether the log of a topic is idle, if so the next logs continue from its last id
+    fn retire_if_idle(&self, name: &str, log: &TopicLog) -> bool {
+        let idle = self.is_idle(name, log);
+        if idle {
+            self.removed_last_id
+                .fetch_max(log.last_id, Ordering::Relaxed);
+        }
+        idle
+    }
+
+    /// Remove the log of a topic if it is idle, so the logs don't pile up with the topic names
+    fn remove_idle_log(&self, name: &str) {
+        self.logs
+            .remove_if(name, |name, log| self.retire_if_idle(name, log));
+    }
+
+    /// Whether the next subscription of the topic should be fanned out by broadcast
+    fn is_hot(&self, name: &str, subscribers: usize) -> bool {
+        self.hot.contains_key(name)
+            || self.config.hot_topics.iter().any(|t| t == name)
+            || matches!(self.config.hot_threshold, Some(n) if subscribers >= n)
+    }
+
+    /// Relay the messages of a hot topic to a subscription
+    async fn forward(
+        self: Arc<Self>,
+        name: String,
+        id: u64,
+        mut brx: broadcast::Receiver<Arc<CommandResponse>>,
+        tx: mpsc::Sender<Arc<CommandResponse>>,
+    ) {
+        loop {
+            match brx.recv().await {
+                Ok(value) => {
+                    if !self.deliver(id, &tx, value).await {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Subscription {} lagged behind, {} messages skipped", id, n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
             }
-        });
+        }
+        // the removal aborts this task, so do it at the very end
+        _ = self.remove_subscription(name, id);
     }
-}
 
-impl Broadcaster {
-    pub fn remove_subscription(&self, name: String, id: u32) -> Option<u32> {
+    /// Send a message to a subscription following the slow subscriber policy,
+    /// return false if the subscription should be removed.
+    async fn deliver(
+        &self,
+        id: u64,
+        tx: &mpsc::Sender<Arc<CommandResponse>>,
+        value: Arc<CommandResponse>,
+    ) -> bool {
+        let policy = self.config.slow_subscriber;
+        if policy == SlowSubscriber::Wait {
+            return match tx.send(value).await {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!("Failed to send message to subscription {}, {}", id, e);
+                    false
+                }
+            };
+        }
+
+        match tx.try_send(value) {
+            Ok(_) => true,
+            Err(TrySendError::Full(_)) if policy == SlowSubscriber::DropMessage => {
+                warn!("Subscription {} is full, the message is dropped", id);
+                true
+            }
+            Err(TrySendError::Full(_)) => {
+                warn!("Subscription {} is full, evicting it", id);
+                self.stats.evicted(1);
+                false
+            }
+            Err(TrySendError::Closed(_)) => {
+                warn!("Subscription {} is closed", id);
+                false
+            }
+        }
+    }
+
+    /// Fail if the topic or the publisher is over its publish rate
+    fn throttle(&self, name: &str, publisher: u64) -> Result<(), KvError> {
+        if let Some(rate) = self.config.max_publish_rate_per_conn {
+            if publisher != 0 && !self.conn_rates.acquire(&publisher, rate) {
+                return Err(KvError::Throttled(format!(
+                    "connection {} publishes over {} messages per second",
+                    publisher, rate
+                )));
+            }
+        }
+        if let Some(rate) = self.config.max_publish_rate {
+            if !self.topic_rates.acquire(&name.to_string(), rate) {
+                return Err(KvError::Throttled(format!(
+                    "topic {} is published over {} messages per second",
+                    name, rate
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Count a new subscription of the owner, fail if the owner is over its quota
+    fn acquire_quota(&self, owner: u64) -> Result<(), KvError> {
+        let max = match self.config.max_subscriptions_per_conn {
+            Some(max) if owner != 0 => max,
+            _ => return Ok(()),
+        };
+
+        let mut count = self.owned.entry(owner).or_default();
+        if *count >= max {
+            warn!(
+                "Connection {} reached the subscription quota {}",
+                owner, max
+            );
+            return Err(KvError::QuotaExceeded(format!(
+                "connection {} could own at most {} subscriptions",
+                owner, max
+            )));
+        }
+        *count += 1;
+        Ok(())
+    }
+
+    /// Give back the quota of a removed subscription
+    fn release_quota(&self, owner: u64) {
+        if let Some(mut count) = self.owned.get_mut(&owner) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                drop(count); // unlock before removing
+                self.owned.remove_if(&owner, |_, v| *v == 0);
+            }
+        }
+    }
+
+    /// Allocate the next subscription id, ids start from 1.
+    fn next_subscription_id(&self) -> u64 {
+        self.last_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn remove_subscription(&self, name: String, id: u64) -> Option<u64> {
         if let Some(v) = self.topics.get_mut(&name) {
             v.remove(&id);
             if v.is_empty() {
                 info!("Topic is empty, removing it: {}", name);
                 drop(v); // unlock quickly
                 self.topics.remove(&name);
+                self.hot.remove(&name);
+                // a wildcard filter may have kept the logs of many topics
+                match self.wildcards.remove(&name) {
+                    Some(_) => self
+                        .logs
+                        .retain(|name, log| !self.retire_if_idle(name, log)),
+                    None => self.remove_idle_log(&name),
+                }
             }
         }
         debug!("Unsubscribed from topic: {}, id: {}", name, id);
-        self.subscriptions.remove(&id).map(|(id, _)| id)
+        let (id, subscriber) = self.subscriptions.remove(&id)?;
+        self.release_quota(subscriber.owner);
+        Some(id)
+    }
+}
+
+/// Whether the topic filter contains wildcards
+fn is_wildcard(filter: &str) -> bool {
+    filter
+        .split(LEVEL_SEPARATOR)
+        .any(|level| level == SINGLE_LEVEL_WILDCARD || level == MULTI_LEVEL_WILDCARD)
+}
+
+/// A wildcard must occupy a whole level, and `#` must be the last level
+fn validate_filter(filter: &str) -> Result<(), KvError> {
+    let levels: Vec<&str> = filter.split(LEVEL_SEPARATOR).collect();
+    for (i, level) in levels.iter().enumerate() {
+        let bad_multi = level.contains(MULTI_LEVEL_WILDCARD)
+            && (*level != MULTI_LEVEL_WILDCARD || i != levels.len() - 1);
+        let bad_single = level.contains(SINGLE_LEVEL_WILDCARD) && *level != SINGLE_LEVEL_WILDCARD;
+        if bad_multi || bad_single {
+            return Err(KvError::InvalidCommand(format!(
+                "invalid topic filter: {}",
+                filter
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// A topic name to publish to must not contain wildcards
+fn validate_topic_name(name: &str) -> Result<(), KvError> {
+    if name.contains(SINGLE_LEVEL_WILDCARD) || name.contains(MULTI_LEVEL_WILDCARD) {
+        return Err(KvError::InvalidCommand(format!(
+            "wildcards are not allowed in topic name: {}",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Whether the topic name matches the topic filter
+fn topic_matches(filter: &str, name: &str) -> bool {
+    let mut filter = filter.split(LEVEL_SEPARATOR);
+    let mut name = name.split(LEVEL_SEPARATOR);
+    loop {
+        match (filter.next(), name.next()) {
+            // `#` matches the parent level too, e.g. `a/#` matches `a`
+            (Some(MULTI_LEVEL_WILDCARD), _) => return true,
+            (Some(SINGLE_LEVEL_WILDCARD), Some(_)) => continue,
+            (Some(f), Some(n)) if f == n => continue,
+            (None, None) => return true,
+            _ => return false,
+        }
     }
 }
 
@@ -125,12 +649,14 @@ mod tests {
         let lobby = "lobby".to_string();
 
         // subscribe to the lobby topic.
-        let mut stream1 = b.clone().subscribe(lobby.clone());
-        let mut stream2 = b.clone().subscribe(lobby.clone());
+        let mut stream1 = b.clone().subscribe(lobby.clone(), 0).unwrap();
+        let mut stream2 = b.clone().subscribe(lobby.clone(), 0).unwrap();
 
         // publish a message to the lobby topic.
         let v: Value = "hello".into();
-        b.clone().publish(lobby.clone(), Arc::new(v.clone().into()));
+        b.clone()
+            .publish(lobby.clone(), Arc::new(v.clone().into()), 0)
+            .unwrap();
 
         // subscribers should be able to receive the message.
         let id1: i64 = stream1.recv().await.unwrap().as_ref().try_into().unwrap();
@@ -142,21 +668,326 @@ mod tests {
         let res1 = stream1.recv().await.unwrap();
         let res2 = stream2.recv().await.unwrap();
         assert_eq!(res1, res2);
-        assert_res_ok(&res1, &[v.clone()], &[]);
+        assert_res_ok(&res1, std::slice::from_ref(&v), &[]);
 
         // if unsubscribe, the subscriber should not receive the message.
-        let result = b.clone().unsubscribe(lobby.clone(), id1 as u32);
+        let result = b.clone().unsubscribe(lobby.clone(), id1 as u64, 0);
         assert!(result.is_ok());
 
         // publish a message to the lobby topic.
         let v: Value = "world".into();
-        b.clone().publish(lobby.clone(), Arc::new(v.clone().into()));
+        b.clone()
+            .publish(lobby.clone(), Arc::new(v.clone().into()), 0)
+            .unwrap();
 
         // the subscriber should not receive the message.
         assert!(stream1.recv().await.is_none());
 
         // the other subscriber should receive the message.
         let res2 = stream2.recv().await.unwrap();
-        assert_res_ok(&res2, &[v.clone()], &[]);
+        assert_res_ok(&res2, std::slice::from_ref(&v), &[]);
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_from_other_connection_should_fail() {
+        let b = Arc::new(Broadcaster::default());
+        let mut stream = b.clone().subscribe("lobby".into(), 1).unwrap();
+        let id: i64 = stream.recv().await.unwrap().as_ref().try_into().unwrap();
+
+        let result = b.clone().unsubscribe("lobby".into(), id as u64, 2);
+        assert!(matches!(result, Err(KvError::PermissionDenied(_))));
+
+        let result = b.clone().unsubscribe("lobby".into(), id as u64, 1);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn subscription_ids_should_be_scoped_per_broadcaster() {
+        let b1 = Broadcaster::default();
+        let b2 = Broadcaster::default();
+        assert_eq!(b1.next_subscription_id(), 1);
+        assert_eq!(b1.next_subscription_id(), 2);
+        assert_eq!(b2.next_subscription_id(), 1);
+    }
+
+    #[tokio::test]
+    async fn hot_topic_pub_sub_should_work() {
+        let b = Arc::new(Broadcaster::new(BroadcasterConfig {
+            hot_threshold: Some(1),
+            ..Default::default()
+        }));
+        let lobby = "lobby".to_string();
+
+        // the first subscriber is direct, the second one is fanned out by broadcast
+        let mut stream1 = b.clone().subscribe(lobby.clone(), 0).unwrap();
+        let mut stream2 = b.clone().subscribe(lobby.clone(), 0).unwrap();
+        let _id1: i64 = stream1.recv().await.unwrap().as_ref().try_into().unwrap();
+        let id2: i64 = stream2.recv().await.unwrap().as_ref().try_into().unwrap();
+        assert!(b.hot.contains_key(&lobby));
+
+        let v: Value = "hello".into();
+        b.clone()
+            .publish(lobby.clone(), Arc::new(v.clone().into()), 0)
+            .unwrap();
+        let res1 = stream1.recv().await.unwrap();
+        let res2 = stream2.recv().await.unwrap();
+        assert_eq!(res1, res2);
+
+        // unsubscribe the hot subscriber, its stream should end
+        assert!(b.clone().unsubscribe(lobby.clone(), id2 as u64, 0).is_ok());
+        assert!(stream2.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn subscription_quota_should_be_released_on_unsubscribe() {
+        let b = Arc::new(Broadcaster::new(BroadcasterConfig {
+            max_subscriptions_per_conn: Some(1),
+            ..Default::default()
+        }));
+        let lobby = "lobby".to_string();
+
+        let mut stream = b.clone().subscribe(lobby.clone(), 1).unwrap();
+        let id: i64 = stream.recv().await.unwrap().as_ref().try_into().unwrap();
+
+        // the quota is per connection, anonymous callers are not limited
+        let res = b.clone().subscribe(lobby.clone(), 1);
+        assert!(matches!(res, Err(KvError::QuotaExceeded(_))));
+        assert!(b.clone().subscribe(lobby.clone(), 2).is_ok());
+        assert!(b.clone().subscribe(lobby.clone(), 0).is_ok());
+        assert!(b.clone().subscribe(lobby.clone(), 0).is_ok());
+
+        b.clone().unsubscribe(lobby.clone(), id as u64, 1).unwrap();
+        assert!(b.clone().subscribe(lobby, 1).is_ok());
+    }
+
+    #[tokio::test]
+    async fn publish_rate_should_be_limited() {
+        let b = Arc::new(Broadcaster::new(BroadcasterConfig {
+            max_publish_rate: Some(3),
+            max_publish_rate_per_conn: Some(2),
+            ..Default::default()
+        }));
+        let msg = || Arc::new(CommandResponse::ok());
+
+        // the connection rate is hit first
+        assert!(b.clone().publish("t1".into(), msg(), 1).is_ok());
+        assert!(b.clone().publish("t1".into(), msg(), 1).is_ok());
+        let res = b.clone().publish("t2".into(), msg(), 1);
+        assert!(matches!(res, Err(KvError::Throttled(_))));
+
+        // then the topic rate, whoever publishes
+        assert!(b.clone().publish("t1".into(), msg(), 2).is_ok());
+        let res = b.clone().publish("t1".into(), msg(), 0);
+        assert!(matches!(res, Err(KvError::Throttled(_))));
+        assert!(b.clone().publish("t2".into(), msg(), 0).is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn messages_should_be_delivered_in_order() {
+        let b = Arc::new(Broadcaster::new(BroadcasterConfig {
+            capacity: 1000,
+            ..Default::default()
+        }));
+        let mut lobby = b.clone().subscribe("lobby".into(), 0).unwrap();
+        let mut wildcard = b.clone().subscribe("#".into(), 0).unwrap();
+        lobby.recv().await.unwrap();
+        wildcard.recv().await.unwrap();
+
+        for i in 0..200 {
+            let v: Value = (i as i64).into();
+            b.clone()
+                .publish("lobby".into(), Arc::new(v.into()), 0)
+                .unwrap();
+        }
+        for stream in [&mut lobby, &mut wildcard] {
+            for id in 1..=200 {
+                let msg = stream.recv().await.unwrap();
+                assert_eq!((msg.message_id, msg.topic.as_str()), (id, "lobby"));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn resume_should_replay_retained_messages() {
+        for hot in [false, true] {
+            let b = Arc::new(Broadcaster::new(BroadcasterConfig {
+                retained_messages: 3,
+                hot_threshold: hot.then_some(0),
+                ..Default::default()
+            }));
+            let lobby = "lobby".to_string();
+            for i in 0..5 {
+                let v: Value = (i as i64).into();
+                b.clone()
+                    .publish(lobby.clone(), Arc::new(v.into()), 0)
+                    .unwrap();
+            }
+
+            // only the last 3 messages are retained, the ids start from 1
+            let mut stream = b.clone().resume(lobby.clone(), 1, 3).unwrap();
+            stream.recv().await.unwrap();
+            let msg = stream.recv().await.unwrap();
+            assert_eq!((msg.message_id, msg.values[0].clone()), (4, 3.into()));
+            let msg = stream.recv().await.unwrap();
+            assert_eq!((msg.message_id, msg.values[0].clone()), (5, 4.into()));
+
+            // then the live messages
+            let v: Value = "live".into();
+            b.clone()
+                .publish(lobby.clone(), Arc::new(v.clone().into()), 0)
+                .unwrap();
+            let msg = stream.recv().await.unwrap();
+            assert_eq!((msg.message_id, msg.values[0].clone()), (6, v));
+
+            // a plain subscription replays nothing
+            let mut stream = b.clone().subscribe(lobby, 2).unwrap();
+            stream.recv().await.unwrap();
+            assert!(stream.try_recv().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn idle_logs_should_be_removed() {
+        async fn drained(b: &Broadcaster) {
+            while b.logs.iter().any(|log| log.draining) {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        // the topics nobody listens to leave no log behind
+        let b = Arc::new(Broadcaster::default());
+        for i in 0..10 {
+            b.clone()
+                .publish(format!("t{}", i), Arc::new(CommandResponse::ok()), 0)
+                .unwrap();
+        }
+        drained(&b).await;
+        assert!(b.logs.is_empty());
+
+        // the log goes away with the last subscription, the ids keep increasing anyway
+        let mut stream = b.clone().subscribe("lobby".into(), 1).unwrap();
+        let id: i64 = stream.recv().await.unwrap().as_ref().try_into().unwrap();
+        b.clone()
+            .publish("lobby".into(), Arc::new(CommandResponse::ok()), 0)
+            .unwrap();
+        assert_eq!(stream.recv().await.unwrap().message_id, 2);
+        drained(&b).await;
+        assert!(b.logs.contains_key("lobby"));
+        b.clone().unsubscribe("lobby".into(), id as u64, 1).unwrap();
+        assert!(b.logs.is_empty());
+
+        // a wildcard subscription keeps the ids of the matching topics going
+        let mut stream = b.clone().subscribe("home/#".into(), 1).unwrap();
+        let id: i64 = stream.recv().await.unwrap().as_ref().try_into().unwrap();
+        for i in 3..=4 {
+            b.clone()
+                .publish("home/temp".into(), Arc::new(CommandResponse::ok()), 0)
+                .unwrap();
+            drained(&b).await;
+            assert_eq!(stream.recv().await.unwrap().message_id, i);
+        }
+        b.clone()
+            .unsubscribe("home/#".into(), id as u64, 1)
+            .unwrap();
+        assert!(b.logs.is_empty());
+
+        // the retained messages keep the log
+        let b = Arc::new(Broadcaster::new(BroadcasterConfig {
+            retained_messages: 1,
+            ..Default::default()
+        }));
+        b.clone()
+            .publish("lobby".into(), Arc::new(CommandResponse::ok()), 0)
+            .unwrap();
+        drained(&b).await;
+        assert!(b.logs.contains_key("lobby"));
+    }
+
+    #[test]
+    fn topic_matches_should_work() {
+        assert!(topic_matches("a/b/c", "a/b/c"));
+        assert!(topic_matches("a/+/c", "a/b/c"));
+        assert!(topic_matches("+/+/+", "a/b/c"));
+        assert!(topic_matches("a/#", "a/b/c"));
+        assert!(topic_matches("a/#", "a"));
+        assert!(topic_matches("#", "a/b"));
+        assert!(!topic_matches("a/+", "a/b/c"));
+        assert!(!topic_matches("a/+/c", "a/b/d"));
+        assert!(!topic_matches("a/b", "a"));
+    }
+
+    #[test]
+    fn validate_filter_should_work() {
+        assert!(validate_filter("a/+/c/#").is_ok());
+        assert!(validate_filter("#").is_ok());
+        assert!(validate_filter("a/#/c").is_err());
+        assert!(validate_filter("a/b#").is_err());
+        assert!(validate_filter("a/b+/c").is_err());
+        assert!(validate_topic_name("a/+").is_err());
+    }
+
+    #[tokio::test]
+    async fn wildcard_pub_sub_should_work() {
+        let b = Arc::new(Broadcaster::default());
+
+        let mut stream1 = b.clone().subscribe("home/+/temp".into(), 0).unwrap();
+        let mut stream2 = b.clone().subscribe("home/#".into(), 0).unwrap();
+        let _id1: i64 = stream1.recv().await.unwrap().as_ref().try_into().unwrap();
+        let _id2: i64 = stream2.recv().await.unwrap().as_ref().try_into().unwrap();
+
+        let v: Value = "hello".into();
+        b.clone()
+            .publish("home/kitchen/temp".into(), Arc::new(v.clone().into()), 0)
+            .unwrap();
+        assert_res_ok(
+            &stream1.recv().await.unwrap(),
+            std::slice::from_ref(&v),
+            &[],
+        );
+        assert_res_ok(
+            &stream2.recv().await.unwrap(),
+            std::slice::from_ref(&v),
+            &[],
+        );
+
+        // only the subtree subscription matches
+        let v: Value = "world".into();
+        b.clone()
+            .publish("home/kitchen/light".into(), Arc::new(v.clone().into()), 0)
+            .unwrap();
+        assert_res_ok(&stream2.recv().await.unwrap(), &[v], &[]);
+        assert!(stream1.try_recv().is_err());
+
+        let res = b
+            .clone()
+            .publish("home/+".into(), Arc::new(CommandResponse::ok()), 0);
+        assert!(matches!(res, Err(KvError::InvalidCommand(_))));
+    }
+
+    #[tokio::test]
+    async fn slow_subscriber_policy_should_work() {
+        for policy in [SlowSubscriber::DropMessage, SlowSubscriber::Evict] {
+            let b = Arc::new(Broadcaster::new(BroadcasterConfig {
+                capacity: 2,
+                slow_subscriber: policy,
+                ..Default::default()
+            }));
+            let lobby = "lobby".to_string();
+
+            let mut stream = b.clone().subscribe(lobby.clone(), 0).unwrap();
+            let id: i64 = stream.recv().await.unwrap().as_ref().try_into().unwrap();
+
+            // the subscriber never reads, so the third message overflows the buffer
+            for i in 0..3 {
+                let v: Value = i.into();
+                b.clone()
+                    .publish(lobby.clone(), Arc::new(v.into()), 0)
+                    .unwrap();
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+
+            let evicted = b.clone().unsubscribe(lobby.clone(), id as u64, 0).is_err();
+            assert_eq!(evicted, policy == SlowSubscriber::Evict);
+        }
     }
 }
@@ -3,26 +3,28 @@ use std::{pin::Pin, sync::Arc};
 use futures::{stream, Stream};
 use tokio_stream::wrappers::ReceiverStream;
 
-use crate::{CommandResponse, Publish, Subscribe, Unsubscribe};
+use crate::{CommandResponse, ConnContext, Publish, Subscribe, Unsubscribe};
 
 use super::topic::Topic;
 
 pub type StreamingResponse = Pin<Box<dyn Stream<Item = Arc<CommandResponse>> + Send>>;
 
 pub trait TopicService {
-    fn execute(self, topic: impl Topic) -> StreamingResponse;
+    fn execute(self, topic: impl Topic, ctx: &ConnContext) -> StreamingResponse;
 }
 
 impl TopicService for Subscribe {
-    fn execute(self, topic: impl Topic) -> StreamingResponse {
-        let rx = topic.subscribe(self.topic);
-        Box::pin(ReceiverStream::new(rx))
+    fn execute(self, topic: impl Topic, ctx: &ConnContext) -> StreamingResponse {
+        match topic.resume(self.topic, ctx.id(), self.resume_after) {
+            Ok(rx) => Box::pin(ReceiverStream::new(rx)),
+            Err(e) => Box::pin(stream::once(async { Arc::new(e.into()) })),
+        }
     }
 }
 
 impl TopicService for Unsubscribe {
-    fn execute(self, topic: impl Topic) -> StreamingResponse {
-        let res = match topic.unsubscribe(self.topic, self.id) {
+    fn execute(self, topic: impl Topic, ctx: &ConnContext) -> StreamingResponse {
+        let res = match topic.unsubscribe(self.topic, self.id, ctx.id()) {
             Ok(_) => CommandResponse::ok(),
             Err(e) => e.into(),
         };
@@ -31,9 +33,12 @@ impl TopicService for Unsubscribe {
 }
 
 impl TopicService for Publish {
-    fn execute(self, topic: impl Topic) -> StreamingResponse {
-        topic.publish(self.topic, Arc::new(self.values.into()));
-        Box::pin(stream::once(async { Arc::new(CommandResponse::ok()) }))
+    fn execute(self, topic: impl Topic, ctx: &ConnContext) -> StreamingResponse {
+        let res = match topic.publish(self.topic, Arc::new(self.values.into()), ctx.id()) {
+            Ok(_) => CommandResponse::ok(),
+            Err(e) => e.into(),
+        };
+        Box::pin(stream::once(async { Arc::new(res) }))
     }
 }
 
@@ -43,7 +48,7 @@ mod tests {
 
     use crate::{
         assert_res_error, assert_res_ok, dispatch_stream, service::topic::Broadcaster,
-        CommandRequest,
+        BroadcasterConfig, CommandRequest,
     };
     use futures::StreamExt;
     use tokio::time;
@@ -54,7 +59,7 @@ mod tests {
     async fn dispatch_publish_should_work() {
         let topic = Arc::new(Broadcaster::default());
         let cmd = CommandRequest::new_publish("test", vec!["hello".into()]);
-        let mut res = dispatch_stream(cmd, topic);
+        let mut res = dispatch_stream(cmd, topic, &ConnContext::default());
         let data = res.next().await.unwrap();
         assert_res_ok(&data, &[], &[]);
     }
@@ -63,7 +68,7 @@ mod tests {
     async fn dispatch_subscribe_should_work() {
         let topic = Arc::new(Broadcaster::default());
         let cmd = CommandRequest::new_subscribe("test");
-        let mut res = dispatch_stream(cmd, topic);
+        let mut res = dispatch_stream(cmd, topic, &ConnContext::default());
         let id: i64 = res.next().await.unwrap().as_ref().try_into().unwrap();
         assert!(id > 0);
     }
@@ -73,30 +78,47 @@ mod tests {
         let topic = Arc::new(Broadcaster::default());
         let id = {
             let cmd = CommandRequest::new_subscribe("lobby");
-            let mut res = dispatch_stream(cmd, topic.clone());
+            let mut res = dispatch_stream(cmd, topic.clone(), &ConnContext::default());
             let id: i64 = res.next().await.unwrap().as_ref().try_into().unwrap();
             drop(res); // abnormal exit
-            id as u32
+            id as u64
         };
 
         // publish to the unactive subscriber, it should be dropped
         let cmd = CommandRequest::new_publish("lobby", vec!["hello".into()]);
-        _ = dispatch_stream(cmd, topic.clone());
+        _ = dispatch_stream(cmd, topic.clone(), &ConnContext::default());
         time::sleep(Duration::from_millis(10)).await;
 
         // try to unsubscribe the subscriber again, it should return error
-        let result = topic.unsubscribe("lobby".into(), id);
+        let result = topic.unsubscribe("lobby".into(), id, 0);
         assert!(result.is_err());
     }
 
+    #[tokio::test]
+    async fn dispatch_subscribe_over_quota_should_error() {
+        let topic = Arc::new(Broadcaster::new(BroadcasterConfig {
+            max_subscriptions_per_conn: Some(1),
+            ..Default::default()
+        }));
+        let ctx = ConnContext::new(1, None);
+        let cmd = CommandRequest::new_subscribe("lobby");
+        let mut res = dispatch_stream(cmd.clone(), topic.clone(), &ctx);
+        let id: i64 = res.next().await.unwrap().as_ref().try_into().unwrap();
+        assert!(id > 0);
+
+        let mut res = dispatch_stream(cmd, topic, &ctx);
+        let data = res.next().await.unwrap();
+        assert_res_error(&data, 429, "Quota exceeded");
+    }
+
     #[tokio::test]
     async fn dispatch_unsubscribe_should_work() {
         let topic = Arc::new(Broadcaster::default());
         let cmd = CommandRequest::new_subscribe("lobby");
-        let mut res = dispatch_stream(cmd, topic.clone());
+        let mut res = dispatch_stream(cmd, topic.clone(), &ConnContext::default());
         let id: i64 = res.next().await.unwrap().as_ref().try_into().unwrap();
         let cmd = CommandRequest::new_unsubscribe("lobby", id as _);
-        let mut res = dispatch_stream(cmd, topic.clone());
+        let mut res = dispatch_stream(cmd, topic.clone(), &ConnContext::default());
         let data = res.next().await.unwrap();
         assert_res_ok(&data, &[], &[]);
     }
@@ -105,7 +127,7 @@ mod tests {
     async fn dispatch_unsubscribe_random_id_should_error() {
         let topic = Arc::new(Broadcaster::default());
         let cmd = CommandRequest::new_unsubscribe("lobby", 121233);
-        let mut res = dispatch_stream(cmd, topic.clone());
+        let mut res = dispatch_stream(cmd, topic.clone(), &ConnContext::default());
         let data = res.next().await.unwrap();
         assert_res_error(&data, 404, "Not found subscription 121233");
     }
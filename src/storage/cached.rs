@@ -0,0 +1,471 @@
+use std::{
+    future::Future,
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
+use crate::{KvError, Kvpair, Value};
+
+use super::{
+    lru::LruIndex, ScanOptions, ScanPage, Storage, StorageStats, StorageStream, UpdateFn, WriteOp,
+};
+
+/// How the writes of a CachedStore update the cache, they always go to the persistent store first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WritePolicy {
+    /// Cache the written values
+    #[default]
+    Through,
+    /// Drop the written keys from the cache, so only the reads fill it
+    Around,
+}
+
+/// The configuration of a CachedStore
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// The max size in bytes of the cache, as given by its `total_size`, None for unbounded
+    pub capacity: Option<usize>,
+    /// Fill the cache with the pairs read from the persistent store on a miss
+    pub read_through: bool,
+    pub write_policy: WritePolicy,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: None,
+            read_through: true,
+            write_policy: WritePolicy::Through,
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Evict the least recently used pairs once the cache is over `capacity` bytes
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    pub fn read_through(mut self, read_through: bool) -> Self {
+        self.read_through = read_through;
+        self
+    }
+
+    pub fn write_policy(mut self, policy: WritePolicy) -> Self {
+        self.write_policy = policy;
+        self
+    }
+}
+
+/// The counters of the point reads and the evictions of a CachedStore
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheMetrics {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+/// A storage putting a cache store in front of a persistent store.
+///
+/// The writes go to the persistent store before the cache, so it always holds all the pairs and
+/// the cache may start empty. The point reads are served from the cache, the misses are read from
+/// the persistent store, and the least recently used pairs are evicted once the cache is over
+/// capacity. The scans, the versions and the sizes are read from the persistent store.
+pub struct CachedStore<C, P> {
+    cache: C,
+    store: P,
+    config: CacheConfig,
+    lru: Mutex<LruIndex>,
+    /// Serialize the writes and the cache fills, so a fill never caches a value older than
+    /// a concurrent write
+    write_lock: Mutex<()>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl<C: Storage, P: Storage> CachedStore<C, P> {
+    pub fn new(cache: C, store: P, config: CacheConfig) -> Self {
+        Self {
+            cache,
+            store,
+            config,
+            lru: Mutex::new(LruIndex::default()),
+            write_lock: Mutex::new(()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// The size in bytes of the cached pairs
+    pub fn cached_size(&self) -> Result<usize, KvError> {
+        self.cache.total_size()
+    }
+
+    pub fn metrics(&self) -> CacheMetrics {
+        CacheMetrics {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    fn lru(&self) -> MutexGuard<'_, LruIndex> {
+        self.lru.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_writes(&self) -> MutexGuard<'_, ()> {
+        self.write_lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Cache a pair with its deadline, and evict the least recently used pairs if over capacity
+    fn fill(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        deadline: Option<u64>,
+    ) -> Result<(), KvError> {
+        self.lru().touch(table, &key);
+        self.cache.set(table, key.clone(), value)?;
+        if deadline.is_some() {
+            self.cache.expire(table, &key, deadline)?;
+        }
+        let Some(capacity) = self.config.capacity else {
+            return Ok(());
+        };
+        while self.cache.total_size()? > capacity {
+            let Some((table, key)) = self.lru().pop() else {
+                break;
+            };
+            self.cache.del(&table, &key)?;
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Update the cache after a write of a pair, as the write policy says
+    fn written(&self, table: &str, key: String, value: Value) -> Result<(), KvError> {
+        match self.config.write_policy {
+            WritePolicy::Through => self.fill(table, key, value, None),
+            WritePolicy::Around => self.invalidate(table, &key),
+        }
+    }
+
+    fn invalidate(&self, table: &str, key: &str) -> Result<(), KvError> {
+        self.lru().remove(table, key);
+        self.cache.del(table, key)?;
+        Ok(())
+    }
+
+    fn get_cached(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let value = self.cache.get(table, key)?;
+        if value.is_some() {
+            self.lru().touch(table, key);
+        }
+        Ok(value)
+    }
+}
+
+impl<C: Storage, P: Storage> Storage for CachedStore<C, P> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        if let Some(value) = self.get_cached(table, key)? {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(Some(value));
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        if !self.config.read_through {
+            return self.store.get(table, key);
+        }
+
+        let _guard = self.lock_writes();
+        // the key may be filled by a concurrent miss
+        if let Some(value) = self.get_cached(table, key)? {
+            return Ok(Some(value));
+        }
+        let Some(value) = self.store.get(table, key)? else {
+            return Ok(None);
+        };
+        let deadline = self.store.deadline(table, key)?;
+        self.fill(table, key.into(), value.clone(), deadline)?;
+        Ok(Some(value))
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
+        let _guard = self.lock_writes();
+        let old = self.store.set(table, key.clone(), value.clone())?;
+        self.written(table, key, value)?;
+        Ok(old)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        Ok(self.cache.contains(table, key)? || self.store.contains(table, key)?)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let _guard = self.lock_writes();
+        let old = self.store.del(table, key)?;
+        self.invalidate(table, key)?;
+        Ok(old)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.store.get_all(table)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        self.store.get_iter(table)
+    }
+
+    fn get_stream(&self, table: &str) -> Result<StorageStream, KvError> {
+        self.store.get_stream(table)
+    }
+
+    fn scan(
+        &self,
+        table: &str,
+        opts: ScanOptions,
+    ) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        self.store.scan(table, opts)
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: Option<&str>,
+        end: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        self.store.get_range(table, start, end, limit)
+    }
+
+    fn scan_page(
+        &self,
+        table: &str,
+        cursor: Option<String>,
+        count: usize,
+    ) -> Result<ScanPage, KvError> {
+        self.store.scan_page(table, cursor, count)
+    }
+
+    fn sample(&self, table: &str, count: usize) -> Result<Vec<Kvpair>, KvError> {
+        self.store.sample(table, count)
+    }
+
+    fn len_of_table(&self, table: &str) -> Result<usize, KvError> {
+        self.store.len_of_table(table)
+    }
+
+    fn copy_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        let _guard = self.lock_writes();
+        let copied = self.store.copy_key(from, to, key, replace)?;
+        if copied {
+            self.invalidate(to, key)?;
+        }
+        Ok(copied)
+    }
+
+    fn move_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        let _guard = self.lock_writes();
+        let moved = self.store.move_key(from, to, key, replace)?;
+        if moved {
+            self.invalidate(from, key)?;
+            self.invalidate(to, key)?;
+        }
+        Ok(moved)
+    }
+
+    fn clear_table(&self, table: &str) -> Result<usize, KvError> {
+        let _guard = self.lock_writes();
+        let removed = self.store.clear_table(table)?;
+        self.lru().remove_table(table);
+        self.cache.clear_table(table)?;
+        Ok(removed)
+    }
+
+    fn size_of_table(&self, table: &str) -> Result<usize, KvError> {
+        self.store.size_of_table(table)
+    }
+
+    fn total_size(&self) -> Result<usize, KvError> {
+        self.store.total_size()
+    }
+
+    /// The stats of the persistent store, with the size and the counters of the cache
+    fn stats(&self) -> Result<StorageStats, KvError> {
+        let mut stats = self.store.stats()?;
+        let metrics = self.metrics();
+        stats.set_info("cache_size", self.cache.total_size()? as u64);
+        stats.set_info("cache_hits", metrics.hits);
+        stats.set_info("cache_misses", metrics.misses);
+        stats.set_info("cache_evictions", metrics.evictions);
+        Ok(stats)
+    }
+
+    fn transaction(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KvError> {
+        let _guard = self.lock_writes();
+        let olds = self.store.transaction(ops.clone())?;
+        for op in ops {
+            match op {
+                WriteOp::Set { table, key, value } => self.written(&table, key, value)?,
+                WriteOp::Del { table, key } => self.invalidate(&table, &key)?,
+            }
+        }
+        Ok(olds)
+    }
+
+    fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<Vec<Option<Value>>, KvError> {
+        let _guard = self.lock_writes();
+        let olds = self.store.set_batch(table, pairs.clone())?;
+        for Kvpair { key, value } in pairs {
+            self.written(table, key, value.unwrap_or_default())?;
+        }
+        Ok(olds)
+    }
+
+    fn del_batch(&self, table: &str, keys: Vec<String>) -> Result<Vec<Option<Value>>, KvError> {
+        let _guard = self.lock_writes();
+        let olds = self.store.del_batch(table, keys.clone())?;
+        for key in keys {
+            self.invalidate(table, &key)?;
+        }
+        Ok(olds)
+    }
+
+    /// The key is updated in the persistent store and dropped from the cache, the next read fills it again
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: &mut UpdateFn,
+    ) -> Result<(Option<Value>, Option<Value>), KvError> {
+        let _guard = self.lock_writes();
+        let updated = self.store.update(table, key, f)?;
+        self.invalidate(table, key)?;
+        Ok(updated)
+    }
+
+    fn flush(&self) -> Result<(), KvError> {
+        self.store.flush()
+    }
+
+    fn flush_async(&self) -> impl Future<Output = Result<(), KvError>> + Send {
+        self.store.flush_async()
+    }
+
+    fn snapshot(&self, writer: &mut dyn Write) -> Result<usize, KvError> {
+        self.store.snapshot(writer)
+    }
+
+    fn expire(&self, table: &str, key: &str, deadline: Option<u64>) -> Result<bool, KvError> {
+        let _guard = self.lock_writes();
+        let exists = self.store.expire(table, key, deadline)?;
+        // a key missing from the cache is not filled by the expiration
+        self.cache.expire(table, key, deadline)?;
+        Ok(exists)
+    }
+
+    fn deadline(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.store.deadline(table, key)
+    }
+
+    fn purge_expired(&self, now: u64) -> Result<Vec<(String, String)>, KvError> {
+        let _guard = self.lock_writes();
+        let purged = self.store.purge_expired(now)?;
+        let cached = self.cache.purge_expired(now)?;
+        let mut lru = self.lru();
+        for (table, key) in purged.iter().chain(&cached) {
+            lru.remove(table, key);
+        }
+        Ok(purged)
+    }
+
+    fn touch(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        if self.cache.touch(table, key)? {
+            self.lru().touch(table, key);
+        }
+        self.store.touch(table, key)
+    }
+
+    /// The cache hits are tracked by the cache, and the misses by the persistent store
+    fn last_access(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        let cached = self.cache.last_access(table, key)?;
+        Ok(cached.max(self.store.last_access(table, key)?))
+    }
+
+    fn version(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.store.version(table, key)
+    }
+
+    fn modified(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.store.modified(table, key)
+    }
+
+    fn get_versioned(&self, table: &str, key: &str) -> Result<Option<(Value, u64)>, KvError> {
+        self.store.get_versioned(table, key)
+    }
+
+    fn set_if_version(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        version: u64,
+    ) -> Result<Option<(Value, u64)>, KvError> {
+        let _guard = self.lock_writes();
+        let written = self
+            .store
+            .set_if_version(table, key.clone(), value.clone(), version)?;
+        if written.is_some() {
+            self.written(table, key, value)?;
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemTable;
+
+    #[test]
+    fn cached_store_should_count_the_hits_and_the_misses() {
+        let store = CachedStore::new(MemTable::new(), MemTable::new(), CacheConfig::default());
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("t1", "k2").unwrap(), None);
+        let metrics = store.metrics();
+        assert_eq!((metrics.hits, metrics.misses, metrics.evictions), (1, 1, 0));
+    }
+
+    #[test]
+    fn cached_store_should_follow_its_policies() {
+        let config = CacheConfig::default()
+            .read_through(false)
+            .write_policy(WritePolicy::Around);
+        let store = CachedStore::new(MemTable::new(), MemTable::new(), config);
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        assert_eq!(store.cached_size().unwrap(), 0);
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.cached_size().unwrap(), 0);
+        assert_eq!(store.metrics().misses, 2);
+
+        // the writes around the cache still drop the stale values
+        let store = CachedStore::new(
+            MemTable::new(),
+            MemTable::new(),
+            CacheConfig::default().write_policy(WritePolicy::Around),
+        );
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.get("t1", "k1").unwrap();
+        store.set("t1", "k1".into(), "v2".into()).unwrap();
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v2".into()));
+        assert_eq!(store.metrics().hits, 0);
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use prost::Message;
+
+use crate::{Hset, KvError, Kvpair};
+
+use super::{SledDb, Storage};
+
+/// The max length of the varint prefixing a dump entry
+const MAX_VARINT_LEN: usize = 10;
+
+/// Write the pairs in the portable dump format: one length-delimited `Hset` message per pair,
+/// the table in the message. Return the number of pairs written.
+pub fn write_dump<W, I>(writer: W, pairs: I) -> Result<usize, KvError>
+where
+    W: Write,
+    I: IntoIterator<Item = Result<(String, Kvpair), KvError>>,
+{
+    let mut writer = io::BufWriter::new(writer);
+    let mut buf = Vec::new();
+    let mut count = 0;
+    for pair in pairs {
+        let (table, pair) = pair?;
+        buf.clear();
+        Hset {
+            table,
+            pair: Some(pair),
+            ..Default::default()
+        }
+        .encode_length_delimited(&mut buf)?;
+        writer.write_all(&buf)?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Read the pairs of a dump written by `write_dump`, lazily
+pub fn read_dump<R: Read>(reader: R) -> DumpReader<R> {
+    DumpReader {
+        reader: BufReader::new(reader),
+        buf: Vec::new(),
+    }
+}
+
+/// An iterator over the pairs of a dump, yielding the table name with each pair
+pub struct DumpReader<R> {
+    reader: BufReader<R>,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> DumpReader<R> {
+    fn read_entry(&mut self) -> Result<Option<(String, Kvpair)>, KvError> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        let mut len = 0u64;
+        for i in 0..MAX_VARINT_LEN {
+            let mut byte = [0u8];
+            self.reader.read_exact(&mut byte)?;
+            len |= ((byte[0] & 0x7f) as u64) << (7 * i);
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+
+        self.buf.resize(len as usize, 0);
+        self.reader.read_exact(&mut self.buf)?;
+        let Hset { table, pair, .. } = Hset::decode(&self.buf[..])?;
+        Ok(Some((table, pair.unwrap_or_default())))
+    }
+}
+
+impl<R: Read> Iterator for DumpReader<R> {
+    type Item = Result<(String, Kvpair), KvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().transpose()
+    }
+}
+
+impl SledDb {
+    /// Dump the pairs of all tables, return the number of pairs written
+    pub fn dump(&self, writer: impl Write) -> Result<usize, KvError> {
+        write_dump(writer, self.iter_all())
+    }
+
+    /// Write the pairs of a dump, overwriting the existing keys, and flush them.
+    /// Return the number of pairs restored.
+    pub fn restore(&self, reader: impl Read) -> Result<usize, KvError> {
+        let mut count = 0;
+        for pair in read_dump(reader) {
+            let (table, Kvpair { key, value }) = pair?;
+            self.set(&table, key, value.unwrap_or_default())?;
+            count += 1;
+        }
+        self.sync()?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::Value;
+
+    #[test]
+    fn sleddb_dump_and_restore_should_work() {
+        let (from_dir, to_dir) = (tempdir().unwrap(), tempdir().unwrap());
+        let from = SledDb::new(&from_dir);
+        from.set("t1", "k1".into(), "v1".into()).unwrap();
+        from.set("t1", "k2".into(), 2.into()).unwrap();
+        let big: Value = bytes::Bytes::from(vec![7u8; 300]).into();
+        from.set("t2", "k1".into(), big).unwrap();
+
+        let mut dump = Vec::new();
+        assert_eq!(from.dump(&mut dump).unwrap(), 3);
+
+        let to = SledDb::new(&to_dir);
+        to.set("t1", "k1".into(), "old".into()).unwrap();
+        assert_eq!(to.restore(&dump[..]).unwrap(), 3);
+        for table in ["t1", "t2"] {
+            assert_eq!(to.get_all(table).unwrap(), from.get_all(table).unwrap());
+        }
+    }
+
+    #[test]
+    fn truncated_dump_should_fail() {
+        let pairs = vec![Ok(("t1".to_string(), Kvpair::new("k1", "v1".into())))];
+        let mut dump = Vec::new();
+        write_dump(&mut dump, pairs).unwrap();
+        dump.pop();
+
+        let res: Result<Vec<_>, _> = read_dump(&dump[..]).collect();
+        assert!(res.is_err());
+    }
+}
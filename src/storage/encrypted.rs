@@ -0,0 +1,352 @@
+use std::{future::Future, io::Write};
+
+use futures::StreamExt;
+use tracing::warn;
+
+use crate::{KvError, Kvpair, Value, ValueCipher};
+
+use super::{
+    read_dump, write_dump, ScanOptions, ScanPage, Storage, StorageStats, StorageStream, Update,
+    UpdateFn, WriteOp,
+};
+
+/// The bytes a sealed value adds to a small value: the nonce, the tag, and the header of the
+/// binary holding them
+const SEAL_OVERHEAD: usize = 24 + 16 + 2;
+
+/// A storage sealing the values with XChaCha20-Poly1305 before they reach the inner storage,
+/// and opening them on the reads, so the files of a persistent storage hold no plaintext value.
+///
+/// The tables and the keys are stored as is, and the sizes are the sizes of the sealed values.
+/// A value is sealed whatever its encryption, the values encrypted by the clients are sealed again.
+pub struct EncryptedStore<S> {
+    store: S,
+    cipher: ValueCipher,
+}
+
+impl<S: Storage> EncryptedStore<S> {
+    /// Seal the values of the store with the key, the same key must be given on every start
+    pub fn new(store: S, key: &[u8; 32]) -> Self {
+        Self {
+            store,
+            cipher: ValueCipher::new(key),
+        }
+    }
+
+    fn seal(&self, value: &Value) -> Result<Value, KvError> {
+        self.cipher.seal(value)
+    }
+
+    fn open(&self, value: Option<Value>) -> Result<Option<Value>, KvError> {
+        value.map(|v| self.cipher.open(&v)).transpose()
+    }
+
+    fn open_pairs(&self, pairs: Vec<Kvpair>) -> Result<Vec<Kvpair>, KvError> {
+        pairs
+            .into_iter()
+            .map(|pair| open_pair(&self.cipher, pair))
+            .collect()
+    }
+
+    /// Open the pairs of an iterator, the pairs failing to open are skipped as the iterator
+    /// could not yield an error
+    fn open_iter(
+        &self,
+        pairs: Box<dyn Iterator<Item = Kvpair>>,
+    ) -> Box<dyn Iterator<Item = Kvpair>> {
+        let cipher = self.cipher.clone();
+        Box::new(pairs.filter_map(move |pair| open_logged(&cipher, pair)))
+    }
+}
+
+fn open_pair(cipher: &ValueCipher, pair: Kvpair) -> Result<Kvpair, KvError> {
+    let value = pair.value.map(|v| cipher.open(&v)).transpose()?;
+    Ok(Kvpair {
+        key: pair.key,
+        value,
+    })
+}
+
+fn open_logged(cipher: &ValueCipher, pair: Kvpair) -> Option<Kvpair> {
+    let key = pair.key.clone();
+    match open_pair(cipher, pair) {
+        Ok(pair) => Some(pair),
+        Err(e) => {
+            warn!(key, error = ?e, "Skipped a value failing to decrypt");
+            None
+        }
+    }
+}
+
+impl<S: Storage> Storage for EncryptedStore<S> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.open(self.store.get(table, key)?)
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
+        self.open(self.store.set(table, key, self.seal(&value)?)?)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.store.contains(table, key)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.open(self.store.del(table, key)?)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.open_pairs(self.store.get_all(table)?)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        Ok(self.open_iter(self.store.get_iter(table)?))
+    }
+
+    fn get_stream(&self, table: &str) -> Result<StorageStream, KvError> {
+        let cipher = self.cipher.clone();
+        let pairs = self.store.get_stream(table)?;
+        Ok(Box::pin(pairs.filter_map(move |pair| {
+            futures::future::ready(open_logged(&cipher, pair))
+        })))
+    }
+
+    fn scan(
+        &self,
+        table: &str,
+        opts: ScanOptions,
+    ) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        Ok(self.open_iter(self.store.scan(table, opts)?))
+    }
+
+    fn get_range(
+        &self,
+        table: &str,
+        start: Option<&str>,
+        end: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        self.open_pairs(self.store.get_range(table, start, end, limit)?)
+    }
+
+    fn scan_page(
+        &self,
+        table: &str,
+        cursor: Option<String>,
+        count: usize,
+    ) -> Result<ScanPage, KvError> {
+        let page = self.store.scan_page(table, cursor, count)?;
+        Ok(ScanPage {
+            pairs: self.open_pairs(page.pairs)?,
+            cursor: page.cursor,
+        })
+    }
+
+    fn sample(&self, table: &str, count: usize) -> Result<Vec<Kvpair>, KvError> {
+        self.open_pairs(self.store.sample(table, count)?)
+    }
+
+    fn len_of_table(&self, table: &str) -> Result<usize, KvError> {
+        self.store.len_of_table(table)
+    }
+
+    /// The sealed value is copied as is, it is not bound to its table or its key
+    fn copy_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        self.store.copy_key(from, to, key, replace)
+    }
+
+    fn move_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        self.store.move_key(from, to, key, replace)
+    }
+
+    fn clear_table(&self, table: &str) -> Result<usize, KvError> {
+        self.store.clear_table(table)
+    }
+
+    /// The size of the plaintext, estimated from the size of the sealed pairs. It is exact for
+    /// the values encoded in less than 88 bytes, and a byte or two less for the larger ones.
+    fn size_of_table(&self, table: &str) -> Result<usize, KvError> {
+        let size = self.store.size_of_table(table)?;
+        let len = self.store.len_of_table(table)?;
+        Ok(size.saturating_sub(len * SEAL_OVERHEAD))
+    }
+
+    /// The size of the storage at rest, the sealed values included
+    fn total_size(&self) -> Result<usize, KvError> {
+        self.store.total_size()
+    }
+
+    /// The sizes of the tables are estimated like `size_of_table`, the figures of the inner
+    /// storage are kept as is
+    fn stats(&self) -> Result<StorageStats, KvError> {
+        let mut stats = self.store.stats()?;
+        for table in stats.tables.values_mut() {
+            table.bytes = table.bytes.saturating_sub(table.keys * SEAL_OVERHEAD);
+        }
+        Ok(stats)
+    }
+
+    fn transaction(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KvError> {
+        let ops = ops
+            .into_iter()
+            .map(|op| match op {
+                WriteOp::Set { table, key, value } => Ok(WriteOp::Set {
+                    table,
+                    key,
+                    value: self.seal(&value)?,
+                }),
+                op => Ok(op),
+            })
+            .collect::<Result<_, KvError>>()?;
+        let olds = self.store.transaction(ops)?;
+        olds.into_iter().map(|old| self.open(old)).collect()
+    }
+
+    fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<Vec<Option<Value>>, KvError> {
+        let pairs = pairs
+            .into_iter()
+            .map(|pair| {
+                let value = self.seal(&pair.value.unwrap_or_default())?;
+                Ok(Kvpair::new(pair.key, value))
+            })
+            .collect::<Result<_, KvError>>()?;
+        let olds = self.store.set_batch(table, pairs)?;
+        olds.into_iter().map(|old| self.open(old)).collect()
+    }
+
+    fn del_batch(&self, table: &str, keys: Vec<String>) -> Result<Vec<Option<Value>>, KvError> {
+        let olds = self.store.del_batch(table, keys)?;
+        olds.into_iter().map(|old| self.open(old)).collect()
+    }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: &mut UpdateFn,
+    ) -> Result<(Option<Value>, Option<Value>), KvError> {
+        // keep the plaintext of the new value rather than opening it again
+        let mut new = None;
+        let (old, _) = self.store.update(table, key, &mut |v| {
+            let old = self.open(v.cloned())?;
+            let update = f(old.as_ref())?;
+            new = match &update {
+                Update::Keep => old,
+                Update::Set(value) | Update::Overwrite(value) | Update::Expiring(value, _) => {
+                    Some(value.clone())
+                }
+                Update::Delete => None,
+            };
+            Ok(match update {
+                Update::Set(value) => Update::Set(self.seal(&value)?),
+                Update::Overwrite(value) => Update::Overwrite(self.seal(&value)?),
+                Update::Expiring(value, deadline) => Update::Expiring(self.seal(&value)?, deadline),
+                update => update,
+            })
+        })?;
+        Ok((self.open(old)?, new))
+    }
+
+    fn flush(&self) -> Result<(), KvError> {
+        self.store.flush()
+    }
+
+    fn flush_async(&self) -> impl Future<Output = Result<(), KvError>> + Send {
+        self.store.flush_async()
+    }
+
+    /// The snapshot of the inner storage is buffered in memory to open its values, so the
+    /// snapshots hold plaintext like the snapshots of the other storages
+    fn snapshot(&self, writer: &mut dyn Write) -> Result<usize, KvError> {
+        let mut sealed = Vec::new();
+        self.store.snapshot(&mut sealed)?;
+        let pairs = read_dump(&sealed[..])
+            .map(|pair| pair.and_then(|(table, pair)| Ok((table, open_pair(&self.cipher, pair)?))));
+        write_dump(writer, pairs)
+    }
+
+    fn expire(&self, table: &str, key: &str, deadline: Option<u64>) -> Result<bool, KvError> {
+        self.store.expire(table, key, deadline)
+    }
+
+    fn deadline(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.store.deadline(table, key)
+    }
+
+    fn purge_expired(&self, now: u64) -> Result<Vec<(String, String)>, KvError> {
+        self.store.purge_expired(now)
+    }
+
+    fn touch(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.store.touch(table, key)
+    }
+
+    fn last_access(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.store.last_access(table, key)
+    }
+
+    fn version(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.store.version(table, key)
+    }
+
+    fn modified(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.store.modified(table, key)
+    }
+
+    fn get_versioned(&self, table: &str, key: &str) -> Result<Option<(Value, u64)>, KvError> {
+        match self.store.get_versioned(table, key)? {
+            Some((value, version)) => Ok(Some((self.cipher.open(&value)?, version))),
+            None => Ok(None),
+        }
+    }
+
+    fn set_if_version(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        version: u64,
+    ) -> Result<Option<(Value, u64)>, KvError> {
+        let value = self.seal(&value)?;
+        match self.store.set_if_version(table, key, value, version)? {
+            Some((old, version)) => Ok(Some((self.cipher.open(&old)?, version))),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SledDb;
+    use tempfile::tempdir;
+
+    #[test]
+    fn encrypted_store_should_keep_no_plaintext_at_rest() {
+        let dir = tempdir().unwrap();
+        let db = SledDb::new(dir.path());
+        let store = EncryptedStore::new(db.clone(), &ValueCipher::generate_key());
+        store.set("t1", "k1".into(), "top secret".into()).unwrap();
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("top secret".into()));
+        assert_eq!(
+            store.get_all("t1").unwrap(),
+            vec![Kvpair::new("k1", "top secret".into())]
+        );
+        store.flush().unwrap();
+
+        let sealed = db.get("t1", "k1").unwrap().unwrap();
+        assert_ne!(sealed, "top secret".into());
+        let found = std::fs::read_dir(dir.path()).unwrap().any(|entry| {
+            let data = std::fs::read(entry.unwrap().path()).unwrap_or_default();
+            data.windows(10).any(|w| w == b"top secret")
+        });
+        assert!(!found);
+
+        // the values could not be opened with another key
+        let store = EncryptedStore::new(db, &ValueCipher::generate_key());
+        assert!(matches!(
+            store.get("t1", "k1"),
+            Err(KvError::DecryptError(_))
+        ));
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::BTreeSet;
+
+/// The deadlines of the expiring keys ordered by time, to find the due ones without a scan.
+///
+/// The index is lazy: an entry is not removed when its key is deleted or given another
+/// deadline, so the popped entries must be checked against the current deadlines.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ExpiryIndex {
+    timers: BTreeSet<(u64, String)>,
+}
+
+impl ExpiryIndex {
+    pub fn insert(&mut self, deadline: u64, key: String) {
+        self.timers.insert((deadline, key));
+    }
+
+    /// Remove and return the entries due at `now`, the earliest first
+    pub fn pop_due(&mut self, now: u64) -> Vec<(u64, String)> {
+        let later = match now.checked_add(1) {
+            Some(next) => self.timers.split_off(&(next, String::new())),
+            None => BTreeSet::new(),
+        };
+        std::mem::replace(&mut self.timers, later)
+            .into_iter()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+}
+
+impl Extend<(u64, String)> for ExpiryIndex {
+    fn extend<T: IntoIterator<Item = (u64, String)>>(&mut self, iter: T) {
+        self.timers.extend(iter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiry_index_should_pop_the_due_entries_in_order() {
+        let mut index = ExpiryIndex::default();
+        index.insert(30, "t1:k3".into());
+        index.insert(10, "t1:k1".into());
+        index.insert(20, "t1:k2".into());
+        index.insert(10, "t2:k1".into());
+        assert_eq!(
+            index.pop_due(20),
+            vec![
+                (10, "t1:k1".into()),
+                (10, "t2:k1".into()),
+                (20, "t1:k2".into())
+            ]
+        );
+        assert!(index.pop_due(20).is_empty());
+        assert_eq!(index.len(), 1);
+
+        index.insert(u64::MAX, "t1:k4".into());
+        assert_eq!(index.pop_due(u64::MAX).len(), 2);
+        assert_eq!(index.len(), 0);
+    }
+}
@@ -0,0 +1,356 @@
+use std::{
+    io::Write,
+    sync::{mpsc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use tracing::{info, warn};
+
+use crate::{KvError, Kvpair, MemTable, SledDb, Value};
+
+use super::{Storage, StorageStats, Update, UpdateFn, WriteOp};
+
+/// A write waiting to be persisted
+enum Op {
+    Set(String, String, Value),
+    Del(String, String),
+    Txn(Vec<WriteOp>),
+    Expire(String, String, Option<u64>),
+    /// Copy a key with its deadline to another table, replacing the destination
+    Copy(String, String, String),
+    /// Move a key with its deadline to another table, replacing the destination
+    Move(String, String, String),
+    /// Remove all keys of a table
+    Clear(String),
+    /// Write a key like `Storage::update`, its deadline is kept unless the update sets one
+    Update(String, String, Update),
+    /// Flush the disk once the previous writes are persisted, and reply the result
+    Flush(mpsc::SyncSender<Result<(), KvError>>),
+}
+
+/// A storage serving all reads and writes from memory,
+/// the writes are persisted to sled in the background,
+/// and the memory is reloaded from sled when the store is opened.
+pub struct HybridStore {
+    mem: MemTable,
+    /// The queue of the writes to persist, None once the store is dropped
+    tx: Option<mpsc::Sender<Op>>,
+    /// The thread persisting the writes
+    persister: Option<JoinHandle<()>>,
+    /// Serialize the writes, so they are queued in the same order as applied to memory
+    write_lock: Mutex<()>,
+}
+
+impl HybridStore {
+    /// Load all pairs of the sled database into memory, and persist the following writes to it
+    pub fn open(disk: SledDb) -> Result<Self, KvError> {
+        let mem = MemTable::new();
+        let mut count = 0;
+        for item in disk.iter_all() {
+            let (table, pair) = item?;
+            mem.set(&table, pair.key, pair.value.unwrap_or_default())?;
+            count += 1;
+        }
+        info!("Loaded {} pairs from sled", count);
+        for item in disk.iter_deadlines() {
+            let (table, key, deadline) = item?;
+            mem.expire(&table, &key, Some(deadline))?;
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let persister = thread::Builder::new()
+            .name("hybrid-persister".into())
+            .spawn(move || persist(disk, rx))?;
+
+        Ok(Self {
+            mem,
+            tx: Some(tx),
+            persister: Some(persister),
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    fn enqueue(&self, op: Op) -> Result<(), KvError> {
+        self.tx
+            .as_ref()
+            .and_then(|tx| tx.send(op).ok())
+            .ok_or_else(|| KvError::Internal("the persister of HybridStore is gone".into()))
+    }
+}
+
+/// Apply the queued writes to sled until the store is dropped
+fn persist(disk: SledDb, rx: mpsc::Receiver<Op>) {
+    for op in rx {
+        let res = match op {
+            Op::Set(table, key, value) => disk.set(&table, key, value).map(|_| ()),
+            Op::Del(table, key) => disk.del(&table, &key).map(|_| ()),
+            Op::Txn(ops) => disk.transaction(ops).map(|_| ()),
+            Op::Expire(table, key, deadline) => disk.expire(&table, &key, deadline).map(|_| ()),
+            Op::Copy(from, to, key) => disk.copy_key(&from, &to, &key, true).map(|_| ()),
+            Op::Move(from, to, key) => disk.move_key(&from, &to, &key, true).map(|_| ()),
+            Op::Clear(table) => disk.clear_table(&table).map(|_| ()),
+            Op::Update(table, key, update) => disk
+                .update(&table, &key, &mut |_| Ok(update.clone()))
+                .map(|_| ()),
+            Op::Flush(reply) => {
+                _ = reply.send(disk.flush());
+                continue;
+            }
+        };
+        if let Err(e) = res {
+            warn!("Failed to persist a write: {:?}", e);
+        }
+    }
+    if let Err(e) = disk.flush() {
+        warn!("Failed to flush sled on close: {:?}", e);
+    }
+}
+
+impl Storage for HybridStore {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.mem.get(table, key)
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
+        let _guard = self.write_lock.lock().unwrap();
+        let old = self.mem.set(table, key.clone(), value.clone())?;
+        self.enqueue(Op::Set(table.into(), key, value))?;
+        Ok(old)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.mem.contains(table, key)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let _guard = self.write_lock.lock().unwrap();
+        let old = self.mem.del(table, key)?;
+        if old.is_some() {
+            self.enqueue(Op::Del(table.into(), key.into()))?;
+        }
+        Ok(old)
+    }
+
+    fn transaction(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KvError> {
+        let _guard = self.write_lock.lock().unwrap();
+        let olds = self.mem.transaction(ops.clone())?;
+        self.enqueue(Op::Txn(ops))?;
+        Ok(olds)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.mem.get_all(table)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        self.mem.get_iter(table)
+    }
+
+    fn len_of_table(&self, table: &str) -> Result<usize, KvError> {
+        self.mem.len_of_table(table)
+    }
+
+    fn copy_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        let _guard = self.write_lock.lock().unwrap();
+        let copied = self.mem.copy_key(from, to, key, replace)?;
+        if copied {
+            self.enqueue(Op::Copy(from.into(), to.into(), key.into()))?;
+        }
+        Ok(copied)
+    }
+
+    fn move_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        let _guard = self.write_lock.lock().unwrap();
+        let moved = self.mem.move_key(from, to, key, replace)?;
+        if moved {
+            self.enqueue(Op::Move(from.into(), to.into(), key.into()))?;
+        }
+        Ok(moved)
+    }
+
+    fn clear_table(&self, table: &str) -> Result<usize, KvError> {
+        let _guard = self.write_lock.lock().unwrap();
+        let removed = self.mem.clear_table(table)?;
+        if removed > 0 {
+            self.enqueue(Op::Clear(table.into()))?;
+        }
+        Ok(removed)
+    }
+
+    fn size_of_table(&self, table: &str) -> Result<usize, KvError> {
+        self.mem.size_of_table(table)
+    }
+
+    fn total_size(&self) -> Result<usize, KvError> {
+        self.mem.total_size()
+    }
+
+    fn stats(&self) -> Result<StorageStats, KvError> {
+        self.mem.stats()
+    }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: &mut UpdateFn,
+    ) -> Result<(Option<Value>, Option<Value>), KvError> {
+        let _guard = self.write_lock.lock().unwrap();
+        let mut write = Update::Keep;
+        let (old, new) = self.mem.update(table, key, &mut |v| {
+            let update = f(v)?;
+            write = update.clone();
+            Ok(update)
+        })?;
+        match write {
+            Update::Keep => {}
+            // a set clears the deadline on the disk too
+            Update::Overwrite(value) => self.enqueue(Op::Set(table.into(), key.into(), value))?,
+            // the deadline of the key is kept or set in the same write, like by the update of memory
+            update @ (Update::Set(_) | Update::Expiring(..)) => {
+                self.enqueue(Op::Update(table.into(), key.into(), update))?
+            }
+            Update::Delete if old.is_some() => self.enqueue(Op::Del(table.into(), key.into()))?,
+            Update::Delete => {}
+        }
+        Ok((old, new))
+    }
+
+    fn expire(&self, table: &str, key: &str, deadline: Option<u64>) -> Result<bool, KvError> {
+        let _guard = self.write_lock.lock().unwrap();
+        let exists = self.mem.expire(table, key, deadline)?;
+        if exists {
+            self.enqueue(Op::Expire(table.into(), key.into(), deadline))?;
+        }
+        Ok(exists)
+    }
+
+    fn deadline(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.mem.deadline(table, key)
+    }
+
+    fn purge_expired(&self, now: u64) -> Result<Vec<(String, String)>, KvError> {
+        let _guard = self.write_lock.lock().unwrap();
+        let purged = self.mem.purge_expired(now)?;
+        for (table, key) in &purged {
+            self.enqueue(Op::Del(table.clone(), key.clone()))?;
+        }
+        Ok(purged)
+    }
+
+    /// The accesses are tracked by the memory table only, they are not persisted
+    fn touch(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.mem.touch(table, key)
+    }
+
+    fn last_access(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.mem.last_access(table, key)
+    }
+
+    /// The versions are given by the memory table and not persisted, they restart after a reopen
+    fn version(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.mem.version(table, key)
+    }
+
+    /// The reloaded keys are modified at the reopen
+    fn modified(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.mem.modified(table, key)
+    }
+
+    fn get_versioned(&self, table: &str, key: &str) -> Result<Option<(Value, u64)>, KvError> {
+        self.mem.get_versioned(table, key)
+    }
+
+    fn set_if_version(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        version: u64,
+    ) -> Result<Option<(Value, u64)>, KvError> {
+        let _guard = self.write_lock.lock().unwrap();
+        let written = self
+            .mem
+            .set_if_version(table, key.clone(), value.clone(), version)?;
+        if written.is_some() {
+            self.enqueue(Op::Set(table.into(), key, value))?;
+        }
+        Ok(written)
+    }
+
+    /// Memory holds all the pairs, and the restored ones are persisted by `set_batch`
+    fn snapshot(&self, writer: &mut dyn Write) -> Result<usize, KvError> {
+        self.mem.snapshot(writer)
+    }
+
+    /// Wait until the queued writes are persisted and flushed to disk
+    fn flush(&self) -> Result<(), KvError> {
+        let (reply, rx) = mpsc::sync_channel(1);
+        self.enqueue(Op::Flush(reply))?;
+        rx.recv()
+            .map_err(|_| KvError::Internal("the persister of HybridStore is gone".into()))?
+    }
+}
+
+impl Drop for HybridStore {
+    fn drop(&mut self) {
+        // close the queue, the persister drains it and exits
+        self.tx.take();
+        if let Some(persister) = self.persister.take() {
+            if persister.join().is_err() {
+                warn!("The persister of HybridStore panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn hybrid_store_should_reload_from_disk() {
+        let dir = tempdir().unwrap();
+        {
+            let store = HybridStore::open(SledDb::new(dir.path())).unwrap();
+            store.set("t1", "k1".into(), "v1".into()).unwrap();
+            store.set("t1", "k2".into(), "v2".into()).unwrap();
+            store.set("t2", "k1".into(), 1.into()).unwrap();
+            store.del("t1", "k2").unwrap();
+            store.set("t3", "k1".into(), 3.into()).unwrap();
+            store.clear_table("t3").unwrap();
+        }
+
+        let store = HybridStore::open(SledDb::new(dir.path())).unwrap();
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("t1", "k2").unwrap(), None);
+        assert_eq!(store.get("t2", "k1").unwrap(), Some(1.into()));
+        assert_eq!(store.get("t3", "k1").unwrap(), None);
+    }
+
+    #[test]
+    fn hybrid_store_should_reload_deadlines() {
+        let dir = tempdir().unwrap();
+        let disk = SledDb::new(dir.path());
+        {
+            let store = HybridStore::open(disk.clone()).unwrap();
+            store.set("t1", "k1".into(), "v1".into()).unwrap();
+            store.expire("t1", "k1", Some(u64::MAX)).unwrap();
+        }
+
+        let store = HybridStore::open(disk).unwrap();
+        assert_eq!(store.deadline("t1", "k1").unwrap(), Some(u64::MAX));
+    }
+
+    #[test]
+    fn hybrid_store_flush_should_wait_for_persistence() {
+        let dir = tempdir().unwrap();
+        let disk = SledDb::new(dir.path());
+        let store = HybridStore::open(disk.clone()).unwrap();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.flush().unwrap();
+        assert_eq!(disk.get("t1", "k1").unwrap(), Some("v1".into()));
+    }
+}
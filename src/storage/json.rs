@@ -0,0 +1,255 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Map, Value as Json};
+
+use crate::{value, KvError, Kvpair, Storage, Value, ValueList, ValueSet, ValueZset, ZsetMember};
+
+use super::{read_dump, restore_pairs};
+
+/// Convert a value to JSON. The strings, the integers, the floats, the booleans and the lists map
+/// to their JSON types, an empty value to null. The other values are objects with a `$` key:
+/// `{"$binary": base64}`, `{"$set": [members]}`, `{"$zset": [{"member", "score"}]}`, the
+/// infinite floats and NaN to `{"$float": "inf"}`, and a value encrypted by the client to
+/// `{"$encryption": n, "$value": value}`.
+impl From<&Value> for Json {
+    fn from(v: &Value) -> Self {
+        let json = match &v.value {
+            None => Json::Null,
+            Some(value::Value::String(s)) => json!(s),
+            Some(value::Value::Binary(b)) => json!({ "$binary": STANDARD.encode(b) }),
+            Some(value::Value::Integer(n)) => json!(n),
+            Some(value::Value::Float(f)) if f.is_finite() => json!(f),
+            Some(value::Value::Float(f)) => json!({ "$float": f.to_string() }),
+            Some(value::Value::Bool(b)) => json!(b),
+            Some(value::Value::List(list)) => list.values.iter().map(Json::from).collect(),
+            Some(value::Value::Set(set)) => json!({ "$set": set.members }),
+            Some(value::Value::Zset(zset)) => {
+                let members: Vec<Json> = zset
+                    .members
+                    .iter()
+                    .map(|m| json!({ "member": m.member, "score": m.score }))
+                    .collect();
+                json!({ "$zset": members })
+            }
+        };
+        match v.encryption {
+            0 => json,
+            n => json!({ "$encryption": n, "$value": json }),
+        }
+    }
+}
+
+/// Convert JSON back to a value, as written by `From<&Value>`. The objects without a known
+/// `$` key are rejected, as well as the integers out of the range of i64.
+impl TryFrom<Json> for Value {
+    type Error = KvError;
+
+    fn try_from(json: Json) -> Result<Self, Self::Error> {
+        let invalid = |json: &Json| KvError::ConvertCommand(json.to_string(), "Value");
+        let value = match json {
+            Json::Null => return Ok(Value::default()),
+            Json::Bool(b) => value::Value::Bool(b),
+            Json::Number(n) => match (n.as_i64(), n.is_f64()) {
+                (Some(n), _) => value::Value::Integer(n),
+                (None, true) => value::Value::Float(n.as_f64().unwrap_or_default()),
+                (None, false) => return Err(invalid(&Json::Number(n))),
+            },
+            Json::String(s) => value::Value::String(s),
+            Json::Array(items) => value::Value::List(ValueList {
+                values: items
+                    .into_iter()
+                    .map(Value::try_from)
+                    .collect::<Result<_, _>>()?,
+            }),
+            Json::Object(map) => return tagged_value(map),
+        };
+        Ok(wrap(value))
+    }
+}
+
+fn tagged_value(mut map: Map<String, Json>) -> Result<Value, KvError> {
+    let invalid =
+        |map: Map<String, Json>| KvError::ConvertCommand(Json::Object(map).to_string(), "Value");
+    if map.len() == 2 {
+        let encryption = map.get("$encryption").and_then(Json::as_u64);
+        return match (encryption, map.remove("$value")) {
+            (Some(n), Some(json)) => {
+                let mut value = Value::try_from(json)?;
+                value.encryption = n as u32;
+                Ok(value)
+            }
+            _ => Err(invalid(map)),
+        };
+    }
+    let Some((tag, json)) = map.iter().next().filter(|_| map.len() == 1) else {
+        return Err(invalid(map));
+    };
+    let value = match (tag.as_str(), json) {
+        ("$binary", Json::String(s)) => STANDARD
+            .decode(s)
+            .ok()
+            .map(|b| value::Value::Binary(b.into())),
+        ("$float", Json::String(s)) => s.parse().ok().map(value::Value::Float),
+        ("$set", Json::Array(items)) => items
+            .iter()
+            .map(|item| item.as_str().map(String::from))
+            .collect::<Option<_>>()
+            .map(|members| value::Value::Set(ValueSet { members })),
+        ("$zset", Json::Array(items)) => items
+            .iter()
+            .map(|item| {
+                Some(ZsetMember {
+                    member: item.get("member")?.as_str()?.into(),
+                    score: item.get("score")?.as_f64()?,
+                })
+            })
+            .collect::<Option<_>>()
+            .map(|members| value::Value::Zset(ValueZset { members })),
+        _ => None,
+    };
+    match value {
+        Some(value) => Ok(wrap(value)),
+        None => Err(invalid(map)),
+    }
+}
+
+fn wrap(value: value::Value) -> Value {
+    Value {
+        value: Some(value),
+        ..Default::default()
+    }
+}
+
+/// Write the pairs as newline-delimited JSON, one `{"table", "key", "value"}` object per line.
+/// Return the number of pairs written.
+pub fn write_ndjson<W, I>(writer: W, pairs: I) -> Result<usize, KvError>
+where
+    W: Write,
+    I: IntoIterator<Item = Result<(String, Kvpair), KvError>>,
+{
+    let mut writer = io::BufWriter::new(writer);
+    let mut count = 0;
+    for pair in pairs {
+        let (table, pair) = pair?;
+        let value = pair.value.as_ref().map(Json::from).unwrap_or_default();
+        let line = json!({ "table": table, "key": pair.key, "value": value });
+        serde_json::to_writer(&mut writer, &line)?;
+        writer.write_all(b"\n")?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Read the pairs of newline-delimited JSON written by `write_ndjson`, lazily.
+/// The blank lines are skipped.
+pub fn read_ndjson<R: Read>(reader: R) -> impl Iterator<Item = Result<(String, Kvpair), KvError>> {
+    BufReader::new(reader)
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| {
+            let mut json: Json = serde_json::from_str(&line?)?;
+            let field = |json: &mut Json, name: &str| json.get_mut(name).map(Json::take);
+            let (Some(Json::String(table)), Some(Json::String(key))) =
+                (field(&mut json, "table"), field(&mut json, "key"))
+            else {
+                return Err(KvError::ConvertCommand(json.to_string(), "Kvpair"));
+            };
+            let value = Value::try_from(field(&mut json, "value").unwrap_or_default())?;
+            Ok((table, Kvpair::new(key, value)))
+        })
+}
+
+/// Export the pairs of a table, or of all the tables, as newline-delimited JSON and return the
+/// number of pairs written. The whole storage is exported from a snapshot buffered in memory.
+pub fn export_ndjson(
+    store: &impl Storage,
+    table: Option<&str>,
+    writer: impl Write,
+) -> Result<usize, KvError> {
+    match table {
+        Some(table) => {
+            let pairs = store.get_iter(table)?;
+            write_ndjson(writer, pairs.map(|pair| Ok((table.to_string(), pair))))
+        }
+        None => {
+            let mut snapshot = Vec::new();
+            store.snapshot(&mut snapshot)?;
+            write_ndjson(writer, read_dump(&snapshot[..]))
+        }
+    }
+}
+
+/// Import newline-delimited JSON, overwriting the existing keys, and return the number of pairs
+/// imported. The pairs are written in batches like `Storage::restore_snapshot`.
+pub fn import_ndjson(store: &impl Storage, reader: impl Read) -> Result<usize, KvError> {
+    restore_pairs(store, read_ndjson(reader))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemTable;
+
+    #[test]
+    fn values_should_round_trip_through_json() {
+        let mut encrypted: Value = bytes::Bytes::from_static(b"sealed").into();
+        encrypted.encryption = 1;
+        let values: Vec<Value> = vec![
+            Value::default(),
+            "v1".into(),
+            42.into(),
+            2.0.into(),
+            f64::INFINITY.into(),
+            true.into(),
+            bytes::Bytes::from_static(b"\x00\xff").into(),
+            wrap(value::Value::List(ValueList {
+                values: vec![1.into(), "a".into()],
+            })),
+            wrap(value::Value::Set(ValueSet {
+                members: vec!["a".into(), "b".into()],
+            })),
+            wrap(value::Value::Zset(ValueZset {
+                members: vec![ZsetMember {
+                    member: "a".into(),
+                    score: 1.5,
+                }],
+            })),
+            encrypted,
+        ];
+        for v in values {
+            let json = Json::from(&v);
+            assert_eq!(Value::try_from(json).unwrap(), v);
+        }
+
+        assert_eq!(Json::from(&Value::from("v1")), json!("v1"));
+        for json in [json!({ "a": 1 }), json!({ "$binary": 1 }), json!(u64::MAX)] {
+            assert!(Value::try_from(json).is_err());
+        }
+    }
+
+    #[test]
+    fn ndjson_export_and_import_should_work() {
+        let from = MemTable::new();
+        from.set("t1", "k1".into(), "v1".into()).unwrap();
+        from.set("t1", "k2".into(), 2.into()).unwrap();
+        from.set("t2", "k1".into(), true.into()).unwrap();
+
+        let mut data = Vec::new();
+        assert_eq!(export_ndjson(&from, Some("t1"), &mut data).unwrap(), 2);
+        let text = String::from_utf8(data).unwrap();
+        assert!(text.contains(r#"{"key":"k1","table":"t1","value":"v1"}"#));
+
+        let mut data = Vec::new();
+        assert_eq!(export_ndjson(&from, None, &mut data).unwrap(), 3);
+        let to = MemTable::new();
+        assert_eq!(import_ndjson(&to, &data[..]).unwrap(), 3);
+        for table in ["t1", "t2"] {
+            assert_eq!(to.get_all(table).unwrap(), from.get_all(table).unwrap());
+        }
+
+        let res: Result<Vec<_>, _> = read_ndjson(&b"{\"table\": \"t1\"}\n"[..]).collect();
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// The order of the last uses of the keys of the tables, to find the least recently used one
+#[derive(Debug, Default, Clone)]
+pub(crate) struct LruIndex {
+    /// The tick of the last use of each key of each table
+    ticks: HashMap<String, HashMap<String, u64>>,
+    /// The keys by the ticks of their last uses
+    order: BTreeMap<u64, (String, String)>,
+    last: u64,
+}
+
+impl LruIndex {
+    /// Mark the key as the most recently used
+    pub fn touch(&mut self, table: &str, key: &str) {
+        self.last += 1;
+        let tick = self.last;
+        let keys = match self.ticks.get_mut(table) {
+            Some(keys) => keys,
+            None => self.ticks.entry(table.into()).or_default(),
+        };
+        let old = match keys.get_mut(key) {
+            Some(old) => Some(std::mem::replace(old, tick)),
+            None => {
+                keys.insert(key.into(), tick);
+                None
+            }
+        };
+        let entry = match old.and_then(|old| self.order.remove(&old)) {
+            Some(entry) => entry,
+            None => (table.into(), key.into()),
+        };
+        self.order.insert(tick, entry);
+    }
+
+    pub fn remove(&mut self, table: &str, key: &str) {
+        let Some(keys) = self.ticks.get_mut(table) else {
+            return;
+        };
+        if let Some(tick) = keys.remove(key) {
+            self.order.remove(&tick);
+        }
+        if keys.is_empty() {
+            self.ticks.remove(table);
+        }
+    }
+
+    pub fn remove_table(&mut self, table: &str) {
+        for tick in self
+            .ticks
+            .remove(table)
+            .into_iter()
+            .flat_map(|keys| keys.into_values())
+        {
+            self.order.remove(&tick);
+        }
+    }
+
+    /// Remove the least recently used key and return it with its table
+    pub fn pop(&mut self) -> Option<(String, String)> {
+        let (_, (table, key)) = self.order.pop_first()?;
+        if let Some(keys) = self.ticks.get_mut(&table) {
+            keys.remove(&key);
+            if keys.is_empty() {
+                self.ticks.remove(&table);
+            }
+        }
+        Some((table, key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru_index_should_pop_the_least_recently_used() {
+        let mut lru = LruIndex::default();
+        lru.touch("t1", "k1");
+        lru.touch("t1", "k2");
+        lru.touch("t2", "k1");
+        lru.touch("t1", "k1");
+        lru.remove("t2", "k1");
+        assert_eq!(lru.pop(), Some(("t1".into(), "k2".into())));
+        assert_eq!(lru.pop(), Some(("t1".into(), "k1".into())));
+        assert_eq!(lru.pop(), None);
+
+        lru.touch("t1", "k1");
+        lru.touch("t2", "k1");
+        lru.remove_table("t1");
+        assert_eq!(lru.pop(), Some(("t2".into(), "k1".into())));
+        assert_eq!(lru.pop(), None);
+    }
+}
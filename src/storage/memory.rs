@@ -1,14 +1,132 @@
-use dashmap::{mapref::one::Ref, DashMap};
+use std::{
+    collections::{BTreeSet, HashSet},
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, RwLock,
+    },
+};
 
-use crate::{Kvpair, Value};
+use dashmap::{
+    mapref::{entry::Entry, one::Ref},
+    DashMap,
+};
+use rand::seq::IteratorRandom;
 
-use super::{Storage, StorageIter};
+use crate::{KvError, Kvpair, Value};
+
+use super::{
+    lru::LruIndex, now_ms, pair_size, write_dump, Storage, StorageIter, StorageStats, Update,
+    UpdateFn, WriteOp,
+};
 
 /// A simple in-memory key-value storage engine built on top of dashmap.
 /// It is thread-safe and supports concurrent read and write operations.
+/// Scans sort a copy of the table, so each page of a cursor scan costs a full table sort.
 #[derive(Debug, Default, Clone)]
 pub struct MemTable {
     tables: DashMap<String, DashMap<String, Value>>,
+    /// The size in bytes of each table, maintained on every write
+    sizes: DashMap<String, usize>,
+    /// The deadlines of the expiring keys of each table
+    deadlines: DashMap<String, DashMap<String, u64>>,
+    /// The last access time of the keys of each table, by a get, a write or a touch
+    accessed: DashMap<String, DashMap<String, u64>>,
+    versions: Versions,
+    locks: TableLocks,
+    budget: Budget,
+}
+
+/// The locks of the tables, the commands share a table lock while a transaction holds it exclusively
+#[derive(Debug, Default)]
+struct TableLocks(DashMap<String, Arc<RwLock<()>>>);
+
+impl TableLocks {
+    fn get(&self, table: &str) -> Arc<RwLock<()>> {
+        match self.0.get(table) {
+            Some(lock) => lock.clone(),
+            None => self.0.entry(table.into()).or_default().clone(),
+        }
+    }
+}
+
+/// A cloned MemTable is independent of the original one, so it gets its own locks
+impl Clone for TableLocks {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+/// The versions of the keys of each table with the times of the writes giving them,
+/// the versions are given from one counter shared by all tables
+#[derive(Debug, Default)]
+struct Versions {
+    keys: DashMap<String, DashMap<String, (u64, u64)>>,
+    /// Shared by the shards of a ShardedMemTable
+    last: Arc<AtomicU64>,
+}
+
+impl Versions {
+    fn get(&self, table: &str, key: &str) -> Option<u64> {
+        self.keys.get(table)?.get(key).map(|v| v.0)
+    }
+
+    fn modified(&self, table: &str, key: &str) -> Option<u64> {
+        self.keys.get(table)?.get(key).map(|v| v.1)
+    }
+
+    /// Give the key a version greater than all the versions given before
+    fn bump(&self, table: &str, key: &str) -> u64 {
+        let version = self.last.fetch_add(1, Ordering::Relaxed) + 1;
+        let keys = match self.keys.get(table) {
+            Some(keys) => keys,
+            None => self.keys.entry(table.into()).or_default().downgrade(),
+        };
+        keys.insert(key.into(), (version, now_ms()));
+        version
+    }
+
+    fn remove(&self, table: &str, key: &str) {
+        if let Some(keys) = self.keys.get(table) {
+            keys.remove(key);
+        }
+    }
+}
+
+impl Clone for Versions {
+    fn clone(&self) -> Self {
+        Self {
+            keys: self.keys.clone(),
+            last: Arc::new(AtomicU64::new(self.last.load(Ordering::Relaxed))),
+        }
+    }
+}
+
+/// The memory budget of a MemTable, the least recently used keys are evicted once the size
+/// of the tables is over it
+#[derive(Debug, Default)]
+struct Budget {
+    max: Option<usize>,
+    /// The order of the accesses, only maintained with a budget
+    lru: Mutex<LruIndex>,
+    evictions: AtomicU64,
+}
+
+impl Budget {
+    fn lru(&self) -> Option<MutexGuard<'_, LruIndex>> {
+        self.max?;
+        Some(self.lru.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl Clone for Budget {
+    fn clone(&self) -> Self {
+        Self {
+            max: self.max,
+            lru: Mutex::new(self.lru().map(|lru| lru.clone()).unwrap_or_default()),
+            evictions: AtomicU64::new(self.evictions.load(Ordering::Relaxed)),
+        }
+    }
 }
 
 impl MemTable {
@@ -17,8 +135,67 @@ impl MemTable {
         Self::default()
     }
 
+    /// Create a MemTable evicting the least recently used keys once the size of its tables,
+    /// the keys and the encoded values, is over `max` bytes, so it acts as a bounded cache
+    pub fn with_max_memory(max: usize) -> Self {
+        Self {
+            budget: Budget {
+                max: Some(max),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Create a MemTable giving the versions from the counter, to share it with other MemTables
+    pub(crate) fn with_version_counter(last: Arc<AtomicU64>) -> Self {
+        Self {
+            versions: Versions {
+                last,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// The lock of a table, the commands share it and the transactions hold it exclusively
+    pub(crate) fn table_lock(&self, table: &str) -> Arc<RwLock<()>> {
+        self.locks.get(table)
+    }
+
+    /// Apply a write of a transaction, the caller holds the write lock of its table
+    pub(crate) fn apply_locked(&self, op: WriteOp) -> Option<Value> {
+        match op {
+            WriteOp::Set { table, key, value } => self.set_locked(&table, key, value),
+            WriteOp::Del { table, key } => self.del_locked(&table, &key),
+        }
+    }
+
+    /// The number of keys evicted to stay within the memory budget
+    pub fn evictions(&self) -> u64 {
+        self.budget.evictions.load(Ordering::Relaxed)
+    }
+
+    /// Evict the least recently used keys until the tables are within the budget.
+    /// It is called once the locks of a write are released, as it takes the table locks.
+    fn evict(&self) {
+        let Some(max) = self.budget.max else {
+            return;
+        };
+        while self.sizes.iter().map(|size| *size.value()).sum::<usize>() > max {
+            let Some((table, key)) = self.budget.lru().and_then(|mut lru| lru.pop()) else {
+                break;
+            };
+            let lock = self.locks.get(&table);
+            let _guard = lock.read().unwrap();
+            if self.del_locked(&table, &key).is_some() {
+                self.budget.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
     /// Create a table if it does not exist, and return a reference to it.
-    pub fn get_or_create_table(&self, name: &str) -> Ref<String, DashMap<String, Value>> {
+    pub fn get_or_create_table(&self, name: &str) -> Ref<'_, String, DashMap<String, Value>> {
         match self.tables.get(name) {
             Some(table) => table,
             None => {
@@ -27,43 +204,541 @@ impl MemTable {
             }
         }
     }
+
+    fn set_locked(&self, table: &str, key: String, value: Value) -> Option<Value> {
+        let added = pair_size(&key, &value);
+        // the version is given under the lock of the entry, so a reader sees both or neither
+        let old = match self.get_or_create_table(table).entry(key.clone()) {
+            Entry::Occupied(mut e) => {
+                self.versions.bump(table, &key);
+                Some(e.insert(value))
+            }
+            Entry::Vacant(e) => {
+                self.versions.bump(table, &key);
+                e.insert(value);
+                None
+            }
+        };
+        self.clear_deadline(table, &key);
+        self.record_access(table, &key);
+        let removed = old.as_ref().map(|v| pair_size(&key, v)).unwrap_or(0);
+        self.adjust_size(table, added, removed);
+        old
+    }
+
+    fn del_locked(&self, table: &str, key: &str) -> Option<Value> {
+        let old = match self.get_or_create_table(table).entry(key.to_string()) {
+            Entry::Occupied(e) => {
+                self.versions.remove(table, key);
+                Some(e.remove())
+            }
+            Entry::Vacant(_) => None,
+        };
+        self.clear_deadline(table, key);
+        self.forget_access(table, key);
+        if let Some(v) = old.as_ref() {
+            self.adjust_size(table, 0, pair_size(key, v));
+        }
+        old
+    }
+
+    fn deadline_of(&self, table: &str, key: &str) -> Option<u64> {
+        self.deadlines
+            .get(table)?
+            .get(key)
+            .map(|deadline| *deadline)
+    }
+
+    fn clear_deadline(&self, table: &str, key: &str) {
+        if let Some(deadlines) = self.deadlines.get(table) {
+            deadlines.remove(key);
+        }
+    }
+
+    fn set_deadline(&self, table: &str, key: &str, deadline: u64) {
+        let deadlines = match self.deadlines.get(table) {
+            Some(deadlines) => deadlines,
+            None => self.deadlines.entry(table.into()).or_default().downgrade(),
+        };
+        deadlines.insert(key.into(), deadline);
+    }
+
+    fn record_access(&self, table: &str, key: &str) {
+        let accessed = match self.accessed.get(table) {
+            Some(accessed) => accessed,
+            None => self.accessed.entry(table.into()).or_default().downgrade(),
+        };
+        accessed.insert(key.into(), now_ms());
+        if let Some(mut lru) = self.budget.lru() {
+            lru.touch(table, key);
+        }
+    }
+
+    fn forget_access(&self, table: &str, key: &str) {
+        if let Some(accessed) = self.accessed.get(table) {
+            accessed.remove(key);
+        }
+        if let Some(mut lru) = self.budget.lru() {
+            lru.remove(table, key);
+        }
+    }
+
+    /// Copy a key with its deadline under the write locks of both tables, and delete the source
+    /// to move it. The tables are locked in the name order, like by the transactions.
+    fn transfer(
+        &self,
+        from: &str,
+        to: &str,
+        key: &str,
+        replace: bool,
+        remove: bool,
+    ) -> Result<bool, KvError> {
+        if from == to {
+            return Err(KvError::InvalidCommand(format!(
+                "cannot copy {} onto itself",
+                key
+            )));
+        }
+        let tables = BTreeSet::from([from, to]);
+        let locks: Vec<_> = tables.into_iter().map(|t| self.locks.get(t)).collect();
+        let _guards: Vec<_> = locks.iter().map(|lock| lock.write().unwrap()).collect();
+
+        let value = match self.is_expired(from, key) {
+            true => None,
+            false => self.get_or_create_table(from).get(key).map(|v| v.clone()),
+        };
+        let Some(value) = value else {
+            return Ok(false);
+        };
+        let exists = !self.is_expired(to, key) && self.get_or_create_table(to).contains_key(key);
+        if exists && !replace {
+            return Ok(false);
+        }
+
+        let deadline = self.deadline_of(from, key);
+        self.set_locked(to, key.into(), value);
+        if let Some(deadline) = deadline {
+            self.set_deadline(to, key, deadline);
+        }
+        if remove {
+            self.del_locked(from, key);
+        }
+        Ok(true)
+    }
+
+    /// Whether the key has expired, the clock is only read for the expiring keys
+    fn is_expired(&self, table: &str, key: &str) -> bool {
+        matches!(self.deadline_of(table, key), Some(deadline) if deadline <= now_ms())
+    }
+
+    /// The live pairs of all tables with their deadlines, read with all the tables locked so
+    /// they are consistent
+    pub(super) fn pairs_with_deadlines(&self) -> Vec<(String, Kvpair, Option<u64>)> {
+        let tables: BTreeSet<String> = self.tables.iter().map(|t| t.key().clone()).collect();
+        let locks: Vec<_> = tables.iter().map(|t| self.locks.get(t)).collect();
+        let _guards: Vec<_> = locks.iter().map(|lock| lock.write().unwrap()).collect();
+        let now = now_ms();
+        let mut pairs = Vec::new();
+        for name in &tables {
+            let Some(table) = self.tables.get(name) else {
+                continue;
+            };
+            for kv in table.iter() {
+                let deadline = self.deadline_of(name, kv.key());
+                if deadline.is_none_or(|deadline| deadline > now) {
+                    let pair = Kvpair::new(kv.key(), kv.value().clone());
+                    pairs.push((name.clone(), pair, deadline));
+                }
+            }
+        }
+        pairs
+    }
+
+    /// The keys of a table whose deadline has passed, hidden from the scans until purged
+    fn expired_keys(&self, table: &str) -> HashSet<String> {
+        let Some(deadlines) = self.deadlines.get(table) else {
+            return HashSet::new();
+        };
+        let now = now_ms();
+        deadlines
+            .iter()
+            .filter(|deadline| *deadline.value() <= now)
+            .map(|deadline| deadline.key().clone())
+            .collect()
+    }
+
+    /// Account the size change of a table after a write
+    fn adjust_size(&self, table: &str, added: usize, removed: usize) {
+        if added == removed {
+            return;
+        }
+        let mut size = match self.sizes.get_mut(table) {
+            Some(size) => size,
+            None => self.sizes.entry(table.into()).or_default(),
+        };
+        *size = (*size + added).saturating_sub(removed);
+    }
 }
 
 impl Storage for MemTable {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, crate::KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.get(key).map(|v| v.value().clone()))
+        let lock = self.locks.get(table);
+        let _guard = lock.read().unwrap();
+        if self.is_expired(table, key) {
+            return Ok(None);
+        }
+        let value = self.get_or_create_table(table).get(key).map(|v| v.clone());
+        if value.is_some() {
+            self.record_access(table, key);
+        }
+        Ok(value)
     }
 
     fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, crate::KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.insert(key, value))
+        let lock = self.locks.get(table);
+        let guard = lock.read().unwrap();
+        let old = self.set_locked(table, key, value);
+        drop(guard);
+        self.evict();
+        Ok(old)
     }
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, crate::KvError> {
+        let lock = self.locks.get(table);
+        let _guard = lock.read().unwrap();
+        if self.is_expired(table, key) {
+            return Ok(false);
+        }
         let table = self.get_or_create_table(table);
         Ok(table.contains_key(key))
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, crate::KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.remove(key).map(|(_k, v)| v))
+        let lock = self.locks.get(table);
+        let _guard = lock.read().unwrap();
+        Ok(self.del_locked(table, key))
     }
 
     fn get_all(&self, table: &str) -> Result<Vec<crate::Kvpair>, crate::KvError> {
+        let lock = self.locks.get(table);
+        let _guard = lock.read().unwrap();
+        let expired = self.expired_keys(table);
         let table = self.get_or_create_table(table);
-        Ok(table
+        let mut pairs: Vec<Kvpair> = table
             .iter()
+            .filter(|kv| !expired.contains(kv.key()))
             .map(|kv| Kvpair::new(kv.key(), kv.value().clone()))
-            .collect())
+            .collect();
+        // dashmap is unordered, sort the pairs to be consistent with the other backends
+        pairs.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        Ok(pairs)
     }
 
     fn get_iter(
         &self,
         table: &str,
     ) -> Result<Box<dyn Iterator<Item = crate::Kvpair>>, crate::KvError> {
+        let lock = self.locks.get(table);
+        let _guard = lock.read().unwrap();
+        let expired = self.expired_keys(table);
         let table = self.get_or_create_table(table).clone();
-        let iter = StorageIter::new(table.into_iter());
+        let iter =
+            StorageIter::new(table.into_iter()).filter(move |pair| !expired.contains(&pair.key));
         Ok(Box::new(iter))
     }
+
+    /// Lock the tables exclusively in the name order, so concurrent transactions never deadlock
+    fn transaction(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KvError> {
+        let tables: BTreeSet<&str> = ops.iter().map(|op| op.table()).collect();
+        let locks: Vec<_> = tables.into_iter().map(|t| self.locks.get(t)).collect();
+        let guards: Vec<_> = locks.iter().map(|lock| lock.write().unwrap()).collect();
+
+        let olds = ops.into_iter().map(|op| self.apply_locked(op)).collect();
+        drop(guards);
+        self.evict();
+        Ok(olds)
+    }
+
+    /// Copy the pairs with all tables locked exclusively in the name order, so no write lands
+    /// in the middle of the copy, and write them once the locks are released
+    fn snapshot(&self, writer: &mut dyn Write) -> Result<usize, KvError> {
+        let pairs = self.pairs_with_deadlines();
+        write_dump(
+            writer,
+            pairs.into_iter().map(|(name, pair, _)| Ok((name, pair))),
+        )
+    }
+
+    /// The entry of the key stays locked during the update, so the updates of a key are serialized
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: &mut UpdateFn,
+    ) -> Result<(Option<Value>, Option<Value>), KvError> {
+        let lock = self.locks.get(table);
+        let guard = lock.read().unwrap();
+        let pairs = self.get_or_create_table(table);
+        let entry = pairs.entry(key.to_string());
+        let (old, removed) = match &entry {
+            Entry::Occupied(e) if !self.is_expired(table, key) => {
+                (Some(e.get().clone()), pair_size(key, e.get()))
+            }
+            Entry::Occupied(e) => (None, pair_size(key, e.get())),
+            Entry::Vacant(_) => (None, 0),
+        };
+
+        let (new, persist, deadline) = match f(old.as_ref())? {
+            Update::Keep => return Ok((old.clone(), old)),
+            Update::Set(value) => (Some(value), false, None),
+            Update::Overwrite(value) => (Some(value), true, None),
+            Update::Expiring(value, deadline) => (Some(value), true, Some(deadline)),
+            Update::Delete => (None, false, None),
+        };
+        match &new {
+            Some(_) => _ = self.versions.bump(table, key),
+            None => self.versions.remove(table, key),
+        }
+        match (entry, &new) {
+            (Entry::Occupied(mut e), Some(value)) => {
+                e.insert(value.clone());
+            }
+            (Entry::Occupied(e), None) => {
+                e.remove();
+            }
+            (Entry::Vacant(e), Some(value)) => {
+                e.insert(value.clone());
+            }
+            (Entry::Vacant(_), None) => {}
+        }
+        drop(pairs);
+
+        // the deadline of an expired key does not apply to the new value
+        match deadline {
+            Some(deadline) => self.set_deadline(table, key, deadline),
+            None if persist || old.is_none() || new.is_none() => self.clear_deadline(table, key),
+            None => {}
+        }
+        match new {
+            Some(_) => self.record_access(table, key),
+            None => self.forget_access(table, key),
+        }
+        let added = new.as_ref().map(|v| pair_size(key, v)).unwrap_or(0);
+        self.adjust_size(table, added, removed);
+        drop(guard);
+        self.evict();
+        Ok((old, new))
+    }
+
+    fn expire(&self, table: &str, key: &str, deadline: Option<u64>) -> Result<bool, KvError> {
+        let lock = self.locks.get(table);
+        let _guard = lock.read().unwrap();
+        if self.is_expired(table, key) || !self.get_or_create_table(table).contains_key(key) {
+            return Ok(false);
+        }
+        match deadline {
+            Some(deadline) => self.set_deadline(table, key, deadline),
+            None => self.clear_deadline(table, key),
+        }
+        Ok(true)
+    }
+
+    fn deadline(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        match self.contains(table, key)? {
+            true => Ok(self.deadline_of(table, key)),
+            false => Ok(None),
+        }
+    }
+
+    /// Each expired key is removed under the exclusive lock of its table,
+    /// so a key set again since it was found expired is kept
+    fn purge_expired(&self, now: u64) -> Result<Vec<(String, String)>, KvError> {
+        let expired: Vec<(String, String)> = self
+            .deadlines
+            .iter()
+            .flat_map(|deadlines| {
+                let table = deadlines.key().clone();
+                deadlines
+                    .iter()
+                    .filter(|deadline| *deadline.value() <= now)
+                    .map(|deadline| (table.clone(), deadline.key().clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        let mut purged = Vec::with_capacity(expired.len());
+        for (table, key) in expired {
+            let lock = self.locks.get(&table);
+            let _guard = lock.write().unwrap();
+            if matches!(self.deadline_of(&table, &key), Some(deadline) if deadline <= now) {
+                self.del_locked(&table, &key);
+                purged.push((table, key));
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Reservoir sampling over the entries in place, only the sampled pairs are cloned.
+    /// The expired keys not purged yet are skipped.
+    fn sample(&self, table: &str, count: usize) -> Result<Vec<Kvpair>, KvError> {
+        let lock = self.locks.get(table);
+        let _guard = lock.read().unwrap();
+        let Some(pairs) = self.tables.get(table) else {
+            return Ok(vec![]);
+        };
+        let now = now_ms();
+        let deadlines = self.deadlines.get(table);
+        let live = |key: &String| match deadlines.as_ref().and_then(|d| d.get(key)) {
+            Some(deadline) => *deadline > now,
+            None => true,
+        };
+        let mut rng = rand::thread_rng();
+        let sampled = pairs
+            .iter()
+            .filter(|entry| live(entry.key()))
+            .choose_multiple(&mut rng, count.min(pairs.len()));
+        Ok(sampled
+            .into_iter()
+            .map(|entry| Kvpair::new(entry.key(), entry.value().clone()))
+            .collect())
+    }
+
+    /// The length of the map, minus the expired keys not purged yet
+    fn len_of_table(&self, table: &str) -> Result<usize, KvError> {
+        let lock = self.locks.get(table);
+        let _guard = lock.read().unwrap();
+        let len = self.tables.get(table).map(|t| t.len()).unwrap_or(0);
+        let now = now_ms();
+        let expired = match self.deadlines.get(table) {
+            Some(deadlines) => deadlines.iter().filter(|d| *d.value() <= now).count(),
+            None => 0,
+        };
+        Ok(len.saturating_sub(expired))
+    }
+
+    fn copy_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        let copied = self.transfer(from, to, key, replace, false)?;
+        self.evict();
+        Ok(copied)
+    }
+
+    fn move_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        self.transfer(from, to, key, replace, true)
+    }
+
+    /// Drop the whole map under the table write lock, so no write lands in between
+    fn clear_table(&self, table: &str) -> Result<usize, KvError> {
+        let lock = self.locks.get(table);
+        let _guard = lock.write().unwrap();
+        let removed = self.tables.remove(table).map(|(_, t)| t.len());
+        self.sizes.remove(table);
+        self.deadlines.remove(table);
+        self.accessed.remove(table);
+        self.versions.keys.remove(table);
+        if let Some(mut lru) = self.budget.lru() {
+            lru.remove_table(table);
+        }
+        Ok(removed.unwrap_or(0))
+    }
+
+    fn touch(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let lock = self.locks.get(table);
+        let _guard = lock.read().unwrap();
+        if self.is_expired(table, key) || !self.get_or_create_table(table).contains_key(key) {
+            return Ok(false);
+        }
+        self.record_access(table, key);
+        Ok(true)
+    }
+
+    fn last_access(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        if !self.contains(table, key)? {
+            return Ok(None);
+        }
+        Ok(self
+            .accessed
+            .get(table)
+            .and_then(|accessed| accessed.get(key).map(|ms| *ms)))
+    }
+
+    fn version(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        let lock = self.locks.get(table);
+        let _guard = lock.read().unwrap();
+        if self.is_expired(table, key) {
+            return Ok(None);
+        }
+        Ok(self.versions.get(table, key))
+    }
+
+    fn modified(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        let lock = self.locks.get(table);
+        let _guard = lock.read().unwrap();
+        if self.is_expired(table, key) {
+            return Ok(None);
+        }
+        Ok(self.versions.modified(table, key))
+    }
+
+    /// The version is read under the lock of the entry, which its writes hold too
+    fn get_versioned(&self, table: &str, key: &str) -> Result<Option<(Value, u64)>, KvError> {
+        let lock = self.locks.get(table);
+        let _guard = lock.read().unwrap();
+        if self.is_expired(table, key) {
+            return Ok(None);
+        }
+        let pairs = self.get_or_create_table(table);
+        let Some(value) = pairs.get(key) else {
+            return Ok(None);
+        };
+        let versioned = (value.clone(), self.versions.get(table, key).unwrap_or(0));
+        drop(value);
+        drop(pairs);
+        self.record_access(table, key);
+        Ok(Some(versioned))
+    }
+
+    /// Check and write under the table write lock, so no write of the key lands in between
+    fn set_if_version(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        version: u64,
+    ) -> Result<Option<(Value, u64)>, KvError> {
+        let lock = self.locks.get(table);
+        let guard = lock.write().unwrap();
+        if self.is_expired(table, &key) || self.versions.get(table, &key) != Some(version) {
+            return Ok(None);
+        }
+        let old = self.set_locked(table, key.clone(), value);
+        let version = self.versions.get(table, &key).unwrap_or(0);
+        drop(guard);
+        self.evict();
+        Ok(old.map(|old| (old, version)))
+    }
+
+    fn size_of_table(&self, table: &str) -> Result<usize, crate::KvError> {
+        Ok(self.sizes.get(table).map(|size| *size).unwrap_or(0))
+    }
+
+    fn total_size(&self) -> Result<usize, crate::KvError> {
+        Ok(self.sizes.iter().map(|size| *size.value()).sum())
+    }
+
+    /// Read from the counters kept on every write, the tables are counted one by one
+    fn stats(&self) -> Result<StorageStats, KvError> {
+        let tables: Vec<String> = self.tables.iter().map(|t| t.key().clone()).collect();
+        let mut stats = StorageStats::default();
+        for table in tables {
+            let keys = self.len_of_table(&table)?;
+            let bytes = self.size_of_table(&table)?;
+            stats.add_table(table, keys, bytes);
+        }
+        stats.set_info("total_size", self.total_size()? as u64);
+        if let Some(max) = self.budget.max {
+            stats.set_info("max_memory", max as u64);
+            stats.set_info("evictions", self.budget.evictions.load(Ordering::Relaxed));
+        }
+        Ok(stats)
+    }
 }
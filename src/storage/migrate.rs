@@ -0,0 +1,173 @@
+use crate::{KvError, Kvpair};
+
+use super::{now_ms, Storage};
+
+/// The default number of pairs read and written at once by a migration
+const DEFAULT_MIGRATE_BATCH: usize = 1000;
+
+/// How far a migration went: the tables sorting before `table` are done, and so are the keys
+/// of `table` up to `cursor`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrateCheckpoint {
+    pub table: String,
+    /// The last key written, None if no key of the table was written yet
+    pub cursor: Option<String>,
+}
+
+/// The options of `migrate_with`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrateOptions {
+    /// The number of pairs read and written at once
+    pub batch_size: usize,
+    /// Resume an interrupted migration from its last checkpoint
+    pub resume: Option<MigrateCheckpoint>,
+}
+
+impl Default for MigrateOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: DEFAULT_MIGRATE_BATCH,
+            resume: None,
+        }
+    }
+}
+
+impl MigrateOptions {
+    pub fn batch_size(mut self, n: usize) -> Self {
+        self.batch_size = n.max(1);
+        self
+    }
+
+    pub fn resume(mut self, checkpoint: MigrateCheckpoint) -> Self {
+        self.resume = Some(checkpoint);
+        self
+    }
+}
+
+/// The progress reported after every batch of a migration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrateProgress {
+    /// The number of pairs written by this run so far
+    pub pairs: usize,
+    /// Where to resume from if the migration stops now
+    pub checkpoint: MigrateCheckpoint,
+}
+
+/// Copy all the pairs of a storage to another one with their deadlines, overwriting the
+/// existing keys, and return the number of pairs copied. See `migrate_with`.
+pub fn migrate(src: &impl Storage, dst: &impl Storage) -> Result<usize, KvError> {
+    migrate_with(src, dst, MigrateOptions::default(), |_| {})
+}
+
+/// Copy all the pairs of a storage to another one in batches, reporting the progress after
+/// every batch written.
+///
+/// The tables are listed by `Storage::stats` and copied in the order of their names, each one
+/// with a cursor scan, so the source may be written during the migration: every key present
+/// during the whole migration is copied, the writes behind the cursor are not.
+pub fn migrate_with(
+    src: &impl Storage,
+    dst: &impl Storage,
+    opts: MigrateOptions,
+    mut progress: impl FnMut(&MigrateProgress),
+) -> Result<usize, KvError> {
+    let tables = src.stats()?.tables.into_keys();
+    let (start, mut cursor) = match opts.resume {
+        Some(checkpoint) => (checkpoint.table, checkpoint.cursor),
+        None => (String::new(), None),
+    };
+
+    let mut count = 0;
+    for table in tables.filter(|table| *table >= start) {
+        if table != start {
+            cursor = None;
+        }
+        loop {
+            let page = src.scan_page(&table, cursor.clone(), opts.batch_size)?;
+            let Some(last) = page.pairs.last().map(|pair| pair.key.clone()) else {
+                break;
+            };
+            count += copy_batch(src, dst, &table, page.pairs)?;
+            cursor = Some(last);
+            progress(&MigrateProgress {
+                pairs: count,
+                checkpoint: MigrateCheckpoint {
+                    table: table.clone(),
+                    cursor: cursor.clone(),
+                },
+            });
+            if page.cursor.is_none() {
+                break;
+            }
+        }
+    }
+    dst.flush()?;
+    Ok(count)
+}
+
+/// Write the pairs of a table, then their deadlines, skipping the keys expired meanwhile
+fn copy_batch(
+    src: &impl Storage,
+    dst: &impl Storage,
+    table: &str,
+    pairs: Vec<Kvpair>,
+) -> Result<usize, KvError> {
+    let mut deadlines = Vec::new();
+    for pair in &pairs {
+        if let Some(deadline) = src.deadline(table, &pair.key)? {
+            deadlines.push((pair.key.clone(), deadline));
+        }
+    }
+    let count = pairs.len();
+    dst.set_batch(table, pairs)?;
+
+    let now = now_ms();
+    for (key, deadline) in deadlines {
+        if deadline > now {
+            dst.expire(table, &key, Some(deadline))?;
+        } else {
+            dst.del(table, &key)?;
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemTable;
+
+    #[test]
+    fn migrate_should_copy_the_tables_and_resume_from_a_checkpoint() {
+        let src = MemTable::new();
+        for i in 0..10 {
+            src.set("t1", format!("k{}", i), i.into()).unwrap();
+        }
+        src.set("t2", "k1".into(), "v1".into()).unwrap();
+        let later = now_ms() + 60_000;
+        src.expire("t2", "k1", Some(later)).unwrap();
+
+        // a batch per checkpoint, the last table in one batch
+        let dst = MemTable::new();
+        let mut checkpoints = Vec::new();
+        let opts = MigrateOptions::default().batch_size(4);
+        migrate_with(&src, &dst, opts.clone(), |p| checkpoints.push(p.clone())).unwrap();
+        assert_eq!(checkpoints.len(), 4);
+        assert_eq!(checkpoints[3].pairs, 11);
+        let first = checkpoints[0].checkpoint.clone();
+        assert_eq!(first.table, "t1");
+
+        // resume a migration interrupted after the first batch
+        let dst = MemTable::new();
+        let mut pairs = src.get_all("t1").unwrap();
+        pairs.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        dst.set_batch("t1", pairs[..4].to_vec()).unwrap();
+        let count = migrate_with(&src, &dst, opts.resume(first), |_| {}).unwrap();
+        assert_eq!(count, 7);
+        assert_eq!(dst.get_all("t1").unwrap(), src.get_all("t1").unwrap());
+        assert_eq!(dst.get("t2", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(dst.deadline("t2", "k1").unwrap(), Some(later));
+
+        assert_eq!(migrate(&src, &MemTable::new()).unwrap(), 11);
+    }
+}
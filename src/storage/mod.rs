@@ -1,14 +1,72 @@
+mod cached;
+mod dump;
+mod encrypted;
+mod expiry;
+mod hybrid;
+mod json;
+mod lru;
 mod memory;
+mod migrate;
+mod sharded;
 mod sleddb;
-
-use crate::{KvError, Kvpair, Value};
-
+mod snapshot;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testkit;
+mod tiered;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{Read, Write},
+    ops::Range,
+    pin::Pin,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use futures::{future, stream, Future, Stream};
+use prost::Message;
+use rand::seq::IteratorRandom;
+
+use crate::{unscoped_table, KvError, Kvpair, Value, ZsetMember};
+
+pub use cached::{CacheConfig, CacheMetrics, CachedStore, WritePolicy};
+pub use dump::{read_dump, write_dump, DumpReader};
+pub use encrypted::EncryptedStore;
+pub use hybrid::HybridStore;
+pub use json::{export_ndjson, import_ndjson, read_ndjson, write_ndjson};
 pub use memory::MemTable;
-pub use sleddb::SledDb;
+pub use migrate::{migrate, migrate_with, MigrateCheckpoint, MigrateOptions, MigrateProgress};
+pub use sharded::ShardedMemTable;
+pub use sleddb::{FlushPolicy, SledDb, SledDbBuilder, ValueChecksum, ValueCompression};
+pub use snapshot::{SnapshotConfig, SnapshotStore};
+pub use tiered::TieredStore;
+
+/// The max number of pairs written at once by `Storage::restore_snapshot`
+const RESTORE_BATCH_SIZE: usize = 1024;
+
+/// An async stream of the key-value pairs of a table
+pub type StorageStream = Pin<Box<dyn Stream<Item = Kvpair> + Send>>;
+
+/// The function of `Storage::update`, computing the write of a key from its current value
+pub type UpdateFn<'a> = dyn FnMut(Option<&Value>) -> Result<Update, KvError> + 'a;
+
+/// The write of a key computed by the function of `Storage::update`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Update {
+    /// Leave the key as is, nothing is written
+    Keep,
+    /// Set the value and keep the deadline of the key
+    Set(Value),
+    /// Set the value and clear the deadline of the key, like `Storage::set`
+    Overwrite(Value),
+    /// Set the value and the deadline of the key, in milliseconds since the unix epoch
+    Expiring(Value, u64),
+    /// Delete the key
+    Delete,
+}
 
 /// Storage is a trait that defines the interface for a key-value storage engine,
 /// the backend may be a memory HashMap or other storage engines like sled, rocksdb, etc.
-pub trait Storage {
+pub trait Storage: Send + Sync + 'static {
     /// Get the value of a key in a table
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
 
@@ -21,11 +79,740 @@ pub trait Storage {
     /// Remove a key in a table and return the removed value
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
 
-    /// Get all keys in a table
+    /// Get all keys in a table, sorted by key
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError>;
 
     /// Get an iterator of all key-value pairs in a table
     fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError>;
+
+    /// Get an async stream of all key-value pairs in a table, sorted by key. The default implementation
+    /// reads the table at once, backends doing blocking IO should read it off the runtime.
+    fn get_stream(&self, table: &str) -> Result<StorageStream, KvError> {
+        let pairs = self.get_all(table)?;
+        Ok(Box::pin(stream::iter(pairs)))
+    }
+
+    /// Scan a table in key order, starting from `opts.start` (inclusive),
+    /// backwards if `opts.reverse` is set, and yielding at most `opts.limit` pairs.
+    /// The default implementation sorts the whole table, ordered backends should override it.
+    fn scan(
+        &self,
+        table: &str,
+        opts: ScanOptions,
+    ) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        let mut pairs: Vec<Kvpair> = self.get_iter(table)?.collect();
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        if opts.reverse {
+            pairs.reverse();
+        }
+
+        let iter = pairs.into_iter().skip_while(move |pair| match &opts.start {
+            Some(start) if opts.reverse => pair.key > *start,
+            Some(start) => pair.key < *start,
+            None => false,
+        });
+        Ok(Box::new(iter.take(opts.limit.unwrap_or(usize::MAX))))
+    }
+
+    /// Get at most `limit` pairs of a table in key order, from `start` (inclusive) to `end` (exclusive),
+    /// None for an unbounded side. The default implementation is built on `scan`.
+    fn get_range(
+        &self,
+        table: &str,
+        start: Option<&str>,
+        end: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let opts = ScanOptions {
+            start: start.map(Into::into),
+            ..Default::default()
+        };
+        Ok(self
+            .scan(table, opts)?
+            .take_while(|pair| end.is_none_or(|end| pair.key.as_str() < end))
+            .take(limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+
+    /// Get a page of at most `count` pairs in key order, resuming after the cursor of the previous page.
+    ///
+    /// The cursor is the last key returned, not an offset, so a full scan returns every key present
+    /// during the whole scan exactly once, whatever is inserted or deleted concurrently.
+    /// A key written during the scan is returned at most once, if it sorts after the cursor.
+    fn scan_page(
+        &self,
+        table: &str,
+        cursor: Option<String>,
+        count: usize,
+    ) -> Result<ScanPage, KvError> {
+        let opts = ScanOptions {
+            start: cursor.clone(),
+            ..Default::default()
+        };
+        // the cursor key itself was returned by the previous page
+        let mut iter = self
+            .scan(table, opts)?
+            .skip_while(|pair| Some(&pair.key) == cursor.as_ref())
+            .peekable();
+        let pairs: Vec<Kvpair> = iter.by_ref().take(count).collect();
+        let cursor = match iter.peek() {
+            Some(_) => pairs.last().map(|pair| pair.key.clone()),
+            None => None,
+        };
+        Ok(ScanPage { pairs, cursor })
+    }
+
+    /// Sample at most `count` distinct random pairs of a table.
+    /// The default implementation walks the whole table with reservoir sampling.
+    fn sample(&self, table: &str, count: usize) -> Result<Vec<Kvpair>, KvError> {
+        // the reservoir is allocated for the count, so it must not exceed the table
+        let count = count.min(self.len_of_table(table)?);
+        let mut rng = rand::thread_rng();
+        Ok(self.get_iter(table)?.choose_multiple(&mut rng, count))
+    }
+
+    /// The number of keys of a table, the expired keys excluded.
+    /// The default implementation iterates the table, backends should override it.
+    fn len_of_table(&self, table: &str) -> Result<usize, KvError> {
+        Ok(self
+            .get_iter(table)?
+            .filter(|pair| self.contains(table, &pair.key).unwrap_or(false))
+            .count())
+    }
+
+    /// Copy a key with its deadline to another table, and return whether it was copied.
+    /// Nothing is copied if the key is missing, or if it exists in the destination without `replace`.
+    /// The default implementation is not atomic, backends should override it.
+    fn copy_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        if from == to {
+            return Err(KvError::InvalidCommand(format!(
+                "cannot copy {} onto itself",
+                key
+            )));
+        }
+        let Some(value) = self.get(from, key)? else {
+            return Ok(false);
+        };
+        if !replace && self.contains(to, key)? {
+            return Ok(false);
+        }
+        let deadline = self.deadline(from, key)?;
+        self.set(to, key.into(), value)?;
+        if deadline.is_some() {
+            self.expire(to, key, deadline)?;
+        }
+        Ok(true)
+    }
+
+    /// Like `copy_key`, and delete the source key once copied
+    fn move_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        let copied = self.copy_key(from, to, key, replace)?;
+        if copied {
+            self.del(from, key)?;
+        }
+        Ok(copied)
+    }
+
+    /// Remove all keys of a table and return the number of keys removed.
+    /// The default implementation deletes the keys one by one, backends should override it.
+    fn clear_table(&self, table: &str) -> Result<usize, KvError> {
+        let keys: Vec<String> = self.get_iter(table)?.map(|pair| pair.key).collect();
+        for key in &keys {
+            self.del(table, key)?;
+        }
+        Ok(keys.len())
+    }
+
+    /// The approximate size in bytes of a table, counting the keys and the encoded values
+    fn size_of_table(&self, table: &str) -> Result<usize, KvError>;
+
+    /// The approximate size in bytes of the whole storage
+    fn total_size(&self) -> Result<usize, KvError>;
+
+    /// The number of keys and the size of each table, and the figures specific to the backend,
+    /// for monitoring. The default implementation only reports `total_size`, as a storage
+    /// could not list its tables; the backends should override it.
+    fn stats(&self) -> Result<StorageStats, KvError> {
+        let mut stats = StorageStats::default();
+        stats.set_info("total_size", self.total_size()? as u64);
+        Ok(stats)
+    }
+
+    /// Apply the writes in order, possibly across tables, and return the old values.
+    /// The default implementation is not atomic, backends supporting transactions should override it.
+    fn transaction(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KvError> {
+        ops.into_iter()
+            .map(|op| match op {
+                WriteOp::Set { table, key, value } => self.set(&table, key, value),
+                WriteOp::Del { table, key } => self.del(&table, &key),
+            })
+            .collect()
+    }
+
+    /// Set the pairs of a table at once and return the old values, like `set` for each pair.
+    /// The default implementation is a transaction, backends with faster batches should override it.
+    fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<Vec<Option<Value>>, KvError> {
+        let ops = pairs
+            .into_iter()
+            .map(|pair| WriteOp::set(table, pair.key, pair.value.unwrap_or_default()))
+            .collect();
+        self.transaction(ops)
+    }
+
+    /// Remove the keys of a table at once and return the removed values, like `del` for each key.
+    /// The default implementation is a transaction, backends with faster batches should override it.
+    fn del_batch(&self, table: &str, keys: Vec<String>) -> Result<Vec<Option<Value>>, KvError> {
+        let ops = keys
+            .into_iter()
+            .map(|key| WriteOp::del(table, key))
+            .collect();
+        self.transaction(ops)
+    }
+
+    /// Read-modify-write a key: `f` gets the current value, None if missing, and returns the
+    /// write of the key, see `Update`. Return the old and the new values, the same for `Keep`,
+    /// which writes nothing: the version and the modified time of the key are left unchanged.
+    /// `f` may be called more than once by optimistic backends, so it must be pure.
+    /// The default implementation is not atomic, backends should override it.
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: &mut UpdateFn,
+    ) -> Result<(Option<Value>, Option<Value>), KvError> {
+        let old = self.get(table, key)?;
+        let new = match f(old.as_ref())? {
+            Update::Keep => return Ok((old.clone(), old)),
+            Update::Set(value) => {
+                let deadline = self.deadline(table, key)?;
+                self.set(table, key.into(), value.clone())?;
+                if deadline.is_some() {
+                    self.expire(table, key, deadline)?;
+                }
+                Some(value)
+            }
+            Update::Overwrite(value) => {
+                self.set(table, key.into(), value.clone())?;
+                Some(value)
+            }
+            Update::Expiring(value, deadline) => {
+                self.set(table, key.into(), value.clone())?;
+                self.expire(table, key, Some(deadline))?;
+                Some(value)
+            }
+            Update::Delete => {
+                self.del(table, key)?;
+                None
+            }
+        };
+        Ok((old, new))
+    }
+
+    /// Flush the pending writes to the durable media, a no-op for memory storages
+    fn flush(&self) -> Result<(), KvError> {
+        Ok(())
+    }
+
+    /// Flush like `flush` without blocking the async runtime.
+    /// The default implementation calls `flush`, backends flushing to disk should override it.
+    fn flush_async(&self) -> impl Future<Output = Result<(), KvError>> + Send {
+        future::ready(self.flush())
+    }
+
+    /// Write a consistent snapshot of the pairs of all tables in the dump format of `write_dump`,
+    /// without the expired keys, and return the number of pairs written.
+    /// The deadlines are not kept, the restored keys never expire.
+    fn snapshot(&self, _writer: &mut dyn Write) -> Result<usize, KvError> {
+        Err(KvError::InvalidCommand(
+            "the storage does not support snapshots".into(),
+        ))
+    }
+
+    /// Write the pairs of a snapshot, overwriting the existing keys, and return the number of
+    /// pairs restored. The pairs are written in batches of the consecutive pairs of a table.
+    fn restore_snapshot(&self, reader: &mut dyn Read) -> Result<usize, KvError> {
+        restore_pairs(self, read_dump(reader))
+    }
+
+    /// Set the deadline of an existing key in milliseconds since the unix epoch, None to make
+    /// it persistent, and return whether the key exists. A set of the key clears the deadline.
+    /// An expired key is hidden from `get` and `contains`, and removed by `purge_expired`.
+    fn expire(&self, _table: &str, _key: &str, _deadline: Option<u64>) -> Result<bool, KvError> {
+        Err(KvError::InvalidCommand(
+            "the storage does not support expiration".into(),
+        ))
+    }
+
+    /// The deadline of a key, None if the key never expires or does not exist
+    fn deadline(&self, _table: &str, _key: &str) -> Result<Option<u64>, KvError> {
+        Ok(None)
+    }
+
+    /// Remove the keys expired at `now`, and return them with their tables
+    fn purge_expired(&self, _now: u64) -> Result<Vec<(String, String)>, KvError> {
+        Ok(vec![])
+    }
+
+    /// Push the values to the front of the list of a key one by one, or to the back,
+    /// and return the new length. A missing key is created as an empty list.
+    /// The push is rejected if the list would be larger than `max_size` bytes encoded.
+    fn list_push(
+        &self,
+        table: &str,
+        key: &str,
+        values: Vec<Value>,
+        front: bool,
+        max_size: Option<usize>,
+    ) -> Result<usize, KvError> {
+        let mut len = 0;
+        self.update(table, key, &mut |v| {
+            let mut list = list_of(v)?;
+            match front {
+                true => values.iter().for_each(|v| list.insert(0, v.clone())),
+                false => list.extend(values.iter().cloned()),
+            }
+            len = list.len();
+            set_within(list.into(), max_size)
+        })?;
+        Ok(len)
+    }
+
+    /// Pop at most `count` values from the front of the list of a key, or from the back,
+    /// in the order they are popped. The key is deleted once its list is empty.
+    fn list_pop(
+        &self,
+        table: &str,
+        key: &str,
+        count: usize,
+        front: bool,
+    ) -> Result<Vec<Value>, KvError> {
+        let mut popped = vec![];
+        self.update(table, key, &mut |v| {
+            let Some(v) = v else {
+                popped = vec![];
+                return Ok(Update::Keep);
+            };
+            let mut list: Vec<Value> = v.try_into()?;
+            let count = count.min(list.len());
+            popped = match front {
+                true => list.drain(..count).collect(),
+                false => list.drain(list.len() - count..).rev().collect(),
+            };
+            Ok(match list.is_empty() {
+                true => Update::Delete,
+                false => Update::Set(list.into()),
+            })
+        })?;
+        Ok(popped)
+    }
+
+    /// The values of the list of a key from `start` to `stop`, see `inclusive_range`
+    fn list_range(
+        &self,
+        table: &str,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<Value>, KvError> {
+        let mut list = list_of(self.get(table, key)?.as_ref())?;
+        let range = inclusive_range(list.len(), start, stop);
+        Ok(list.drain(range).collect())
+    }
+
+    /// Add the members to the set of a key, and return the number of the new members.
+    /// A missing key is created as an empty set, like `list_push` for `max_size`.
+    fn set_add(
+        &self,
+        table: &str,
+        key: &str,
+        members: &[String],
+        max_size: Option<usize>,
+    ) -> Result<usize, KvError> {
+        let mut added = 0;
+        self.update(table, key, &mut |v| {
+            let mut set = set_of(v)?;
+            added = members.iter().filter(|m| set.insert(m.to_string())).count();
+            set_within(set.into(), max_size)
+        })?;
+        Ok(added)
+    }
+
+    /// Remove the members from the set of a key, and return the number of the removed members.
+    /// The key is deleted once its set is empty.
+    fn set_remove(&self, table: &str, key: &str, members: &[String]) -> Result<usize, KvError> {
+        let mut removed = 0;
+        self.update(table, key, &mut |v| {
+            let Some(v) = v else {
+                removed = 0;
+                return Ok(Update::Keep);
+            };
+            let mut set: BTreeSet<String> = v.try_into()?;
+            removed = members.iter().filter(|m| set.remove(*m)).count();
+            Ok(match set.is_empty() {
+                true => Update::Delete,
+                false => Update::Set(set.into()),
+            })
+        })?;
+        Ok(removed)
+    }
+
+    /// The members of the set of a key, empty for a missing key
+    fn set_members(&self, table: &str, key: &str) -> Result<BTreeSet<String>, KvError> {
+        set_of(self.get(table, key)?.as_ref())
+    }
+
+    /// Add the members to the sorted set of a key or update their scores, and return the number
+    /// of the new members. A missing key is created as an empty sorted set, like `list_push`
+    /// for `max_size`.
+    fn zset_add(
+        &self,
+        table: &str,
+        key: &str,
+        members: &[ZsetMember],
+        max_size: Option<usize>,
+    ) -> Result<usize, KvError> {
+        let mut added = 0;
+        self.update(table, key, &mut |v| {
+            let mut zset = zset_of(v)?;
+            let len = zset.len();
+            for m in members {
+                zset.retain(|old| old.member != m.member);
+                zset.push(m.clone());
+            }
+            added = zset.len() - len;
+            zset.sort_by(|a, b| {
+                a.score
+                    .total_cmp(&b.score)
+                    .then_with(|| a.member.cmp(&b.member))
+            });
+            set_within(zset.into(), max_size)
+        })?;
+        Ok(added)
+    }
+
+    /// The members of the sorted set of a key in order, empty for a missing key
+    fn zset_members(&self, table: &str, key: &str) -> Result<Vec<ZsetMember>, KvError> {
+        zset_of(self.get(table, key)?.as_ref())
+    }
+
+    /// The members of the sorted set of a key with a score between `min` and `max` inclusive.
+    /// The members are stored in score order, so the range is found by a binary search.
+    fn zset_range_by_score(
+        &self,
+        table: &str,
+        key: &str,
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<ZsetMember>, KvError> {
+        let mut zset = self.zset_members(table, key)?;
+        let start = zset.partition_point(|m| m.score < min);
+        let end = zset.partition_point(|m| m.score <= max).max(start);
+        Ok(zset.drain(start..end).collect())
+    }
+
+    /// Mark a key as accessed now without reading it, and return whether the key exists
+    fn touch(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.contains(table, key)
+    }
+
+    /// The last time a key was read, written or touched, in milliseconds since the unix epoch.
+    /// None if the key does not exist or the storage does not track the accesses.
+    fn last_access(&self, _table: &str, _key: &str) -> Result<Option<u64>, KvError> {
+        Ok(None)
+    }
+
+    /// The version of a key, None if the key does not exist or the storage does not track versions.
+    /// Every write of a key gives it a version greater than all the versions given before,
+    /// so a version is never reused, even by a key deleted and set again.
+    fn version(&self, _table: &str, _key: &str) -> Result<Option<u64>, KvError> {
+        Ok(None)
+    }
+
+    /// The last time a key was written, in milliseconds since the unix epoch.
+    /// None if the key does not exist or the storage does not track the writes.
+    fn modified(&self, _table: &str, _key: &str) -> Result<Option<u64>, KvError> {
+        Ok(None)
+    }
+
+    /// Get the value of a key with its version, 0 if the storage does not track versions.
+    /// The default implementation does not read them atomically, backends should override it.
+    fn get_versioned(&self, table: &str, key: &str) -> Result<Option<(Value, u64)>, KvError> {
+        let Some(value) = self.get(table, key)? else {
+            return Ok(None);
+        };
+        Ok(Some((value, self.version(table, key)?.unwrap_or(0))))
+    }
+
+    /// Set a key only if it exists with the given version, and return the old value with the new
+    /// version, None if the version does not match. The deadline of the key is cleared, like by `set`.
+    fn set_if_version(
+        &self,
+        _table: &str,
+        _key: String,
+        _value: Value,
+        _version: u64,
+    ) -> Result<Option<(Value, u64)>, KvError> {
+        Err(KvError::InvalidCommand(
+            "the storage does not support versions".into(),
+        ))
+    }
+}
+
+/// The values of the list stored in a value, empty for a missing key
+fn list_of(value: Option<&Value>) -> Result<Vec<Value>, KvError> {
+    value
+        .map(|v| v.try_into())
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+/// The members of the set stored in a value, empty for a missing key
+fn set_of(value: Option<&Value>) -> Result<BTreeSet<String>, KvError> {
+    value
+        .map(|v| v.try_into())
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+/// The members of the sorted set stored in a value, empty for a missing key
+fn zset_of(value: Option<&Value>) -> Result<Vec<ZsetMember>, KvError> {
+    value
+        .map(|v| v.try_into())
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+/// Check the encoded size of a value against the limit if any
+pub(crate) fn check_size(value: &Value, max_size: Option<usize>) -> Result<(), KvError> {
+    match max_size {
+        Some(max) if value.encoded_len() > max => {
+            Err(KvError::ValueTooLarge(value.encoded_len(), max))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Set the value updated in place, unless it grew over the size limit
+pub(crate) fn set_within(value: Value, max_size: Option<usize>) -> Result<Update, KvError> {
+    check_size(&value, max_size)?;
+    Ok(Update::Set(value))
+}
+
+/// Write the pairs in batches of the consecutive pairs of a table, overwriting the existing keys,
+/// and return the number of pairs written
+pub(crate) fn restore_pairs<S, I>(store: &S, pairs: I) -> Result<usize, KvError>
+where
+    S: Storage + ?Sized,
+    I: IntoIterator<Item = Result<(String, Kvpair), KvError>>,
+{
+    let mut count = 0;
+    let mut batch: Option<(String, Vec<Kvpair>)> = None;
+    for pair in pairs {
+        let (table, pair) = pair?;
+        match &mut batch {
+            Some((name, pairs)) if *name == table && pairs.len() < RESTORE_BATCH_SIZE => {
+                pairs.push(pair)
+            }
+            _ => {
+                if let Some((name, pairs)) = batch.replace((table, vec![pair])) {
+                    count += pairs.len();
+                    store.set_batch(&name, pairs)?;
+                }
+            }
+        }
+    }
+    if let Some((name, pairs)) = batch {
+        count += pairs.len();
+        store.set_batch(&name, pairs)?;
+    }
+    Ok(count)
+}
+
+/// Resolve the inclusive offsets of a range over a sequence of `len` items, the negative
+/// offsets count from the end. The range is empty if the end is before the start.
+pub(crate) fn inclusive_range(len: usize, start: i64, end: i64) -> Range<usize> {
+    let len = len as i64;
+    let start = match start < 0 {
+        true => (len + start).max(0),
+        false => start.min(len),
+    };
+    let end = match end < 0 {
+        true => len + end,
+        false => end.min(len - 1),
+    };
+    start as usize..(end + 1).max(start) as usize
+}
+
+/// The current time in milliseconds since the unix epoch, the unit of the key deadlines
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// A write of a transaction
+#[derive(Debug, Clone, PartialEq)]
+pub enum WriteOp {
+    Set {
+        table: String,
+        key: String,
+        value: Value,
+    },
+    Del {
+        table: String,
+        key: String,
+    },
+}
+
+impl WriteOp {
+    pub fn set(table: impl Into<String>, key: impl Into<String>, value: Value) -> Self {
+        Self::Set {
+            table: table.into(),
+            key: key.into(),
+            value,
+        }
+    }
+
+    pub fn del(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self::Del {
+            table: table.into(),
+            key: key.into(),
+        }
+    }
+
+    /// The table the write operates on
+    pub fn table(&self) -> &str {
+        match self {
+            Self::Set { table, .. } | Self::Del { table, .. } => table,
+        }
+    }
+
+    /// The key the write operates on
+    pub fn key(&self) -> &str {
+        match self {
+            Self::Set { key, .. } | Self::Del { key, .. } => key,
+        }
+    }
+}
+
+/// The options of a table scan
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanOptions {
+    /// The key to start from (inclusive), None to start from the first (or the last) key
+    pub start: Option<String>,
+    /// Iterate from the largest key to the smallest one
+    pub reverse: bool,
+    /// The max number of pairs to yield, None for unlimited
+    pub limit: Option<usize>,
+}
+
+impl ScanOptions {
+    /// Scan forward from the start key
+    pub fn from(start: impl Into<String>) -> Self {
+        Self {
+            start: Some(start.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Scan backwards
+    pub fn reverse(mut self) -> Self {
+        self.reverse = true;
+        self
+    }
+
+    /// Yield at most `limit` pairs
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+}
+
+/// A page of a cursor scan
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanPage {
+    pub pairs: Vec<Kvpair>,
+    /// The cursor to get the next page, None if the scan is done
+    pub cursor: Option<String>,
+}
+
+/// The number of keys and the approximate size in bytes of a table
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableStats {
+    pub keys: usize,
+    pub bytes: usize,
+}
+
+/// The statistics of a storage, as returned by `Storage::stats`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageStats {
+    /// The stats of the tables holding keys, by name
+    pub tables: BTreeMap<String, TableStats>,
+    /// The figures specific to the backend, like the size on disk of sled
+    pub info: BTreeMap<String, u64>,
+}
+
+impl StorageStats {
+    /// Add the stats of a table, the empty tables are left out
+    pub fn add_table(&mut self, table: impl Into<String>, keys: usize, bytes: usize) {
+        if keys == 0 {
+            return;
+        }
+        let stats = self.tables.entry(table.into()).or_default();
+        stats.keys += keys;
+        stats.bytes += bytes;
+    }
+
+    pub fn set_info(&mut self, name: impl Into<String>, value: u64) {
+        self.info.insert(name.into(), value);
+    }
+
+    /// Add the tables and the figures of another part of the storage, like a shard
+    pub(crate) fn merge(&mut self, other: StorageStats) {
+        for (table, stats) in other.tables {
+            self.add_table(table, stats.keys, stats.bytes);
+        }
+        for (name, value) in other.info {
+            *self.info.entry(name).or_default() += value;
+        }
+    }
+
+    /// Keep the tables of a namespace and a database, named as its clients name them, so the
+    /// tables of the other scopes and of the server are left out, see `unscoped_table`
+    pub fn scoped(mut self, namespace: &str, db: u32) -> Self {
+        self.tables = std::mem::take(&mut self.tables)
+            .into_iter()
+            .filter_map(|(name, stats)| {
+                unscoped_table(namespace, db, &name).map(|table| (table.to_string(), stats))
+            })
+            .collect();
+        self
+    }
+
+    /// The stats as integer pairs: `table:<name>:keys` and `table:<name>:bytes` for each table,
+    /// then `storage:<name>` for each figure
+    pub fn pairs(&self) -> Vec<Kvpair> {
+        let tables = self.tables.iter().flat_map(|(table, stats)| {
+            [
+                Kvpair::new(format!("table:{}:keys", table), (stats.keys as i64).into()),
+                Kvpair::new(
+                    format!("table:{}:bytes", table),
+                    (stats.bytes as i64).into(),
+                ),
+            ]
+        });
+        let info = self
+            .info
+            .iter()
+            .map(|(name, value)| Kvpair::new(format!("storage:{}", name), (*value as i64).into()));
+        tables.chain(info).collect()
+    }
+}
+
+/// The size of a key-value pair, used by the size reporting of storages
+pub(crate) fn pair_size(key: &str, value: &Value) -> usize {
+    key.len() + value.encoded_len()
 }
 
 /// An iterator that converts the item type of the underlying iterator to Kvpair
@@ -73,117 +860,136 @@ where
 
 #[cfg(test)]
 mod tests {
+    use tempfile::tempdir;
+
     use super::*;
 
-    #[test]
-    fn memtable_basic_interface_should_work() {
-        let store = MemTable::new();
-        test_basic_interface(store);
-    }
+    crate::storage_conformance_tests!(memtable, MemTable::new());
+    crate::storage_conformance_tests!(bounded_memtable, MemTable::with_max_memory(1 << 20));
+    crate::storage_conformance_tests!(sharded_memtable, ShardedMemTable::new(4));
+    crate::storage_conformance_tests!(sleddb, dir = tempdir().unwrap() => SledDb::new(&dir));
+    // every value is tried, so the suite also runs through the compressed ones
+    crate::storage_conformance_tests!(
+        sleddb_zstd,
+        dir = tempdir().unwrap() => SledDb::builder(&dir)
+            .compression(ValueCompression::Zstd {
+                level: 1,
+                min_size: 0
+            })
+            .open()
+            .unwrap()
+    );
+    crate::storage_conformance_tests!(
+        sleddb_crc32,
+        dir = tempdir().unwrap() => SledDb::builder(&dir)
+            .checksum(ValueChecksum::Crc32)
+            .compression(ValueCompression::Zstd {
+                level: 1,
+                min_size: 0
+            })
+            .open()
+            .unwrap()
+    );
+    crate::storage_conformance_tests!(
+        hybrid,
+        dir = tempdir().unwrap() => HybridStore::open(SledDb::new(&dir)).unwrap()
+    );
+    // a small cache, so the suite also runs through the evictions
+    crate::storage_conformance_tests!(
+        tiered,
+        dir = tempdir().unwrap() => TieredStore::with_capacity(SledDb::new(&dir), 256)
+    );
+    crate::storage_conformance_tests!(
+        cached_around,
+        CachedStore::new(
+            MemTable::new(),
+            MemTable::new(),
+            CacheConfig::default()
+                .read_through(false)
+                .write_policy(WritePolicy::Around)
+        )
+    );
+    crate::storage_conformance_tests!(
+        snapshot,
+        dir = tempdir().unwrap() =>
+            SnapshotStore::open(SnapshotConfig::new(dir.path().join("kvdb.snapshot"))).unwrap()
+    );
+    crate::storage_conformance_tests!(
+        encrypted,
+        dir = tempdir().unwrap() => EncryptedStore::new(SledDb::new(&dir), &[7; 32])
+    );
 
     #[test]
-    fn memtable_get_all_should_work() {
-        let store = MemTable::new();
-        test_get_all(store);
+    fn memtable_should_evict_the_least_recently_used_over_max_memory() {
+        let size = pair_size("k0", &Value::from("v0"));
+        let store = MemTable::with_max_memory(size * 3);
+        for i in 0..3 {
+            store
+                .set("t1", format!("k{}", i), format!("v{}", i).into())
+                .unwrap();
+        }
+        assert_eq!(store.evictions(), 0);
+        store.get("t1", "k0").unwrap();
+        store.set("t2", "k3".into(), "v3".into()).unwrap();
+
+        assert_eq!(store.evictions(), 1);
+        assert_eq!(store.total_size().unwrap(), size * 3);
+        assert!(!store.contains("t1", "k1").unwrap());
+        for (table, key) in [("t1", "k0"), ("t1", "k2"), ("t2", "k3")] {
+            assert!(store.contains(table, key).unwrap());
+        }
+
+        // a cleared table is forgotten by the eviction order
+        store.clear_table("t1").unwrap();
+        store.set("t1", "k4".into(), "v4".into()).unwrap();
+        store.set("t1", "k5".into(), "v5".into()).unwrap();
+        store.set("t1", "k6".into(), "v6".into()).unwrap();
+        assert_eq!(store.evictions(), 2);
+        assert!(!store.contains("t2", "k3").unwrap());
     }
 
     #[test]
-    fn memtable_get_iter_should_work() {
-        let store = MemTable::new();
-        test_get_iter(store);
+    fn memtable_transactions_should_not_deadlock() {
+        let store = std::sync::Arc::new(MemTable::new());
+
+        // the writers touch the tables in opposite orders
+        let writers: Vec<_> = [("t1", "t2"), ("t2", "t1")]
+            .into_iter()
+            .map(|(first, second)| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        let ops = vec![
+                            WriteOp::set(first, "k", i.into()),
+                            WriteOp::set(second, "k", i.into()),
+                        ];
+                        store.transaction(ops).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(store.get("t1", "k").unwrap(), store.get("t2", "k").unwrap());
     }
 
-    fn test_basic_interface(store: impl Storage) {
-        // 1. set an unexisting key, should return None
-        assert_eq!(
-            None,
-            store.set("t1", "hello".into(), "value".into()).unwrap()
-        );
-
-        // 2. set an existing key, should return the old value
-        assert_eq!(
-            Some("value".into()),
-            store.set("t1", "hello".into(), "value2".into()).unwrap()
-        );
-
-        // 3. get the key, should return the new value
-        assert_eq!(Some("value2".into()), store.get("t1", "hello").unwrap());
-
-        // 4. get the unexisting key or table, should return None
-        assert_eq!(None, store.get("t1", "unexisting").unwrap());
-        assert_eq!(None, store.get("unexisting", "hello").unwrap());
-
-        // 5. check the existing key, should return true
-        assert!(store.contains("t1", "hello").unwrap());
-
-        // 6. check the unexisting key or table, should return false
-        assert!(!store.contains("t1", "unexisting").unwrap());
-        assert!(!store.contains("unexisting", "hello").unwrap());
-
-        // 7. del the key, should return the value
-        let v = store.del("t1", "hello");
-        assert_eq!(Some("value2".into()), v.unwrap());
-
-        // 8. get the key, should return None
-        assert_eq!(None, store.get("t1", "hello").unwrap());
-
-        // 9. del the unexisting key or table   , should return None
-        assert_eq!(None, store.del("t1", "unexisting").unwrap());
-        assert_eq!(None, store.del("unexisting", "hello").unwrap());
-    }
-
-    fn test_get_all(store: impl Storage) {
-        assert!(store.get_all("t2").unwrap().is_empty());
-
-        store.set("t2", "k1".into(), "v1".into()).unwrap();
-        store.set("t2", "k2".into(), "v2".into()).unwrap();
-
-        let mut data = store.get_all("t2").unwrap();
-        data.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        assert_eq!(
-            data,
-            vec![
-                Kvpair::new("k1", "v1".into()),
-                Kvpair::new("k2", "v2".into())
-            ]
-        );
-    }
-
-    #[allow(unused)]
-    fn test_get_iter(store: impl Storage) {
-        store.set("t3", "k1".into(), "v1".into()).unwrap();
-        store.set("t3", "k2".into(), "v2".into()).unwrap();
-
-        let iter = store.get_iter("t3").unwrap();
-        let mut pairs = iter.collect::<Vec<_>>();
-        pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        assert_eq!(
-            pairs,
-            vec![
-                Kvpair::new("k1", "v1".into()),
-                Kvpair::new("k2", "v2".into())
-            ]
-        );
-    }
-
-    use tempfile::tempdir;
-
-    #[test]
-    fn sleddb_basic_interface_should_work() {
-        let dir = tempdir().unwrap();
-        let store = SledDb::new(dir);
-        test_basic_interface(store);
-    }
-    #[test]
-    fn sleddb_get_all_should_work() {
-        let dir = tempdir().unwrap();
-        let store = SledDb::new(dir);
-        test_get_all(store);
-    }
     #[test]
-    fn sleddb_iter_should_work() {
+    fn sleddb_flush_policy_should_persist_writes() {
         let dir = tempdir().unwrap();
-        let store = SledDb::new(dir);
-        test_get_iter(store);
+        for policy in [
+            FlushPolicy::EveryWrite,
+            FlushPolicy::Interval(std::time::Duration::from_millis(10)),
+            FlushPolicy::Manual,
+        ] {
+            let path = dir.path().join(format!("{:?}", policy));
+            {
+                let store = SledDb::builder(&path).flush_policy(policy).open().unwrap();
+                store.set("t1", "k1".into(), "v1".into()).unwrap();
+                store.flush().unwrap();
+            }
+            let store = SledDb::builder(&path).open().unwrap();
+            assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        }
     }
 }
@@ -0,0 +1,234 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeSet},
+    hash::BuildHasher,
+    io::Write,
+    sync::{atomic::AtomicU64, Arc},
+    thread,
+};
+
+use crate::{KvError, Kvpair, Value};
+
+use super::{MemTable, Storage, StorageStats, UpdateFn, WriteOp};
+
+/// A MemTable split into shards by the hash of the keys, so the concurrent writes to a table
+/// spread over the locks and the size counters of the shards instead of contending on one set.
+///
+/// A key is in the same shard whatever its table, so copying or moving a key stays in one shard.
+/// The versions are given from one counter shared by the shards. The transactions lock the tables
+/// of all the shards they write, but a snapshot is taken shard by shard.
+#[derive(Debug)]
+pub struct ShardedMemTable {
+    shards: Vec<MemTable>,
+    hasher: RandomState,
+}
+
+impl Default for ShardedMemTable {
+    /// One shard per available core
+    fn default() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+}
+
+impl ShardedMemTable {
+    /// Create a MemTable of `shards` shards, at least one
+    pub fn new(shards: usize) -> Self {
+        let last = Arc::new(AtomicU64::new(0));
+        Self {
+            shards: (0..shards.max(1))
+                .map(|_| MemTable::with_version_counter(last.clone()))
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn index(&self, key: &str) -> usize {
+        (self.hasher.hash_one(key) % self.shards.len() as u64) as usize
+    }
+
+    fn shard(&self, key: &str) -> &MemTable {
+        &self.shards[self.index(key)]
+    }
+
+    /// Sum a count over the shards
+    fn sum(&self, f: impl FnMut(&MemTable) -> Result<usize, KvError>) -> Result<usize, KvError> {
+        self.shards.iter().map(f).sum()
+    }
+}
+
+impl Storage for ShardedMemTable {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.shard(key).get(table, key)
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
+        self.shard(&key).set(table, key, value)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.shard(key).contains(table, key)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.shard(key).del(table, key)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        let mut pairs = Vec::new();
+        for shard in &self.shards {
+            pairs.extend(shard.get_all(table)?);
+        }
+        pairs.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        Ok(pairs)
+    }
+
+    /// The pairs of the shards one after another, unordered like the pairs of a MemTable
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        let iters = self
+            .shards
+            .iter()
+            .map(|shard| shard.get_iter(table))
+            .collect::<Result<Vec<_>, KvError>>()?;
+        Ok(Box::new(iters.into_iter().flatten()))
+    }
+
+    fn len_of_table(&self, table: &str) -> Result<usize, KvError> {
+        self.sum(|shard| shard.len_of_table(table))
+    }
+
+    fn copy_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        self.shard(key).copy_key(from, to, key, replace)
+    }
+
+    fn move_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        self.shard(key).move_key(from, to, key, replace)
+    }
+
+    fn clear_table(&self, table: &str) -> Result<usize, KvError> {
+        self.sum(|shard| shard.clear_table(table))
+    }
+
+    fn size_of_table(&self, table: &str) -> Result<usize, KvError> {
+        self.sum(|shard| shard.size_of_table(table))
+    }
+
+    fn total_size(&self) -> Result<usize, KvError> {
+        self.sum(|shard| shard.total_size())
+    }
+
+    /// The stats of the shards added up, so the tables and the figures are the storage wide ones
+    fn stats(&self) -> Result<StorageStats, KvError> {
+        let mut stats = StorageStats::default();
+        for shard in &self.shards {
+            stats.merge(shard.stats()?);
+        }
+        stats.set_info("shards", self.shards.len() as u64);
+        Ok(stats)
+    }
+
+    /// Lock the tables exclusively in the order of the shards and the names,
+    /// so concurrent transactions never deadlock
+    fn transaction(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KvError> {
+        let tables: BTreeSet<(usize, &str)> = ops
+            .iter()
+            .map(|op| (self.index(op.key()), op.table()))
+            .collect();
+        let locks: Vec<_> = tables
+            .into_iter()
+            .map(|(i, table)| self.shards[i].table_lock(table))
+            .collect();
+        let guards: Vec<_> = locks.iter().map(|lock| lock.write().unwrap()).collect();
+
+        let olds = ops
+            .into_iter()
+            .map(|op| self.shard(op.key()).apply_locked(op))
+            .collect();
+        drop(guards);
+        Ok(olds)
+    }
+
+    /// Each shard is consistent on its own, the writes to the shards not snapshotted yet
+    /// may be in the snapshot
+    fn snapshot(&self, writer: &mut dyn Write) -> Result<usize, KvError> {
+        self.sum(|shard| shard.snapshot(writer))
+    }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: &mut UpdateFn,
+    ) -> Result<(Option<Value>, Option<Value>), KvError> {
+        self.shard(key).update(table, key, f)
+    }
+
+    fn expire(&self, table: &str, key: &str, deadline: Option<u64>) -> Result<bool, KvError> {
+        self.shard(key).expire(table, key, deadline)
+    }
+
+    fn deadline(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.shard(key).deadline(table, key)
+    }
+
+    fn purge_expired(&self, now: u64) -> Result<Vec<(String, String)>, KvError> {
+        let mut purged = Vec::new();
+        for shard in &self.shards {
+            purged.extend(shard.purge_expired(now)?);
+        }
+        Ok(purged)
+    }
+
+    fn touch(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.shard(key).touch(table, key)
+    }
+
+    fn last_access(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.shard(key).last_access(table, key)
+    }
+
+    fn version(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.shard(key).version(table, key)
+    }
+
+    fn modified(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.shard(key).modified(table, key)
+    }
+
+    fn get_versioned(&self, table: &str, key: &str) -> Result<Option<(Value, u64)>, KvError> {
+        self.shard(key).get_versioned(table, key)
+    }
+
+    fn set_if_version(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        version: u64,
+    ) -> Result<Option<(Value, u64)>, KvError> {
+        self.shard(&key).set_if_version(table, key, value, version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sharded_memtable_should_spread_the_keys_over_the_shards() {
+        let store = ShardedMemTable::new(4);
+        for i in 0..100 {
+            store.set("t1", format!("k{}", i), i.into()).unwrap();
+        }
+        assert!(store
+            .shards
+            .iter()
+            .all(|shard| shard.len_of_table("t1").unwrap() > 0));
+        assert_eq!(store.len_of_table("t1").unwrap(), 100);
+        assert_eq!(store.get_iter("t1").unwrap().count(), 100);
+
+        // the versions are given from one counter, so they are never reused across the shards
+        let versions: BTreeSet<u64> = (0..100)
+            .map(|i| store.version("t1", &format!("k{}", i)).unwrap().unwrap())
+            .collect();
+        assert_eq!(versions.len(), 100);
+    }
+}
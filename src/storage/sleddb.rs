@@ -1,86 +1,1701 @@
-use std::{path::Path, str::from_utf8};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    future::Future,
+    io::Write,
+    path::{Path, PathBuf},
+    str::from_utf8,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
 
+use dashmap::DashMap;
 use prost::Message;
-use sled::{Db, IVec};
+use rand::{seq::IteratorRandom, Rng};
+use sled::{
+    transaction::{ConflictableTransactionError, ConflictableTransactionResult, TransactionError},
+    Batch, Db, IVec, Transactional, Tree,
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::warn;
 
 use crate::{KvError, Kvpair, Value};
 
-use super::{Storage, StorageIter};
+use super::{
+    expiry::ExpiryIndex, now_ms, pair_size, write_dump, ScanOptions, Storage, StorageStats,
+    StorageStream, Update, UpdateFn, WriteOp,
+};
+
+/// The number of pairs buffered between the scanning thread and the stream
+const STREAM_CAPACITY: usize = 64;
+
+/// The number of random seeks tried per requested sample before falling back to a scan
+const SAMPLE_ATTEMPTS: usize = 4;
+
+/// The tree of the key deadlines, the full keys mapped to the big endian deadlines
+const DEADLINES_TREE: &str = "deadlines";
+
+/// The tree of the deadlines ordered by time, the big endian deadlines followed by the full keys
+/// mapped to nothing. The entries are not removed with their keys, only once they are due.
+const EXPIRY_TREE: &str = "expiry";
+
+/// The tree of the key versions, the full keys mapped to the big endian versions
+/// followed by the big endian times of the writes giving them
+const VERSIONS_TREE: &str = "versions";
+
+/// The tree of the metadata of the database itself
+const META_TREE: &str = "meta";
+
+/// The key of the version of the key encoding in the meta tree
+const KEY_FORMAT_KEY: &str = "key_format";
+
+/// The version of the key encoding: the full keys are `table:key` with `%` and `:` escaped in
+/// the table, see `encode_table`. The databases written before have no version.
+const KEY_FORMAT: u8 = 1;
+
+/// The flag byte prefixing a value compressed with zstd. A value encoded by prost never starts
+/// with it as the field numbers start from 1, so the plain values are stored without a flag.
+const ZSTD_FLAG: u8 = 1;
+
+/// The flag byte prefixing a value stored with its checksum, followed by the big endian CRC32 of
+/// the rest, which is the value stored without a checksum
+const CRC32_FLAG: u8 = 2;
+
+/// When the writes of SledDb are flushed to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Flush after every write, the most durable and the slowest
+    EveryWrite,
+    /// Flush in the background periodically, this is sled's default (500ms)
+    Interval(Duration),
+    /// Only flush when `SledDb::sync` is called or the db is dropped
+    Manual,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        Self::Interval(Duration::from_millis(500))
+    }
+}
+
+/// How SledDb compresses the values before persisting them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValueCompression {
+    /// Store the encoded values as is
+    #[default]
+    None,
+    /// Compress the values encoded in at least `min_size` bytes with zstd at `level`,
+    /// a value is stored compressed only if it gets smaller
+    Zstd { level: i32, min_size: usize },
+}
+
+/// How SledDb checks the values it reads back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValueChecksum {
+    /// Store the values without a checksum
+    #[default]
+    None,
+    /// Store the CRC32 of every value, a value not matching it is read as `KvError::Corruption`
+    Crc32,
+}
 
 /// SledDb is a storage engine that uses sled as the backend.
 #[derive(Debug, Clone)]
-pub struct SledDb(Db);
+pub struct SledDb {
+    db: Db,
+    /// Whether to flush after every write, see `FlushPolicy::EveryWrite`
+    flush_every_write: bool,
+    compression: ValueCompression,
+    checksum: ValueChecksum,
+    /// The size in bytes of the tables, computed by a scan on the first request
+    /// and then maintained on every write.
+    sizes: Arc<DashMap<String, usize>>,
+    /// The deadlines of the expiring keys
+    deadlines: Tree,
+    /// The persisted index of the deadlines by time
+    expiry: Tree,
+    /// The index of the expiry tree in memory, rebuilt on open, so the sweeps find the due keys
+    /// without reading the trees
+    timers: Arc<Mutex<ExpiryIndex>>,
+    /// The versions of the keys, given from the ids generated by sled
+    versions: Tree,
+    /// The last access time of the keys by their full keys, kept in memory only
+    /// so the reads do not write to disk, a reopened database starts with none
+    accessed: Arc<DashMap<String, u64>>,
+}
+
+/// The builder of SledDb
+#[derive(Debug, Clone)]
+pub struct SledDbBuilder {
+    path: PathBuf,
+    flush_policy: FlushPolicy,
+    compression: ValueCompression,
+    checksum: ValueChecksum,
+    /// The size in bytes of the page cache of sled, None for sled's default (1GB)
+    cache_capacity: Option<u64>,
+    /// The size in bytes of the segments of the log of sled, None for sled's default (512KB)
+    segment_size: Option<usize>,
+}
+
+impl SledDbBuilder {
+    /// Set the flush policy
+    pub fn flush_policy(mut self, policy: FlushPolicy) -> Self {
+        self.flush_policy = policy;
+        self
+    }
+
+    /// Set the compression of the values written from now on, the values are read back
+    /// whatever the compression they were written with
+    pub fn compression(mut self, compression: ValueCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Set the checksum of the values written from now on, the checksums of the values are
+    /// verified whatever the setting they were written with
+    pub fn checksum(mut self, checksum: ValueChecksum) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Set the size of the page cache of sled
+    pub fn cache_capacity(mut self, bytes: u64) -> Self {
+        self.cache_capacity = Some(bytes);
+        self
+    }
+
+    /// Set the size of the segments of the log of sled, a power of 2 between 256 bytes and 16MB.
+    /// It is fixed when the database is created, so it must be the same on every open.
+    pub fn segment_size(mut self, bytes: usize) -> Self {
+        self.segment_size = Some(bytes);
+        self
+    }
+
+    /// Open the sled database
+    pub fn open(self) -> Result<SledDb, KvError> {
+        let flush_every_ms = match self.flush_policy {
+            FlushPolicy::Interval(d) => Some(d.as_millis().max(1) as u64),
+            FlushPolicy::EveryWrite | FlushPolicy::Manual => None,
+        };
+        let mut config = sled::Config::new()
+            .path(self.path)
+            .flush_every_ms(flush_every_ms);
+        if let Some(bytes) = self.cache_capacity {
+            config = config.cache_capacity(bytes);
+        }
+        if let Some(bytes) = self.segment_size {
+            config = config.segment_size(bytes);
+        }
+        let db = config.open()?;
+        let deadlines = db.open_tree(DEADLINES_TREE)?;
+        let expiry = db.open_tree(EXPIRY_TREE)?;
+        let versions = db.open_tree(VERSIONS_TREE)?;
+        upgrade_keys(
+            &db,
+            &deadlines,
+            &versions,
+            &expiry,
+            &db.open_tree(META_TREE)?,
+        )?;
+        let timers = load_expiry(&deadlines, &expiry)?;
+
+        Ok(SledDb {
+            deadlines,
+            expiry,
+            timers: Arc::new(Mutex::new(timers)),
+            versions,
+            db,
+            flush_every_write: self.flush_policy == FlushPolicy::EveryWrite,
+            compression: self.compression,
+            checksum: self.checksum,
+            sizes: Arc::new(DashMap::new()),
+            accessed: Arc::new(DashMap::new()),
+        })
+    }
+}
 
 impl SledDb {
-    /// Create a new SledDb instance
+    /// Create a new SledDb instance with the default options, panic if the database fails to open
     pub fn new(path: impl AsRef<Path>) -> Self {
-        Self(sled::open(path).unwrap())
+        Self::open(path).unwrap()
+    }
+
+    /// Open a SledDb instance with the default options
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        Self::builder(path).open()
+    }
+
+    /// Create a builder to configure the SledDb instance
+    pub fn builder(path: impl AsRef<Path>) -> SledDbBuilder {
+        SledDbBuilder {
+            path: path.as_ref().to_path_buf(),
+            flush_policy: FlushPolicy::default(),
+            compression: ValueCompression::default(),
+            checksum: ValueChecksum::default(),
+            cache_capacity: None,
+            segment_size: None,
+        }
+    }
+
+    /// Flush all pending writes to disk, return the number of bytes flushed
+    pub fn sync(&self) -> Result<usize, KvError> {
+        Ok(self.db.flush()?)
+    }
+
+    /// Iterate over the pairs of all tables, yielding the table name with each pair
+    pub(crate) fn iter_all(&self) -> impl Iterator<Item = Result<(String, Kvpair), KvError>> {
+        self.db.iter().map(|item| {
+            let (k, v) = item?;
+            let (table, key) = split_full_key(&k)?;
+            let value = decode_value(&v)?;
+            Ok((table.into_owned(), Kvpair::new(key, value)))
+        })
+    }
+
+    /// Iterate over the deadlines of all expiring keys, yielding the table, the key and the deadline
+    pub(crate) fn iter_deadlines(
+        &self,
+    ) -> impl Iterator<Item = Result<(String, String, u64), KvError>> {
+        self.deadlines.iter().map(|item| {
+            let (k, v) = item?;
+            let (table, key) = split_full_key(&k)?;
+            Ok((table.into_owned(), key.to_string(), decode_deadline(&v)?))
+        })
+    }
+
+    fn deadline_of(&self, full_key: &str) -> Result<Option<u64>, KvError> {
+        self.deadlines
+            .get(full_key)?
+            .map(|v| decode_deadline(&v))
+            .transpose()
+    }
+
+    fn is_expired(&self, full_key: &str) -> Result<bool, KvError> {
+        Ok(matches!(self.deadline_of(full_key)?, Some(deadline) if deadline <= now_ms()))
+    }
+
+    fn record_access(&self, full_key: String) {
+        self.accessed.insert(full_key, now_ms());
+    }
+
+    fn forget_access(&self, full_key: &str) {
+        self.accessed.remove(full_key);
+    }
+
+    fn timers(&self) -> MutexGuard<'_, ExpiryIndex> {
+        self.timers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Remove a due entry of the expiry index, and its pair if the key still has the deadline
+    fn purge_due(&self, deadline: u64, name: &str) -> Result<Option<(String, String)>, KvError> {
+        let index_key = expiry_key(deadline, name);
+        let result = (&*self.db, &self.deadlines, &self.expiry, &self.versions).transaction(
+            |(db, deadlines, expiry, versions)| -> ConflictableTransactionResult<Option<IVec>, KvError> {
+                expiry.remove(index_key.as_slice())?;
+                match deadlines.get(name)? {
+                    Some(current) if *current == deadline.to_be_bytes() => {
+                        deadlines.remove(name)?;
+                        versions.remove(name)?;
+                        Ok(db.remove(name)?)
+                    }
+                    _ => Ok(None),
+                }
+            },
+        );
+        let removed = result.map_err(|e| match e {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => e.into(),
+        })?;
+
+        let Some(value) = removed else {
+            return Ok(None);
+        };
+        let (table, key) = split_full_key(name.as_bytes())?;
+        self.forget_access(name);
+        self.adjust_size(&table, 0, key.len() + value_len(&value)?);
+        Ok(Some((table.into_owned(), key.to_string())))
+    }
+
+    /// Copy a key with its deadline in one transaction over the data and the deadlines,
+    /// and delete the source to move it
+    fn transfer(
+        &self,
+        from: &str,
+        to: &str,
+        key: &str,
+        replace: bool,
+        remove: bool,
+    ) -> Result<bool, KvError> {
+        if from == to {
+            return Err(KvError::InvalidCommand(format!(
+                "cannot copy {} onto itself",
+                key
+            )));
+        }
+        let (src, dst) = (Self::get_full_key(from, key), Self::get_full_key(to, key));
+        let abort = ConflictableTransactionError::Abort;
+        let now = now_ms();
+        let result = (&*self.db, &self.deadlines, &self.expiry, &self.versions).transaction(
+            |(db, deadlines, expiry, versions)| -> ConflictableTransactionResult<_, KvError> {
+                let deadline_of = |name: &str| -> ConflictableTransactionResult<_, KvError> {
+                    match deadlines.get(name)? {
+                        Some(v) => Ok(Some(decode_deadline(&v).map_err(abort)?)),
+                        None => Ok(None),
+                    }
+                };
+                let deadline = deadline_of(&src)?;
+                let value = match db.get(&src)? {
+                    Some(v) if !matches!(deadline, Some(d) if d <= now) => v,
+                    _ => return Ok(None),
+                };
+                let old = db.get(&dst)?;
+                let exists = old.is_some() && !matches!(deadline_of(&dst)?, Some(d) if d <= now);
+                if exists && !replace {
+                    return Ok(None);
+                }
+
+                db.insert(dst.as_bytes(), value.clone())?;
+                match deadline {
+                    Some(deadline) => {
+                        expiry.insert(expiry_key(deadline, &dst), &[])?;
+                        deadlines.insert(dst.as_bytes(), &deadline.to_be_bytes())?
+                    }
+                    None => deadlines.remove(dst.as_bytes())?,
+                };
+                let version = versions.generate_id()? + 1;
+                versions.insert(dst.as_bytes(), &encode_version(version))?;
+                if remove {
+                    db.remove(src.as_bytes())?;
+                    deadlines.remove(src.as_bytes())?;
+                    versions.remove(src.as_bytes())?;
+                }
+                let len = value_len(&value).map_err(abort)?;
+                let old_len = old.map(|v| value_len(&v)).transpose().map_err(abort)?;
+                Ok(Some((len, old_len, deadline)))
+            },
+        );
+        let copied = result.map_err(|e| match e {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => e.into(),
+        })?;
+        let Some((len, old_len, deadline)) = copied else {
+            return Ok(false);
+        };
+        if let Some(deadline) = deadline {
+            self.timers().insert(deadline, dst.clone());
+        }
+        self.flush_if_needed()?;
+        self.record_access(dst);
+        if remove {
+            self.forget_access(&src);
+        }
+
+        let removed = old_len.map(|v| key.len() + v).unwrap_or(0);
+        self.adjust_size(to, key.len() + len, removed);
+        if remove {
+            self.adjust_size(from, 0, key.len() + len);
+        }
+        Ok(true)
+    }
+
+    /// Encode a value to be stored, compressed then checksummed as configured
+    fn encode_value(&self, value: &Value) -> Result<Vec<u8>, KvError> {
+        let data = self.compress(value.encode_to_vec())?;
+        Ok(match self.checksum {
+            ValueChecksum::None => data,
+            ValueChecksum::Crc32 => {
+                let mut sealed = Vec::with_capacity(5 + data.len());
+                sealed.push(CRC32_FLAG);
+                sealed.extend_from_slice(&crc32fast::hash(&data).to_be_bytes());
+                sealed.extend(data);
+                sealed
+            }
+        })
+    }
+
+    fn compress(&self, data: Vec<u8>) -> Result<Vec<u8>, KvError> {
+        let ValueCompression::Zstd { level, min_size } = self.compression else {
+            return Ok(data);
+        };
+        if data.len() < min_size {
+            return Ok(data);
+        }
+        let mut compressed = vec![ZSTD_FLAG];
+        compressed.extend(zstd::bulk::compress(&data, level)?);
+        Ok(match compressed.len() < data.len() {
+            true => compressed,
+            false => data,
+        })
+    }
+
+    /// Flush after a write if the policy requires it
+    fn flush_if_needed(&self) -> Result<(), KvError> {
+        if self.flush_every_write {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Account the size change of a table after a write, if the table size is tracked
+    fn adjust_size(&self, table: &str, added: usize, removed: usize) {
+        if let Some(mut size) = self.sizes.get_mut(table) {
+            *size = (*size + added).saturating_sub(removed);
+        }
     }
 
     /// Get the full key from table and key
     fn get_full_key(table: &str, key: &str) -> String {
-        format!("{table}:{key}")
+        format!("{}:{key}", encode_table(table))
     }
 
     /// Get the prefix of the table, because sled does not support table, but support scan_prefix.
     fn get_table_prefix(table: &str) -> String {
-        format!("{table}:")
+        format!("{}:", encode_table(table))
+    }
+
+    /// Get the smallest key greater than every key of the table
+    fn get_table_end(table: &str) -> String {
+        format!("{};", encode_table(table))
     }
 }
 
 impl Storage for SledDb {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
         let name = Self::get_full_key(table, key);
-        let result = self.0.get(name.as_bytes())?.map(|v| v.as_ref().try_into());
-        result.transpose()
+        if self.is_expired(&name)? {
+            return Ok(None);
+        }
+        let result = self.db.get(name.as_bytes())?.map(|v| decode_value(&v));
+        let value = result.transpose()?;
+        if value.is_some() {
+            self.record_access(name);
+        }
+        Ok(value)
     }
 
     fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
         let name = Self::get_full_key(table, &key);
-        let data = value.encode_to_vec();
-        let result = self.0.insert(name, data)?.map(|v| v.as_ref().try_into());
-        result.transpose()
+        let data = self.encode_value(&value)?;
+        // the deadline and the version change with the value, so `set_if_version` never
+        // sees the new value under the old version
+        let result = (&*self.db, &self.deadlines, &self.versions).transaction(
+            |(db, deadlines, versions)| -> ConflictableTransactionResult<_, KvError> {
+                let old = db.insert(name.as_bytes(), data.as_slice())?;
+                deadlines.remove(name.as_bytes())?;
+                let version = versions.generate_id()? + 1;
+                versions.insert(name.as_bytes(), &encode_version(version))?;
+                Ok(old)
+            },
+        );
+        let old = result.map_err(|e| match e {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => e.into(),
+        })?;
+        self.flush_if_needed()?;
+        self.record_access(name);
+        let old = old.map(|v| decode_value(&v)).transpose()?;
+        let removed = old.as_ref().map(|v| pair_size(&key, v)).unwrap_or(0);
+        self.adjust_size(table, pair_size(&key, &value), removed);
+        Ok(old)
     }
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
         let name = Self::get_full_key(table, key);
-        Ok(self.0.contains_key(name)?)
+        Ok(!self.is_expired(&name)? && self.db.contains_key(name)?)
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
         let name = Self::get_full_key(table, key);
-        let result = self.0.remove(name)?.map(|v| v.as_ref().try_into());
-        result.transpose()
+        let result = self.db.remove(name.as_bytes())?.map(|v| decode_value(&v));
+        self.deadlines.remove(name.as_bytes())?;
+        self.versions.remove(name.as_bytes())?;
+        self.flush_if_needed()?;
+        self.forget_access(&name);
+        let old = result.transpose()?;
+        if let Some(v) = old.as_ref() {
+            self.adjust_size(table, 0, pair_size(key, v));
+        }
+        Ok(old)
     }
 
+    /// The pairs, their deadlines and their versions change in one transaction over the three trees
+    fn transaction(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KvError> {
+        // encode before the transaction, sled may run its closure more than once
+        let writes = ops
+            .iter()
+            .map(|op| {
+                let (WriteOp::Set { table, key, .. } | WriteOp::Del { table, key }) = op;
+                let data = match op {
+                    WriteOp::Set { value, .. } => Some(self.encode_value(value)?),
+                    WriteOp::Del { .. } => None,
+                };
+                Ok((Self::get_full_key(table, key), data))
+            })
+            .collect::<Result<Vec<_>, KvError>>()?;
+        let result = (&*self.db, &self.deadlines, &self.versions).transaction(
+            |(db, deadlines, versions)| -> ConflictableTransactionResult<_, KvError> {
+                let mut olds = Vec::with_capacity(writes.len());
+                for (name, data) in &writes {
+                    let old = match data {
+                        Some(data) => {
+                            let version = versions.generate_id()? + 1;
+                            versions.insert(name.as_bytes(), &encode_version(version))?;
+                            db.insert(name.as_bytes(), data.as_slice())?
+                        }
+                        None => {
+                            versions.remove(name.as_bytes())?;
+                            db.remove(name.as_bytes())?
+                        }
+                    };
+                    deadlines.remove(name.as_bytes())?;
+                    let old = old
+                        .map(|v| decode_value(&v))
+                        .transpose()
+                        .map_err(ConflictableTransactionError::Abort)?;
+                    olds.push(old);
+                }
+                Ok(olds)
+            },
+        );
+        let olds = result.map_err(|e| match e {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => e.into(),
+        })?;
+        for op in &ops {
+            let (WriteOp::Set { table, key, .. } | WriteOp::Del { table, key }) = op;
+            let name = Self::get_full_key(table, key);
+            match op {
+                WriteOp::Set { .. } => self.record_access(name),
+                WriteOp::Del { .. } => self.forget_access(&name),
+            }
+        }
+        self.flush_if_needed()?;
+
+        for (op, old) in ops.iter().zip(&olds) {
+            let removed = |key: &str| old.as_ref().map(|v| pair_size(key, v)).unwrap_or(0);
+            match op {
+                WriteOp::Set { table, key, value } => {
+                    self.adjust_size(table, pair_size(key, value), removed(key))
+                }
+                WriteOp::Del { table, key } => self.adjust_size(table, 0, removed(key)),
+            }
+        }
+        Ok(olds)
+    }
+
+    /// Write the pairs in one batch per tree, much faster than a transaction. The batch of the pairs
+    /// is atomic, but the old values are read before it, so they may miss a concurrent write.
+    fn set_batch(&self, table: &str, pairs: Vec<Kvpair>) -> Result<Vec<Option<Value>>, KvError> {
+        let (mut batch, mut deadlines, mut versions) =
+            (Batch::default(), Batch::default(), Batch::default());
+        // a key set twice by the batch has the first value as the old value of the second
+        let mut written: HashMap<String, Value> = HashMap::new();
+        let mut olds = Vec::with_capacity(pairs.len());
+        for pair in &pairs {
+            let name = Self::get_full_key(table, &pair.key);
+            let value = pair.value.clone().unwrap_or_default();
+            let old = match written.insert(pair.key.clone(), value.clone()) {
+                Some(old) => Some(old),
+                None => self.db.get(&name)?.map(|v| decode_value(&v)).transpose()?,
+            };
+            olds.push(old);
+            batch.insert(name.as_bytes(), self.encode_value(&value)?);
+            deadlines.remove(name.as_bytes());
+            let version = self.db.generate_id()? + 1;
+            versions.insert(name.as_bytes(), &encode_version(version));
+        }
+        self.db.apply_batch(batch)?;
+        self.deadlines.apply_batch(deadlines)?;
+        self.versions.apply_batch(versions)?;
+        self.flush_if_needed()?;
+
+        for (pair, old) in pairs.iter().zip(&olds) {
+            self.record_access(Self::get_full_key(table, &pair.key));
+            let added = pair_size(&pair.key, pair.value.as_ref().unwrap_or(&Value::default()));
+            let removed = old.as_ref().map(|v| pair_size(&pair.key, v)).unwrap_or(0);
+            self.adjust_size(table, added, removed);
+        }
+        Ok(olds)
+    }
+
+    /// Remove the keys in one batch per tree, with the same caveat as `set_batch`
+    fn del_batch(&self, table: &str, keys: Vec<String>) -> Result<Vec<Option<Value>>, KvError> {
+        let (mut batch, mut deadlines, mut versions) =
+            (Batch::default(), Batch::default(), Batch::default());
+        let mut removed: HashSet<&str> = HashSet::new();
+        let mut olds = Vec::with_capacity(keys.len());
+        for key in &keys {
+            let name = Self::get_full_key(table, key);
+            let old = match removed.insert(key) {
+                true => self.db.get(&name)?.map(|v| decode_value(&v)).transpose()?,
+                false => None,
+            };
+            olds.push(old);
+            batch.remove(name.as_bytes());
+            deadlines.remove(name.as_bytes());
+            versions.remove(name.as_bytes());
+        }
+        self.db.apply_batch(batch)?;
+        self.deadlines.apply_batch(deadlines)?;
+        self.versions.apply_batch(versions)?;
+        self.flush_if_needed()?;
+
+        for (key, old) in keys.iter().zip(&olds) {
+            self.forget_access(&Self::get_full_key(table, key));
+            if let Some(v) = old {
+                self.adjust_size(table, 0, pair_size(key, v));
+            }
+        }
+        Ok(olds)
+    }
+
+    /// sled keeps the keys sorted, so the pairs are already in key order
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
         let prefix = Self::get_table_prefix(table);
-        let result = self.0.scan_prefix(prefix).map(|v| v.into()).collect();
-        Ok(result)
+        let expired = expired_keys(&self.deadlines, &prefix)?;
+        live_pairs(self.db.scan_prefix(prefix), expired)
+            .map(decode_pair)
+            .collect()
     }
 
     fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
         let prefix = Self::get_table_prefix(table);
-        let iter = StorageIter::new(self.0.scan_prefix(prefix));
-        Ok(Box::new(iter))
+        let expired = expired_keys(&self.deadlines, &prefix)?;
+        let iter = live_pairs(self.db.scan_prefix(prefix), expired);
+        Ok(Box::new(decode_pairs(iter)))
     }
-}
 
-impl From<Result<(IVec, IVec), sled::Error>> for Kvpair {
-    fn from(v: Result<(IVec, IVec), sled::Error>) -> Self {
-        match v {
-            Ok((k, v)) => match v.as_ref().try_into() {
-                Ok(v) => Kvpair::new(ivec_to_key(k.as_ref()), v),
-                Err(_) => Kvpair::default(),
+    /// The table is scanned on a blocking thread, so it must be called in a tokio runtime
+    fn get_stream(&self, table: &str) -> Result<StorageStream, KvError> {
+        let (tx, rx) = mpsc::channel(STREAM_CAPACITY);
+        let db = self.db.clone();
+        let prefix = Self::get_table_prefix(table);
+        let expired = expired_keys(&self.deadlines, &prefix)?;
+
+        tokio::task::spawn_blocking(move || {
+            for pair in decode_pairs(live_pairs(db.scan_prefix(prefix), expired)) {
+                // the receiver is gone, stop scanning
+                if tx.blocking_send(pair).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Box::pin(ReceiverStream::new(rx)))
+    }
+
+    /// Sample by seeking to random keys between the first and the last key of the table,
+    /// it only scans the table when the seeks can't find enough distinct keys (e.g. small tables).
+    /// The expired keys not purged yet are skipped.
+    fn sample(&self, table: &str, count: usize) -> Result<Vec<Kvpair>, KvError> {
+        let prefix = Self::get_table_prefix(table);
+        let end = Self::get_table_end(table);
+        let expired = expired_keys(&self.deadlines, &prefix)?;
+        let (first, last) = match (
+            self.db.range(prefix.as_str()..end.as_str()).next(),
+            self.db.range(prefix.as_str()..end.as_str()).next_back(),
+        ) {
+            (Some(first), Some(last)) => (first?.0, last?.0),
+            _ => return Ok(vec![]),
+        };
+
+        let mut rng = rand::thread_rng();
+        let mut picked = std::collections::BTreeMap::new();
+        // a run of seeks finding no new key means the count is close to the size of the table
+        let mut misses = 0;
+        for _ in 0..count.saturating_mul(SAMPLE_ATTEMPTS) {
+            if picked.len() == count || misses == SAMPLE_ATTEMPTS {
+                break;
+            }
+            let seek = random_key_between(&first, &last, &mut rng);
+            if let Some(item) = self.db.range(seek..end.as_bytes().to_vec()).next() {
+                let (k, v) = item?;
+                if expired.contains(&k) {
+                    misses += 1;
+                    continue;
+                }
+                match picked.insert(k, v) {
+                    Some(_) => misses += 1,
+                    None => misses = 0,
+                }
+            }
+        }
+
+        if picked.len() < count {
+            let pairs: Vec<_> = live_pairs(self.db.scan_prefix(prefix), expired)
+                .map(decode_pair)
+                .collect::<Result<_, _>>()?;
+            let count = count.min(pairs.len());
+            return Ok(pairs.into_iter().choose_multiple(&mut rng, count));
+        }
+        picked.into_iter().map(|kv| decode_pair(Ok(kv))).collect()
+    }
+
+    /// A range query on the live tree, so a cursor page costs the page size, not the table size
+    fn scan(
+        &self,
+        table: &str,
+        opts: ScanOptions,
+    ) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        let prefix = Self::get_table_prefix(table);
+        let end = Self::get_table_end(table);
+        let start = opts.start.map(|key| Self::get_full_key(table, &key));
+        let expired = expired_keys(&self.deadlines, &prefix)?;
+
+        let iter = match (start, opts.reverse) {
+            (None, false) => self.db.scan_prefix(prefix),
+            (None, true) => self.db.range(prefix..end),
+            (Some(start), false) => self.db.range(start..end),
+            (Some(start), true) => self.db.range(prefix..=start),
+        };
+        let iter: Box<dyn Iterator<Item = _>> = match opts.reverse {
+            true => Box::new(iter.rev()),
+            false => Box::new(iter),
+        };
+
+        let iter = live_pairs(iter, expired).take(opts.limit.unwrap_or(usize::MAX));
+        Ok(Box::new(decode_pairs(iter)))
+    }
+
+    /// A range query on the live tree, so it costs the pairs in the range, not the table size
+    fn get_range(
+        &self,
+        table: &str,
+        start: Option<&str>,
+        end: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let from = match start {
+            Some(key) => Self::get_full_key(table, key),
+            None => Self::get_table_prefix(table),
+        };
+        let to = match end {
+            Some(key) => Self::get_full_key(table, key),
+            None => Self::get_table_end(table),
+        };
+        if from >= to {
+            return Ok(vec![]);
+        }
+        let expired = expired_keys(&self.deadlines, &Self::get_table_prefix(table))?;
+        let iter = live_pairs(self.db.range(from..to), expired);
+        iter.take(limit.unwrap_or(usize::MAX))
+            .map(decode_pair)
+            .collect()
+    }
+
+    /// Count the keys of the prefix without decoding the values, minus the expired keys not purged yet
+    fn len_of_table(&self, table: &str) -> Result<usize, KvError> {
+        let prefix = Self::get_table_prefix(table);
+        let mut len: usize = 0;
+        for key in self.db.scan_prefix(&prefix).keys() {
+            key?;
+            len += 1;
+        }
+        let expired = expired_keys(&self.deadlines, &prefix)?;
+        Ok(len.saturating_sub(expired.len()))
+    }
+
+    fn copy_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        self.transfer(from, to, key, replace, false)
+    }
+
+    fn move_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        self.transfer(from, to, key, replace, true)
+    }
+
+    /// Remove the keys of the prefix and their deadlines in one batch per tree.
+    /// The keys written during the clear may survive it.
+    fn clear_table(&self, table: &str) -> Result<usize, KvError> {
+        let prefix = Self::get_table_prefix(table);
+        let mut batch = Batch::default();
+        let mut removed = 0;
+        for key in self.db.scan_prefix(&prefix).keys() {
+            batch.remove(key?);
+            removed += 1;
+        }
+        self.db.apply_batch(batch)?;
+
+        let mut batch = Batch::default();
+        for key in self.deadlines.scan_prefix(&prefix).keys() {
+            batch.remove(key?);
+        }
+        self.deadlines.apply_batch(batch)?;
+
+        let mut batch = Batch::default();
+        for key in self.versions.scan_prefix(&prefix).keys() {
+            batch.remove(key?);
+        }
+        self.versions.apply_batch(batch)?;
+        self.accessed.retain(|name, _| !name.starts_with(&prefix));
+        // the size is scanned again on the next read
+        self.sizes.remove(table);
+        self.flush_if_needed()?;
+        Ok(removed)
+    }
+
+    fn size_of_table(&self, table: &str) -> Result<usize, KvError> {
+        if let Some(size) = self.sizes.get(table) {
+            return Ok(*size);
+        }
+
+        // scan the table only once, the following writes keep the size up to date
+        let prefix = Self::get_table_prefix(table);
+        let mut size = 0;
+        for item in self.db.scan_prefix(&prefix) {
+            let (k, v) = item?;
+            size += k.len() - prefix.len() + value_len(&v)?;
+        }
+        Ok(*self.sizes.entry(table.into()).or_insert(size))
+    }
+
+    /// The space sled takes on disk, it includes the metadata and the stale data
+    /// not yet compacted, and misses the writes not flushed yet,
+    /// so it is only an estimate of the data size.
+    fn total_size(&self) -> Result<usize, KvError> {
+        Ok(self.db.size_on_disk()? as usize)
+    }
+
+    /// Counted in one pass over the pairs, reading the sizes of the values from their headers
+    /// rather than decoding them. The sizes count the expired keys not purged yet, like
+    /// `size_of_table`.
+    fn stats(&self) -> Result<StorageStats, KvError> {
+        let mut stats = StorageStats::default();
+        for item in self.db.iter() {
+            let (k, v) = item?;
+            let (table, key) = split_full_key(&k)?;
+            stats.add_table(table, 1, key.len() + value_len(&v)?);
+        }
+        let now = now_ms();
+        for item in self.iter_deadlines() {
+            let (table, _, deadline) = item?;
+            if deadline <= now {
+                if let Some(table) = stats.tables.get_mut(&table) {
+                    table.keys = table.keys.saturating_sub(1);
+                }
+            }
+        }
+        stats.tables.retain(|_, table| table.keys > 0);
+        stats.set_info("size_on_disk", self.db.size_on_disk()?);
+        stats.set_info("expiring_keys", self.deadlines.len() as u64);
+        stats.set_info("expiry_timers", self.timers().len() as u64);
+        Ok(stats)
+    }
+
+    fn flush(&self) -> Result<(), KvError> {
+        self.sync().map(|_| ())
+    }
+
+    fn flush_async(&self) -> impl Future<Output = Result<(), KvError>> + Send {
+        let db = self.db.clone();
+        async move {
+            db.flush_async().await?;
+            Ok(())
+        }
+    }
+
+    /// sled has no point-in-time reads, so the writes made during the scan may or may not be
+    /// in the snapshot, stop the writers for a consistent one
+    fn snapshot(&self, writer: &mut dyn Write) -> Result<usize, KvError> {
+        let pairs = self.iter_all().filter(|pair| match pair {
+            Ok((table, pair)) => !matches!(
+                self.is_expired(&Self::get_full_key(table, &pair.key)),
+                Ok(true)
+            ),
+            Err(_) => true,
+        });
+        write_dump(writer, pairs)
+    }
+
+    /// Update in a transaction over the pairs, the deadlines with their index and the versions,
+    /// retried on conflicts
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: &mut UpdateFn,
+    ) -> Result<(Option<Value>, Option<Value>), KvError> {
+        let name = Self::get_full_key(table, key);
+        // sled may rerun the closure of the transaction, which must be Fn
+        let f = RefCell::new(f);
+        let abort = ConflictableTransactionError::Abort;
+        let result = (&*self.db, &self.deadlines, &self.versions, &self.expiry).transaction(
+            |(db, deadlines, versions, expiry)| -> ConflictableTransactionResult<_, KvError> {
+                let raw = db.get(&name)?;
+                let expired = match deadlines.get(&name)? {
+                    Some(deadline) => decode_deadline(&deadline).map_err(abort)? <= now_ms(),
+                    None => false,
+                };
+                let old = match (&raw, expired) {
+                    (Some(v), false) => Some(decode_value(v).map_err(abort)?),
+                    _ => None,
+                };
+
+                let update = (f.borrow_mut())(old.as_ref()).map_err(abort)?;
+                let (new, persist, deadline) = match update {
+                    Update::Keep => return Ok((old.clone(), old, None, None)),
+                    Update::Set(value) => (Some(value), false, None),
+                    Update::Overwrite(value) => (Some(value), true, None),
+                    Update::Expiring(value, deadline) => (Some(value), true, Some(deadline)),
+                    Update::Delete => (None, false, None),
+                };
+                match &new {
+                    Some(value) => {
+                        let version = versions.generate_id()? + 1;
+                        versions.insert(name.as_bytes(), &encode_version(version))?;
+                        let data = self.encode_value(value).map_err(abort)?;
+                        db.insert(name.as_bytes(), data)?
+                    }
+                    None => {
+                        versions.remove(name.as_bytes())?;
+                        db.remove(name.as_bytes())?
+                    }
+                };
+                // the deadline of an expired key does not apply to the new value
+                match deadline {
+                    Some(deadline) => {
+                        expiry.insert(expiry_key(deadline, &name), &[])?;
+                        deadlines.insert(name.as_bytes(), &deadline.to_be_bytes())?;
+                    }
+                    None if persist || old.is_none() || new.is_none() => {
+                        deadlines.remove(name.as_bytes())?;
+                    }
+                    None => {}
+                }
+                let removed = match raw {
+                    Some(v) => key.len() + value_len(&v).map_err(abort)?,
+                    None => 0,
+                };
+                Ok((old, new, Some(removed), deadline))
+            },
+        );
+        let (old, new, removed, deadline) = result.map_err(|e| match e {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => e.into(),
+        })?;
+        // the key is kept as is, nothing is written
+        let Some(removed) = removed else {
+            return Ok((old, new));
+        };
+        if let Some(deadline) = deadline {
+            self.timers().insert(deadline, name.clone());
+        }
+        self.flush_if_needed()?;
+        match new {
+            Some(_) => self.record_access(name),
+            None => self.forget_access(&name),
+        }
+
+        let added = new.as_ref().map(|v| pair_size(key, v)).unwrap_or(0);
+        self.adjust_size(table, added, removed);
+        Ok((old, new))
+    }
+
+    fn expire(&self, table: &str, key: &str, deadline: Option<u64>) -> Result<bool, KvError> {
+        if !self.contains(table, key)? {
+            return Ok(false);
+        }
+        let name = Self::get_full_key(table, key);
+        match deadline {
+            // index the deadline first, a crash in between leaves a stale entry at worst
+            Some(deadline) => {
+                self.expiry.insert(expiry_key(deadline, &name), &[])?;
+                self.deadlines
+                    .insert(name.as_bytes(), &deadline.to_be_bytes())?;
+                self.timers().insert(deadline, name);
+            }
+            None => {
+                self.deadlines.remove(name)?;
+            }
+        };
+        self.flush_if_needed()?;
+        Ok(true)
+    }
+
+    fn deadline(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        match self.contains(table, key)? {
+            true => self.deadline_of(&Self::get_full_key(table, key)),
+            false => Ok(None),
+        }
+    }
+
+    fn touch(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        if !self.contains(table, key)? {
+            return Ok(false);
+        }
+        self.record_access(Self::get_full_key(table, key));
+        Ok(true)
+    }
+
+    fn last_access(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        if !self.contains(table, key)? {
+            return Ok(None);
+        }
+        let name = Self::get_full_key(table, key);
+        Ok(self.accessed.get(&name).map(|ms| *ms))
+    }
+
+    fn version(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        if !self.contains(table, key)? {
+            return Ok(None);
+        }
+        let name = Self::get_full_key(table, key);
+        self.versions
+            .get(name)?
+            .map(|v| decode_version(&v).map(|(version, _)| version))
+            .transpose()
+    }
+
+    fn modified(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        if !self.contains(table, key)? {
+            return Ok(None);
+        }
+        let name = Self::get_full_key(table, key);
+        self.versions
+            .get(name)?
+            .map(|v| decode_version(&v).map(|(_, modified)| modified))
+            .transpose()
+    }
+
+    /// Read the pair, its deadline and its version in one transaction
+    fn get_versioned(&self, table: &str, key: &str) -> Result<Option<(Value, u64)>, KvError> {
+        let name = Self::get_full_key(table, key);
+        let abort = ConflictableTransactionError::Abort;
+        let now = now_ms();
+        let result = (&*self.db, &self.deadlines, &self.versions).transaction(
+            |(db, deadlines, versions)| -> ConflictableTransactionResult<_, KvError> {
+                if let Some(deadline) = deadlines.get(&name)? {
+                    if decode_deadline(&deadline).map_err(abort)? <= now {
+                        return Ok(None);
+                    }
+                }
+                let Some(raw) = db.get(&name)? else {
+                    return Ok(None);
+                };
+                let value = decode_value(&raw).map_err(abort)?;
+                let version = match versions.get(&name)? {
+                    Some(v) => decode_version(&v).map_err(abort)?.0,
+                    None => 0,
+                };
+                Ok(Some((value, version)))
             },
-            _ => Kvpair::default(),
+        );
+        let versioned = result.map_err(|e| match e {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => e.into(),
+        })?;
+        if versioned.is_some() {
+            self.record_access(name);
         }
+        Ok(versioned)
+    }
+
+    /// Check the version and write in one transaction over the pairs, the deadlines and the versions
+    fn set_if_version(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        version: u64,
+    ) -> Result<Option<(Value, u64)>, KvError> {
+        let name = Self::get_full_key(table, &key);
+        let data = self.encode_value(&value)?;
+        let abort = ConflictableTransactionError::Abort;
+        let now = now_ms();
+        let result = (&*self.db, &self.deadlines, &self.versions).transaction(
+            |(db, deadlines, versions)| -> ConflictableTransactionResult<_, KvError> {
+                if let Some(deadline) = deadlines.get(&name)? {
+                    if decode_deadline(&deadline).map_err(abort)? <= now {
+                        return Ok(None);
+                    }
+                }
+                let current = match versions.get(&name)? {
+                    Some(v) => decode_version(&v).map_err(abort)?.0,
+                    None => return Ok(None),
+                };
+                if current != version {
+                    return Ok(None);
+                }
+                let Some(old) = db.get(&name)? else {
+                    return Ok(None);
+                };
+                db.insert(name.as_bytes(), data.as_slice())?;
+                deadlines.remove(name.as_bytes())?;
+                let version = versions.generate_id()? + 1;
+                versions.insert(name.as_bytes(), &encode_version(version))?;
+                let old = decode_value(&old).map_err(abort)?;
+                Ok(Some((old, version)))
+            },
+        );
+        let written = result.map_err(|e| match e {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => e.into(),
+        })?;
+        let Some((old, version)) = written else {
+            return Ok(None);
+        };
+        self.flush_if_needed()?;
+        self.record_access(name);
+        self.adjust_size(table, pair_size(&key, &value), pair_size(&key, &old));
+        Ok(Some((old, version)))
+    }
+
+    /// Pop the due entries of the expiry index, a pair is removed with its deadline in
+    /// a transaction unless the key was deleted or given another deadline since
+    fn purge_expired(&self, now: u64) -> Result<Vec<(String, String)>, KvError> {
+        let due = self.timers().pop_due(now);
+        let mut purged = Vec::new();
+        let mut due = due.into_iter();
+        while let Some((deadline, name)) = due.next() {
+            match self.purge_due(deadline, &name) {
+                Ok(Some((table, key))) => purged.push((table, key)),
+                Ok(None) => {}
+                Err(e) => {
+                    // keep the entries not purged yet for the next sweep
+                    self.timers()
+                        .extend(std::iter::once((deadline, name)).chain(due));
+                    return Err(e);
+                }
+            }
+        }
+        self.flush_if_needed()?;
+        Ok(purged)
     }
 }
 
-fn ivec_to_key(ivec: &[u8]) -> &str {
-    let s = from_utf8(ivec).unwrap();
-    let mut iter = s.split(':');
-    iter.next();
-    iter.next().unwrap()
+/// Load the expiry index, indexing the deadlines first if the database was written without it
+fn load_expiry(deadlines: &Tree, expiry: &Tree) -> Result<ExpiryIndex, KvError> {
+    if expiry.is_empty() && !deadlines.is_empty() {
+        let mut batch = Batch::default();
+        for item in deadlines.iter() {
+            let (name, deadline) = item?;
+            batch.insert(
+                expiry_key(decode_deadline(&deadline)?, from_utf8_key(&name)?),
+                &[],
+            );
+        }
+        expiry.apply_batch(batch)?;
+    }
+
+    let mut index = ExpiryIndex::default();
+    for key in expiry.iter().keys() {
+        let key = key?;
+        if key.len() < 8 {
+            return Err(KvError::Internal(format!("invalid expiry key: {:?}", key)));
+        }
+        let (deadline, name) = key.split_at(8);
+        index.insert(decode_deadline(deadline)?, from_utf8_key(name)?.to_string());
+    }
+    Ok(index)
+}
+
+/// The key of a deadline in the expiry tree, ordered by the deadline first
+fn expiry_key(deadline: u64, full_key: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(8 + full_key.len());
+    key.extend_from_slice(&deadline.to_be_bytes());
+    key.extend_from_slice(full_key.as_bytes());
+    key
+}
+
+fn from_utf8_key(data: &[u8]) -> Result<&str, KvError> {
+    from_utf8(data).map_err(|e| KvError::Internal(e.to_string()))
+}
+
+/// Split a full key into the table and the key
+fn split_full_key(full_key: &[u8]) -> Result<(Cow<'_, str>, &str), KvError> {
+    let full_key = from_utf8_key(full_key)?;
+    let (table, key) = full_key
+        .split_once(':')
+        .ok_or_else(|| KvError::Internal(format!("invalid key: {}", full_key)))?;
+    Ok((decode_table(table)?, key))
+}
+
+/// Escape `%` and `:` in a table name, so a full key splits at its first `:` and the prefix of
+/// a table never matches the keys of another table. The keys are stored as is.
+fn encode_table(table: &str) -> Cow<'_, str> {
+    match table.contains(['%', ':']) {
+        true => Cow::Owned(table.replace('%', "%25").replace(':', "%3A")),
+        false => Cow::Borrowed(table),
+    }
+}
+
+fn decode_table(table: &str) -> Result<Cow<'_, str>, KvError> {
+    if !table.contains('%') {
+        return Ok(Cow::Borrowed(table));
+    }
+    let mut decoded = String::with_capacity(table.len());
+    let mut rest = table;
+    while let Some(i) = rest.find('%') {
+        decoded.push_str(&rest[..i]);
+        match rest.get(i + 1..i + 3) {
+            Some("25") => decoded.push('%'),
+            Some("3A") => decoded.push(':'),
+            _ => {
+                return Err(KvError::Internal(format!(
+                    "invalid table in key: {}",
+                    table
+                )))
+            }
+        }
+        rest = &rest[i + 3..];
+    }
+    decoded.push_str(rest);
+    Ok(Cow::Owned(decoded))
+}
+
+/// The full key of a key written before the tables were escaped, None if it is unchanged.
+/// The tables were stored as is and ended at the first `:`, so only a `%` in them changes.
+fn upgraded_key(full_key: &[u8]) -> Option<String> {
+    let (table, key) = from_utf8(full_key).ok()?.split_once(':')?;
+    table
+        .contains('%')
+        .then(|| format!("{}:{key}", encode_table(table)))
+}
+
+/// Upgrade the keys of a database written before the key format was versioned, renaming them
+/// in all the trees in one transaction, so an interrupted upgrade is run again from scratch
+fn upgrade_keys(
+    db: &Db,
+    deadlines: &Tree,
+    versions: &Tree,
+    expiry: &Tree,
+    meta: &Tree,
+) -> Result<(), KvError> {
+    match meta.get(KEY_FORMAT_KEY)? {
+        Some(v) if *v == [KEY_FORMAT] => return Ok(()),
+        Some(v) => {
+            return Err(KvError::Internal(format!(
+                "unsupported key format {:?}, the latest is {}",
+                v, KEY_FORMAT
+            )))
+        }
+        None => {}
+    }
+
+    // the renames by tree: the data, the deadlines, the versions, then the expiry index
+    let mut renames = Vec::new();
+    for (i, tree) in [&**db, deadlines, versions].into_iter().enumerate() {
+        for key in tree.iter().keys() {
+            let key = key?;
+            if let Some(new) = upgraded_key(&key) {
+                renames.push((i, key, new.into_bytes()));
+            }
+        }
+    }
+    for key in expiry.iter().keys() {
+        let key = key?;
+        if let Some(new) = key.get(8..).and_then(upgraded_key) {
+            let new = [&key[..8], new.as_bytes()].concat();
+            renames.push((3, key, new));
+        }
+    }
+
+    let result = (&**db, deadlines, versions, expiry, meta).transaction(
+        |(db, deadlines, versions, expiry, meta)| -> ConflictableTransactionResult<(), KvError> {
+            let trees = [db, deadlines, versions, expiry];
+            for (i, old, new) in &renames {
+                if let Some(value) = trees[*i].remove(old)? {
+                    trees[*i].insert(new.as_slice(), value)?;
+                }
+            }
+            meta.insert(KEY_FORMAT_KEY, &[KEY_FORMAT])?;
+            Ok(())
+        },
+    );
+    result.map_err(|e| match e {
+        TransactionError::Abort(e) => e,
+        TransactionError::Storage(e) => e.into(),
+    })
+}
+
+/// Decode a stored value, verifying its checksum if it has one
+fn decode_value(data: &[u8]) -> Result<Value, KvError> {
+    match data.split_first() {
+        Some((&CRC32_FLAG, _)) => decode_value(verify_checksum(data)?),
+        Some((&ZSTD_FLAG, compressed)) => {
+            let data = zstd::bulk::decompress(compressed, value_len(data)?)?;
+            Value::try_from(data.as_slice())
+        }
+        _ => Value::try_from(data)
+            .map_err(|e| KvError::Corruption(format!("cannot decode a stored value: {}", e))),
+    }
+}
+
+/// Check the checksum of a stored value and return the value stored without it
+fn verify_checksum(data: &[u8]) -> Result<&[u8], KvError> {
+    let (Some(stored), Some(rest)) = (data.get(1..5), data.get(5..)) else {
+        return Err(KvError::Corruption("truncated checksum".into()));
+    };
+    let stored = u32::from_be_bytes(stored.try_into().unwrap());
+    let computed = crc32fast::hash(rest);
+    match stored == computed {
+        true => Ok(rest),
+        false => Err(KvError::Corruption(format!(
+            "checksum {:08x} of a stored value, {:08x} expected",
+            computed, stored
+        ))),
+    }
+}
+
+/// Decode a pair read from the data, the key without its table
+fn decode_pair(item: Result<(IVec, IVec), sled::Error>) -> Result<Kvpair, KvError> {
+    let (k, v) = item?;
+    let (_, key) = split_full_key(&k)?;
+    let value = decode_value(&v).map_err(|e| match e {
+        KvError::Corruption(msg) => KvError::Corruption(format!("{}: {}", key, msg)),
+        e => e,
+    })?;
+    Ok(Kvpair::new(key, value))
+}
+
+/// The full keys of a table whose deadline has passed, hidden from the scans until purged
+fn expired_keys(deadlines: &Tree, prefix: &str) -> Result<HashSet<IVec>, KvError> {
+    let now = now_ms();
+    let mut expired = HashSet::new();
+    for item in deadlines.scan_prefix(prefix) {
+        let (name, deadline) = item?;
+        if decode_deadline(&deadline)? <= now {
+            expired.insert(name);
+        }
+    }
+    Ok(expired)
+}
+
+/// Skip the pairs of the expired keys
+fn live_pairs(
+    iter: impl Iterator<Item = Result<(IVec, IVec), sled::Error>>,
+    expired: HashSet<IVec>,
+) -> impl Iterator<Item = Result<(IVec, IVec), sled::Error>> {
+    iter.filter(move |item| !matches!(item, Ok((name, _)) if expired.contains(name)))
+}
+
+/// Decode the pairs read lazily from the data, the pairs failing to decode are skipped
+fn decode_pairs(
+    iter: impl Iterator<Item = Result<(IVec, IVec), sled::Error>>,
+) -> impl Iterator<Item = Kvpair> {
+    iter.filter_map(|item| match decode_pair(item) {
+        Ok(pair) => Some(pair),
+        Err(e) => {
+            warn!(error = %e, "Skipped a pair failing to decode");
+            None
+        }
+    })
+}
+
+/// The encoded length of a stored value before its compression, read from the zstd frame header
+fn value_len(data: &[u8]) -> Result<usize, KvError> {
+    match data.split_first() {
+        Some((&CRC32_FLAG, _)) => value_len(data.get(5..).unwrap_or_default()),
+        Some((&ZSTD_FLAG, compressed)) => {
+            match zstd::zstd_safe::get_frame_content_size(compressed) {
+                Ok(Some(len)) => Ok(len as usize),
+                _ => Err(KvError::Internal("invalid compressed value".into())),
+            }
+        }
+        _ => Ok(data.len()),
+    }
+}
+
+/// Encode a version with the time of the write giving it
+fn encode_version(version: u64) -> [u8; 16] {
+    let mut data = [0; 16];
+    data[..8].copy_from_slice(&version.to_be_bytes());
+    data[8..].copy_from_slice(&now_ms().to_be_bytes());
+    data
+}
+
+/// Decode a version and the time of the write giving it
+fn decode_version(data: &[u8]) -> Result<(u64, u64), KvError> {
+    let bytes: [u8; 16] = data
+        .try_into()
+        .map_err(|_| KvError::Internal(format!("invalid version: {:?}", data)))?;
+    let (version, modified) = bytes.split_at(8);
+    Ok((
+        u64::from_be_bytes(version.try_into().unwrap()),
+        u64::from_be_bytes(modified.try_into().unwrap()),
+    ))
+}
+
+fn decode_deadline(data: &[u8]) -> Result<u64, KvError> {
+    let bytes = data
+        .try_into()
+        .map_err(|_| KvError::Internal(format!("invalid deadline: {:?}", data)))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// Generate a random key in the range [first, last]
+fn random_key_between(first: &[u8], last: &[u8], rng: &mut impl Rng) -> Vec<u8> {
+    // keep the common prefix, then pick a random byte between the first differing bytes
+    let common = first.iter().zip(last).take_while(|(a, b)| a == b).count();
+    let mut key = first[..common].to_vec();
+    let low = first.get(common).copied().unwrap_or(0);
+    let high = last.get(common).copied().unwrap_or(u8::MAX);
+    key.push(rng.gen_range(low..=high));
+    key.extend((0..8).map(|_| rng.gen::<u8>()));
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn sleddb_compression_should_shrink_the_large_values() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::builder(dir.path())
+            .compression(ValueCompression::Zstd {
+                level: 3,
+                min_size: 64,
+            })
+            .open()
+            .unwrap();
+        let text: Value = "lorem ipsum ".repeat(100).into();
+        store.set("t1", "k1".into(), text.clone()).unwrap();
+        store.set("t1", "k2".into(), "short".into()).unwrap();
+
+        let raw = store.db.get("t1:k1").unwrap().unwrap();
+        assert_eq!(raw[0], ZSTD_FLAG);
+        assert!(raw.len() < text.encoded_len() / 10);
+        let raw = store.db.get("t1:k2").unwrap().unwrap();
+        assert_eq!(raw.as_ref(), Value::from("short").encode_to_vec());
+
+        // the values are read whatever the compression, and sized before it
+        let plain = SledDb {
+            compression: ValueCompression::None,
+            sizes: Arc::new(DashMap::new()),
+            ..store.clone()
+        };
+        assert_eq!(plain.get("t1", "k1").unwrap(), Some(text.clone()));
+        let size = pair_size("k1", &text) + pair_size("k2", &"short".into());
+        assert_eq!(plain.size_of_table("t1").unwrap(), size);
+        assert_eq!(store.size_of_table("t1").unwrap(), size);
+    }
+
+    #[test]
+    fn sleddb_checksums_should_detect_the_corrupted_values() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::builder(dir.path())
+            .checksum(ValueChecksum::Crc32)
+            .open()
+            .unwrap();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t1", "k2".into(), "v2".into()).unwrap();
+        let raw = store.db.get("t1:k1").unwrap().unwrap();
+        assert_eq!(raw[0], CRC32_FLAG);
+        let size = pair_size("k1", &"v1".into()) + pair_size("k2", &"v2".into());
+        assert_eq!(store.size_of_table("t1").unwrap(), size);
+
+        // the values are read whatever the checksum setting they were written with
+        let plain = SledDb {
+            checksum: ValueChecksum::None,
+            ..store.clone()
+        };
+        plain.set("t1", "k3".into(), "v3".into()).unwrap();
+        assert_eq!(store.get("t1", "k3").unwrap(), Some("v3".into()));
+        assert_eq!(plain.get("t1", "k1").unwrap(), Some("v1".into()));
+
+        let mut corrupted = raw.to_vec();
+        *corrupted.last_mut().unwrap() ^= 1;
+        store.db.insert("t1:k1", corrupted).unwrap();
+        assert!(matches!(store.get("t1", "k1"), Err(KvError::Corruption(_))));
+        assert!(matches!(
+            store.get_all("t1"),
+            Err(KvError::Corruption(msg)) if msg.starts_with("k1:")
+        ));
+        // the lazy reads skip the pair
+        let keys: Vec<_> = store.get_iter("t1").unwrap().map(|p| p.key).collect();
+        assert_eq!(keys, vec!["k2", "k3"]);
+
+        store.db.insert("t1:k2", &[0xff][..]).unwrap();
+        assert!(matches!(store.get("t1", "k2"), Err(KvError::Corruption(_))));
+    }
+
+    #[tokio::test]
+    async fn sleddb_builder_should_apply_the_sled_options() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::builder(dir.path())
+            .cache_capacity(1 << 20)
+            .segment_size(1 << 16)
+            .flush_policy(FlushPolicy::Manual)
+            .open()
+            .unwrap();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.flush_async().await.unwrap();
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+
+        // the invalid options are errors rather than panics
+        let res = SledDb::builder(dir.path().join("other"))
+            .segment_size(1000)
+            .open();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn sleddb_expiry_index_should_be_rebuilt_from_the_trees() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir.path());
+        for key in ["k1", "k2", "k3"] {
+            store.set("t1", key.into(), "v".into()).unwrap();
+        }
+        let later = now_ms() + 60_000;
+        store.expire("t1", "k1", Some(1)).unwrap();
+        store.expire("t1", "k2", Some(later)).unwrap();
+        store.expire("t1", "k3", Some(later)).unwrap();
+        assert!(store.expire("t1", "k3", None).unwrap());
+
+        // a restart loads the index persisted, with the stale entry of k3
+        let reopen = |store: &SledDb| SledDb {
+            timers: Arc::new(Mutex::new(
+                load_expiry(&store.deadlines, &store.expiry).unwrap(),
+            )),
+            ..store.clone()
+        };
+        let restarted = reopen(&store);
+        assert_eq!(restarted.timers().len(), 3);
+        let purged = restarted.purge_expired(now_ms()).unwrap();
+        assert_eq!(purged, vec![("t1".to_string(), "k1".to_string())]);
+        assert_eq!(restarted.get("t1", "k3").unwrap(), Some("v".into()));
+        // the entries are removed once due, the stale entry of k3 stays until then
+        assert_eq!(restarted.expiry.len(), 2);
+
+        // a database written without the index gets it from the deadlines
+        restarted.expiry.clear().unwrap();
+        let restarted = reopen(&restarted);
+        assert_eq!(restarted.timers().len(), 1);
+        let purged = restarted.purge_expired(later).unwrap();
+        assert_eq!(purged, vec![("t1".to_string(), "k2".to_string())]);
+        assert!(restarted.expiry.is_empty());
+        assert_eq!(restarted.get("t1", "k3").unwrap(), Some("v".into()));
+    }
+
+    #[test]
+    fn sleddb_writes_should_clear_the_deadlines_with_the_values() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir.path());
+        let later = now_ms() + 60_000;
+        for key in ["k1", "k2", "k3"] {
+            store.set("t1", key.into(), "v".into()).unwrap();
+            store.expire("t1", key, Some(later)).unwrap();
+        }
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        let ops = vec![
+            WriteOp::Set {
+                table: "t1".into(),
+                key: "k2".into(),
+                value: "v2".into(),
+            },
+            WriteOp::Del {
+                table: "t1".into(),
+                key: "k3".into(),
+            },
+        ];
+        store.transaction(ops).unwrap();
+        assert_eq!(store.deadline("t1", "k1").unwrap(), None);
+        assert_eq!(store.deadline("t1", "k2").unwrap(), None);
+        assert!(store.deadlines.is_empty());
+    }
+
+    #[test]
+    fn sleddb_set_if_version_should_not_overwrite_a_concurrent_set() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir.path());
+        store.set("t1", "k1".into(), "v0".into()).unwrap();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..2000 {
+                    store
+                        .set("t1", "k1".into(), format!("v{i}").into())
+                        .unwrap();
+                }
+            });
+            s.spawn(|| {
+                for _ in 0..2000 {
+                    let (value, version) = store.get_versioned("t1", "k1").unwrap().unwrap();
+                    let res = store.set_if_version("t1", "k1".into(), "cas".into(), version);
+                    // a write between the read and the swap changes the version, so a swap
+                    // that succeeds replaces exactly the value read
+                    if let Some((old, _)) = res.unwrap() {
+                        assert_eq!(old, value);
+                    }
+                }
+            });
+        });
+    }
+
+    #[test]
+    fn sleddb_tables_with_separators_should_round_trip() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir.path());
+        store.set("a:b", "k:1".into(), "v1".into()).unwrap();
+        store.set("a", "b:k:1".into(), "v2".into()).unwrap();
+        store.set("50%", "k1".into(), "v3".into()).unwrap();
+        store.expire("a:b", "k:1", Some(now_ms() + 60_000)).unwrap();
+
+        assert!(store.db.get("a%3Ab:k:1").unwrap().is_some());
+        assert_eq!(store.get("a:b", "k:1").unwrap(), Some("v1".into()));
+        assert_eq!(
+            store.get_all("a").unwrap(),
+            vec![Kvpair::new("b:k:1", "v2".into())]
+        );
+        assert_eq!(
+            store.get_all("a:b").unwrap(),
+            vec![Kvpair::new("k:1", "v1".into())]
+        );
+        let tables: Vec<_> = store.stats().unwrap().tables.into_keys().collect();
+        assert_eq!(tables, vec!["50%", "a", "a:b"]);
+        let deadlines: Vec<_> = store.iter_deadlines().map(Result::unwrap).collect();
+        assert_eq!(deadlines[0].0, "a:b");
+        assert_eq!(deadlines[0].1, "k:1");
+    }
+
+    #[test]
+    fn sleddb_keys_written_before_the_key_format_should_be_upgraded() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir.path());
+        let meta = store.db.open_tree(META_TREE).unwrap();
+        assert_eq!(
+            meta.get(KEY_FORMAT_KEY).unwrap().unwrap().as_ref(),
+            &[KEY_FORMAT]
+        );
+
+        // the keys of the tables with a `%` as the previous versions wrote them
+        let later = now_ms() + 60_000;
+        let value = Value::from("v1").encode_to_vec();
+        store.db.insert("50%:k1", value.as_slice()).unwrap();
+        store.db.insert("t1:k1", value.as_slice()).unwrap();
+        store
+            .deadlines
+            .insert("50%:k1", &later.to_be_bytes())
+            .unwrap();
+        store
+            .expiry
+            .insert(expiry_key(later, "50%:k1"), &[])
+            .unwrap();
+        meta.remove(KEY_FORMAT_KEY).unwrap();
+
+        let upgrade = || {
+            upgrade_keys(
+                &store.db,
+                &store.deadlines,
+                &store.versions,
+                &store.expiry,
+                &meta,
+            )
+        };
+        upgrade().unwrap();
+        assert_eq!(store.get("50%", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.deadline("50%", "k1").unwrap(), Some(later));
+        assert!(store.db.get("50%:k1").unwrap().is_none());
+        let mut index = load_expiry(&store.deadlines, &store.expiry).unwrap();
+        assert_eq!(index.pop_due(later), vec![(later, "50%25:k1".into())]);
+
+        // the upgrade runs once
+        upgrade().unwrap();
+        assert_eq!(store.get("50%", "k1").unwrap(), Some("v1".into()));
+        meta.insert(KEY_FORMAT_KEY, &[KEY_FORMAT + 1]).unwrap();
+        assert!(upgrade().is_err());
+    }
 }
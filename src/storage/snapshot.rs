@@ -0,0 +1,426 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use tracing::{info, warn};
+
+use crate::{KvError, Kvpair, MemTable, Value};
+
+use super::{read_dump, write_dump, Storage, StorageStats, UpdateFn, WriteOp};
+
+/// The prefix of the tables holding the deadlines of the keys of a snapshot, the deadlines
+/// of a table follow its pairs as the keys of `__deadlines__.<table>` mapped to the deadlines
+const DEADLINES_PREFIX: &str = "__deadlines__.";
+
+/// The configuration of the background snapshots of a SnapshotStore
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotConfig {
+    /// The snapshot file
+    pub path: PathBuf,
+    /// Take a snapshot every `interval` if there are changes
+    pub interval: Duration,
+    /// Take a snapshot once there are `changes` writes since the last one, None to only rely on the interval
+    pub changes: Option<usize>,
+}
+
+impl SnapshotConfig {
+    /// Snapshot to `path` every 60 seconds
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            interval: Duration::from_secs(60),
+            changes: None,
+        }
+    }
+
+    /// Set the snapshot interval
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Also snapshot after `changes` writes
+    pub fn changes(mut self, changes: usize) -> Self {
+        self.changes = Some(changes);
+        self
+    }
+}
+
+impl MemTable {
+    /// Write all pairs and their deadlines to a snapshot file, replacing it atomically,
+    /// and return the number of pairs
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<usize, KvError> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        let pairs = self.pairs_with_deadlines();
+        let count = pairs.len();
+        let deadlines: Vec<_> = pairs
+            .iter()
+            .filter_map(|(name, pair, deadline)| {
+                let table = format!("{}{}", DEADLINES_PREFIX, name);
+                deadline.map(|deadline| (table, Kvpair::new(&pair.key, (deadline as i64).into())))
+            })
+            .collect();
+        let pairs = pairs.into_iter().map(|(name, pair, _)| (name, pair));
+        write_dump(&mut file, pairs.chain(deadlines).map(Ok))?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(count)
+    }
+
+    /// Load a MemTable from a snapshot file, an empty one if the file does not exist
+    pub fn load_snapshot(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        let table = Self::new();
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(table),
+            Err(e) => return Err(e.into()),
+        };
+
+        for pair in read_dump(&data[..]) {
+            let (name, Kvpair { key, value }) = pair?;
+            let value = value.unwrap_or_default();
+            match name.strip_prefix(DEADLINES_PREFIX) {
+                Some(name) => {
+                    let deadline: i64 = (&value).try_into()?;
+                    table.expire(name, &key, Some(deadline as u64))?;
+                }
+                None => _ = table.set(&name, key, value)?,
+            }
+        }
+        Ok(table)
+    }
+}
+
+/// A MemTable snapshotted to a file in the background, and restored from it when opened
+pub struct SnapshotStore {
+    mem: Arc<MemTable>,
+    /// The number of writes since the last snapshot
+    changes: Arc<AtomicUsize>,
+    threshold: Option<usize>,
+    /// Wake the snapshotter up, None once the store is dropped
+    wake: Option<mpsc::SyncSender<()>>,
+    snapshotter: Option<JoinHandle<()>>,
+}
+
+impl SnapshotStore {
+    /// Load the latest snapshot if present, and start taking snapshots in the background
+    pub fn open(config: SnapshotConfig) -> Result<Self, KvError> {
+        let mem = Arc::new(MemTable::load_snapshot(&config.path)?);
+        info!("Loaded snapshot {:?}", config.path);
+
+        let changes = Arc::new(AtomicUsize::new(0));
+        let (wake, rx) = mpsc::sync_channel(1);
+        let threshold = config.changes;
+        let snapshotter = {
+            let mem = mem.clone();
+            let changes = changes.clone();
+            thread::Builder::new()
+                .name("memtable-snapshotter".into())
+                .spawn(move || snapshot_loop(mem, changes, config, rx))?
+        };
+
+        Ok(Self {
+            mem,
+            changes,
+            threshold,
+            wake: Some(wake),
+            snapshotter: Some(snapshotter),
+        })
+    }
+
+    /// Count a write, and wake the snapshotter up if the threshold is reached
+    fn changed(&self) {
+        let changes = self.changes.fetch_add(1, Ordering::Relaxed) + 1;
+        if matches!(self.threshold, Some(threshold) if changes >= threshold) {
+            if let Some(wake) = &self.wake {
+                // a pending wake up is enough
+                _ = wake.try_send(());
+            }
+        }
+    }
+}
+
+/// Take a snapshot on every wake up or interval with changes, and a final one when the store is dropped
+fn snapshot_loop(
+    mem: Arc<MemTable>,
+    changes: Arc<AtomicUsize>,
+    config: SnapshotConfig,
+    rx: mpsc::Receiver<()>,
+) {
+    loop {
+        let closed = matches!(
+            rx.recv_timeout(config.interval),
+            Err(mpsc::RecvTimeoutError::Disconnected)
+        );
+        if changes.swap(0, Ordering::Relaxed) > 0 {
+            match mem.save_snapshot(&config.path) {
+                Ok(count) => info!("Saved {} pairs to snapshot {:?}", count, config.path),
+                Err(e) => warn!("Failed to save snapshot {:?}: {:?}", config.path, e),
+            }
+        }
+        if closed {
+            break;
+        }
+    }
+}
+
+impl Storage for SnapshotStore {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.mem.get(table, key)
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
+        let old = self.mem.set(table, key, value)?;
+        self.changed();
+        Ok(old)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.mem.contains(table, key)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let old = self.mem.del(table, key)?;
+        if old.is_some() {
+            self.changed();
+        }
+        Ok(old)
+    }
+
+    fn transaction(&self, ops: Vec<WriteOp>) -> Result<Vec<Option<Value>>, KvError> {
+        let olds = self.mem.transaction(ops)?;
+        self.changed();
+        Ok(olds)
+    }
+
+    fn snapshot(&self, writer: &mut dyn Write) -> Result<usize, KvError> {
+        self.mem.snapshot(writer)
+    }
+
+    fn update(
+        &self,
+        table: &str,
+        key: &str,
+        f: &mut UpdateFn,
+    ) -> Result<(Option<Value>, Option<Value>), KvError> {
+        let updated = self.mem.update(table, key, f)?;
+        self.changed();
+        Ok(updated)
+    }
+
+    fn expire(&self, table: &str, key: &str, deadline: Option<u64>) -> Result<bool, KvError> {
+        let exists = self.mem.expire(table, key, deadline)?;
+        if exists {
+            self.changed();
+        }
+        Ok(exists)
+    }
+
+    fn deadline(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.mem.deadline(table, key)
+    }
+
+    fn purge_expired(&self, now: u64) -> Result<Vec<(String, String)>, KvError> {
+        let purged = self.mem.purge_expired(now)?;
+        if !purged.is_empty() {
+            self.changed();
+        }
+        Ok(purged)
+    }
+
+    fn touch(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.mem.touch(table, key)
+    }
+
+    fn last_access(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.mem.last_access(table, key)
+    }
+
+    /// The versions are not part of the snapshots, they start over after a restart
+    fn version(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.mem.version(table, key)
+    }
+
+    fn modified(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        self.mem.modified(table, key)
+    }
+
+    fn get_versioned(&self, table: &str, key: &str) -> Result<Option<(Value, u64)>, KvError> {
+        self.mem.get_versioned(table, key)
+    }
+
+    fn set_if_version(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        version: u64,
+    ) -> Result<Option<(Value, u64)>, KvError> {
+        let written = self.mem.set_if_version(table, key, value, version)?;
+        if written.is_some() {
+            self.changed();
+        }
+        Ok(written)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.mem.get_all(table)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        self.mem.get_iter(table)
+    }
+
+    fn len_of_table(&self, table: &str) -> Result<usize, KvError> {
+        self.mem.len_of_table(table)
+    }
+
+    fn copy_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        let copied = self.mem.copy_key(from, to, key, replace)?;
+        if copied {
+            self.changed();
+        }
+        Ok(copied)
+    }
+
+    fn move_key(&self, from: &str, to: &str, key: &str, replace: bool) -> Result<bool, KvError> {
+        let moved = self.mem.move_key(from, to, key, replace)?;
+        if moved {
+            self.changed();
+        }
+        Ok(moved)
+    }
+
+    fn clear_table(&self, table: &str) -> Result<usize, KvError> {
+        let removed = self.mem.clear_table(table)?;
+        if removed > 0 {
+            self.changed();
+        }
+        Ok(removed)
+    }
+
+    fn size_of_table(&self, table: &str) -> Result<usize, KvError> {
+        self.mem.size_of_table(table)
+    }
+
+    fn total_size(&self) -> Result<usize, KvError> {
+        self.mem.total_size()
+    }
+
+    fn stats(&self) -> Result<StorageStats, KvError> {
+        let mut stats = self.mem.stats()?;
+        let changes = self.changes.load(Ordering::Relaxed);
+        stats.set_info("changes_since_snapshot", changes as u64);
+        Ok(stats)
+    }
+}
+
+impl Drop for SnapshotStore {
+    fn drop(&mut self) {
+        // disconnect the snapshotter, it takes a final snapshot and exits
+        self.wake.take();
+        if let Some(snapshotter) = self.snapshotter.take() {
+            if snapshotter.join().is_err() {
+                warn!("The snapshotter of SnapshotStore panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::now_ms;
+
+    #[test]
+    fn memtable_snapshot_should_roundtrip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("kvdb.snapshot");
+        assert_eq!(
+            MemTable::load_snapshot(&path)
+                .unwrap()
+                .total_size()
+                .unwrap(),
+            0
+        );
+
+        let store = MemTable::new();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t2", "k1".into(), 1.into()).unwrap();
+        assert_eq!(store.save_snapshot(&path).unwrap(), 2);
+
+        let store = MemTable::load_snapshot(&path).unwrap();
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("t2", "k1").unwrap(), Some(1.into()));
+    }
+
+    #[test]
+    fn snapshot_store_should_restore_after_restart() {
+        let dir = tempdir().unwrap();
+        let config = SnapshotConfig::new(dir.path().join("kvdb.snapshot"));
+        {
+            let store = SnapshotStore::open(config.clone()).unwrap();
+            store.set("t1", "k1".into(), "v1".into()).unwrap();
+            store.set("t1", "k2".into(), "v2".into()).unwrap();
+            store.del("t1", "k2").unwrap();
+        }
+
+        let store = SnapshotStore::open(config).unwrap();
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("t1", "k2").unwrap(), None);
+    }
+
+    #[test]
+    fn snapshot_store_should_keep_the_deadlines_after_restart() {
+        let dir = tempdir().unwrap();
+        let config = SnapshotConfig::new(dir.path().join("kvdb.snapshot"));
+        let later = now_ms() + 60_000;
+        {
+            let store = SnapshotStore::open(config.clone()).unwrap();
+            for key in ["k1", "k2", "k3"] {
+                store.set("t1", key.into(), key.into()).unwrap();
+            }
+            store.expire("t1", "k1", Some(later)).unwrap();
+            store.expire("t1", "k2", Some(0)).unwrap();
+        }
+
+        let store = SnapshotStore::open(config).unwrap();
+        assert_eq!(store.deadline("t1", "k1").unwrap(), Some(later));
+        assert_eq!(store.get("t1", "k2").unwrap(), None);
+        assert_eq!(store.deadline("t1", "k3").unwrap(), None);
+        assert_eq!(store.get_all("t1").unwrap().len(), 2);
+        // the deadlines are not restored as tables
+        assert_eq!(store.get_all("__deadlines__.t1").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn snapshot_store_should_snapshot_after_changes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("kvdb.snapshot");
+        let config = SnapshotConfig::new(&path).changes(2);
+        let store = SnapshotStore::open(config).unwrap();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t1", "k2".into(), "v2".into()).unwrap();
+
+        for _ in 0..100 {
+            if path.exists() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let restored = MemTable::load_snapshot(&path).unwrap();
+        assert_eq!(restored.get("t1", "k2").unwrap(), Some("v2".into()));
+    }
+}
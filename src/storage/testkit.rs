@@ -0,0 +1,957 @@
+//! A conformance suite of the `Storage` contract, shared by the built-in backends
+//! and available to third-party backends with the `test-util` feature.
+//!
+//! Every check takes a fresh, empty storage, so the whole suite runs with:
+//!
+//! ```ignore
+//! kvdb::storage_conformance_tests!(my_store, MyStore::new());
+//! ```
+//!
+//! or a check alone, like `kvdb::storage::testkit::test_basic_interface(MyStore::new())`.
+
+use std::{future::Future, thread};
+
+use prost::Message;
+
+use crate::{
+    now_ms, KvError, Kvpair, ScanOptions, ScanPage, Storage, TableStats, Update, Value, WriteOp,
+    ZsetMember,
+};
+
+use super::pair_size;
+
+/// Generate a test module running the whole conformance suite against the storage
+/// created by the expression, the expression is evaluated once per test. A guard the storage
+/// depends on, like the directory of a persistent one, is bound first and lives for the test:
+/// `storage_conformance_tests!(my_store, dir = tempdir().unwrap() => MyStore::open(&dir))`.
+#[macro_export]
+macro_rules! storage_conformance_tests {
+    ($name:ident, $guard:ident = $setup:expr => $store:expr) => {
+        $crate::storage_conformance_tests!(@suite $name, [$guard = $setup], $store);
+    };
+    ($name:ident, $store:expr) => {
+        $crate::storage_conformance_tests!(@suite $name, [], $store);
+    };
+    (@suite $name:ident, [$($guard:ident = $setup:expr)?], $store:expr) => {
+        mod $name {
+            use super::*;
+
+            #[test]
+            fn basic_interface() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_basic_interface($store);
+            }
+
+            #[test]
+            fn empty_table() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_empty_table($store);
+            }
+
+            #[test]
+            fn get_all() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_get_all($store);
+            }
+
+            #[test]
+            fn get_iter() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_get_iter($store);
+            }
+
+            #[test]
+            fn get_stream() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::block_on($crate::storage::testkit::test_get_stream(
+                    $store,
+                ));
+            }
+
+            #[test]
+            fn scan() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_scan($store);
+            }
+
+            #[test]
+            fn scan_page() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_scan_page($store);
+            }
+
+            #[test]
+            fn sample() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_sample($store);
+            }
+
+            #[test]
+            fn transaction() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_transaction($store);
+            }
+
+            #[test]
+            fn size() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_size($store);
+            }
+
+            #[test]
+            fn len() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_len($store);
+            }
+
+            #[test]
+            fn copy_and_move() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_copy_and_move($store);
+            }
+
+            #[test]
+            fn clear_table() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_clear_table($store);
+            }
+
+            #[test]
+            fn expire() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_expire($store);
+            }
+
+            #[test]
+            fn update() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_update($store);
+            }
+
+            #[test]
+            fn touch() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_touch($store);
+            }
+
+            #[test]
+            fn list() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_list($store);
+            }
+
+            #[test]
+            fn set() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_set($store);
+            }
+
+            #[test]
+            fn zset() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_zset($store);
+            }
+
+            #[test]
+            fn versions() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_versions($store);
+            }
+
+            #[test]
+            fn batch() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_batch($store);
+            }
+
+            #[test]
+            fn range() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_range($store);
+            }
+
+            #[test]
+            fn snapshot() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_snapshot($store);
+            }
+
+            #[test]
+            fn stats() {
+                $(let $guard = $setup;)?
+                $crate::storage::testkit::test_stats($store);
+            }
+        }
+    };
+}
+
+/// Run an async check on a new runtime
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build the runtime")
+        .block_on(fut)
+}
+
+/// get/set/contains/del on existing and unexisting keys and tables
+pub fn test_basic_interface(store: impl Storage) {
+    // 1. set an unexisting key, should return None
+    assert_eq!(
+        None,
+        store.set("t1", "hello".into(), "value".into()).unwrap()
+    );
+
+    // 2. set an existing key, should return the old value
+    assert_eq!(
+        Some("value".into()),
+        store.set("t1", "hello".into(), "value2".into()).unwrap()
+    );
+
+    // 3. get the key, should return the new value
+    assert_eq!(Some("value2".into()), store.get("t1", "hello").unwrap());
+
+    // 4. get the unexisting key or table, should return None
+    assert_eq!(None, store.get("t1", "unexisting").unwrap());
+    assert_eq!(None, store.get("unexisting", "hello").unwrap());
+
+    // 5. check the existing key, should return true
+    assert!(store.contains("t1", "hello").unwrap());
+
+    // 6. check the unexisting key or table, should return false
+    assert!(!store.contains("t1", "unexisting").unwrap());
+    assert!(!store.contains("unexisting", "hello").unwrap());
+
+    // 7. del the key, should return the value
+    let v = store.del("t1", "hello");
+    assert_eq!(Some("value2".into()), v.unwrap());
+
+    // 8. get the key, should return None
+    assert_eq!(None, store.get("t1", "hello").unwrap());
+
+    // 9. del the unexisting key or table   , should return None
+    assert_eq!(None, store.del("t1", "unexisting").unwrap());
+    assert_eq!(None, store.del("unexisting", "hello").unwrap());
+}
+
+/// a table never written, or emptied, reads as empty through every method
+pub fn test_empty_table(store: impl Storage) {
+    store.set("t31", "k1".into(), "v1".into()).unwrap();
+    store.del("t31", "k1").unwrap();
+    for table in ["t30", "t31"] {
+        assert!(store.get_all(table).unwrap().is_empty());
+        assert_eq!(store.get_iter(table).unwrap().count(), 0);
+        let opts = ScanOptions::default();
+        assert_eq!(store.scan(table, opts.reverse()).unwrap().count(), 0);
+        assert_eq!(
+            store.scan_page(table, None, 10).unwrap(),
+            ScanPage::default()
+        );
+        assert!(store.get_range(table, None, None, None).unwrap().is_empty());
+        assert!(store.sample(table, 3).unwrap().is_empty());
+        assert_eq!(store.len_of_table(table).unwrap(), 0);
+        assert_eq!(store.size_of_table(table).unwrap(), 0);
+        assert_eq!(store.clear_table(table).unwrap(), 0);
+    }
+}
+
+/// get_all returns every pair of the table sorted by key
+pub fn test_get_all(store: impl Storage) {
+    assert!(store.get_all("t2").unwrap().is_empty());
+
+    store.set("t2", "k1".into(), "v1".into()).unwrap();
+    store.set("t2", "k2".into(), "v2".into()).unwrap();
+
+    let mut data = store.get_all("t2").unwrap();
+    data.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(
+        data,
+        vec![
+            Kvpair::new("k1", "v1".into()),
+            Kvpair::new("k2", "v2".into())
+        ]
+    );
+
+    // the pairs are sorted by key whatever the insertion order is
+    for i in (0..100).rev() {
+        store.set("t2", format!("key{:03}", i), i.into()).unwrap();
+    }
+    let keys: Vec<String> = store
+        .get_all("t2")
+        .unwrap()
+        .into_iter()
+        .map(|p| p.key)
+        .collect();
+    let mut sorted = keys.clone();
+    sorted.sort();
+    assert_eq!(keys, sorted);
+}
+
+/// get_iter yields every pair of the table
+pub fn test_get_iter(store: impl Storage) {
+    store.set("t3", "k1".into(), "v1".into()).unwrap();
+    store.set("t3", "k2".into(), "v2".into()).unwrap();
+
+    let iter = store.get_iter("t3").unwrap();
+    let mut pairs = iter.collect::<Vec<_>>();
+    pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(
+        pairs,
+        vec![
+            Kvpair::new("k1", "v1".into()),
+            Kvpair::new("k2", "v2".into())
+        ]
+    );
+}
+
+/// get_stream yields every pair of the table, but the expired keys
+pub async fn test_get_stream(store: impl Storage) {
+    use futures::StreamExt;
+
+    store.set("t6", "k1".into(), "v1".into()).unwrap();
+    store.set("t6", "k2".into(), "v2".into()).unwrap();
+    store.set("t6", "k3".into(), "v3".into()).unwrap();
+    store.expire("t6", "k3", Some(0)).unwrap();
+
+    let stream = store.get_stream("t6").unwrap();
+    let mut pairs = stream.collect::<Vec<_>>().await;
+    pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(
+        pairs,
+        vec![
+            Kvpair::new("k1", "v1".into()),
+            Kvpair::new("k2", "v2".into())
+        ]
+    );
+}
+
+/// scan honours the start key, the direction and the limit
+pub fn test_scan(store: impl Storage) {
+    for key in ["k3", "k1", "k4", "k2"] {
+        store.set("t5", key.into(), key.into()).unwrap();
+    }
+    // a neighbour table sharing the prefix should not be scanned
+    store.set("t55", "k0".into(), "v0".into()).unwrap();
+
+    let keys = |opts: ScanOptions| -> Vec<String> {
+        store.scan("t5", opts).unwrap().map(|p| p.key).collect()
+    };
+    assert_eq!(keys(ScanOptions::default()), ["k1", "k2", "k3", "k4"]);
+    assert_eq!(
+        keys(ScanOptions::default().reverse()),
+        ["k4", "k3", "k2", "k1"]
+    );
+    assert_eq!(keys(ScanOptions::from("k2").limit(2)), ["k2", "k3"]);
+    assert_eq!(keys(ScanOptions::from("k3").reverse()), ["k3", "k2", "k1"]);
+    assert_eq!(keys(ScanOptions::from("k25")), ["k3", "k4"]);
+    assert_eq!(keys(ScanOptions::from("k5")), Vec::<String>::new());
+}
+
+/// a cursor scan returns the keys present for the whole scan exactly once despite concurrent writes
+pub fn test_scan_page(store: impl Storage) {
+    for i in 0..20 {
+        store.set("t9", format!("key{:02}", i), i.into()).unwrap();
+    }
+
+    let mut keys = Vec::new();
+    let mut cursor = None;
+    let mut round = 0;
+    loop {
+        let page = store.scan_page("t9", cursor, 3).unwrap();
+        assert!(page.pairs.len() <= 3);
+        keys.extend(page.pairs.into_iter().map(|p| p.key));
+
+        // insert before and after the cursor, and delete a key not yet returned
+        store
+            .set("t9", format!("key{:02}a", round), round.into())
+            .unwrap();
+        store
+            .set("t9", format!("aaa{}", round), round.into())
+            .unwrap();
+        if round == 1 {
+            store.del("t9", "key15").unwrap();
+        }
+        round += 1;
+
+        match page.cursor {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+
+    let mut deduped = keys.clone();
+    deduped.dedup();
+    assert_eq!(keys, deduped, "no key is returned twice");
+    for i in (0..20).filter(|i| *i != 15) {
+        assert!(
+            keys.contains(&format!("key{:02}", i)),
+            "key{:02} is missing",
+            i
+        );
+    }
+    assert!(!keys.iter().any(|k| k.starts_with("aaa")));
+
+    // an empty table has a single empty page
+    assert_eq!(
+        store.scan_page("t10", None, 3).unwrap(),
+        ScanPage::default()
+    );
+}
+
+/// sample returns distinct existing pairs
+pub fn test_sample(store: impl Storage) {
+    assert!(store.sample("t7", 3).unwrap().is_empty());
+
+    for i in 0..100 {
+        store.set("t7", format!("key{i}"), i.into()).unwrap();
+    }
+    let pairs = store.sample("t7", 10).unwrap();
+    let mut keys: Vec<_> = pairs.iter().map(|p| p.key.clone()).collect();
+    keys.sort();
+    keys.dedup();
+    assert_eq!(keys.len(), 10);
+    for pair in pairs {
+        assert_eq!(store.get("t7", &pair.key).unwrap(), pair.value);
+    }
+
+    // asking for more than the table has returns the whole table
+    store.set("t8", "k1".into(), "v1".into()).unwrap();
+    assert_eq!(store.sample("t8", 3).unwrap().len(), 1);
+    assert_eq!(store.sample("t7", usize::MAX).unwrap().len(), 100);
+}
+
+/// transaction applies the writes across tables in order and returns the old values
+pub fn test_transaction(store: impl Storage) {
+    store.set("users", "u1".into(), "alice".into()).unwrap();
+
+    let olds = store
+        .transaction(vec![
+            WriteOp::set("users", "u1", "bob".into()),
+            WriteOp::set("users_by_name", "bob", "u1".into()),
+            WriteOp::del("users_by_name", "alice"),
+            WriteOp::set("users", "u1", "carol".into()),
+        ])
+        .unwrap();
+    assert_eq!(
+        olds,
+        vec![Some("alice".into()), None, None, Some("bob".into())]
+    );
+    assert_eq!(store.get("users", "u1").unwrap(), Some("carol".into()));
+    assert_eq!(
+        store.get("users_by_name", "bob").unwrap(),
+        Some("u1".into())
+    );
+    assert_eq!(
+        store.size_of_table("users").unwrap(),
+        pair_size("u1", &"carol".into())
+    );
+}
+
+/// size_of_table follows the writes
+pub fn test_size(store: impl Storage) {
+    assert_eq!(store.size_of_table("t4").unwrap(), 0);
+
+    let v1: Value = "v1".into();
+    store.set("t4", "k1".into(), v1.clone()).unwrap();
+    let size = pair_size("k1", &v1);
+    assert_eq!(store.size_of_table("t4").unwrap(), size);
+
+    // overwrite with a larger value
+    let v2: Value = "a longer value".into();
+    store.set("t4", "k1".into(), v2.clone()).unwrap();
+    store.set("t4", "k2".into(), v1.clone()).unwrap();
+    let size = pair_size("k1", &v2) + pair_size("k2", &v1);
+    assert_eq!(store.size_of_table("t4").unwrap(), size);
+
+    store.del("t4", "k1").unwrap();
+    store.del("t4", "unexisting").unwrap();
+    assert_eq!(store.size_of_table("t4").unwrap(), pair_size("k2", &v1));
+    store.flush().unwrap();
+    assert!(store.total_size().unwrap() > 0);
+}
+
+/// stats reports the tables holding live keys, as counted by len_of_table and size_of_table
+pub fn test_stats(store: impl Storage) {
+    for key in ["k1", "k2"] {
+        store.set("t25", key.into(), "v".into()).unwrap();
+    }
+    store.set("t26", "k1".into(), "v1".into()).unwrap();
+    store.set("t27", "k1".into(), "v1".into()).unwrap();
+    store.del("t27", "k1").unwrap();
+    store.set("t28", "k1".into(), "v1".into()).unwrap();
+    assert!(store.expire("t28", "k1", Some(0)).unwrap());
+
+    let stats = store.stats().unwrap();
+    let expected = |table: &str| TableStats {
+        keys: store.len_of_table(table).unwrap(),
+        bytes: store.size_of_table(table).unwrap(),
+    };
+    assert_eq!(stats.tables.get("t25"), Some(&expected("t25")));
+    assert_eq!(stats.tables["t25"].keys, 2);
+    assert_eq!(stats.tables.get("t26"), Some(&expected("t26")));
+    assert!(!stats.tables.contains_key("t27"));
+    assert!(!stats.tables.contains_key("t28"));
+    assert!(!stats.info.is_empty());
+}
+
+/// len_of_table counts the live keys
+pub fn test_len(store: impl Storage) {
+    assert_eq!(store.len_of_table("t13").unwrap(), 0);
+    for key in ["k1", "k2", "k3"] {
+        store.set("t13", key.into(), "v".into()).unwrap();
+    }
+    // another table sharing the prefix is not counted
+    store.set("t13x", "k1".into(), "v".into()).unwrap();
+    assert_eq!(store.len_of_table("t13").unwrap(), 3);
+
+    store.del("t13", "k2").unwrap();
+    store.expire("t13", "k3", Some(now_ms() + 60_000)).unwrap();
+    assert_eq!(store.len_of_table("t13").unwrap(), 2);
+    store.expire("t13", "k3", Some(0)).unwrap();
+    assert_eq!(store.len_of_table("t13").unwrap(), 1);
+}
+
+/// copy_key and move_key carry the deadline, and only replace the destination if asked to
+pub fn test_copy_and_move(store: impl Storage) {
+    let later = now_ms() + 60_000;
+    store.set("t15", "k1".into(), "v1".into()).unwrap();
+    store.expire("t15", "k1", Some(later)).unwrap();
+    store.set("t16", "k2".into(), "old".into()).unwrap();
+    store.set("t15", "k2".into(), "v2".into()).unwrap();
+
+    assert!(!store.copy_key("t15", "t16", "missing", false).unwrap());
+    assert!(store.copy_key("t15", "t16", "k1", false).unwrap());
+    assert_eq!(store.get("t16", "k1").unwrap(), Some("v1".into()));
+    assert_eq!(store.deadline("t16", "k1").unwrap(), Some(later));
+    assert_eq!(store.get("t15", "k1").unwrap(), Some("v1".into()));
+
+    assert!(!store.move_key("t15", "t16", "k2", false).unwrap());
+    assert_eq!(store.get("t16", "k2").unwrap(), Some("old".into()));
+    assert!(store.move_key("t15", "t16", "k2", true).unwrap());
+    assert_eq!(store.get("t16", "k2").unwrap(), Some("v2".into()));
+    assert_eq!(store.deadline("t16", "k2").unwrap(), None);
+    assert!(!store.contains("t15", "k2").unwrap());
+
+    assert_eq!(
+        store.size_of_table("t15").unwrap(),
+        pair_size("k1", &"v1".into())
+    );
+    assert_eq!(
+        store.size_of_table("t16").unwrap(),
+        pair_size("k1", &"v1".into()) + pair_size("k2", &"v2".into())
+    );
+}
+
+/// clear_table removes the keys and the deadlines of one table only
+pub fn test_clear_table(store: impl Storage) {
+    assert_eq!(store.clear_table("t14").unwrap(), 0);
+    for key in ["k1", "k2", "k3"] {
+        store.set("t14", key.into(), "v".into()).unwrap();
+    }
+    store.set("t14x", "k1".into(), "v".into()).unwrap();
+    store.expire("t14", "k1", Some(now_ms() + 60_000)).unwrap();
+
+    assert_eq!(store.clear_table("t14").unwrap(), 3);
+    assert_eq!(store.get_all("t14").unwrap(), vec![]);
+    assert_eq!(store.size_of_table("t14").unwrap(), 0);
+    assert!(store.contains("t14x", "k1").unwrap());
+
+    // a key written again does not get the old deadline
+    store.set("t14", "k1".into(), "v".into()).unwrap();
+    assert_eq!(store.deadline("t14", "k1").unwrap(), None);
+    assert_eq!(store.len_of_table("t14").unwrap(), 1);
+}
+
+/// an expired key is hidden from the point reads until it is purged, a set clears the deadline
+pub fn test_expire(store: impl Storage) {
+    assert!(!store.expire("t11", "k1", Some(0)).unwrap());
+    for key in ["k1", "k2", "k3"] {
+        store.set("t11", key.into(), key.into()).unwrap();
+    }
+    let later = now_ms() + 60_000;
+    assert!(store.expire("t11", "k1", Some(0)).unwrap());
+    assert!(store.expire("t11", "k2", Some(later)).unwrap());
+    assert!(store.expire("t11", "k3", Some(0)).unwrap());
+    store.set("t11", "k3".into(), "k3".into()).unwrap();
+
+    assert_eq!(store.get("t11", "k1").unwrap(), None);
+    assert!(!store.contains("t11", "k1").unwrap());
+    assert!(!store.expire("t11", "k1", None).unwrap());
+    assert_eq!(store.deadline("t11", "k1").unwrap(), None);
+    assert_eq!(store.deadline("t11", "k2").unwrap(), Some(later));
+    assert_eq!(store.deadline("t11", "k3").unwrap(), None);
+
+    // the scans hide the expired keys not purged yet
+    let keys = |pairs: Vec<Kvpair>| pairs.into_iter().map(|p| p.key).collect::<Vec<_>>();
+    assert_eq!(keys(store.get_all("t11").unwrap()), ["k2", "k3"]);
+    let mut iterated = keys(store.get_iter("t11").unwrap().collect());
+    iterated.sort();
+    assert_eq!(iterated, ["k2", "k3"]);
+    let scanned = store.scan("t11", ScanOptions::default()).unwrap();
+    assert_eq!(keys(scanned.collect()), ["k2", "k3"]);
+    let range = store.get_range("t11", None, None, Some(1)).unwrap();
+    assert_eq!(keys(range), ["k2"]);
+    assert_eq!(store.sample("t11", 10).unwrap().len(), 2);
+
+    let purged = store.purge_expired(now_ms()).unwrap();
+    assert_eq!(purged, vec![("t11".to_string(), "k1".to_string())]);
+    let size = pair_size("k2", &"k2".into()) + pair_size("k3", &"k3".into());
+    assert_eq!(store.size_of_table("t11").unwrap(), size);
+
+    // a persistent key never expires
+    assert!(store.expire("t11", "k2", None).unwrap());
+    assert_eq!(store.deadline("t11", "k2").unwrap(), None);
+    assert!(store.purge_expired(u64::MAX).unwrap().is_empty());
+}
+
+/// update is atomic, keeps the deadline unless overwriting, deletes the key on Delete,
+/// and writes nothing on Keep
+pub fn test_update(store: impl Storage) {
+    let incr = |v: Option<&Value>| -> Result<Update, KvError> {
+        let n: i64 = v.map(|v| v.try_into()).transpose()?.unwrap_or(0);
+        Ok(Update::Set((n + 1).into()))
+    };
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..100 {
+                    store.update("t12", "k1", &mut { incr }).unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(store.get("t12", "k1").unwrap(), Some(400.into()));
+
+    let later = now_ms() + 60_000;
+    store.expire("t12", "k1", Some(later)).unwrap();
+    let updated = store.update("t12", "k1", &mut { incr }).unwrap();
+    assert_eq!(updated, (Some(400.into()), Some(401.into())));
+    assert_eq!(store.deadline("t12", "k1").unwrap(), Some(later));
+
+    // a failed update writes nothing
+    let res = store.update("t12", "k1", &mut |_| {
+        Err(KvError::Internal("failed".into()))
+    });
+    assert!(res.is_err());
+    assert_eq!(store.get("t12", "k1").unwrap(), Some(401.into()));
+
+    let updated = store
+        .update("t12", "k1", &mut |_| Ok(Update::Delete))
+        .unwrap();
+    assert_eq!(updated, (Some(401.into()), None));
+    assert!(!store.contains("t12", "k1").unwrap());
+    assert_eq!(store.size_of_table("t12").unwrap(), 0);
+
+    let updated = store
+        .update("t12", "k2", &mut |_| Ok(Update::Keep))
+        .unwrap();
+    assert_eq!(updated, (None, None));
+    assert!(!store.contains("t12", "k2").unwrap());
+
+    store.set("t12", "k2".into(), "v1".into()).unwrap();
+    store.expire("t12", "k2", Some(later)).unwrap();
+    let (version, modified) = (
+        store.version("t12", "k2").unwrap(),
+        store.modified("t12", "k2").unwrap(),
+    );
+    let updated = store
+        .update("t12", "k2", &mut |_| Ok(Update::Keep))
+        .unwrap();
+    assert_eq!(updated, (Some("v1".into()), Some("v1".into())));
+    assert_eq!(store.version("t12", "k2").unwrap(), version);
+    assert_eq!(store.modified("t12", "k2").unwrap(), modified);
+    assert_eq!(store.deadline("t12", "k2").unwrap(), Some(later));
+
+    let overwrite = |_: Option<&Value>| Ok(Update::Overwrite("v2".into()));
+    let updated = store.update("t12", "k2", &mut { overwrite }).unwrap();
+    assert_eq!(updated, (Some("v1".into()), Some("v2".into())));
+    assert_eq!(store.get("t12", "k2").unwrap(), Some("v2".into()));
+    assert_eq!(store.deadline("t12", "k2").unwrap(), None);
+
+    // an expiring update sets the deadline with the value, on a missing key too
+    let expiring = |_: Option<&Value>| Ok(Update::Expiring("v3".into(), later));
+    let updated = store.update("t12", "k2", &mut { expiring }).unwrap();
+    assert_eq!(updated, (Some("v2".into()), Some("v3".into())));
+    assert_eq!(store.deadline("t12", "k2").unwrap(), Some(later));
+    let updated = store.update("t12", "k3", &mut { expiring }).unwrap();
+    assert_eq!(updated, (None, Some("v3".into())));
+    assert_eq!(store.deadline("t12", "k3").unwrap(), Some(later));
+    store
+        .update("t12", "k3", &mut |_| Ok(Update::Expiring("v4".into(), 1)))
+        .unwrap();
+    assert!(!store.contains("t12", "k3").unwrap());
+}
+
+/// touch reports whether the key exists, and a tracked access time is never in the future
+pub fn test_touch(store: impl Storage) {
+    assert!(!store.touch("t17", "k1").unwrap());
+    assert_eq!(store.last_access("t17", "k1").unwrap(), None);
+
+    let before = now_ms();
+    store.set("t17", "k1".into(), "v1".into()).unwrap();
+    assert!(store.touch("t17", "k1").unwrap());
+    if let Some(ms) = store.last_access("t17", "k1").unwrap() {
+        assert!(ms >= before && ms <= now_ms());
+    }
+
+    store.del("t17", "k1").unwrap();
+    assert!(!store.touch("t17", "k1").unwrap());
+    assert_eq!(store.last_access("t17", "k1").unwrap(), None);
+}
+
+/// The versions increase on every write and are never reused, skipped if not tracked
+pub fn test_versions(store: impl Storage) {
+    assert_eq!(store.version("t21", "k1").unwrap(), None);
+    store.set("t21", "k1".into(), "v1".into()).unwrap();
+    let Some(v1) = store.version("t21", "k1").unwrap() else {
+        return;
+    };
+    if let Some(ms) = store.modified("t21", "k1").unwrap() {
+        assert!(ms <= now_ms());
+    }
+    assert_eq!(
+        store.get_versioned("t21", "k1").unwrap(),
+        Some(("v1".into(), v1))
+    );
+
+    store
+        .update("t21", "k1", &mut |_| Ok(Update::Set("v2".into())))
+        .unwrap();
+    let v2 = store.version("t21", "k1").unwrap().unwrap();
+    assert!(v2 > v1);
+
+    // a stale version is rejected and writes nothing
+    assert_eq!(
+        store
+            .set_if_version("t21", "k1".into(), "v3".into(), v1)
+            .unwrap(),
+        None
+    );
+    let (old, v3) = store
+        .set_if_version("t21", "k1".into(), "v3".into(), v2)
+        .unwrap()
+        .unwrap();
+    assert_eq!((old, v3 > v2), ("v2".into(), true));
+    assert_eq!(
+        store.get_versioned("t21", "k1").unwrap(),
+        Some(("v3".into(), v3))
+    );
+
+    // a key set again after a delete gets a new version
+    store.del("t21", "k1").unwrap();
+    assert_eq!(store.version("t21", "k1").unwrap(), None);
+    assert_eq!(store.modified("t21", "k1").unwrap(), None);
+    assert_eq!(
+        store
+            .set_if_version("t21", "k1".into(), "v4".into(), v3)
+            .unwrap(),
+        None
+    );
+    store.set("t21", "k1".into(), "v4".into()).unwrap();
+    assert!(store.version("t21", "k1").unwrap().unwrap() > v3);
+}
+
+/// set_batch and del_batch behave like set and del on each key in order
+pub fn test_batch(store: impl Storage) {
+    store.set("t22", "k1".into(), "old".into()).unwrap();
+    store.expire("t22", "k1", Some(u64::MAX)).unwrap();
+    let pairs = vec![
+        Kvpair::new("k1", "v1".into()),
+        Kvpair::new("k2", "v2".into()),
+        Kvpair::new("k2", "v3".into()),
+    ];
+    let olds = store.set_batch("t22", pairs).unwrap();
+    assert_eq!(olds, vec![Some("old".into()), None, Some("v2".into())]);
+    assert_eq!(
+        store.get_all("t22").unwrap(),
+        vec![
+            Kvpair::new("k1", "v1".into()),
+            Kvpair::new("k2", "v3".into())
+        ]
+    );
+    assert_eq!(store.deadline("t22", "k1").unwrap(), None);
+    let size = store.size_of_table("t22").unwrap();
+    assert_eq!(
+        size,
+        pair_size("k1", &"v1".into()) + pair_size("k2", &"v3".into())
+    );
+
+    let keys = vec!["k1".to_string(), "k3".into(), "k1".into()];
+    let olds = store.del_batch("t22", keys).unwrap();
+    assert_eq!(olds, vec![Some("v1".into()), None, None]);
+    assert_eq!(
+        store.get_all("t22").unwrap(),
+        vec![Kvpair::new("k2", "v3".into())]
+    );
+    assert_eq!(
+        store.size_of_table("t22").unwrap(),
+        pair_size("k2", &"v3".into())
+    );
+}
+
+/// get_range yields the pairs between the bounds in key order, ignoring the other tables
+pub fn test_range(store: impl Storage) {
+    for key in ["a", "b", "c", "d"] {
+        store.set("t23", key.into(), key.into()).unwrap();
+    }
+    store.set("t230", "b".into(), "other".into()).unwrap();
+    let keys = |pairs: Vec<Kvpair>| -> Vec<String> { pairs.into_iter().map(|p| p.key).collect() };
+
+    let range = store.get_range("t23", Some("b"), Some("d"), None).unwrap();
+    assert_eq!(range[0], Kvpair::new("b", "b".into()));
+    assert_eq!(keys(range), ["b", "c"]);
+    let range = store.get_range("t23", None, None, Some(2)).unwrap();
+    assert_eq!(keys(range), ["a", "b"]);
+    let range = store.get_range("t23", Some("bb"), None, None).unwrap();
+    assert_eq!(keys(range), ["c", "d"]);
+    assert!(store
+        .get_range("t23", Some("d"), Some("a"), None)
+        .unwrap()
+        .is_empty());
+    assert!(store.get_range("t24", None, None, None).unwrap().is_empty());
+}
+
+/// A snapshot skips the expired keys, and restoring it overwrites the changed keys,
+/// the storages without snapshots are skipped
+pub fn test_snapshot(store: impl Storage) {
+    store.set("t24", "k1".into(), "v1".into()).unwrap();
+    store.set("t24", "k2".into(), 2.into()).unwrap();
+    store.set("t240", "k1".into(), "other".into()).unwrap();
+    store.set("t24", "k3".into(), "gone".into()).unwrap();
+    let expired = store
+        .expire("t24", "k3", Some(now_ms() - 1))
+        .unwrap_or(false);
+
+    let mut snapshot = Vec::new();
+    let count = match store.snapshot(&mut snapshot) {
+        Ok(count) => count,
+        Err(KvError::InvalidCommand(_)) => return,
+        Err(e) => panic!("snapshot failed: {:?}", e),
+    };
+    assert_eq!(count, if expired { 3 } else { 4 });
+
+    store.del("t24", "k1").unwrap();
+    store.set("t24", "k2".into(), "changed".into()).unwrap();
+    store.set("t24", "k4".into(), "new".into()).unwrap();
+    assert_eq!(store.restore_snapshot(&mut &snapshot[..]).unwrap(), count);
+    assert_eq!(store.get("t24", "k1").unwrap(), Some("v1".into()));
+    assert_eq!(store.get("t24", "k2").unwrap(), Some(2.into()));
+    assert_eq!(store.get("t24", "k4").unwrap(), Some("new".into()));
+    assert_eq!(store.get("t240", "k1").unwrap(), Some("other".into()));
+    if expired {
+        assert_eq!(store.get("t24", "k3").unwrap(), None);
+    }
+}
+
+/// The lists are stored as one value, pushed and popped at both ends
+pub fn test_list(store: impl Storage) {
+    let values = |v: &[i64]| -> Vec<Value> { v.iter().map(|i| (*i).into()).collect() };
+    assert_eq!(
+        store
+            .list_push("t18", "l1", values(&[2, 3]), false, None)
+            .unwrap(),
+        2
+    );
+    assert_eq!(
+        store
+            .list_push("t18", "l1", values(&[1, 0]), true, None)
+            .unwrap(),
+        4
+    );
+    assert_eq!(
+        store.list_range("t18", "l1", 0, -1).unwrap(),
+        values(&[0, 1, 2, 3])
+    );
+
+    // a push growing the list over the size limit is rejected, the list is left unchanged
+    let size = store.get("t18", "l1").unwrap().unwrap().encoded_len();
+    let res = store.list_push("t18", "l1", values(&[4]), false, Some(size));
+    assert!(matches!(res, Err(KvError::ValueTooLarge(_, max)) if max == size));
+    assert_eq!(store.list_range("t18", "l1", 0, -1).unwrap().len(), 4);
+    assert_eq!(store.list_range("t18", "l1", 5, 10).unwrap(), vec![]);
+
+    assert_eq!(store.list_pop("t18", "l1", 1, true).unwrap(), values(&[0]));
+    assert_eq!(
+        store.list_pop("t18", "l1", 10, false).unwrap(),
+        values(&[3, 2, 1])
+    );
+    assert!(!store.contains("t18", "l1").unwrap());
+    assert_eq!(store.list_pop("t18", "l1", 1, false).unwrap(), vec![]);
+
+    store.set("t18", "k1".into(), "v1".into()).unwrap();
+    assert!(store
+        .list_push("t18", "k1", values(&[1]), false, None)
+        .is_err());
+    assert_eq!(store.get("t18", "k1").unwrap(), Some("v1".into()));
+}
+
+/// The sets are stored as one value, an emptied set deletes its key
+pub fn test_set(store: impl Storage) {
+    let members = |m: &[&str]| -> Vec<String> { m.iter().map(|m| m.to_string()).collect() };
+    assert_eq!(
+        store
+            .set_add("t19", "s1", &members(&["b", "a"]), None)
+            .unwrap(),
+        2
+    );
+    assert_eq!(
+        store
+            .set_add("t19", "s1", &members(&["a", "c"]), None)
+            .unwrap(),
+        1
+    );
+    let set = store.set_members("t19", "s1").unwrap();
+    assert_eq!(
+        set.into_iter().collect::<Vec<_>>(),
+        members(&["a", "b", "c"])
+    );
+
+    assert_eq!(
+        store
+            .set_remove("t19", "s1", &members(&["a", "x"]))
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        store
+            .set_remove("t19", "s1", &members(&["b", "c"]))
+            .unwrap(),
+        2
+    );
+    assert!(!store.contains("t19", "s1").unwrap());
+    assert!(store.set_members("t19", "s1").unwrap().is_empty());
+    assert_eq!(store.set_remove("t19", "s1", &members(&["a"])).unwrap(), 0);
+}
+
+/// The sorted sets keep their members ordered by score then member
+pub fn test_zset(store: impl Storage) {
+    let members = |m: &[(&str, f64)]| -> Vec<ZsetMember> {
+        m.iter().map(|(m, s)| ZsetMember::new(*m, *s)).collect()
+    };
+    let added = store.zset_add(
+        "t20",
+        "z1",
+        &members(&[("b", 2.0), ("a", 2.0), ("c", 1.0)]),
+        None,
+    );
+    assert_eq!(added.unwrap(), 3);
+    assert_eq!(
+        store
+            .zset_add("t20", "z1", &members(&[("c", 3.0)]), None)
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        store.zset_members("t20", "z1").unwrap(),
+        members(&[("a", 2.0), ("b", 2.0), ("c", 3.0)])
+    );
+    assert_eq!(
+        store.zset_range_by_score("t20", "z1", 2.5, 10.0).unwrap(),
+        members(&[("c", 3.0)])
+    );
+    assert!(store
+        .zset_range_by_score("t20", "z1", 3.0, 2.0)
+        .unwrap()
+        .is_empty());
+    assert!(store.zset_members("t20", "z2").unwrap().is_empty());
+}
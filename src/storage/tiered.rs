@@ -0,0 +1,64 @@
+use crate::{MemTable, SledDb};
+
+use super::{CacheConfig, CachedStore};
+
+/// A MemTable caching the recently used pairs of a SledDb, written through
+pub type TieredStore = CachedStore<MemTable, SledDb>;
+
+impl TieredStore {
+    /// Cache at most `capacity` bytes of the pairs of the sled database, a reopened store
+    /// starts with an empty cache
+    pub fn with_capacity(disk: SledDb, capacity: usize) -> Self {
+        Self::new(
+            MemTable::new(),
+            disk,
+            CacheConfig::default().capacity(capacity),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{storage::pair_size, Storage, Value};
+
+    #[test]
+    fn tiered_store_should_evict_the_least_recently_used() {
+        let size = pair_size("k0", &Value::from("v0"));
+        let dir = tempdir().unwrap();
+        let store = TieredStore::with_capacity(SledDb::new(&dir), size * 2);
+        store.set("t1", "k0".into(), "v0".into()).unwrap();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.get("t1", "k0").unwrap();
+        store.set("t1", "k2".into(), "v2".into()).unwrap();
+        assert_eq!(store.cached_size().unwrap(), size * 2);
+        assert_eq!(store.metrics().evictions, 1);
+
+        // the evicted pair is read from sled and cached again, evicting k0
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("t1", "k2").unwrap(), Some("v2".into()));
+        let metrics = store.metrics();
+        assert_eq!((metrics.hits, metrics.misses, metrics.evictions), (2, 1, 2));
+        assert_eq!(store.len_of_table("t1").unwrap(), 3);
+    }
+
+    #[test]
+    fn tiered_store_should_reopen_with_the_pairs_of_sled() {
+        let dir = tempdir().unwrap();
+        {
+            let store = TieredStore::with_capacity(SledDb::new(dir.path()), 1024);
+            store.set("t1", "k1".into(), "v1".into()).unwrap();
+            store.expire("t1", "k1", Some(u64::MAX)).unwrap();
+            store.flush().unwrap();
+        }
+
+        let store = TieredStore::with_capacity(SledDb::new(dir.path()), 1024);
+        assert_eq!(store.cached_size().unwrap(), 0);
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.get("t1", "k1").unwrap(), Some("v1".into()));
+        assert_eq!(store.metrics().hits, 1);
+        assert_eq!(store.deadline("t1", "k1").unwrap(), Some(u64::MAX));
+    }
+}
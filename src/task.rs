@@ -0,0 +1,25 @@
+use std::future::Future;
+
+use tokio::task::JoinHandle;
+
+/// Spawn a task with a name, the name is shown in tokio-console
+/// when the crate is built with `--cfg tokio_unstable`.
+pub fn spawn_named<F>(name: &str, fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(tokio_unstable)]
+    {
+        tokio::task::Builder::new()
+            .name(name)
+            .spawn(fut)
+            .expect("failed to spawn task")
+    }
+
+    #[cfg(not(tokio_unstable))]
+    {
+        let _ = name;
+        tokio::spawn(fut)
+    }
+}
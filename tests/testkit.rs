@@ -0,0 +1,15 @@
+//! The conformance suite run from outside the crate, like a third-party backend would
+#![cfg(feature = "test-util")]
+
+use kvdb::{storage::testkit, MemTable, SledDb};
+use tempfile::tempdir;
+
+kvdb::storage_conformance_tests!(memtable, MemTable::new());
+kvdb::storage_conformance_tests!(sleddb, dir = tempdir().unwrap() => SledDb::new(&dir));
+
+#[test]
+fn testkit_checks_should_run_alone() {
+    let dir = tempdir().unwrap();
+    testkit::test_basic_interface(SledDb::new(dir.path().join("basic")));
+    testkit::test_empty_table(SledDb::new(dir.path().join("empty")));
+}